@@ -1,47 +1,62 @@
-use crate::{event::*, cali_error::*};
+use crate::{event::*, cali_error::*, migration::*, store::*, tag::*, task::*};
 use std::path::PathBuf;
-use std::error::Error;
-use rusqlite::{params, Connection, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::{Connection, Result};
 
 
 pub struct Calendar {
+    id: i64,
     name: String,
     default: bool,
-    path: PathBuf,
+    store: Store,
 }
 
 impl Calendar {
-    
-    pub fn new(name: &str, path: &PathBuf) -> Result<Calendar, Box<dyn Error>> {
-        init_database(&path).unwrap();
-        if check_calendar(&path, &name)? {
-            return Err(Box::new(CalendarExistsError));
-        }
-        let existing_default = get_default(&path)?;
 
-        Ok(Calendar { 
-            name: name.to_string(), 
-            default: existing_default.is_none(), 
-            path: path.to_path_buf()
+    pub fn new(name: &str, store: &Store) -> Result<Calendar, CaliError> {
+        store.transaction(|store| {
+            if check_calendar(store, name)? {
+                return Err(CalendarExistsError.into());
+            }
+            let existing_default = get_default(store)?;
+            let default = existing_default.is_none();
+
+            Ok(Calendar {
+                id: store.get_backend().insert_calendar(name, default)?,
+                name: name.to_string(),
+                default,
+                store: store.clone(),
+            })
         })
     }
 
-    pub fn from(name: &str, path: &PathBuf) -> Result<Calendar, Box<dyn Error>> {
-        init_database(&path).unwrap();
-
-        if check_calendar(&path, name)? {
-            Calendar::from_existing(name, path)
+    pub fn from(name: &str, store: &Store) -> Result<Calendar, CaliError> {
+        if check_calendar(store, name)? {
+            Calendar::from_existing(name, store)
         } else {
-            Calendar::new(name, path)
+            Calendar::new(name, store)
         }
     }
 
-    fn from_existing(name: &str, path: &PathBuf) -> Result<Calendar, Box<dyn Error>> {
-        Ok( Calendar { 
-            name: name.to_string(), 
-            default: check_default(&path, name).unwrap(),
-            path: path.to_path_buf()
-        })
+    fn from_existing(name: &str, store: &Store) -> Result<Calendar, CaliError> {
+        let record = find_calendar(store, name)?
+            .ok_or_else(|| CalendarNotFoundError { name: name.to_string() })?;
+
+        Ok(Calendar::from_record(record, store))
+    }
+
+    fn from_record(record: CalendarRecord, store: &Store) -> Calendar {
+        Calendar {
+            id: record.id,
+            name: record.name,
+            default: record.default,
+            store: store.clone(),
+        }
+    }
+
+    pub fn get_id(&self) -> i64 {
+        self.id
     }
 
     pub fn get_name(&self) -> &str {
@@ -52,142 +67,167 @@ impl Calendar {
         &self.default
     }
 
-    pub fn get_path(&self) -> &PathBuf {
-        &self.path
+    pub fn get_path(&self) -> Option<&PathBuf> {
+        self.store.get_path()
+    }
+
+    pub fn get_store(&self) -> &Store {
+        &self.store
     }
 
-    pub fn update_name(&mut self, new_name: &str) -> Result<(), Box<dyn Error>> {
+    pub fn update_name(&mut self, new_name: &str) -> Result<(), CaliError> {
         rename_calendar(self, new_name)?;
         self.name = new_name.to_string();
         Ok(())
     }
 
-    pub fn add_event(&self, event: &Event) -> Result<(), Box<dyn Error>> {
-        insert_event(&self, &event)?;
+    pub fn add_event(&self, event: &Event) -> Result<(), CaliError> {
+        insert_event(self, event)?;
         Ok(())
     }
 
-    pub fn update_event(&self, event: &Event) -> Result<(), Box<dyn Error>> {
-        update_event(&self, &event)?;
+    pub fn update_event(&self, event: &Event) -> Result<(), CaliError> {
+        update_event(self, event)?;
         Ok(())
     }
 
-    pub fn remove_event(&self, event: &Event) -> Result<(), Box<dyn Error>> {
-        remove_event(&self, &event)?;
+    pub fn remove_event(&self, event: &Event) -> Result<(), CaliError> {
+        remove_event(self, event)?;
         Ok(())
     }
 
-    // pub fn next_event(&self) -> Option<&Event<Tz>> {
-
-    // }
+    pub fn update_event_scoped(&self, event: &mut Event, scope: Scope, changes: &EventChanges) -> Result<Option<Event>, CaliError> {
+        update_event_scoped(self, event, scope, changes)
+    }
 
-    // pub fn events_between(&self, start: NaiveDateTime, end: NaiveDateTime
-    // ) -> impl Iterator<Item = &Event<Tz>> {
+    pub fn remove_event_scoped(&self, event: &mut Event, scope: Scope) -> Result<(), CaliError> {
+        remove_event_scoped(self, event, scope)
+    }
 
-    // }
-}
+    pub fn add_task(&self, task: &Task) -> Result<(), CaliError> {
+        insert_task(self, task)?;
+        Ok(())
+    }
 
+    pub fn update_task(&self, task: &Task) -> Result<(), CaliError> {
+        update_task(self, task)?;
+        Ok(())
+    }
 
-// Create the calendar table if it doesn't already exist
-pub fn init_database(path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let conn = Connection::open(path)?;
+    pub fn remove_task(&self, task: &Task) -> Result<(), CaliError> {
+        remove_task(self, task)?;
+        Ok(())
+    }
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS calendars (
-            event_id TEXT NOT NULL,
-            calendar_name TEXT NOT NULL,
-            event_name TEXT NOT NULL,
-            event_start TEXT NOT NULL,
-            event_end TEXT NOT NULL,
-            event_recurring TEXT NOT NULL,
-            is_default INTEGER NOT NULL
-        )",
-        params![],
-    )?;
+    pub fn add_subtask(&self, task: &mut Task, parent: Option<&Task>) -> Result<(), CaliError> {
+        insert_subtask(self, task, parent)
+    }
 
-    Ok(())
-}
+    pub fn move_task(&self, task: &mut Task, parent: Option<&Task>) -> Result<(), CaliError> {
+        move_task(self, task, parent)
+    }
 
-// Checks if there is a calendar by the specified name
-pub fn check_calendar(path: &PathBuf, name: &str) -> Result<bool, Box<dyn Error>> {
-    let conn = Connection::open(path)?;
-    let check_name: Result<String> = conn.query_row(
-        "SELECT calendar_name FROM calendars WHERE calendar_name = ?1",
-        params![name],
-        |row| row.get(0),
-    );
+    pub fn block_task(&self, task: &mut Task, blocker: &Task) -> Result<(), CaliError> {
+        block_task(self, task, blocker)
+    }
 
-    match check_name {
-        Ok(_) => Ok(true),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
-        Err(e) => Err(Box::new(e)),
+    pub fn unblock_task(&self, task: &mut Task, blocker: &Task) -> Result<bool, CaliError> {
+        unblock_task(self, task, blocker)
     }
-}
 
-// Checks if an existing calendar set to default
-pub fn check_default(path: &PathBuf, name: &str) -> Result<bool, Box<dyn Error>> {
-    let conn = Connection::open(path)?;
-    let check_name: Result<String> = conn.query_row(
-        "SELECT is_default FROM calendars WHERE calendar_name = ?1 AND is_default = 1",
-        params![name],
-        |row| row.get(0),
-    );
+    pub fn complete_task(&self, task: &mut Task, at: DateTime<Tz>) -> Result<(), CaliError> {
+        complete_task(self, task, at)
+    }
 
-    match check_name {
-        Ok(_) => Ok(true),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
-        Err(e) => Err(Box::new(e)),
+    pub fn reopen_task(&self, task: &mut Task) -> Result<(), CaliError> {
+        reopen_task(self, task)
     }
-}
 
-// Gets the name of the default calendar
-pub fn get_default(path: &PathBuf) -> Result<Option<String>, Box<dyn Error>> {
-    let conn = Connection::open(path)?;
+    pub fn next_event(&self) -> Result<Option<Occurrence>, CaliError> {
+        get_next_event(self, &Utc::now().with_timezone(&Tz::UTC))
+    }
 
-    let default_calendar_name: Result<Option<String>> = conn.query_row(
-        "SELECT calendar_name FROM calendars WHERE is_default = 1",
-        params![],
-        |row| row.get(0),
-    );
+    pub fn events_between(&self, start: &DateTime<Tz>, end: &DateTime<Tz>) -> Result<Vec<Occurrence>, CaliError> {
+        get_events_between(self, start, end)
+    }
 
-    match default_calendar_name {
-        Ok(name) => Ok(name),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Err(e) => Err(Box::new(e)),
+    // Gets the occurrences in the window of the events whose tags match `filter`
+    pub fn events_between_tagged(&self, start: &DateTime<Tz>, end: &DateTime<Tz>, filter: &TagFilter) -> Result<Vec<Occurrence>, CaliError> {
+        get_events_between_tagged(self, start, end, filter)
     }
 }
 
-// Udpates the specified calendar to be the default
-pub fn update_default(path: &PathBuf, new_default: &str) -> Result<(), Box<dyn Error>> {
+
+// Opens a connection to the database with foreign key enforcement enabled
+pub fn open_database(path: &PathBuf) -> Result<Connection> {
     let conn = Connection::open(path)?;
-    // Reset calendar currently set to be the default
-    let mut remove_current = conn.prepare("UPDATE calendars SET is_default = 0 WHERE is_default <> 0")?;
-    remove_current.execute(params![])?;
-    // Set the specified calendar as the new default
-    let mut update_default = conn.prepare("UPDATE calendars SET is_default = 1 WHERE calendar_name = ?1")?;
-    update_default.execute(params![new_default])?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    Ok(conn)
+}
+
+// Creates or upgrades the database schema to the current version
+pub fn init_database(path: &PathBuf) -> Result<(), CaliError> {
+    let mut conn = open_database(path)?;
+    migrate(&mut conn)?;
 
     Ok(())
 }
 
-// Removes an existing calendar from the database
-pub fn remove_calendar(calendar: &Calendar) -> Result<()> {
-    let conn = Connection::open(calendar.get_path())?;
-    conn.execute(
-        "DELETE FROM calendars WHERE calendar_name = ?1",
-        params![calendar.get_name().to_string()],
-    )?;
+// Finds the calendar by the specified name
+fn find_calendar(store: &Store, name: &str) -> Result<Option<CalendarRecord>, CaliError> {
+    Ok(store.get_backend().get_calendars()?.into_iter().find(|record| record.name == name))
+}
 
-    Ok(())
+// Checks if there is a calendar by the specified name
+pub fn check_calendar(store: &Store, name: &str) -> Result<bool, CaliError> {
+    Ok(find_calendar(store, name)?.is_some())
+}
+
+// Checks if an existing calendar set to default
+pub fn check_default(store: &Store, name: &str) -> Result<bool, CaliError> {
+    Ok(find_calendar(store, name)?.is_some_and(|record| record.default))
+}
+
+// Gets the name of the default calendar
+pub fn get_default(store: &Store) -> Result<Option<String>, CaliError> {
+    let calendars = store.get_backend().get_calendars()?;
+    Ok(calendars.into_iter().find(|record| record.default).map(|record| record.name))
 }
 
-// Renames an existing calendar in the database
-pub fn rename_calendar(calendar: &Calendar, new_name: &str) -> Result<()> {
-    let conn = Connection::open(calendar.get_path())?;
-    conn.execute(
-        "UPDATE calendars SET calendar_name = ?2 WHERE calendar_name = ?1",
-        params![calendar.get_name().to_string(), new_name.to_string()],
-    )?;
+// Gets every calendar in the store, ordered by name
+pub fn get_calendars(store: &Store) -> Result<Vec<Calendar>, CaliError> {
+    let calendars = store.get_backend().get_calendars()?;
+    Ok(calendars.into_iter().map(|record| Calendar::from_record(record, store)).collect())
+}
 
-    Ok(())
-}
\ No newline at end of file
+// Gets the events set aside when the store was upgraded because they could
+// not be read
+pub fn get_unreadable_events(store: &Store) -> Result<Vec<UnreadableEvent>, CaliError> {
+    store.get_backend().get_unreadable_events()
+}
+
+// Udpates the specified calendar to be the default. Both changes are made
+// together, so there is never a moment without a default.
+pub fn update_default(store: &Store, new_default: &str) -> Result<(), CaliError> {
+    store.transaction(|store| {
+        let record = find_calendar(store, new_default)?
+            .ok_or_else(|| CalendarNotFoundError { name: new_default.to_string() })?;
+        store.get_backend().update_default(record.id)
+    })
+}
+
+// Removes an existing calendar and all of its events and tasks
+pub fn remove_calendar(calendar: &Calendar) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|store| store.get_backend().remove_calendar(calendar.get_id()))
+}
+
+// Renames an existing calendar in the store, unless another calendar already
+// has the new name
+pub fn rename_calendar(calendar: &Calendar, new_name: &str) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|store| {
+        if check_calendar(store, new_name)? {
+            return Err(CalendarExistsError.into());
+        }
+        store.get_backend().rename_calendar(calendar.get_id(), new_name)
+    })
+}
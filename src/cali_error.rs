@@ -7,4 +7,469 @@ impl std::fmt::Display for CalendarExistsError {
     }
 }
 
-impl std::error::Error for CalendarExistsError {}
\ No newline at end of file
+impl std::error::Error for CalendarExistsError {}
+
+#[derive(Debug)]
+pub struct SchemaVersionError {
+    pub found: u32,
+    pub supported: u32,
+}
+
+impl std::fmt::Display for SchemaVersionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Database schema version {} is newer than the supported version {}.", self.found, self.supported)
+    }
+}
+
+impl std::error::Error for SchemaVersionError {}
+
+
+#[derive(Debug)]
+pub struct InvalidTimeError {
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidTimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid event time: {}.", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidTimeError {}
+
+
+#[derive(Debug)]
+pub struct InvalidRecurrenceError {
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidRecurrenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid recurrence rule: {}.", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidRecurrenceError {}
+
+
+#[derive(Debug)]
+pub struct InvalidTagError {
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidTagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid tag: {}.", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidTagError {}
+
+
+#[derive(Debug)]
+pub struct InvalidTaskError {
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidTaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid task: {}.", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidTaskError {}
+
+
+#[derive(Debug)]
+pub struct InvalidSearchError {
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidSearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid search: {}.", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidSearchError {}
+
+
+#[derive(Debug)]
+pub struct CalendarNotFoundError {
+    pub name: String,
+}
+
+impl std::fmt::Display for CalendarNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No calendar named '{}' exists.", self.name)
+    }
+}
+
+impl std::error::Error for CalendarNotFoundError {}
+
+
+#[derive(Debug)]
+pub struct EventNotFoundError {
+    pub query: String,
+}
+
+impl std::fmt::Display for EventNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No event matches '{}'.", self.query)
+    }
+}
+
+impl std::error::Error for EventNotFoundError {}
+
+
+#[derive(Debug)]
+pub struct AmbiguousEventError {
+    pub query: String,
+    pub matches: Vec<String>,
+}
+
+impl std::fmt::Display for AmbiguousEventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' matches {} events; use one of their ids:", self.query, self.matches.len())?;
+        for event in &self.matches {
+            write!(f, "\n  {}", event)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AmbiguousEventError {}
+
+
+#[derive(Debug)]
+pub struct TaskNotFoundError {
+    pub query: String,
+}
+
+impl std::fmt::Display for TaskNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No task matches '{}'.", self.query)
+    }
+}
+
+impl std::error::Error for TaskNotFoundError {}
+
+
+#[derive(Debug)]
+pub struct AmbiguousTaskError {
+    pub query: String,
+    pub matches: Vec<String>,
+}
+
+impl std::fmt::Display for AmbiguousTaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' matches {} tasks; use one of their ids:", self.query, self.matches.len())?;
+        for task in &self.matches {
+            write!(f, "\n  {}", task)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AmbiguousTaskError {}
+
+
+#[derive(Debug)]
+pub struct BlockedTaskError {
+    pub title: String,
+    pub blockers: Vec<String>,
+}
+
+impl std::fmt::Display for BlockedTaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' is blocked by open tasks: {}.", self.title, self.blockers.join(", "))
+    }
+}
+
+impl std::error::Error for BlockedTaskError {}
+
+
+#[derive(Debug)]
+pub struct InvalidICalendarError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidICalendarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid iCalendar data on line {}: {}.", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidICalendarError {}
+
+
+#[derive(Debug)]
+pub struct HomeDirectoryError {
+    pub variable: String,
+}
+
+impl std::fmt::Display for HomeDirectoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not find the home directory: set HOME or {}.", self.variable)
+    }
+}
+
+impl std::error::Error for HomeDirectoryError {}
+
+
+#[derive(Debug)]
+pub struct UnknownSettingError {
+    pub name: String,
+}
+
+impl std::fmt::Display for UnknownSettingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "There is no setting named '{}'.", self.name)
+    }
+}
+
+impl std::error::Error for UnknownSettingError {}
+
+
+#[derive(Debug)]
+pub struct InvalidSettingError {
+    pub name: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidSettingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid setting '{}': {}.", self.name, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidSettingError {}
+
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub what: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not read {}: {}.", self.what, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+
+// Every failure reported by cali, so callers can match on what went wrong
+#[derive(Debug)]
+pub enum CaliError {
+    CalendarNotFound(CalendarNotFoundError),
+    EventNotFound(EventNotFoundError),
+    AmbiguousEvent(AmbiguousEventError),
+    TaskNotFound(TaskNotFoundError),
+    AmbiguousTask(AmbiguousTaskError),
+    BlockedTask(BlockedTaskError),
+    AlreadyExists(CalendarExistsError),
+    InvalidTime(InvalidTimeError),
+    InvalidRecurrence(InvalidRecurrenceError),
+    InvalidTag(InvalidTagError),
+    InvalidTask(InvalidTaskError),
+    InvalidSearch(InvalidSearchError),
+    InvalidICalendar(InvalidICalendarError),
+    UnknownSetting(UnknownSettingError),
+    InvalidSetting(InvalidSettingError),
+    SchemaVersion(SchemaVersionError),
+    HomeDirectory(HomeDirectoryError),
+    Parse(ParseError),
+    Storage(rusqlite::Error),
+    Io(std::io::Error),
+}
+
+impl CaliError {
+    // Gets the process exit code for the failure, following sysexits(3)
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CaliError::UnknownSetting(_) => 64,
+            CaliError::InvalidTime(_)
+            | CaliError::InvalidRecurrence(_)
+            | CaliError::InvalidTag(_)
+            | CaliError::InvalidTask(_)
+            | CaliError::InvalidSearch(_)
+            | CaliError::InvalidICalendar(_)
+            | CaliError::AmbiguousEvent(_)
+            | CaliError::AmbiguousTask(_)
+            | CaliError::Parse(_) => 65,
+            CaliError::CalendarNotFound(_) | CaliError::EventNotFound(_) | CaliError::TaskNotFound(_) => 66,
+            CaliError::AlreadyExists(_) => 73,
+            CaliError::Storage(_) | CaliError::Io(_) => 74,
+            CaliError::BlockedTask(_) => 75,
+            CaliError::InvalidSetting(_) | CaliError::SchemaVersion(_) | CaliError::HomeDirectory(_) => 78,
+        }
+    }
+}
+
+impl std::fmt::Display for CaliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaliError::CalendarNotFound(e) => e.fmt(f),
+            CaliError::EventNotFound(e) => e.fmt(f),
+            CaliError::AmbiguousEvent(e) => e.fmt(f),
+            CaliError::TaskNotFound(e) => e.fmt(f),
+            CaliError::AmbiguousTask(e) => e.fmt(f),
+            CaliError::BlockedTask(e) => e.fmt(f),
+            CaliError::AlreadyExists(e) => e.fmt(f),
+            CaliError::InvalidTime(e) => e.fmt(f),
+            CaliError::InvalidRecurrence(e) => e.fmt(f),
+            CaliError::InvalidTag(e) => e.fmt(f),
+            CaliError::InvalidTask(e) => e.fmt(f),
+            CaliError::InvalidSearch(e) => e.fmt(f),
+            CaliError::InvalidICalendar(e) => e.fmt(f),
+            CaliError::UnknownSetting(e) => e.fmt(f),
+            CaliError::InvalidSetting(e) => e.fmt(f),
+            CaliError::SchemaVersion(e) => e.fmt(f),
+            CaliError::HomeDirectory(e) => e.fmt(f),
+            CaliError::Parse(e) => e.fmt(f),
+            CaliError::Storage(e) => write!(f, "Database error: {}.", e),
+            CaliError::Io(e) => write!(f, "{}.", e),
+        }
+    }
+}
+
+impl std::error::Error for CaliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CaliError::Storage(e) => Some(e),
+            CaliError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CalendarNotFoundError> for CaliError {
+    fn from(e: CalendarNotFoundError) -> CaliError {
+        CaliError::CalendarNotFound(e)
+    }
+}
+
+impl From<EventNotFoundError> for CaliError {
+    fn from(e: EventNotFoundError) -> CaliError {
+        CaliError::EventNotFound(e)
+    }
+}
+
+impl From<AmbiguousEventError> for CaliError {
+    fn from(e: AmbiguousEventError) -> CaliError {
+        CaliError::AmbiguousEvent(e)
+    }
+}
+
+impl From<TaskNotFoundError> for CaliError {
+    fn from(e: TaskNotFoundError) -> CaliError {
+        CaliError::TaskNotFound(e)
+    }
+}
+
+impl From<AmbiguousTaskError> for CaliError {
+    fn from(e: AmbiguousTaskError) -> CaliError {
+        CaliError::AmbiguousTask(e)
+    }
+}
+
+impl From<BlockedTaskError> for CaliError {
+    fn from(e: BlockedTaskError) -> CaliError {
+        CaliError::BlockedTask(e)
+    }
+}
+
+impl From<CalendarExistsError> for CaliError {
+    fn from(e: CalendarExistsError) -> CaliError {
+        CaliError::AlreadyExists(e)
+    }
+}
+
+impl From<InvalidTimeError> for CaliError {
+    fn from(e: InvalidTimeError) -> CaliError {
+        CaliError::InvalidTime(e)
+    }
+}
+
+impl From<InvalidRecurrenceError> for CaliError {
+    fn from(e: InvalidRecurrenceError) -> CaliError {
+        CaliError::InvalidRecurrence(e)
+    }
+}
+
+impl From<InvalidTagError> for CaliError {
+    fn from(e: InvalidTagError) -> CaliError {
+        CaliError::InvalidTag(e)
+    }
+}
+
+impl From<InvalidTaskError> for CaliError {
+    fn from(e: InvalidTaskError) -> CaliError {
+        CaliError::InvalidTask(e)
+    }
+}
+
+impl From<InvalidSearchError> for CaliError {
+    fn from(e: InvalidSearchError) -> CaliError {
+        CaliError::InvalidSearch(e)
+    }
+}
+
+impl From<InvalidICalendarError> for CaliError {
+    fn from(e: InvalidICalendarError) -> CaliError {
+        CaliError::InvalidICalendar(e)
+    }
+}
+
+impl From<UnknownSettingError> for CaliError {
+    fn from(e: UnknownSettingError) -> CaliError {
+        CaliError::UnknownSetting(e)
+    }
+}
+
+impl From<InvalidSettingError> for CaliError {
+    fn from(e: InvalidSettingError) -> CaliError {
+        CaliError::InvalidSetting(e)
+    }
+}
+
+impl From<SchemaVersionError> for CaliError {
+    fn from(e: SchemaVersionError) -> CaliError {
+        CaliError::SchemaVersion(e)
+    }
+}
+
+impl From<HomeDirectoryError> for CaliError {
+    fn from(e: HomeDirectoryError) -> CaliError {
+        CaliError::HomeDirectory(e)
+    }
+}
+
+impl From<ParseError> for CaliError {
+    fn from(e: ParseError) -> CaliError {
+        CaliError::Parse(e)
+    }
+}
+
+impl From<rusqlite::Error> for CaliError {
+    fn from(e: rusqlite::Error) -> CaliError {
+        CaliError::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for CaliError {
+    fn from(e: std::io::Error) -> CaliError {
+        CaliError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for CaliError {
+    fn from(e: toml::de::Error) -> CaliError {
+        CaliError::Parse(ParseError { what: "the configuration file".to_string(), reason: e.message().to_string() })
+    }
+}
+
@@ -0,0 +1,208 @@
+use crate::{cali_error::*, date_expr::*, time::*, view::*};
+use chrono::Weekday;
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use toml::{Table, Value};
+
+// The settings cali reads, with their built-in defaults and descriptions
+pub const SETTINGS: [(&str, &str, &str); 7] = [
+    ("view", "agenda", "View shown when no subcommand is given: agenda, week, day or month"),
+    ("week_start", "monday", "Day the weeks start on"),
+    ("clock", "24h", "Clock times are shown with: 24h or 12h"),
+    ("timezone", "local", "IANA time zone times are shown and entered in, or local"),
+    ("date_format", "%Y-%m-%d", "strftime format dates are shown with"),
+    ("color", "auto", "Whether output is highlighted: auto, always or never"),
+    ("db", "", "Database or vdir directory to use, instead of calendar.db in the cali data directory"),
+];
+
+// Where the value of a setting comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Environment,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "file"),
+            Source::Environment => write!(f, "env"),
+        }
+    }
+}
+
+// The settings given in the configuration file and the environment. A
+// setting in the environment overrides the file, which overrides the built-in
+// default; command line options override them all.
+#[derive(Clone, Default)]
+pub struct Config {
+    path: Option<PathBuf>,
+    file: Table,
+    environment: BTreeMap<String, String>,
+}
+
+impl Config {
+    // Creates a configuration holding only the built-in defaults
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    // Loads the configuration file at `path`, if there is one, and the
+    // CALI_* environment variables of the settings
+    pub fn load(path: Option<PathBuf>) -> Result<Config, CaliError> {
+        let mut config = Config::new();
+        if let Some(path) = path {
+            match fs::read_to_string(&path) {
+                Ok(text) => config.file = parse_config(&text)?,
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+            }
+            config.path = Some(path);
+        }
+
+        for (name, _, _) in SETTINGS {
+            if let Ok(value) = std::env::var(environment_variable(name)) {
+                check_setting(name, &value)?;
+                config.environment.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(config)
+    }
+
+    // Reads the settings in the text of a configuration file, without a file
+    // to save them to
+    pub fn from(text: &str) -> Result<Config, CaliError> {
+        Ok(Config { file: parse_config(text)?, ..Config::new() })
+    }
+
+    pub fn get_path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    // Gets the value of a setting and where it comes from
+    pub fn get_setting(&self, name: &str) -> Result<(String, Source), UnknownSettingError> {
+        let default = setting_default(name)?;
+        if let Some(value) = self.environment.get(name) {
+            return Ok((value.to_string(), Source::Environment));
+        }
+        match self.file.get(name).and_then(Value::as_str) {
+            Some(value) => Ok((value.to_string(), Source::File)),
+            None => Ok((default.to_string(), Source::Default)),
+        }
+    }
+
+    // Gets the value of a setting
+    pub fn get_value(&self, name: &str) -> Result<String, UnknownSettingError> {
+        Ok(self.get_setting(name)?.0)
+    }
+
+    // Changes a setting in the file, once it is checked to be valid
+    pub fn update_setting(&mut self, name: &str, value: &str) -> Result<(), CaliError> {
+        check_setting(name, value)?;
+        self.file.insert(name.to_string(), Value::String(value.to_string()));
+        Ok(())
+    }
+
+    // Writes the file settings to the configuration file, creating its directory
+    pub fn save(&self) -> Result<(), CaliError> {
+        let path = self.path.as_ref().ok_or_else(|| InvalidSettingError {
+            name: "config".to_string(),
+            reason: "there is no configuration file to save to".to_string(),
+        })?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, self.file.to_string())?;
+
+        Ok(())
+    }
+
+    pub fn get_week_start(&self) -> Result<Weekday, CaliError> {
+        Ok(parse_week_start(&self.get_value("week_start")?)?)
+    }
+
+    // Gets the time zone times are shown in, which is the local one unless
+    // another is set
+    pub fn get_timezone(&self) -> Result<Tz, CaliError> {
+        match self.get_value("timezone")?.as_str() {
+            "local" => Ok(get_local_timezone()),
+            name => Ok(parse_timezone(name)?),
+        }
+    }
+
+    pub fn get_time_format(&self) -> Result<TimeFormat, CaliError> {
+        Ok(TimeFormat {
+            date: self.get_value("date_format")?,
+            twelve_hour: self.get_value("clock")? == "12h",
+        })
+    }
+}
+
+// Gets the name of the environment variable overriding a setting
+pub fn environment_variable(name: &str) -> String {
+    format!("CALI_{}", name.to_uppercase())
+}
+
+// Reads the day a week starts on
+pub fn parse_week_start(name: &str) -> Result<Weekday, InvalidTimeError> {
+    parse_weekday_name(&name.to_lowercase())
+        .ok_or_else(|| InvalidTimeError { reason: format!("'{}' is not a weekday", name) })
+}
+
+// Checks that `value` is valid for the setting `name`
+pub fn check_setting(name: &str, value: &str) -> Result<(), CaliError> {
+    setting_default(name)?;
+    let invalid = |reason: String| InvalidSettingError { name: name.to_string(), reason };
+    let one_of = |choices: &[&str]| match choices.contains(&value) {
+        true => Ok(()),
+        false => Err(invalid(format!("'{}' is not one of {}", value, choices.join(", ")))),
+    };
+
+    match name {
+        "view" => one_of(&["agenda", "week", "day", "month"])?,
+        "clock" => one_of(&["24h", "12h"])?,
+        "color" => one_of(&["auto", "always", "never"])?,
+        "week_start" => {
+            parse_week_start(value).map_err(|e| invalid(e.reason))?;
+        }
+        "timezone" if value != "local" => {
+            parse_timezone(value).map_err(|e| invalid(e.reason))?;
+        }
+        "date_format" if StrftimeItems::new(value).any(|item| item == Item::Error) => {
+            return Err(invalid(format!("'{}' is not a valid strftime format", value)).into());
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+// Gets the built-in default of a setting
+fn setting_default(name: &str) -> Result<&'static str, UnknownSettingError> {
+    SETTINGS.iter()
+        .find(|(setting, _, _)| *setting == name)
+        .map(|(_, default, _)| *default)
+        .ok_or_else(|| UnknownSettingError { name: name.to_string() })
+}
+
+// Reads and checks the settings in the text of a configuration file
+fn parse_config(text: &str) -> Result<Table, CaliError> {
+    let table: Table = text.parse()?;
+    for (name, value) in &table {
+        let value = value.as_str().ok_or_else(|| InvalidSettingError {
+            name: name.to_string(),
+            reason: "the value must be a string".to_string(),
+        })?;
+        check_setting(name, value)?;
+    }
+
+    Ok(table)
+}
@@ -0,0 +1,452 @@
+use crate::{cali_error::*, time::*};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use chrono_tz::Tz;
+
+// The date, time of day or exact instant named by a date expression
+#[derive(Default)]
+struct Parts {
+    date: Option<NaiveDate>,
+    time: Option<NaiveTime>,
+    instant: Option<DateTime<Tz>>,
+}
+
+impl Parts {
+    fn set_date(&mut self, date: NaiveDate) -> Result<(), InvalidTimeError> {
+        if self.date.is_some() || self.instant.is_some() {
+            return Err(invalid("more than one date was given"));
+        }
+        self.date = Some(date);
+        Ok(())
+    }
+
+    fn set_time(&mut self, time: NaiveTime) -> Result<(), InvalidTimeError> {
+        if self.time.is_some() {
+            return Err(invalid("more than one time of day was given"));
+        }
+        self.time = Some(time);
+        Ok(())
+    }
+
+    fn set_instant(&mut self, instant: DateTime<Tz>) -> Result<(), InvalidTimeError> {
+        if self.date.is_some() || self.instant.is_some() {
+            return Err(invalid("more than one date was given"));
+        }
+        self.instant = Some(instant);
+        Ok(())
+    }
+
+    // Combines the parts into one time. A date without a time of day means
+    // midnight, and a time of day without a date means today.
+    fn resolve(self, now: &DateTime<Tz>) -> Result<DateTime<Tz>, InvalidTimeError> {
+        let naive = match (self.instant, self.date, self.time) {
+            (Some(instant), _, None) => return Ok(instant),
+            (Some(instant), _, Some(time)) => instant.date_naive().and_time(time),
+            (None, Some(date), time) => date.and_time(time.unwrap_or(NaiveTime::MIN)),
+            (None, None, Some(time)) => now.date_naive().and_time(time),
+            (None, None, None) => return Err(invalid("no date or time was given")),
+        };
+
+        resolve_local(now.timezone(), &naive)
+            .ok_or_else(|| invalid(&format!("{} does not exist in {}", naive, now.timezone())))
+    }
+}
+
+// Reads a point in time written the way it would be typed at a terminal, such
+// as "tomorrow 9:30", "next friday 2pm", "in 3 days", "jul 24 noon" or
+// "2023-07-24 09:30". Relative words are resolved against `now`, and the
+// result is in the time zone of `now`.
+pub fn parse_datetime(input: &str, now: &DateTime<Tz>) -> Result<DateTime<Tz>, InvalidTimeError> {
+    parse_tokens(&tokenize(input), now)
+}
+
+// Reads a point in time like parse_datetime, also reporting whether it was
+// given to the minute: "friday" only names a day, while "friday 5pm" and
+// "in 2 hours" name a time
+pub fn parse_date_or_time(input: &str, now: &DateTime<Tz>) -> Result<(DateTime<Tz>, bool), InvalidTimeError> {
+    parse_timed_tokens(&tokenize(input), now)
+}
+
+// Reads the start of an event and, when one is given, its end. The end is
+// either a duration after "for", as in "tomorrow 9:30 for 15m", or a second
+// time after "-", "to" or "until", as in "next friday 2pm-4pm". An end that
+// is only a time of day falls on the start's day, or the day after when it
+// would otherwise come before the start.
+pub fn parse_range(input: &str, now: &DateTime<Tz>) -> Result<(DateTime<Tz>, Option<DateTime<Tz>>), InvalidTimeError> {
+    let mut tokens = tokenize(input);
+
+    if let Some(index) = tokens.iter().rposition(|token| token == "for") {
+        let start = parse_tokens(&tokens[..index], now)?;
+        let duration = read_duration(&tokens[index + 1..].join(" "))?;
+        return Ok((start, Some(shift(&start, duration)?)));
+    }
+
+    // "2pm-4pm" typed without spaces is split into "2pm", "-", "4pm"
+    if let Some(index) = tokens.iter().position(|token| is_joined_range(token)) {
+        let (left, right) = tokens[index].split_once('-').unwrap();
+        let (left, right) = (left.to_string(), right.to_string());
+        tokens.splice(index..=index, [left, "-".to_string(), right]);
+    }
+
+    let separator = tokens.iter().position(|token| ["-", "to", "until", "till"].contains(&token.as_str()));
+    let Some(index) = separator else {
+        return Ok((parse_tokens(&tokens, now)?, None));
+    };
+
+    let (left, right) = (&tokens[..index], &tokens[index + 1..]);
+    let left = inherit_meridiem(left, right);
+    let start = parse_tokens(&left, now)?;
+    let end = match read_clock(right, true) {
+        Some((time, consumed)) if consumed == right.len() => {
+            let mut end = start.date_naive().and_time(time);
+            if end <= start.naive_local() {
+                end += Duration::days(1);
+            }
+            resolve_local(now.timezone(), &end)
+                .ok_or_else(|| invalid(&format!("{} does not exist in {}", end, now.timezone())))?
+        }
+        _ => parse_tokens(right, now)?,
+    };
+
+    Ok((start, Some(end)))
+}
+
+// Reads the first and last days of an all-day event, such as "friday",
+// "jul 24 to jul 26" or "monday for 3 days". A second date is the last day,
+// and a length after "for" must be a whole number of days.
+pub fn parse_day_range(input: &str, now: &DateTime<Tz>) -> Result<(NaiveDate, NaiveDate), InvalidTimeError> {
+    let tokens = tokenize(input);
+    if let Some(index) = tokens.iter().rposition(|token| token == "for") {
+        let first = parse_tokens(&tokens[..index], now)?.date_naive();
+        let length = tokens[index + 1..].join(" ");
+        return match read_duration(&length)? {
+            (duration, true) if duration.num_days() >= 1 => Ok((first, first + Duration::days(duration.num_days() - 1))),
+            _ => Err(invalid(&format!("'{}' is not a number of days", length))),
+        };
+    }
+
+    let (start, end) = parse_range(input, now)?;
+    let first = start.date_naive();
+    let last = end.map_or(first, |end| end.date_naive());
+    if last < first {
+        return Err(invalid(&format!("last day {} is before first day {}", last, first)));
+    }
+
+    Ok((first, last))
+}
+
+// Reads a length of time such as "15m", "1h30m", "90 min" or
+// "1 hour and 30 minutes"
+pub fn parse_duration(input: &str) -> Result<Duration, InvalidTimeError> {
+    read_duration(input).map(|(duration, _)| duration)
+}
+
+// Reads a length of time, also reporting whether it was given only in days
+// and weeks so that it can be applied to the calendar rather than the clock
+fn read_duration(input: &str) -> Result<(Duration, bool), InvalidTimeError> {
+    let input = input.to_lowercase().replace(" and ", " ");
+    let mut chars = input.chars().peekable();
+    let mut total = Duration::zero();
+    let mut found = false;
+    let mut whole_days = true;
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+            number.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut unit = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+            unit.push(c);
+        }
+
+        let unit = match unit.as_str() {
+            "s" | "sec" | "secs" | "second" | "seconds" => Duration::seconds(1),
+            "m" | "min" | "mins" | "minute" | "minutes" => Duration::minutes(1),
+            "h" | "hr" | "hrs" | "hour" | "hours" => Duration::hours(1),
+            "d" | "day" | "days" => Duration::days(1),
+            "w" | "wk" | "wks" | "week" | "weeks" => Duration::weeks(1),
+            _ => return Err(invalid(&format!("'{}' is not a duration such as 1h30m", input.trim()))),
+        };
+        let part = number.parse::<i32>().ok()
+            .and_then(|number| unit.checked_mul(number))
+            .ok_or_else(|| invalid(&format!("'{}' is not a duration such as 1h30m", input.trim())))?;
+        total = total.checked_add(&part).ok_or_else(|| invalid("the duration is too long"))?;
+        whole_days &= unit >= Duration::days(1);
+        found = true;
+    }
+
+    if !found {
+        return Err(invalid("no duration was given"));
+    }
+
+    Ok((total, whole_days))
+}
+
+// Builds the error reported for an unreadable date expression
+fn invalid(reason: &str) -> InvalidTimeError {
+    InvalidTimeError { reason: reason.to_string() }
+}
+
+// Splits an expression into lowercase words
+fn tokenize(input: &str) -> Vec<String> {
+    input.to_lowercase()
+        .replace(',', " ")
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+// Reads an expression that has already been split into words
+fn parse_tokens(tokens: &[String], now: &DateTime<Tz>) -> Result<DateTime<Tz>, InvalidTimeError> {
+    parse_timed_tokens(tokens, now).map(|(time, _)| time)
+}
+
+// Reads an expression that has already been split into words, also reporting
+// whether it gave a time of day or an exact instant
+fn parse_timed_tokens(tokens: &[String], now: &DateTime<Tz>) -> Result<(DateTime<Tz>, bool), InvalidTimeError> {
+    if let [token] = tokens {
+        if let Ok(time) = DateTime::parse_from_rfc3339(token) {
+            return Ok((time.with_timezone(&now.timezone()), true));
+        }
+    }
+
+    let mut parts = Parts::default();
+    let mut index = 0;
+    while index < tokens.len() {
+        index += read_part(&tokens[index..], now, &mut parts)?;
+    }
+
+    let timed = parts.time.is_some() || parts.instant.is_some();
+    Ok((parts.resolve(now)?, timed))
+}
+
+// Reads the part of an expression at the start of `tokens` and returns how
+// many words it used
+fn read_part(tokens: &[String], now: &DateTime<Tz>, parts: &mut Parts) -> Result<usize, InvalidTimeError> {
+    let today = now.date_naive();
+    let token = tokens[0].as_str();
+    let next = tokens.get(1).map(String::as_str);
+
+    match token {
+        "now" => return parts.set_instant(*now).map(|_| 1),
+        "today" => return parts.set_date(today).map(|_| 1),
+        "tomorrow" => return parts.set_date(today + Duration::days(1)).map(|_| 1),
+        "yesterday" => return parts.set_date(today - Duration::days(1)).map(|_| 1),
+        "noon" | "midday" => return parts.set_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap()).map(|_| 1),
+        "midnight" => return parts.set_time(NaiveTime::MIN).map(|_| 1),
+        "on" => return Ok(1),
+        "at" => {
+            let (time, consumed) = read_clock(&tokens[1..], true)
+                .ok_or_else(|| invalid("expected a time of day after 'at'"))?;
+            return parts.set_time(time).map(|_| consumed + 1);
+        }
+        "this" | "next" | "last" => {
+            let date = match next {
+                Some("week") if token == "next" => today + Duration::weeks(1),
+                Some("week") if token == "last" => today - Duration::weeks(1),
+                Some(name) => {
+                    let weekday = parse_weekday_name(name)
+                        .ok_or_else(|| invalid(&format!("expected a weekday after '{}'", token)))?;
+                    relative_weekday(today, weekday, token)
+                }
+                None => return Err(invalid(&format!("expected a weekday after '{}'", token))),
+            };
+            return parts.set_date(date).map(|_| 2);
+        }
+        "in" => {
+            let (duration, consumed) = (2..=tokens.len()).rev()
+                .find_map(|end| Some((read_duration(&tokens[1..end].join(" ")).ok()?, end)))
+                .ok_or_else(|| invalid("expected a duration after 'in'"))?;
+            return parts.set_instant(shift(now, duration)?).map(|_| consumed);
+        }
+        _ => {}
+    }
+
+    // "3 days ago"
+    if let Some(ago) = tokens.iter().position(|token| token == "ago") {
+        if let Ok((duration, whole_days)) = read_duration(&tokens[..ago].join(" ")) {
+            return parts.set_instant(shift(now, (-duration, whole_days))?).map(|_| ago + 1);
+        }
+    }
+
+    if let Some(weekday) = parse_weekday_name(token) {
+        return parts.set_date(relative_weekday(today, weekday, "this")).map(|_| 1);
+    }
+
+    // "2023-07-24" or "2023-07-24t09:30"
+    let (date_text, time_text) = token.split_once('t').unwrap_or((token, ""));
+    if let Ok(date) = NaiveDate::parse_from_str(date_text, "%Y-%m-%d") {
+        parts.set_date(date)?;
+        if !time_text.is_empty() {
+            let (time, _) = read_clock(&[time_text.to_string()], true)
+                .ok_or_else(|| invalid(&format!("'{}' is not a time of day", time_text)))?;
+            parts.set_time(time)?;
+        }
+        return Ok(1);
+    }
+
+    // "jul 24", "24 jul" and either followed by a year
+    let month_day = match (parse_month_name(token), next.and_then(parse_day)) {
+        (Some(month), Some(day)) => Some((month, day)),
+        _ => match (parse_day(token), next.and_then(parse_month_name)) {
+            (Some(day), Some(month)) => Some((month, day)),
+            _ => None,
+        },
+    };
+    if let Some((month, day)) = month_day {
+        let year = tokens.get(2).filter(|year| year.len() == 4).and_then(|year| year.parse().ok());
+        let date = NaiveDate::from_ymd_opt(year.unwrap_or(today.year()), month, day)
+            .ok_or_else(|| invalid(&format!("{} {} is not a date", token, next.unwrap_or_default())))?;
+        parts.set_date(date)?;
+        return Ok(if year.is_some() { 3 } else { 2 });
+    }
+
+    if let Some((time, consumed)) = read_clock(tokens, false) {
+        return parts.set_time(time).map(|_| consumed);
+    }
+
+    Err(invalid(&format!("'{}' is not understood as part of a date or time", token)))
+}
+
+// Reads a time of day such as "9:30", "14:00", "2pm" or "9 am" from the start
+// of `tokens`. A bare hour such as "9" is only read when `bare` is set.
+fn read_clock(tokens: &[String], bare: bool) -> Option<(NaiveTime, usize)> {
+    let token = tokens.first()?.as_str();
+    let (body, meridiem, consumed) = if let Some(body) = token.strip_suffix("am") {
+        (body, Some(false), 1)
+    } else if let Some(body) = token.strip_suffix("pm") {
+        (body, Some(true), 1)
+    } else {
+        match tokens.get(1).map(String::as_str) {
+            Some("am") => (token, Some(false), 2),
+            Some("pm") => (token, Some(true), 2),
+            _ => (token, None, 1),
+        }
+    };
+
+    let mut fields = body.split(':');
+    let hour: u32 = parse_number(fields.next()?)?;
+    let minute: u32 = fields.next().map_or(Some(0), parse_number)?;
+    let second: u32 = fields.next().map_or(Some(0), parse_number)?;
+    if fields.next().is_some() || (meridiem.is_none() && !bare && !body.contains(':')) {
+        return None;
+    }
+
+    let hour = match meridiem {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+
+    NaiveTime::from_hms_opt(hour, minute, second).map(|time| (time, consumed))
+}
+
+// Reads a number written only with ASCII digits
+fn parse_number(text: &str) -> Option<u32> {
+    if text.is_empty() || text.len() > 2 || !text.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+// Checks for a range typed as one word, such as "2pm-4pm" or "9:00-10:30"
+fn is_joined_range(token: &str) -> bool {
+    let Some((left, right)) = token.split_once('-') else {
+        return false;
+    };
+    let left = [left.to_string()];
+    let right = [right.to_string()];
+    read_clock(&left, true).is_some() && read_clock(&right, true).is_some()
+}
+
+// Gives the start of a range such as "2-4pm" the meridiem of its end, using
+// "am" instead when "pm" would put the start after the end, as in "11-1pm"
+fn inherit_meridiem(left: &[String], right: &[String]) -> Vec<String> {
+    let mut left = left.to_vec();
+    let (Some(last), Some(end)) = (left.last(), read_clock(right, true)) else {
+        return left;
+    };
+    let meridiem = right.iter().take(2).find_map(|token| {
+        ["am", "pm"].into_iter().find(|suffix| token.ends_with(suffix))
+    });
+    let Some(meridiem) = meridiem else {
+        return left;
+    };
+    if last.ends_with("am") || last.ends_with("pm") || read_clock(std::slice::from_ref(last), true).is_none() {
+        return left;
+    }
+
+    let candidate = format!("{}{}", last, meridiem);
+    let inherited = match read_clock(std::slice::from_ref(&candidate), true) {
+        Some((start, _)) if start > end.0 => format!("{}am", last),
+        Some(_) => candidate,
+        None => return left,
+    };
+    *left.last_mut().unwrap() = inherited;
+    left
+}
+
+// Finds `weekday` relative to `today`: "this" is today or the coming one,
+// "next" is the coming one after today and "last" the most recent before it
+fn relative_weekday(today: NaiveDate, weekday: Weekday, which: &str) -> NaiveDate {
+    let ahead = (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+    match which {
+        "next" => today + Duration::days(if ahead == 0 { 7 } else { ahead as i64 }),
+        "last" => today - Duration::days(if ahead == 0 { 7 } else { 7 - ahead as i64 }),
+        _ => today + Duration::days(ahead as i64),
+    }
+}
+
+// Moves a time by a duration read by `read_duration`. Days and weeks keep the
+// wall-clock time across daylight saving changes, while hours, minutes and
+// seconds are exact.
+fn shift(time: &DateTime<Tz>, (duration, whole_days): (Duration, bool)) -> Result<DateTime<Tz>, InvalidTimeError> {
+    let shifted = if whole_days {
+        time.naive_local().checked_add_signed(duration)
+            .and_then(|naive: NaiveDateTime| resolve_local(time.timezone(), &naive))
+    } else {
+        time.checked_add_signed(duration)
+    };
+
+    shifted.ok_or_else(|| invalid("the time is out of range"))
+}
+
+// Reads an English weekday name or abbreviation
+pub fn parse_weekday_name(name: &str) -> Option<Weekday> {
+    match name {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tues" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thur" | "thurs" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+// Reads an English month name or abbreviation as a month number
+fn parse_month_name(name: &str) -> Option<u32> {
+    let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    let full = [
+        "january", "february", "march", "april", "may", "june",
+        "july", "august", "september", "october", "november", "december",
+    ];
+    months.iter().zip(full.iter())
+        .position(|(short, full)| name == *short || name == *full || (name == "sept" && *short == "sep"))
+        .map(|index| index as u32 + 1)
+}
+
+// Reads a day of the month such as "24" or "24th"
+fn parse_day(text: &str) -> Option<u32> {
+    let digits = ["st", "nd", "rd", "th"].iter()
+        .find_map(|suffix| text.strip_suffix(suffix))
+        .unwrap_or(text);
+    parse_number(digits).filter(|day| (1..=31).contains(day))
+}
@@ -1,16 +1,35 @@
-use crate::calendar::*;
+use crate::{calendar::*, cali_error::*, recurrence::*, store::*, tag::*, time::*};
 use std::fmt;
-use std::error::Error;
+use std::iter::{Copied, Peekable};
+use std::rc::Rc;
+use std::slice::Iter;
+use std::str::FromStr;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
 use uuid::Uuid;
-use rusqlite::{params, Connection, Result};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Recurring {
     No,
     Daily,
     Weekly,
     Monthly,
-    Yearly
+    Yearly,
+    Rule(RRule),
+}
+
+impl Recurring {
+    // Gets the recurrence rule equivalent to this setting
+    pub fn to_rule(&self) -> Option<RRule> {
+        match self {
+            Recurring::No => None,
+            Recurring::Daily => Some(RRule::new(Frequency::Daily)),
+            Recurring::Weekly => Some(RRule::new(Frequency::Weekly)),
+            Recurring::Monthly => Some(RRule::new(Frequency::Monthly)),
+            Recurring::Yearly => Some(RRule::new(Frequency::Yearly)),
+            Recurring::Rule(rule) => Some(rule.clone()),
+        }
+    }
 }
 
 impl fmt::Display for Recurring {
@@ -21,37 +40,97 @@ impl fmt::Display for Recurring {
             Recurring::Weekly => write!(f, "Weekly"),
             Recurring::Monthly => write!(f, "Monthly"),
             Recurring::Yearly => write!(f, "Yearly"),
+            Recurring::Rule(rule) => write!(f, "{}", rule),
+        }
+    }
+}
+
+impl FromStr for Recurring {
+    type Err = InvalidRecurrenceError;
+
+    fn from_str(value: &str) -> Result<Recurring, InvalidRecurrenceError> {
+        match value {
+            "No" => Ok(Recurring::No),
+            "Daily" => Ok(Recurring::Daily),
+            "Weekly" => Ok(Recurring::Weekly),
+            "Monthly" => Ok(Recurring::Monthly),
+            "Yearly" => Ok(Recurring::Yearly),
+            rule => Ok(Recurring::Rule(rule.parse()?)),
         }
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct Event {
     id: Uuid,
     name: String,
-    start: String,
-    end: String,
+    start: DateTime<Tz>,
+    end: DateTime<Tz>,
     recurring: Recurring,
+    exdates: Vec<DateTime<Tz>>,
+    rdates: Vec<DateTime<Tz>>,
+    overrides: Vec<Override>,
+    uid: Option<String>,
+    all_day: bool,
+    description: Option<String>,
+    location: Option<String>,
+    url: Option<String>,
+    categories: Vec<String>,
+    tags: Vec<String>,
 }
 
 impl Event {
-    pub fn new(name: &str, start: &str, end: &str, recurring: Recurring) -> Event {
-        Event { 
+    pub fn new(name: &str, start: DateTime<Tz>, end: DateTime<Tz>, recurring: Recurring) -> Result<Event, CaliError> {
+        let (start, end) = validate_times(start, end)?;
+        Ok(Event { 
             id: Uuid::new_v4(), 
             name: name.to_string(), 
-            start: start.to_string(), 
-            end: end.to_string(), 
+            start, 
+            end, 
             recurring,
-        }
+            exdates: Vec::new(),
+            rdates: Vec::new(),
+            overrides: Vec::new(),
+            uid: None,
+            all_day: false,
+            description: None,
+            location: None,
+            url: None,
+            categories: Vec::new(),
+            tags: Vec::new(),
+        })
     }
 
-    pub fn from(id: &str, name: &str, start: &str, end: &str, recurring: Recurring) -> Event {
-        Event { 
-            id: Uuid::parse_str(id).unwrap(),
+    pub fn from(id: &str, name: &str, start: DateTime<Tz>, end: DateTime<Tz>, recurring: Recurring) -> Result<Event, CaliError> {
+        let (start, end) = validate_times(start, end)?;
+        Ok(Event { 
+            id: parse_id(id, "an event id")?,
             name: name.to_string(), 
-            start: start.to_string(), 
-            end: end.to_string(), 
+            start, 
+            end, 
             recurring,
+            exdates: Vec::new(),
+            rdates: Vec::new(),
+            overrides: Vec::new(),
+            uid: None,
+            all_day: false,
+            description: None,
+            location: None,
+            url: None,
+            categories: Vec::new(),
+            tags: Vec::new(),
+        })
+    }
+
+    // Creates an event taking up the whole days from `first` to `last`. It
+    // falls on those dates in whatever time zone it is read.
+    pub fn new_all_day(name: &str, first: NaiveDate, last: NaiveDate, recurring: Recurring) -> Result<Event, CaliError> {
+        if last < first {
+            return Err(InvalidTimeError { reason: format!("last day {} is before first day {}", last, first) }.into());
         }
+        let mut event = Event::new(name, day_start(first), day_start(last + Duration::days(1)), recurring)?;
+        event.all_day = true;
+        Ok(event)
     }
 
     pub fn get_id(&self) -> &Uuid {
@@ -62,135 +141,816 @@ impl Event {
         &self.name
     }
 
-    pub fn get_start(&self) -> &str{
+    pub fn get_start(&self) -> &DateTime<Tz> {
         &self.start
     }
 
-    pub fn get_end(&self) -> &str {
+    pub fn get_end(&self) -> &DateTime<Tz> {
         &self.end
     }
 
+    pub fn get_timezone(&self) -> Tz {
+        self.start.timezone()
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.end - self.start
+    }
+
     pub fn get_recurring(&self) -> &Recurring {
         &self.recurring
     }
 
-    pub fn update_name(&mut self, new_name: &str) -> Result<(), Box<dyn Error>> {
+    pub fn get_exdates(&self) -> &[DateTime<Tz>] {
+        &self.exdates
+    }
+
+    pub fn get_rdates(&self) -> &[DateTime<Tz>] {
+        &self.rdates
+    }
+
+    // Gets the UID the event was imported with, when it differs from the id
+    pub fn get_uid(&self) -> Option<&str> {
+        self.uid.as_deref()
+    }
+
+    pub fn get_overrides(&self) -> &[Override] {
+        &self.overrides
+    }
+
+    pub fn is_all_day(&self) -> bool {
+        self.all_day
+    }
+
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn get_location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    pub fn get_url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    pub fn get_categories(&self) -> &[String] {
+        &self.categories
+    }
+
+    // Gets the tags of the event, in lowercase and in order
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    // Checks whether the name, description, location, URL or a category of
+    // the event contains `text`, in any case
+    pub fn contains_text(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        [Some(self.name.as_str()), self.get_description(), self.get_location(), self.get_url()]
+            .into_iter()
+            .flatten()
+            .chain(self.categories.iter().map(String::as_str))
+            .any(|field| field.to_lowercase().contains(&text))
+    }
+
+    // Gets the first and last days the event takes place on, read in its own
+    // time zone. An event ending exactly at midnight does not take place on
+    // the day it ends on.
+    pub fn get_days(&self) -> (NaiveDate, NaiveDate) {
+        let mut last = self.end.date_naive();
+        if self.end > self.start && self.end.time() == NaiveTime::MIN {
+            last -= Duration::days(1);
+        }
+        (self.start.date_naive(), last)
+    }
+
+    // Iterates over the occurrences of the event. They share one copy of the
+    // event, made when the first of them is reached.
+    pub fn occurrences(&self) -> Occurrences<'_> {
+        Occurrences { slots: self.slots(), event: self, shared: None }
+    }
+
+    // Gets the occurrences of the event overlapping the window from `start` to
+    // `end`, in order of recurrence id and read in the zone of `end`
+    pub fn occurrences_between(&self, start: &DateTime<Tz>, end: &DateTime<Tz>) -> Vec<Occurrence> {
+        let slots = self.slots_between(start, end);
+        if slots.is_empty() {
+            return Vec::new();
+        }
+        let event = Rc::new(self.clone());
+        slots.into_iter().map(|slot| slot.into_occurrence(&event)).collect()
+    }
+
+    // Gets the times of the occurrences overlapping the window from `start`
+    // to `end`, in order of recurrence id and read in the zone of `end`
+    fn slots_between(&self, start: &DateTime<Tz>, end: &DateTime<Tz>) -> Vec<Slot> {
+        // Occurrences come in order of recurrence id, so stop after the window
+        // unless a later occurrence was moved into it
+        let day = Duration::days(1);
+        let limit = self.overrides
+            .iter()
+            .filter(|existing| existing.start < *end + day)
+            .map(|existing| existing.recurrence_id.with_timezone(&end.timezone()))
+            .fold(*end, |limit, recurrence_id| limit.max(recurrence_id));
+        let limit = if self.all_day { limit + day } else { limit };
+        self.slots()
+            .take_while(|slot| slot.recurrence_id <= limit)
+            .map(|slot| slot.localize(self.all_day, end.timezone()))
+            .filter(|slot| overlaps(&slot.start, &slot.end, start, end))
+            .collect()
+    }
+
+    // Iterates over the times of the event's occurrences
+    fn slots(&self) -> Slots<'_> {
+        Slots {
+            event: self,
+            starts: self.rule_starts().peekable(),
+            rdates: self.rdates.iter().copied().peekable(),
+        }
+    }
+
+    // Checks whether the event has an occurrence originally scheduled at `recurrence_id`
+    pub fn has_occurrence(&self, recurrence_id: &DateTime<Tz>) -> bool {
+        let recurrence_id = &self.float(recurrence_id);
+        self.slots()
+            .take_while(|slot| slot.recurrence_id <= *recurrence_id)
+            .any(|slot| slot.recurrence_id == *recurrence_id)
+    }
+
+    // Start times produced by the recurrence rule alone. A single event
+    // behaves like a rule that stops after its first occurrence.
+    fn rule_starts(&self) -> RuleOccurrences {
+        let mut rule = self.recurring.to_rule().unwrap_or_else(|| RRule::new(Frequency::Daily));
+        if self.recurring == Recurring::No {
+            rule.update_count(Some(1));
+        }
+        rule.occurrences(self.start)
+    }
+
+    // Skips the occurrence at `exdate` (EXDATE)
+    pub fn add_exdate(&mut self, exdate: DateTime<Tz>) {
+        let exdate = self.float(&exdate).with_timezone(&self.get_timezone());
+        insert_sorted(&mut self.exdates, exdate);
+    }
+
+    // Adds an extra occurrence at `rdate` (RDATE)
+    pub fn add_rdate(&mut self, rdate: DateTime<Tz>) {
+        let rdate = self.float(&rdate).with_timezone(&self.get_timezone());
+        insert_sorted(&mut self.rdates, rdate);
+    }
+
+    // Replaces one occurrence's time or name, superseding any earlier override of it
+    pub fn add_override(&mut self, mut new_override: Override) -> Result<(), CaliError> {
+        if self.all_day {
+            new_override.recurrence_id = self.float(&new_override.recurrence_id);
+            new_override.start = self.float(&new_override.start);
+            new_override.end = self.float(&new_override.end);
+        }
+        if !self.has_occurrence(&new_override.recurrence_id) {
+            return Err(missing_occurrence(&new_override.recurrence_id).into());
+        }
+        self.overrides.retain(|existing| existing.recurrence_id != new_override.recurrence_id);
+        self.overrides.push(new_override);
+        self.overrides.sort_by_key(|existing| existing.recurrence_id);
+        Ok(())
+    }
+
+    // Adds an override read back from storage, which was checked when it was
+    // first added, in order of recurrence id
+    pub(crate) fn restore_override(&mut self, existing: Override) {
+        self.overrides.push(existing);
+    }
+
+    // Applies changes to the whole series. Moving the start moves the
+    // recurrence dates and overrides with it, by the same wall-clock offset.
+    pub fn apply_changes(&mut self, changes: &EventChanges) -> Result<(), CaliError> {
+        let timezone = self.get_timezone();
+        let start = changes.start.map_or(self.start, |start| self.float(&start).with_timezone(&timezone));
+        let end = changes.end.map_or(start + self.get_duration(), |end| self.float_last_day(&end));
+        let (start, end) = validate_times(start, end)?;
+
+        let shift = start.naive_local() - self.start.naive_local();
+        if shift != Duration::zero() {
+            let moved = |time: &DateTime<Tz>| resolve_local(timezone, &(time.naive_local() + shift)).unwrap_or(*time);
+            self.exdates = self.exdates.iter().map(moved).collect();
+            self.rdates = self.rdates.iter().map(moved).collect();
+            for existing in &mut self.overrides {
+                existing.recurrence_id = moved(&existing.recurrence_id);
+            }
+        }
+        if let Some(name) = &changes.name {
+            self.name = name.to_string();
+        }
+        self.start = start;
+        self.end = end;
+        Ok(())
+    }
+
+    // Overrides the occurrence at `recurrence_id` with the given changes,
+    // building on any override it already has
+    pub fn override_occurrence(&mut self, recurrence_id: &DateTime<Tz>, changes: &EventChanges) -> Result<(), CaliError> {
+        let recurrence_id = &self.float(recurrence_id);
+        let existing = self.overrides.iter().find(|existing| existing.recurrence_id == *recurrence_id);
+        let (start, end, name) = match existing {
+            Some(existing) => (existing.start, existing.end, existing.name.clone()),
+            None => (*recurrence_id, *recurrence_id + self.get_duration(), None),
+        };
+        let new_start = changes.start.map_or(start, |start| self.float(&start));
+        let new_end = changes.end.map_or(new_start + (end - start), |end| self.float_last_day(&end));
+        let name = changes.name.clone().or(name);
+
+        self.add_override(Override::new(*recurrence_id, new_start, new_end, name.as_deref())?)
+    }
+
+    // Removes the occurrence at `recurrence_id` from the series
+    pub fn exclude_occurrence(&mut self, recurrence_id: &DateTime<Tz>) -> Result<(), CaliError> {
+        let recurrence_id = &self.float(recurrence_id);
+        if !self.has_occurrence(recurrence_id) {
+            return Err(missing_occurrence(recurrence_id).into());
+        }
+        self.overrides.retain(|existing| existing.recurrence_id != *recurrence_id);
+        if self.rdates.contains(recurrence_id) {
+            self.rdates.retain(|rdate| rdate != recurrence_id);
+        } else {
+            self.add_exdate(*recurrence_id);
+        }
+        Ok(())
+    }
+
+    // Ends the series just before the occurrence at `recurrence_id`
+    pub fn truncate_before(&mut self, recurrence_id: &DateTime<Tz>) -> Result<(), CaliError> {
+        let recurrence_id = &self.float(recurrence_id);
+        if !self.has_occurrence(recurrence_id) {
+            return Err(missing_occurrence(recurrence_id).into());
+        }
+        if let Some(mut rule) = self.recurring.to_rule() {
+            let last = (*recurrence_id - Duration::seconds(1)).with_timezone(&Utc);
+            rule.update_until(Some(Until::DateTime(last)));
+            self.recurring = Recurring::Rule(rule);
+        }
+        self.exdates.retain(|exdate| exdate < recurrence_id);
+        self.rdates.retain(|rdate| rdate < recurrence_id);
+        self.overrides.retain(|existing| existing.recurrence_id < *recurrence_id);
+        Ok(())
+    }
+
+    // Splits the series in two at the occurrence at `recurrence_id`. This
+    // event keeps the earlier occurrences and the returned event, which has a
+    // new id, takes over the occurrence at `recurrence_id` and those after it.
+    pub fn split_at(&mut self, recurrence_id: &DateTime<Tz>) -> Result<Event, CaliError> {
+        let recurrence_id = &self.float(recurrence_id);
+        if !self.has_occurrence(recurrence_id) {
+            return Err(missing_occurrence(recurrence_id).into());
+        }
+        let mut following = self.clone();
+        following.id = Uuid::new_v4();
+        following.uid = None;
+        (following.start, following.end) = validate_times(*recurrence_id, *recurrence_id + self.get_duration())?;
+        if let Some(mut rule) = self.recurring.to_rule() {
+            // COUNT includes the occurrences left behind in this event
+            if let Some(count) = rule.get_count() {
+                let before = self.rule_starts().take_while(|start| start < recurrence_id).count() as u32;
+                rule.update_count(Some(count.saturating_sub(before).max(1)));
+                following.recurring = Recurring::Rule(rule);
+            }
+        }
+        following.exdates.retain(|exdate| exdate >= recurrence_id);
+        following.rdates.retain(|rdate| rdate >= recurrence_id);
+        following.overrides.retain(|existing| existing.recurrence_id >= *recurrence_id);
+
+        self.truncate_before(recurrence_id)?;
+        Ok(following)
+    }
+
+    pub fn update_name(&mut self, new_name: &str) -> Result<(), CaliError> {
         self.name = new_name.to_string();
         Ok(())
     }
 
-    pub fn update_start(&mut self, new_start: &str) -> Result<(), Box<dyn Error>> {
-        self.start = new_start.to_string();
+    pub fn update_start(&mut self, new_start: DateTime<Tz>) -> Result<(), CaliError> {
+        (self.start, self.end) = validate_times(self.float(&new_start), self.end)?;
         Ok(())
     }
 
-    pub fn update_end(&mut self, new_end: &str) -> Result<(), Box<dyn Error>> {
-        self.end = new_end.to_string();
+    pub fn update_end(&mut self, new_end: DateTime<Tz>) -> Result<(), CaliError> {
+        (self.start, self.end) = validate_times(self.start, self.float(&new_end))?;
         Ok(())
     }
 
+    pub fn update_uid(&mut self, new_uid: Option<&str>) -> Result<(), CaliError> {
+        self.uid = new_uid.map(str::to_string);
+        Ok(())
+    }
+
+    pub fn update_recurring(&mut self, new_recurring: Recurring) -> Result<(), CaliError> {
+        self.recurring = new_recurring;
+        Ok(())
+    }
+
+    // Sets the notes of the event, which may run over several lines. Blank
+    // notes clear them.
+    pub fn update_description(&mut self, new_description: Option<&str>) -> Result<(), CaliError> {
+        self.description = non_blank(new_description);
+        Ok(())
+    }
+
+    pub fn update_location(&mut self, new_location: Option<&str>) -> Result<(), CaliError> {
+        self.location = non_blank(new_location);
+        Ok(())
+    }
+
+    pub fn update_url(&mut self, new_url: Option<&str>) -> Result<(), CaliError> {
+        self.url = non_blank(new_url);
+        Ok(())
+    }
+
+    // Tags the event, unless it already has the tag
+    pub fn add_tag(&mut self, tag: &str) -> Result<(), CaliError> {
+        let tag = normalize_tag(tag)?;
+        if let Err(index) = self.tags.binary_search(&tag) {
+            self.tags.insert(index, tag);
+        }
+        Ok(())
+    }
+
+    // Takes a tag off the event, reporting whether it had it
+    pub fn remove_tag(&mut self, tag: &str) -> Result<bool, CaliError> {
+        let tag = normalize_tag(tag)?;
+        let before = self.tags.len();
+        self.tags.retain(|existing| *existing != tag);
+        Ok(self.tags.len() < before)
+    }
+
+    // Replaces every tag of the event
+    pub fn update_tags<S: AsRef<str>>(&mut self, new_tags: &[S]) -> Result<(), CaliError> {
+        self.tags.clear();
+        for tag in new_tags {
+            self.add_tag(tag.as_ref())?;
+        }
+        Ok(())
+    }
+
+    // Sets the categories of the event. Each is trimmed and a name holding
+    // commas is split at them, since categories are kept and exported as a
+    // comma separated list. Blank and repeated names are dropped.
+    pub fn update_categories<S: AsRef<str>>(&mut self, new_categories: &[S]) -> Result<(), CaliError> {
+        self.categories.clear();
+        for category in new_categories.iter().flat_map(|category| category.as_ref().split(',')) {
+            let category = category.trim();
+            if !category.is_empty() && !self.categories.iter().any(|existing| existing == category) {
+                self.categories.push(category.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Makes the event take up whole days, or not. Its times and those of its
+    // exceptions move to the start of the days they fall on in their own time
+    // zone, and an end partway through a day takes in the rest of it.
+    pub fn update_all_day(&mut self, all_day: bool) -> Result<(), CaliError> {
+        if !all_day || self.all_day {
+            self.all_day = all_day;
+            return Ok(());
+        }
+
+        let (first, last) = self.get_days();
+        self.all_day = true;
+        (self.start, self.end) = validate_times(day_start(first), day_start(last.max(first) + Duration::days(1)))?;
+        self.exdates = self.exdates.iter().map(|exdate| self.float(exdate)).collect();
+        self.rdates = self.rdates.iter().map(|rdate| self.float(rdate)).collect();
+        let overrides: Vec<Override> = self.overrides.drain(..).collect();
+        for existing in overrides {
+            self.add_override(existing)?;
+        }
+        Ok(())
+    }
+
+    // Moves a time of an all-day event to the start of its day, leaving
+    // other events' times as they are
+    fn float(&self, time: &DateTime<Tz>) -> DateTime<Tz> {
+        match self.all_day {
+            true => day_start(time.date_naive()),
+            false => *time,
+        }
+    }
+
+    // Reads a new end, which for an all-day event names its last day
+    fn float_last_day(&self, end: &DateTime<Tz>) -> DateTime<Tz> {
+        match self.all_day {
+            true => day_start(end.date_naive() + Duration::days(1)),
+            false => *end,
+        }
+    }
+
 }
 
+// Replaces the time or name of one occurrence of a recurring event. The
+// occurrence is identified by its recurrence id, the start it was originally
+// scheduled for (RECURRENCE-ID).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    recurrence_id: DateTime<Tz>,
+    start: DateTime<Tz>,
+    end: DateTime<Tz>,
+    name: Option<String>,
+}
 
-// Inserts a new event into the database
-pub fn insert_event(calendar: &Calendar, event: &Event) -> Result<()> {
-    let conn = Connection::open(calendar.get_path())?;
-    conn.execute(
-        "INSERT INTO calendars (calendar_name, event_id, event_name, event_start, event_end, event_recurring, is_default) 
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-        params![
-            calendar.get_name().to_string(), 
-            event.get_id().to_string(), 
-            event.get_name().to_string(), 
-            event.get_start().to_string(), 
-            event.get_end().to_string(), 
-            event.get_recurring().to_string(), 
-            calendar.get_default()
-            ],
-    )?;
+impl Override {
+    pub fn new(recurrence_id: DateTime<Tz>, start: DateTime<Tz>, end: DateTime<Tz>, name: Option<&str>) -> Result<Override, CaliError> {
+        let (start, end) = validate_times(start, end)?;
+        Ok(Override {
+            recurrence_id: recurrence_id.with_timezone(&start.timezone()),
+            start,
+            end,
+            name: name.map(str::to_string),
+        })
+    }
 
-    Ok(())
+    pub fn get_recurrence_id(&self) -> &DateTime<Tz> {
+        &self.recurrence_id
+    }
+
+    pub fn get_start(&self) -> &DateTime<Tz> {
+        &self.start
+    }
+
+    pub fn get_end(&self) -> &DateTime<Tz> {
+        &self.end
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 }
 
-// Reads an existing event from the database
-pub fn get_event(calendar: &Calendar, name: &str, exact: bool) -> Result<Vec<Event>, Box<dyn Error>> {
-    let conn = Connection::open(calendar.get_path())?;
-
-    let get_query = if exact {
-        "SELECT * FROM calendars WHERE calendar_name = ?1 AND event_name = ?2"
-    } else {
-        "SELECT * FROM calendars WHERE calendar_name = ?1 AND event_name LIKE ?2"
-    };
-
-    let event_name = if exact {
-        name.to_string()
-    } else {
-        format!("%{}%", name)
-    };
-
-    let mut stmt = conn.prepare(get_query)?;
-    let event_iter = stmt.query_map(params![calendar.get_name(), event_name], |row| {
-        let id: String = row.get("event_id")?;
-        let name: String = row.get("event_name")?;
-        let start: String = row.get("event_start")?;
-        let end: String = row.get("event_end")?;
-        let recurring_str: String = row.get("event_recurring")?;
-
-        // Parse the recurring field from the database string representation into the Recurring enum
-        let recurring = match recurring_str.as_str() {
-            "No" => Recurring::No,
-            "Daily" => Recurring::Daily,
-            "Weekly" => Recurring::Weekly,
-            "Monthly" => Recurring::Monthly,
-            "Yearly" => Recurring::Yearly,
-            _ => Recurring::No, // Handle unknown values, you may want to adjust this based on your data
-        };
+// Which occurrences of an event an update or removal applies to. Single
+// occurrences are identified by their recurrence id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    This(DateTime<Tz>),
+    ThisAndFollowing(DateTime<Tz>),
+    All,
+}
 
-        Ok(Event::from(&id, &name, &start, &end, recurring))
-    })?;
+// Changes to an event or to some of its occurrences. Fields left as None
+// keep their current value; a new start without a new end keeps the duration.
+#[derive(Debug, Clone, Default)]
+pub struct EventChanges {
+    pub name: Option<String>,
+    pub start: Option<DateTime<Tz>>,
+    pub end: Option<DateTime<Tz>>,
+}
 
-    let mut events = Vec::new();
-    for event_result in event_iter {
-        events.push(event_result?);
+// A single concrete occurrence of an event. Non-recurring events have one
+// occurrence; recurring events have one per repetition. Occurrences of the
+// same event share it.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    event: Rc<Event>,
+    recurrence_id: DateTime<Tz>,
+    start: DateTime<Tz>,
+    end: DateTime<Tz>,
+    name: Option<String>,
+}
+
+impl Occurrence {
+    pub fn get_event(&self) -> &Event {
+        &self.event
+    }
+
+    // The start this occurrence was originally scheduled for
+    pub fn get_recurrence_id(&self) -> &DateTime<Tz> {
+        &self.recurrence_id
     }
 
+    pub fn get_start(&self) -> &DateTime<Tz> {
+        &self.start
+    }
+
+    pub fn get_end(&self) -> &DateTime<Tz> {
+        &self.end
+    }
+
+    // The occurrence's own name if it was overridden, otherwise the event's
+    pub fn get_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.event.name)
+    }
+
+    pub fn is_overridden(&self) -> bool {
+        self.event.overrides.iter().any(|existing| existing.recurrence_id == self.recurrence_id)
+    }
+
+    // Places an all-day occurrence on its days in `timezone`, so that it
+    // covers them from midnight to midnight there. Other occurrences are
+    // left as they are.
+    pub fn localize(mut self, timezone: Tz) -> Occurrence {
+        (self.start, self.end) = localize_times(self.event.all_day, timezone, self.start, self.end);
+        self
+    }
+}
+
+// The times and name of one occurrence, before it is tied to its event
+struct Slot {
+    recurrence_id: DateTime<Tz>,
+    start: DateTime<Tz>,
+    end: DateTime<Tz>,
+    name: Option<String>,
+}
+
+impl Slot {
+    fn localize(mut self, all_day: bool, timezone: Tz) -> Slot {
+        (self.start, self.end) = localize_times(all_day, timezone, self.start, self.end);
+        self
+    }
+
+    fn into_occurrence(self, event: &Rc<Event>) -> Occurrence {
+        Occurrence {
+            event: Rc::clone(event),
+            recurrence_id: self.recurrence_id,
+            start: self.start,
+            end: self.end,
+            name: self.name,
+        }
+    }
+}
+
+// Reads an all-day event's times, kept at midnight UTC, as midnight in
+// `timezone`. Times of other events are left as they are.
+fn localize_times(all_day: bool, timezone: Tz, start: DateTime<Tz>, end: DateTime<Tz>) -> (DateTime<Tz>, DateTime<Tz>) {
+    if !all_day {
+        return (start, end);
+    }
+    let local = |time: &DateTime<Tz>| resolve_local(timezone, &time.naive_utc()).unwrap_or(*time);
+    (local(&start), local(&end))
+}
+
+// Iterates over the times of an event's occurrences in order of recurrence
+// id. Rule occurrences and RDATEs are merged, EXDATEs skipped and overrides
+// applied.
+struct Slots<'a> {
+    event: &'a Event,
+    starts: Peekable<RuleOccurrences>,
+    rdates: Peekable<Copied<Iter<'a, DateTime<Tz>>>>,
+}
+
+impl Iterator for Slots<'_> {
+    type Item = Slot;
+
+    fn next(&mut self) -> Option<Slot> {
+        loop {
+            let recurrence_id = match (self.starts.peek(), self.rdates.peek()) {
+                (Some(start), Some(rdate)) if rdate < start => self.rdates.next()?,
+                (Some(start), Some(rdate)) if rdate == start => {
+                    self.rdates.next();
+                    self.starts.next()?
+                }
+                (Some(_), _) => self.starts.next()?,
+                (None, Some(_)) => self.rdates.next()?,
+                (None, None) => return None,
+            };
+            if self.event.exdates.contains(&recurrence_id) {
+                continue;
+            }
+
+            let existing = self.event.overrides.iter().find(|existing| existing.recurrence_id == recurrence_id);
+            return Some(match existing {
+                Some(existing) => Slot {
+                    recurrence_id,
+                    start: existing.start,
+                    end: existing.end,
+                    name: existing.name.clone(),
+                },
+                None => Slot {
+                    recurrence_id,
+                    start: recurrence_id,
+                    end: recurrence_id + self.event.get_duration(),
+                    name: None,
+                },
+            });
+        }
+    }
+}
+
+// Iterates over the occurrences of an event in order of recurrence id
+pub struct Occurrences<'a> {
+    slots: Slots<'a>,
+    event: &'a Event,
+    shared: Option<Rc<Event>>,
+}
+
+impl Iterator for Occurrences<'_> {
+    type Item = Occurrence;
+
+    fn next(&mut self) -> Option<Occurrence> {
+        let slot = self.slots.next()?;
+        let event = self.shared.get_or_insert_with(|| Rc::new(self.event.clone()));
+        Some(slot.into_occurrence(event))
+    }
+}
+
+// Trims a text field, treating one left blank as not given
+fn non_blank(text: Option<&str>) -> Option<String> {
+    text.map(str::trim).filter(|text| !text.is_empty()).map(str::to_string)
+}
+
+// Gets the time an all-day event keeps for the start of `day`: midnight UTC,
+// which is read as midnight wherever the event is shown
+fn day_start(day: NaiveDate) -> DateTime<Tz> {
+    Tz::UTC.from_utc_datetime(&day.and_time(NaiveTime::MIN))
+}
+
+fn missing_occurrence(recurrence_id: &DateTime<Tz>) -> InvalidTimeError {
+    InvalidTimeError {
+        reason: format!("the event has no occurrence at {}", recurrence_id.to_rfc3339()),
+    }
+}
+
+fn insert_sorted(times: &mut Vec<DateTime<Tz>>, time: DateTime<Tz>) {
+    if let Err(index) = times.binary_search(&time) {
+        times.insert(index, time);
+    }
+}
+
+// Checks that an event does not end before it starts. The end is expressed in
+// the start's time zone and both are truncated to the second stored in the database.
+fn validate_times(start: DateTime<Tz>, end: DateTime<Tz>) -> Result<(DateTime<Tz>, DateTime<Tz>), InvalidTimeError> {
+    let start = start.with_nanosecond(0).unwrap_or(start);
+    let end = end.with_nanosecond(0).unwrap_or(end).with_timezone(&start.timezone());
+    if end < start {
+        return Err(InvalidTimeError {
+            reason: format!("end {} is before start {}", end.to_rfc3339(), start.to_rfc3339()),
+        });
+    }
+
+    Ok((start, end))
+}
+
+
+// Inserts a new event into the calendar's store
+pub fn insert_event(calendar: &Calendar, event: &Event) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|store| store.get_backend().insert_event(calendar.get_id(), event))
+}
+
+// Reads every event in a calendar, ordered by start
+pub fn get_events(calendar: &Calendar) -> Result<Vec<Event>, CaliError> {
+    calendar.get_store().get_backend().get_events(calendar.get_id())
+}
+
+// Reads the events in a calendar named `name` or, when not `exact`, with a
+// name containing it
+pub fn get_event(calendar: &Calendar, name: &str, exact: bool) -> Result<Vec<Event>, CaliError> {
+    calendar.get_store().get_backend().get_events_named(calendar.get_id(), name, exact)
+}
+
+// Reads the events in a calendar named `name` or, when not `exact`, with a
+// name containing it, keeping only those whose tags match `filter`
+pub fn get_event_tagged(calendar: &Calendar, name: &str, exact: bool, filter: &TagFilter) -> Result<Vec<Event>, CaliError> {
+    let mut events = get_event(calendar, name, exact)?;
+    events.retain(|event| filter.matches(event.get_tags()));
     Ok(events)
 }
 
-// Updates an existing event in the database
-pub fn update_event(calendar: &Calendar, event: &Event) -> Result<()> {
-    let conn = Connection::open(calendar.get_path())?;
-    conn.execute(
-        "UPDATE calendars
-            SET calendar_name = ?1, 
-            event_id = ?2, 
-            event_name = ?3, 
-            event_start = ?4, 
-            event_end = ?5, 
-            event_recurring = ?6, 
-            is_default = ?7 
-        WHERE event_id = ?2",
-        params![
-            calendar.get_name().to_string(), 
-            event.get_id().to_string(), 
-            event.get_name().to_string(), 
-            event.get_start().to_string(), 
-            event.get_end().to_string(), 
-            event.get_recurring().to_string(), 
-            calendar.get_default()
-            ],
-    )?;
+// Reads the events in a calendar whose name, description, location, URL or
+// categories contain `text`, in any case
+pub fn get_events_containing(calendar: &Calendar, text: &str) -> Result<Vec<Event>, CaliError> {
+    calendar.get_store().get_backend().get_events_containing(calendar.get_id(), text)
+}
 
-    Ok(())
+// Checks if any calendar in the store has an event with the given id
+pub fn check_event(store: &Store, id: &Uuid) -> Result<bool, CaliError> {
+    store.get_backend().check_event(id)
+}
+
+// Reads the event with the given id, if the calendar has one
+pub fn get_event_by_id(calendar: &Calendar, id: &Uuid) -> Result<Option<Event>, CaliError> {
+    calendar.get_store().get_backend().get_event_by_id(calendar.get_id(), id)
+}
+
+// Reads every occurrence overlapping the window from `start` to `end`,
+// expanding recurring events, in chronological order
+pub fn get_events_between(calendar: &Calendar, start: &DateTime<Tz>, end: &DateTime<Tz>) -> Result<Vec<Occurrence>, CaliError> {
+    // All-day events are kept on UTC days but shown on local ones, which can
+    // be up to a day either side, so the search is widened by a day each way
+    let day = Duration::days(1);
+    let events = calendar.get_store().get_backend().get_events_overlapping(calendar.get_id(), &(*start - day), &(*end + day))?;
+
+    let mut occurrences: Vec<Occurrence> = Vec::new();
+    for event in events {
+        let slots = event.slots_between(start, end);
+        if !slots.is_empty() {
+            let event = Rc::new(event);
+            occurrences.extend(slots.into_iter().map(|slot| slot.into_occurrence(&event)));
+        }
+    }
+    occurrences.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.event.name.cmp(&b.event.name)));
+
+    Ok(occurrences)
+}
+
+// Reads the occurrences overlapping the window from `start` to `end` of the
+// events whose tags match `filter`
+pub fn get_events_between_tagged(calendar: &Calendar, start: &DateTime<Tz>, end: &DateTime<Tz>, filter: &TagFilter) -> Result<Vec<Occurrence>, CaliError> {
+    let mut occurrences = get_events_between(calendar, start, end)?;
+    occurrences.retain(|occurrence| filter.matches(occurrence.event.get_tags()));
+    Ok(occurrences)
+}
+
+// Reads the first occurrence starting at or after `after`
+pub fn get_next_event(calendar: &Calendar, after: &DateTime<Tz>) -> Result<Option<Occurrence>, CaliError> {
+    let events = calendar.get_store().get_backend().get_events_after(calendar.get_id(), after)?;
+
+    let next = events
+        .iter()
+        .filter_map(|event| next_occurrence(event, after))
+        .min_by(|a, b| a.start.cmp(&b.start).then_with(|| a.event.name.cmp(&b.event.name)));
+
+    Ok(next)
 }
 
-// Removes an existing event from the database
-pub fn remove_event(calendar: &Calendar, event: &Event) -> Result<()> {
-    let conn = Connection::open(calendar.get_path())?;
-    conn.execute(
-        "DELETE FROM calendars WHERE calendar_name = ?1 AND event_id = ?2",
-        params![calendar.get_name().to_string(), event.get_id().to_string()],
-    )?;
+// Finds an event's first occurrence starting at or after `after`. Overridden
+// occurrences may be out of order, so the search continues past the first
+// match until no override with a later recurrence id remains.
+fn next_occurrence(event: &Event, after: &DateTime<Tz>) -> Option<Occurrence> {
+    let last_override = event.overrides.iter().map(|existing| existing.recurrence_id).max();
+    let mut next: Option<Slot> = None;
+    for slot in event.slots().map(|slot| slot.localize(event.all_day, after.timezone())) {
+        let recurrence_id = slot.recurrence_id;
+        if slot.start >= *after && next.as_ref().is_none_or(|next| slot.start < next.start) {
+            next = Some(slot);
+        }
+        if next.is_some() && last_override.is_none_or(|last| recurrence_id >= last) {
+            break;
+        }
+    }
+    next.map(|slot| slot.into_occurrence(&Rc::new(event.clone())))
+}
+
+// Updates the occurrences of an event within `scope`. Changing this and the
+// following occurrences splits the series, and the new series is returned.
+// `event` is only changed once the change is stored.
+pub fn update_event_scoped(calendar: &Calendar, event: &mut Event, scope: Scope, changes: &EventChanges) -> Result<Option<Event>, CaliError> {
+    let mut changed = event.clone();
+    let following = calendar.get_store().transaction(|_| match scope {
+        Scope::This(recurrence_id) => {
+            changed.override_occurrence(&recurrence_id, changes)?;
+            update_event(calendar, &changed)?;
+            Ok(None)
+        }
+        Scope::ThisAndFollowing(recurrence_id) if recurrence_id != changed.start => {
+            let mut following = changed.split_at(&recurrence_id)?;
+            following.apply_changes(changes)?;
+            update_event(calendar, &changed)?;
+            insert_event(calendar, &following)?;
+            Ok(Some(following))
+        }
+        Scope::ThisAndFollowing(_) | Scope::All => {
+            changed.apply_changes(changes)?;
+            update_event(calendar, &changed)?;
+            Ok(None)
+        }
+    })?;
+    *event = changed;
+
+    Ok(following)
+}
+
+// Removes the occurrences of an event within `scope`, removing the event
+// itself once no occurrences remain. `event` is only changed once the change
+// is stored.
+pub fn remove_event_scoped(calendar: &Calendar, event: &mut Event, scope: Scope) -> Result<(), CaliError> {
+    let mut changed = event.clone();
+    calendar.get_store().transaction(|_| {
+        match scope {
+            Scope::This(recurrence_id) => {
+                changed.exclude_occurrence(&recurrence_id)?;
+                if changed.slots().next().is_none() {
+                    remove_event(calendar, &changed)?;
+                } else {
+                    update_event(calendar, &changed)?;
+                }
+            }
+            Scope::ThisAndFollowing(recurrence_id) if recurrence_id != changed.start => {
+                changed.truncate_before(&recurrence_id)?;
+                update_event(calendar, &changed)?;
+            }
+            Scope::ThisAndFollowing(_) | Scope::All => remove_event(calendar, &changed)?,
+        }
+
+        Ok(())
+    })?;
+    *event = changed;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// Updates an existing event in the calendar's store, moving it to the
+// calendar if it was in another one
+pub fn update_event(calendar: &Calendar, event: &Event) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|store| store.get_backend().update_event(calendar.get_id(), event))
+}
+
+// Removes an existing event from the calendar's store
+pub fn remove_event(calendar: &Calendar, event: &Event) -> Result<(), CaliError> {
+    calendar.get_store().get_backend().remove_event(calendar.get_id(), event.get_id())
+}
@@ -0,0 +1,1151 @@
+use crate::{calendar::*, cali_error::*, event::*, recurrence::*, task::*, time::*};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc, Weekday};
+use chrono_tz::{OffsetComponents, Tz};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// The longest a content line may be, in octets, before it is folded
+const MAX_LINE_OCTETS: usize = 75;
+
+// The property cali keeps an event's tags in. Tags are its own, unlike
+// CATEGORIES, so they are written as an extension other clients ignore.
+const TAGS_PROPERTY: &str = "X-CALI-TAGS";
+
+// Windows time zone names used by some calendar exports, with their IANA equivalents
+const WINDOWS_TIMEZONES: &[(&str, &str)] = &[
+    ("UTC", "UTC"),
+    ("GMT Standard Time", "Europe/London"),
+    ("W. Europe Standard Time", "Europe/Berlin"),
+    ("Romance Standard Time", "Europe/Paris"),
+    ("Central Europe Standard Time", "Europe/Budapest"),
+    ("Central European Standard Time", "Europe/Warsaw"),
+    ("E. Europe Standard Time", "Europe/Chisinau"),
+    ("FLE Standard Time", "Europe/Kiev"),
+    ("Russian Standard Time", "Europe/Moscow"),
+    ("Eastern Standard Time", "America/New_York"),
+    ("Central Standard Time", "America/Chicago"),
+    ("Mountain Standard Time", "America/Denver"),
+    ("US Mountain Standard Time", "America/Phoenix"),
+    ("Pacific Standard Time", "America/Los_Angeles"),
+    ("Alaskan Standard Time", "America/Anchorage"),
+    ("Hawaiian Standard Time", "Pacific/Honolulu"),
+    ("Atlantic Standard Time", "America/Halifax"),
+    ("E. South America Standard Time", "America/Sao_Paulo"),
+    ("India Standard Time", "Asia/Kolkata"),
+    ("China Standard Time", "Asia/Shanghai"),
+    ("Tokyo Standard Time", "Asia/Tokyo"),
+    ("Korea Standard Time", "Asia/Seoul"),
+    ("Singapore Standard Time", "Asia/Singapore"),
+    ("AUS Eastern Standard Time", "Australia/Sydney"),
+    ("New Zealand Standard Time", "Pacific/Auckland"),
+];
+
+// One content line of an iCalendar file, such as
+// `DTSTART;TZID=America/Chicago:20230724T090000`
+#[derive(Debug, Clone)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+    pub line: usize,
+}
+
+impl Property {
+    // Gets the value of a parameter, matched without regard to case
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// A component such as VCALENDAR, VEVENT or VTIMEZONE, with its properties
+// and the components nested inside it
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+    pub line: usize,
+}
+
+impl Component {
+    // Gets the first property called `name`
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|property| property.name == name)
+    }
+
+    // Gets every property called `name`
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> + 'a {
+        self.properties.iter().filter(move |property| property.name == name)
+    }
+}
+
+// An event read from a VEVENT component. A VEVENT with a RECURRENCE-ID
+// changes or cancels one occurrence of the event sharing its UID.
+#[derive(Debug, Clone)]
+pub struct VEvent {
+    pub uid: Option<String>,
+    pub summary: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub url: Option<String>,
+    pub categories: Vec<String>,
+    pub tags: Vec<String>,
+    pub start: DateTime<Tz>,
+    pub end: DateTime<Tz>,
+    pub all_day: bool,
+    pub rrule: Option<RRule>,
+    pub exdates: Vec<DateTime<Tz>>,
+    pub rdates: Vec<DateTime<Tz>>,
+    pub recurrence_id: Option<DateTime<Tz>>,
+    pub cancelled: bool,
+}
+
+// A task read from a VTODO component, with the UIDs of the task it is a
+// subtask of and of the tasks it depends on
+#[derive(Debug, Clone)]
+pub struct VTodo {
+    pub uid: Option<String>,
+    pub summary: String,
+    pub due: Option<Due>,
+    pub priority: u8,
+    pub status: TaskStatus,
+    pub percent_complete: u8,
+    pub completed: Option<DateTime<Tz>>,
+    pub parent: Option<String>,
+    pub blocked_by: Vec<String>,
+}
+
+// The events and tasks of an iCalendar file, along with the reasons any were
+// left out
+#[derive(Debug, Default)]
+pub struct ParsedCalendar {
+    pub events: Vec<VEvent>,
+    pub todos: Vec<VTodo>,
+    pub skipped: Vec<String>,
+}
+
+// The outcome of importing an iCalendar file into a calendar. Tasks, new or
+// updated, are counted apart from events.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub tasks: usize,
+    pub skipped: Vec<String>,
+}
+
+// Splits iCalendar text into its components. Folded lines are joined, and
+// parameter values may be quoted.
+pub fn parse_components(text: &str) -> Result<Vec<Component>, InvalidICalendarError> {
+    let mut top = Vec::new();
+    let mut stack: Vec<Component> = Vec::new();
+
+    for property in unfold(text).into_iter().map(|(line, content)| parse_line(&content, line)) {
+        let property = property?;
+        match property.name.as_str() {
+            "BEGIN" => stack.push(Component {
+                name: property.value.to_uppercase(),
+                properties: Vec::new(),
+                components: Vec::new(),
+                line: property.line,
+            }),
+            "END" => {
+                let component = stack.pop()
+                    .filter(|component| component.name == property.value.to_uppercase())
+                    .ok_or_else(|| invalid(property.line, &format!("END:{} does not close an open component", property.value)))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.components.push(component),
+                    None => top.push(component),
+                }
+            }
+            _ => stack.last_mut()
+                .ok_or_else(|| invalid(property.line, &format!("{} is outside of any component", property.name)))?
+                .properties
+                .push(property),
+        }
+    }
+
+    if let Some(open) = stack.pop() {
+        return Err(invalid(open.line, &format!("BEGIN:{} is never closed", open.name)));
+    }
+
+    Ok(top)
+}
+
+// Reads the events and tasks of every VCALENDAR in the text. Times without a
+// time zone are read in `default_timezone`. Events and tasks that cannot be
+// read are left out and reported in `skipped`, while malformed files are an
+// error.
+pub fn parse_ical(text: &str, default_timezone: Tz) -> Result<ParsedCalendar, InvalidICalendarError> {
+    let components = parse_components(text)?;
+    if components.is_empty() {
+        return Err(invalid(1, "there is no VCALENDAR"));
+    }
+
+    let mut parsed = ParsedCalendar::default();
+    for calendar in &components {
+        if calendar.name != "VCALENDAR" {
+            return Err(invalid(calendar.line, &format!("expected VCALENDAR but found {}", calendar.name)));
+        }
+
+        let mut timezones = HashMap::new();
+        for vtimezone in calendar.components.iter().filter(|component| component.name == "VTIMEZONE") {
+            if let Some(tzid) = vtimezone.get("TZID") {
+                if let Some(timezone) = timezone_from_vtimezone(vtimezone) {
+                    timezones.insert(tzid.value.clone(), timezone);
+                }
+            }
+        }
+
+        for vevent in calendar.components.iter().filter(|component| component.name == "VEVENT") {
+            match read_vevent(vevent, &timezones, default_timezone) {
+                Ok(event) => parsed.events.push(event),
+                Err(e) => parsed.skipped.push(e.to_string()),
+            }
+        }
+
+        for vtodo in calendar.components.iter().filter(|component| component.name == "VTODO") {
+            match read_vtodo(vtodo, &timezones, default_timezone) {
+                Ok(todo) => parsed.todos.push(todo),
+                Err(e) => parsed.skipped.push(e.to_string()),
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+// Converts parsed VEVENTs into events. Occurrence changes are attached to the
+// event with the same UID as overrides or, when cancelled, excluded dates.
+// Each event's id is its UID when that is a UUID, and otherwise a UUID
+// derived from the UID, so importing the same file again gives the same ids.
+pub fn events_from_vevents(vevents: Vec<VEvent>) -> (Vec<Event>, Vec<String>) {
+    let mut events: Vec<Event> = Vec::new();
+    let mut skipped = Vec::new();
+    let (masters, changes): (Vec<VEvent>, Vec<VEvent>) = vevents.into_iter()
+        .partition(|vevent| vevent.recurrence_id.is_none());
+
+    for vevent in masters {
+        if vevent.cancelled {
+            skipped.push(format!("'{}' is cancelled", vevent.summary));
+            continue;
+        }
+        let uid = vevent.uid.clone()
+            .unwrap_or_else(|| format!("{}/{}", vevent.summary, to_storage_time(&vevent.start)));
+        match event_from_vevent(&uid, &vevent) {
+            Ok(event) if events.iter().any(|existing| existing.get_id() == event.get_id()) => {
+                skipped.push(format!("'{}' repeats the UID {}", vevent.summary, uid));
+            }
+            Ok(event) => events.push(event),
+            Err(e) => skipped.push(format!("'{}' could not be read: {}", vevent.summary, e)),
+        }
+    }
+
+    for vevent in changes {
+        let recurrence_id = vevent.recurrence_id.unwrap();
+        let uid = vevent.uid.clone().unwrap_or_default();
+        let id = id_for_uid(&uid);
+        let Some(event) = events.iter_mut().find(|event| *event.get_id() == id) else {
+            // A changed occurrence whose series is not in the file stands alone
+            if !vevent.cancelled {
+                let uid = format!("{}/{}", uid, to_storage_time(&recurrence_id));
+                match event_from_vevent(&uid, &VEvent { rrule: None, ..vevent.clone() }) {
+                    Ok(event) => events.push(event),
+                    Err(e) => skipped.push(format!("'{}' could not be read: {}", vevent.summary, e)),
+                }
+            }
+            continue;
+        };
+
+        let result = if vevent.cancelled {
+            event.exclude_occurrence(&recurrence_id)
+        } else {
+            let name = (vevent.summary != event.get_name()).then_some(vevent.summary.as_str());
+            Override::new(recurrence_id, vevent.start, vevent.end, name)
+                .and_then(|change| event.add_override(change))
+        };
+        if let Err(e) = result {
+            skipped.push(format!("the change to '{}' at {} could not be applied: {}", event.get_name(), recurrence_id, e));
+        }
+    }
+
+    (events, skipped)
+}
+
+// Converts parsed VTODOs into tasks. As with events, each task's id is its
+// UID when that is a UUID, and otherwise a UUID derived from the UID.
+pub fn tasks_from_vtodos(vtodos: Vec<VTodo>) -> (Vec<Task>, Vec<String>) {
+    let mut tasks: Vec<Task> = Vec::new();
+    let mut skipped = Vec::new();
+    for vtodo in vtodos {
+        let uid = vtodo.uid.clone().unwrap_or_else(|| vtodo.summary.clone());
+        match task_from_vtodo(&uid, &vtodo) {
+            Ok(task) if tasks.iter().any(|existing| existing.get_id() == task.get_id()) => {
+                skipped.push(format!("'{}' repeats the UID {}", vtodo.summary, uid));
+            }
+            Ok(task) => tasks.push(task),
+            Err(e) => skipped.push(format!("'{}' could not be read: {}", vtodo.summary, e)),
+        }
+    }
+
+    (tasks, skipped)
+}
+
+// Imports the events and tasks of an iCalendar file into `calendar`. Events
+// and tasks imported before, in this or another calendar, are replaced by
+// their new version.
+pub fn import_ical(calendar: &Calendar, text: &str, default_timezone: Tz) -> Result<ImportSummary, CaliError> {
+    let parsed = parse_ical(text, default_timezone)?;
+    let (events, skipped) = events_from_vevents(parsed.events);
+    let (tasks, skipped_tasks) = tasks_from_vtodos(parsed.todos);
+
+    let mut summary = ImportSummary { skipped: parsed.skipped, ..ImportSummary::default() };
+    summary.skipped.extend(skipped);
+    summary.skipped.extend(skipped_tasks);
+    // The whole file is imported or, if writing any event fails, none of it
+    calendar.get_store().transaction(|store| {
+        for event in &events {
+            if check_event(store, event.get_id())? {
+                update_event(calendar, event)?;
+                summary.updated += 1;
+            } else {
+                insert_event(calendar, event)?;
+                summary.added += 1;
+            }
+        }
+        for task in &tasks {
+            if check_task(store, task.get_id())? {
+                update_task(calendar, task)?;
+            } else {
+                insert_task(calendar, task)?;
+            }
+            summary.tasks += 1;
+        }
+
+        Ok(summary)
+    })
+}
+
+// Gets the event id used for an iCalendar UID
+pub fn id_for_uid(uid: &str) -> Uuid {
+    Uuid::parse_str(uid).unwrap_or_else(|_| Uuid::new_v5(&Uuid::NAMESPACE_OID, uid.as_bytes()))
+}
+
+// Builds the error reported for malformed iCalendar data
+fn invalid(line: usize, reason: &str) -> InvalidICalendarError {
+    InvalidICalendarError { line, reason: reason.to_string() }
+}
+
+// Joins folded lines, which continue the line before them after a space or
+// tab, and numbers each joined line by where it starts
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, previous))) => previous.push_str(rest),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push((index + 1, line.to_string())),
+        }
+    }
+
+    lines
+}
+
+// Reads one unfolded content line: NAME;PARAM=VALUE;...:VALUE
+fn parse_line(content: &str, line: usize) -> Result<Property, InvalidICalendarError> {
+    let name_end = content.find([';', ':'])
+        .ok_or_else(|| invalid(line, &format!("'{}' is not a property", content)))?;
+    let name = content[..name_end].trim().to_uppercase();
+    if name.is_empty() {
+        return Err(invalid(line, "a property has no name"));
+    }
+
+    let mut params = Vec::new();
+    let mut rest = &content[name_end..];
+    while let Some(param) = rest.strip_prefix(';') {
+        let equals = param.find('=').ok_or_else(|| invalid(line, "a parameter has no value"))?;
+        let param_name = param[..equals].to_uppercase();
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut end = param.len() - equals - 1;
+        for (index, c) in param[equals + 1..].char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                ';' | ':' if !quoted => {
+                    end = index;
+                    break;
+                }
+                _ => value.push(c),
+            }
+        }
+        params.push((param_name, value));
+        rest = &param[equals + 1 + end..];
+    }
+
+    let value = rest.strip_prefix(':').ok_or_else(|| invalid(line, &format!("{} has no value", name)))?;
+    Ok(Property { name, params, value: value.to_string(), line })
+}
+
+// Reads a TEXT value, undoing the escaping of backslashes, separators and newlines
+fn unescape(value: &str) -> String {
+    let mut text = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+
+    text
+}
+
+// Splits a list of TEXT values at the commas that are not escaped, and
+// unescapes each value
+fn split_text_list(value: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                values.push(unescape(&value[start..index]));
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    values.push(unescape(&value[start..]));
+
+    values
+}
+
+// Reads one VEVENT component
+fn read_vevent(vevent: &Component, timezones: &HashMap<String, Tz>, default_timezone: Tz) -> Result<VEvent, InvalidICalendarError> {
+    let summary = vevent.get("SUMMARY").map(|summary| unescape(&summary.value)).unwrap_or_default();
+    let dtstart = vevent.get("DTSTART")
+        .ok_or_else(|| invalid(vevent.line, &format!("the event '{}' has no DTSTART", summary)))?;
+    let (start, all_day) = read_time(dtstart, &dtstart.value, timezones, default_timezone)?;
+
+    let end = if let Some(dtend) = vevent.get("DTEND") {
+        read_time(dtend, &dtend.value, timezones, default_timezone)?.0
+    } else if let Some(duration) = vevent.get("DURATION") {
+        let length = parse_ical_duration(&duration.value)
+            .ok_or_else(|| invalid(duration.line, &format!("'{}' is not a duration", duration.value)))?;
+        add_duration(&start, length, all_day)
+            .ok_or_else(|| invalid(duration.line, "the end of the event is out of range"))?
+    } else if all_day {
+        add_duration(&start, Duration::days(1), true)
+            .ok_or_else(|| invalid(dtstart.line, "the end of the event is out of range"))?
+    } else {
+        start
+    };
+    if end < start {
+        return Err(invalid(dtstart.line, &format!("the event '{}' ends before it starts", summary)));
+    }
+
+    let rrule = match vevent.get("RRULE") {
+        Some(rrule) => Some(rrule.value.parse::<RRule>().map_err(|e| invalid(rrule.line, &e.to_string()))?),
+        None => None,
+    };
+    let recurrence_id = match vevent.get("RECURRENCE-ID") {
+        Some(property) => Some(read_time(property, &property.value, timezones, default_timezone)?.0),
+        None => None,
+    };
+
+    Ok(VEvent {
+        uid: vevent.get("UID").map(|uid| uid.value.trim().to_string()).filter(|uid| !uid.is_empty()),
+        summary,
+        description: vevent.get("DESCRIPTION").map(|description| unescape(&description.value)),
+        location: vevent.get("LOCATION").map(|location| unescape(&location.value)),
+        url: vevent.get("URL").map(|url| url.value.trim().to_string()),
+        categories: vevent.get_all("CATEGORIES").flat_map(|categories| split_text_list(&categories.value)).collect(),
+        tags: vevent.get_all(TAGS_PROPERTY).flat_map(|tags| split_text_list(&tags.value)).collect(),
+        start,
+        end,
+        all_day,
+        rrule,
+        exdates: read_time_lists(vevent.get_all("EXDATE"), timezones, default_timezone)?,
+        rdates: read_time_lists(vevent.get_all("RDATE"), timezones, default_timezone)?,
+        recurrence_id,
+        cancelled: vevent.get("STATUS").is_some_and(|status| status.value.eq_ignore_ascii_case("CANCELLED")),
+    })
+}
+
+// Reads one VTODO component
+fn read_vtodo(vtodo: &Component, timezones: &HashMap<String, Tz>, default_timezone: Tz) -> Result<VTodo, InvalidICalendarError> {
+    let summary = vtodo.get("SUMMARY").map(|summary| unescape(&summary.value)).unwrap_or_default();
+    let due = match vtodo.get("DUE") {
+        Some(due) => match read_time(due, &due.value, timezones, default_timezone)? {
+            (time, true) => Some(Due::Date(time.date_naive())),
+            (time, false) => Some(Due::Time(time)),
+        },
+        None => None,
+    };
+    let number = |name: &str, most: u8| -> Result<u8, InvalidICalendarError> {
+        match vtodo.get(name) {
+            Some(property) => property.value.trim().parse().ok()
+                .filter(|value| *value <= most)
+                .ok_or_else(|| invalid(property.line, &format!("'{}' is not a {} from 0 to {}", property.value, name, most))),
+            None => Ok(0),
+        }
+    };
+    let status = match vtodo.get("STATUS") {
+        Some(status) => status.value.parse().map_err(|e: InvalidTaskError| invalid(status.line, &e.reason))?,
+        None => TaskStatus::NeedsAction,
+    };
+    let completed = match vtodo.get("COMPLETED") {
+        Some(property) => Some(read_time(property, &property.value, timezones, default_timezone)?.0),
+        None => None,
+    };
+    // RELATED-TO names the parent unless its RELTYPE says otherwise; other
+    // kinds of relation are not kept
+    let mut parent = None;
+    let mut blocked_by = Vec::new();
+    for related in vtodo.get_all("RELATED-TO").filter(|related| !related.value.trim().is_empty()) {
+        let uid = related.value.trim().to_string();
+        match related.get_param("RELTYPE").map(str::to_uppercase).as_deref() {
+            None | Some("PARENT") => parent = parent.or(Some(uid)),
+            Some("DEPENDS-ON") => blocked_by.push(uid),
+            Some(_) => {}
+        }
+    }
+
+    Ok(VTodo {
+        uid: vtodo.get("UID").map(|uid| uid.value.trim().to_string()).filter(|uid| !uid.is_empty()),
+        summary,
+        due,
+        priority: number("PRIORITY", LOWEST_PRIORITY)?,
+        status,
+        percent_complete: number("PERCENT-COMPLETE", 100)?,
+        completed,
+        parent,
+        blocked_by,
+    })
+}
+
+// Reads every time listed in properties such as EXDATE, which may repeat and
+// may each hold several comma separated values
+fn read_time_lists<'a>(
+    properties: impl Iterator<Item = &'a Property>,
+    timezones: &HashMap<String, Tz>,
+    default_timezone: Tz,
+) -> Result<Vec<DateTime<Tz>>, InvalidICalendarError> {
+    let mut times = Vec::new();
+    for property in properties {
+        if property.get_param("VALUE").is_some_and(|value| value.eq_ignore_ascii_case("PERIOD")) {
+            return Err(invalid(property.line, "PERIOD values are not supported"));
+        }
+        for value in property.value.split(',').filter(|value| !value.trim().is_empty()) {
+            times.push(read_time(property, value.trim(), timezones, default_timezone)?.0);
+        }
+    }
+
+    Ok(times)
+}
+
+// Reads a DATE or DATE-TIME value, which is in UTC when it ends in "Z", in
+// the zone named by its TZID parameter, or otherwise in `default_timezone`.
+// Dates are read as midnight and reported as such.
+fn read_time(property: &Property, value: &str, timezones: &HashMap<String, Tz>, default_timezone: Tz) -> Result<(DateTime<Tz>, bool), InvalidICalendarError> {
+    let not_a_time = || invalid(property.line, &format!("'{}' is not a date or time", value));
+    let timezone = match property.get_param("TZID") {
+        Some(tzid) => timezones.get(tzid).copied()
+            .or_else(|| timezone_from_name(tzid))
+            .ok_or_else(|| invalid(property.line, &format!("'{}' is not a known time zone", tzid)))?,
+        None => default_timezone,
+    };
+
+    let is_date = property.get_param("VALUE").is_some_and(|kind| kind.eq_ignore_ascii_case("DATE")) || value.len() == 8;
+    if is_date {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| not_a_time())?;
+        let time = resolve_local(default_timezone, &date.and_time(NaiveTime::MIN)).ok_or_else(not_a_time)?;
+        return Ok((time, true));
+    }
+
+    if let Some(utc) = value.strip_suffix(['Z', 'z']) {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| not_a_time())?;
+        return Ok((Utc.from_utc_datetime(&time).with_timezone(&Tz::UTC), false));
+    }
+
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| not_a_time())?;
+    Ok((resolve_local(timezone, &time).ok_or_else(not_a_time)?, false))
+}
+
+// Finds the IANA zone for a TZID. Besides IANA names this accepts names with
+// a vendor prefix, such as "/example.org/2023/America/Chicago", and Windows
+// zone names.
+fn timezone_from_name(name: &str) -> Option<Tz> {
+    let name = name.trim().trim_matches('"');
+    let segments: Vec<&str> = name.split('/').collect();
+    (0..segments.len())
+        .find_map(|skip| segments[skip..].join("/").parse().ok())
+        .or_else(|| {
+            WINDOWS_TIMEZONES.iter()
+                .find(|(windows, _)| windows.eq_ignore_ascii_case(name))
+                .and_then(|(_, iana)| iana.parse().ok())
+        })
+}
+
+// Finds the zone a VTIMEZONE describes, from its name or, failing that, the
+// offset of its standard time. Zones only known by offset lose their
+// daylight saving rules.
+fn timezone_from_vtimezone(vtimezone: &Component) -> Option<Tz> {
+    let named = vtimezone.get("X-LIC-LOCATION")
+        .or_else(|| vtimezone.get("TZID"))
+        .and_then(|name| timezone_from_name(&name.value));
+    if named.is_some() {
+        return named;
+    }
+
+    let standard = vtimezone.components.iter()
+        .find(|component| component.name == "STANDARD")
+        .or_else(|| vtimezone.components.first())?;
+    let offset = standard.get("TZOFFSETTO")?.value.trim().to_string();
+    let (sign, digits) = offset.split_at(1);
+    let hours: i32 = digits.get(..2)?.parse().ok()?;
+    if digits.get(2..4).is_some_and(|minutes| minutes != "00") {
+        return None;
+    }
+
+    // The Etc zones use POSIX signs, so UTC+1 is "Etc/GMT-1"
+    match (sign, hours) {
+        (_, 0) => Some(Tz::UTC),
+        ("+", hours) => format!("Etc/GMT-{}", hours).parse().ok(),
+        ("-", hours) => format!("Etc/GMT+{}", hours).parse().ok(),
+        _ => None,
+    }
+}
+
+// Reads an RFC 5545 duration such as "PT15M", "P1DT2H" or "-P1W"
+pub fn parse_ical_duration(value: &str) -> Option<Duration> {
+    let value = value.trim().to_uppercase();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(&value)),
+    };
+    let value = value.strip_prefix('P')?;
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    let mut found = false;
+    for c in value.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'T' if !in_time && number.is_empty() => {
+                in_time = true;
+                continue;
+            }
+            'W' if !in_time => Duration::weeks(1),
+            'D' if !in_time => Duration::days(1),
+            'H' if in_time => Duration::hours(1),
+            'M' if in_time => Duration::minutes(1),
+            'S' if in_time => Duration::seconds(1),
+            _ => return None,
+        };
+        let amount: i32 = number.parse().ok()?;
+        total = total.checked_add(&unit.checked_mul(amount)?)?;
+        number.clear();
+        found = true;
+    }
+    if !found || !number.is_empty() {
+        return None;
+    }
+
+    Some(if negative { -total } else { total })
+}
+
+// Adds a duration to a start, keeping the wall-clock time when the event is
+// all day
+fn add_duration(start: &DateTime<Tz>, duration: Duration, all_day: bool) -> Option<DateTime<Tz>> {
+    if all_day {
+        resolve_local(start.timezone(), &start.naive_local().checked_add_signed(duration)?)
+    } else {
+        start.checked_add_signed(duration)
+    }
+}
+
+// Builds the event for a VEVENT that starts a series
+fn event_from_vevent(uid: &str, vevent: &VEvent) -> Result<Event, CaliError> {
+    let id = id_for_uid(uid);
+    let recurring = match &vevent.rrule {
+        Some(rule) => Recurring::Rule(rule.clone()),
+        None => Recurring::No,
+    };
+
+    let mut event = Event::from(&id.to_string(), &vevent.summary, vevent.start, vevent.end, recurring)?;
+    if Uuid::parse_str(uid).is_err() {
+        event.update_uid(Some(uid))?;
+    }
+    event.update_all_day(vevent.all_day)?;
+    event.update_description(vevent.description.as_deref())?;
+    event.update_location(vevent.location.as_deref())?;
+    event.update_url(vevent.url.as_deref())?;
+    event.update_categories(&vevent.categories)?;
+    event.update_tags(&vevent.tags)?;
+    for exdate in &vevent.exdates {
+        event.add_exdate(*exdate);
+    }
+    for rdate in &vevent.rdates {
+        event.add_rdate(*rdate);
+    }
+
+    Ok(event)
+}
+
+// Builds the task for a VTODO
+fn task_from_vtodo(uid: &str, vtodo: &VTodo) -> Result<Task, CaliError> {
+    let id = id_for_uid(uid);
+    let mut task = Task::from(&id.to_string(), &vtodo.summary, vtodo.due)?;
+    if Uuid::parse_str(uid).is_err() {
+        task.update_uid(Some(uid))?;
+    }
+    task.update_priority(vtodo.priority)?;
+    task.update_status(vtodo.status)?;
+    task.update_percent_complete(vtodo.percent_complete)?;
+    task.update_completed(vtodo.completed)?;
+    task.update_parent(vtodo.parent.as_deref().map(id_for_uid))?;
+    for blocker in &vtodo.blocked_by {
+        task.add_blocker(id_for_uid(blocker))?;
+    }
+
+    Ok(task)
+}
+
+// Writes a calendar's events and tasks as an iCalendar file
+pub fn export_ical(calendar: &Calendar) -> Result<String, CaliError> {
+    let events = get_events(calendar)?;
+    let tasks = get_tasks(calendar)?;
+    Ok(write_calendar(calendar.get_name(), &events, &tasks, &Utc::now()))
+}
+
+// Writes events as an iCalendar file, stamped with `stamp`
+pub fn write_ical(name: &str, events: &[Event], stamp: &DateTime<Utc>) -> String {
+    write_calendar(name, events, &[], stamp)
+}
+
+// Writes events and tasks as an iCalendar file, stamped with `stamp`. Each
+// event's UID is the UID it was imported with or else its id, and each
+// changed occurrence is written as a VEVENT with a RECURRENCE-ID. A VTIMEZONE
+// is generated for every zone other than UTC that the events and due times
+// use, and all-day events and tasks due on a day are written with DATE values.
+pub fn write_calendar(name: &str, events: &[Event], tasks: &[Task], stamp: &DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//cali//cali {}//EN", env!("CARGO_PKG_VERSION")),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+    ];
+
+    let due_times: Vec<&DateTime<Tz>> = tasks.iter()
+        .filter_map(|task| match task.get_due() {
+            Some(Due::Time(time)) => Some(time),
+            _ => None,
+        })
+        .collect();
+    let zoned_times: Vec<&DateTime<Tz>> = events.iter().map(Event::get_start).chain(due_times).collect();
+    let mut timezones: Vec<Tz> = zoned_times.iter().map(|time| time.timezone()).filter(|timezone| *timezone != Tz::UTC).collect();
+    timezones.sort_by_key(|timezone| timezone.name());
+    timezones.dedup();
+    // Series that come to an end are covered up to their last occurrence,
+    // and those that never end rely on the zone's ongoing rule, if it has
+    // one. So do times past the horizon.
+    let horizon = stamp.year() + EXPLICIT_TIMEZONE_YEARS;
+    let series_ends: Vec<DateTime<Tz>> = events.iter().filter_map(|event| series_end(event, horizon)).collect();
+    for timezone in timezones {
+        let years = zoned_times.iter()
+            .copied()
+            .chain(&series_ends)
+            .filter(|time| time.timezone() == timezone)
+            .map(|time| time.year());
+        let last = years.clone().max().unwrap_or(1970).max(stamp.year()).min(horizon) + 1;
+        let first = years.min().unwrap_or(1970).min(last);
+        if let Some(vtimezone) = write_vtimezone(timezone, first, last) {
+            lines.extend(vtimezone);
+        }
+    }
+
+    let stamp = stamp.format("%Y%m%dT%H%M%SZ").to_string();
+    for event in events {
+        let all_day = event.is_all_day();
+        let uid = event.get_uid().map_or_else(|| event.get_id().to_string(), str::to_string);
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}", escape(&uid)));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(time_property("DTSTART", event.get_start(), all_day));
+        lines.push(time_property("DTEND", event.get_end(), all_day));
+        lines.push(format!("SUMMARY:{}", escape(event.get_name())));
+        if let Some(description) = event.get_description() {
+            lines.push(format!("DESCRIPTION:{}", escape(description)));
+        }
+        if let Some(location) = event.get_location() {
+            lines.push(format!("LOCATION:{}", escape(location)));
+        }
+        if let Some(url) = event.get_url() {
+            lines.push(format!("URL:{}", url));
+        }
+        if !event.get_categories().is_empty() {
+            let categories: Vec<String> = event.get_categories().iter().map(|category| escape(category)).collect();
+            lines.push(format!("CATEGORIES:{}", categories.join(",")));
+        }
+        if !event.get_tags().is_empty() {
+            lines.push(format!("{}:{}", TAGS_PROPERTY, event.get_tags().join(",")));
+        }
+        if let Some(rule) = event.get_recurring().to_rule() {
+            lines.push(format!("RRULE:{}", rule));
+        }
+        if !event.get_exdates().is_empty() {
+            lines.push(time_list_property("EXDATE", event.get_exdates(), all_day));
+        }
+        if !event.get_rdates().is_empty() {
+            lines.push(time_list_property("RDATE", event.get_rdates(), all_day));
+        }
+        lines.push("END:VEVENT".to_string());
+
+        for change in event.get_overrides() {
+            lines.push("BEGIN:VEVENT".to_string());
+            lines.push(format!("UID:{}", escape(&uid)));
+            lines.push(format!("DTSTAMP:{}", stamp));
+            lines.push(time_property("RECURRENCE-ID", change.get_recurrence_id(), all_day));
+            lines.push(time_property("DTSTART", change.get_start(), all_day));
+            lines.push(time_property("DTEND", change.get_end(), all_day));
+            lines.push(format!("SUMMARY:{}", escape(change.get_name().unwrap_or(event.get_name()))));
+            lines.push("END:VEVENT".to_string());
+        }
+    }
+
+    // Related tasks are named by their UID when they are written too
+    let uid_of = |id: &Uuid| -> String {
+        tasks.iter()
+            .find(|task| task.get_id() == id)
+            .and_then(Task::get_uid)
+            .map_or_else(|| id.to_string(), str::to_string)
+    };
+    for task in tasks {
+        let uid = uid_of(task.get_id());
+        lines.push("BEGIN:VTODO".to_string());
+        lines.push(format!("UID:{}", escape(&uid)));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(format!("SUMMARY:{}", escape(task.get_title())));
+        match task.get_due() {
+            Some(Due::Date(day)) => lines.push(format!("DUE;VALUE=DATE:{}", day.format("%Y%m%d"))),
+            Some(Due::Time(time)) => lines.push(time_property("DUE", time, false)),
+            None => {}
+        }
+        if task.get_priority() > 0 {
+            lines.push(format!("PRIORITY:{}", task.get_priority()));
+        }
+        lines.push(format!("STATUS:{}", task.get_status()));
+        if task.get_percent_complete() > 0 {
+            lines.push(format!("PERCENT-COMPLETE:{}", task.get_percent_complete()));
+        }
+        // COMPLETED is always in UTC
+        if let Some(completed) = task.get_completed() {
+            lines.push(format!("COMPLETED:{}", completed.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")));
+        }
+        if let Some(parent) = task.get_parent() {
+            lines.push(format!("RELATED-TO;RELTYPE=PARENT:{}", escape(&uid_of(parent))));
+        }
+        for blocker in task.get_blocked_by() {
+            lines.push(format!("RELATED-TO;RELTYPE=DEPENDS-ON:{}", escape(&uid_of(blocker))));
+        }
+        lines.push("END:VTODO".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+// Escapes a TEXT value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Folds a content line into lines of at most 75 octets, each continuation
+// starting with a space, without splitting a character, and ends it with CRLF
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+
+    folded
+}
+
+// Formats a DATE-TIME value, in UTC or as local time in its zone
+fn format_time(time: &DateTime<Tz>) -> String {
+    if time.timezone() == Tz::UTC {
+        time.format("%Y%m%dT%H%M%SZ").to_string()
+    } else {
+        time.format("%Y%m%dT%H%M%S").to_string()
+    }
+}
+
+// Writes a property holding one time, naming its zone unless it is UTC, or
+// only its date when `all_day`
+fn time_property(name: &str, time: &DateTime<Tz>, all_day: bool) -> String {
+    if all_day {
+        return format!("{};VALUE=DATE:{}", name, time.format("%Y%m%d"));
+    }
+    match time.timezone() {
+        Tz::UTC => format!("{}:{}", name, format_time(time)),
+        timezone => format!("{};TZID={}:{}", name, timezone.name(), format_time(time)),
+    }
+}
+
+// Writes a property holding a list of times in one zone, or of dates when `all_day`
+fn time_list_property(name: &str, times: &[DateTime<Tz>], all_day: bool) -> String {
+    if all_day {
+        let values: Vec<String> = times.iter().map(|time| time.format("%Y%m%d").to_string()).collect();
+        return format!("{};VALUE=DATE:{}", name, values.join(","));
+    }
+    let values: Vec<String> = times.iter().map(format_time).collect();
+    match times[0].timezone() {
+        Tz::UTC => format!("{}:{}", name, values.join(",")),
+        timezone => format!("{};TZID={}:{}", name, timezone.name(), values.join(",")),
+    }
+}
+
+// How many years after those an export covers are checked for a zone's
+// ongoing yearly rule
+const RULE_CHECK_YEARS: i32 = 10;
+
+// How many years after an export is stamped its time zones list changes of
+// offset for one by one at most. Later years rely on the zones' ongoing rules.
+const EXPLICIT_TIMEZONE_YEARS: i32 = 50;
+
+// Gets when a series that comes to an end last takes place: from its UNTIL
+// when it has one, or else by going through its COUNT occurrences, though
+// no further than the end of the year `horizon`
+fn series_end(event: &Event, horizon: i32) -> Option<DateTime<Tz>> {
+    let rule = event.get_recurring().to_rule()?;
+    match (rule.get_until(), rule.get_count()) {
+        (Some(until), _) => {
+            let until = match until {
+                Until::Date(date) => Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN)),
+                Until::DateTime(time) => time,
+            };
+            Some(until.with_timezone(&event.get_timezone()) + event.get_duration())
+        }
+        (None, Some(_)) => event.occurrences()
+            .take_while(|occurrence| occurrence.get_start().year() <= horizon)
+            .last()
+            .map(|occurrence| *occurrence.get_end()),
+        (None, None) => None,
+    }
+}
+
+// Writes a VTIMEZONE for `timezone` listing each change of offset from the
+// start of `first_year` to the end of `last_year`. When the zone keeps
+// changing by one yearly rule, the changes from the first year the rule
+// holds are written once each, with an RRULE, so later years are covered too.
+// Gets None for years chrono cannot represent.
+fn write_vtimezone(timezone: Tz, first_year: i32, last_year: i32) -> Option<Vec<String>> {
+    let mut lines = vec![
+        "BEGIN:VTIMEZONE".to_string(),
+        format!("TZID:{}", timezone.name()),
+        format!("X-LIC-LOCATION:{}", timezone.name()),
+    ];
+
+    let changes = offset_changes(timezone, first_year, last_year + RULE_CHECK_YEARS)?;
+    let year_of = |change: &OffsetChange| change.local_before().year();
+    let in_year = |year: i32| -> Vec<&OffsetChange> {
+        changes.iter().filter(|change| year_of(change) == year).collect()
+    };
+
+    // The rule must hold every year after the export, and is written from
+    // the earliest year it holds in without a break
+    let rules = yearly_rules(&(last_year + 1..=last_year + RULE_CHECK_YEARS).map(in_year).collect::<Vec<_>>());
+    let rule_start = rules.as_ref().map(|rules| {
+        let mut year = last_year + 1;
+        while year > first_year && follows_rules(rules, &in_year(year - 1)) {
+            year -= 1;
+        }
+        year
+    });
+
+    // The observance in effect at the start, then one for each change before the rule
+    let start = Utc.with_ymd_and_hms(first_year, 1, 1, 0, 0, 0).single()?;
+    let offset = offset_at(timezone, start);
+    write_observance(&mut lines, timezone, start, start.naive_utc(), offset, offset, None);
+    let explicit_until = rule_start.unwrap_or(last_year + 1);
+    for change in changes.iter().filter(|change| year_of(change) < explicit_until) {
+        write_observance(&mut lines, timezone, change.at, change.local_before(), change.from, change.to, None);
+    }
+    if let (Some(rules), Some(rule_start)) = (rules, rule_start) {
+        for (rule, change) in rules.iter().zip(in_year(rule_start)) {
+            let rrule = format!(
+                "RRULE:FREQ=YEARLY;BYMONTH={};BYDAY={}{}",
+                rule.month, rule.ordinal, weekday_code(rule.weekday),
+            );
+            write_observance(&mut lines, timezone, change.at, change.local_before(), change.from, change.to, Some(rrule));
+        }
+    }
+    lines.push("END:VTIMEZONE".to_string());
+
+    Some(lines)
+}
+
+// Writes one observance of a VTIMEZONE, which starts at `at`, or at `onset`
+// in local time before it, and repeats by `rrule` if there is one
+fn write_observance(
+    lines: &mut Vec<String>,
+    timezone: Tz,
+    at: DateTime<Utc>,
+    onset: NaiveDateTime,
+    from: i32,
+    to: i32,
+    rrule: Option<String>,
+) {
+    let local = at.with_timezone(&timezone);
+    let kind = if local.offset().dst_offset().is_zero() { "STANDARD" } else { "DAYLIGHT" };
+    lines.push(format!("BEGIN:{}", kind));
+    lines.push(format!("DTSTART:{}", onset.format("%Y%m%dT%H%M%S")));
+    lines.extend(rrule);
+    lines.push(format!("TZOFFSETFROM:{}", format_offset(from)));
+    lines.push(format!("TZOFFSETTO:{}", format_offset(to)));
+    lines.push(format!("TZNAME:{}", escape(&local.format("%Z").to_string())));
+    lines.push(format!("END:{}", kind));
+}
+
+// Gets a zone's offset from UTC at `time`, in seconds
+fn offset_at(timezone: Tz, time: DateTime<Utc>) -> i32 {
+    time.with_timezone(&timezone).offset().fix().local_minus_utc()
+}
+
+// A change of a zone's offset from UTC, in seconds, at a moment
+struct OffsetChange {
+    at: DateTime<Utc>,
+    from: i32,
+    to: i32,
+}
+
+impl OffsetChange {
+    // Gets the wall-clock time the change happens at, read before it
+    fn local_before(&self) -> NaiveDateTime {
+        self.at.naive_utc() + Duration::seconds(self.from as i64)
+    }
+}
+
+// Finds each change of offset from the start of `first_year` to the end of
+// `last_year`, to the second. The offset is looked up a week apart, as no
+// zone changes it twice within a week. Gets None for years chrono cannot
+// represent.
+fn offset_changes(timezone: Tz, first_year: i32, last_year: i32) -> Option<Vec<OffsetChange>> {
+    let start = Utc.with_ymd_and_hms(first_year, 1, 1, 0, 0, 0).single()?;
+    let end = Utc.with_ymd_and_hms(last_year + 1, 1, 1, 0, 0, 0).single()?;
+
+    let mut changes = Vec::new();
+    let mut week = start;
+    while week < end {
+        let next = week + Duration::weeks(1);
+        let offset = offset_at(timezone, week);
+        if offset_at(timezone, next) != offset {
+            let (mut before, mut after) = (week, next);
+            while after - before > Duration::seconds(1) {
+                let middle = before + (after - before) / 2;
+                if offset_at(timezone, middle) == offset {
+                    before = middle;
+                } else {
+                    after = middle;
+                }
+            }
+            changes.push(OffsetChange { at: after, from: offset, to: offset_at(timezone, after) });
+        }
+        week = next;
+    }
+
+    Some(changes)
+}
+
+// A change of offset that happens every year on a weekday of a month, the
+// last of them when `ordinal` is -1, at a wall-clock time read before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct YearlyRule {
+    month: u32,
+    weekday: Weekday,
+    ordinal: i32,
+    time: NaiveTime,
+    from: i32,
+    to: i32,
+}
+
+impl YearlyRule {
+    // Describes a change as the nth of its weekday in its month, or as the last one
+    fn of(change: &OffsetChange, last: bool) -> YearlyRule {
+        let local = change.local_before();
+        YearlyRule {
+            month: local.month(),
+            weekday: local.weekday(),
+            ordinal: if last { -1 } else { local.day0() as i32 / 7 + 1 },
+            time: local.time(),
+            from: change.from,
+            to: change.to,
+        }
+    }
+
+    fn matches(&self, change: &OffsetChange) -> bool {
+        let local = change.local_before();
+        let last = local.date().checked_add_signed(Duration::days(7)).is_none_or(|later| later.month() != local.month());
+        *self == YearlyRule::of(change, self.ordinal == -1 && last)
+    }
+}
+
+// Finds the rules that give a zone's changes in each of `years`, when every
+// year has the same two changes by weekday, as daylight saving time does
+fn yearly_rules(years: &[Vec<&OffsetChange>]) -> Option<Vec<YearlyRule>> {
+    let first = years.first()?;
+    if first.len() != 2 || years.iter().any(|changes| changes.len() != 2) {
+        return None;
+    }
+    first.iter()
+        .enumerate()
+        .map(|(index, change)| {
+            [false, true].into_iter()
+                .map(|last| YearlyRule::of(change, last))
+                .find(|rule| years.iter().all(|changes| rule.matches(changes[index])))
+        })
+        .collect()
+}
+
+// Checks whether a year's changes are exactly those `rules` give, in order
+fn follows_rules(rules: &[YearlyRule], changes: &[&OffsetChange]) -> bool {
+    rules.len() == changes.len() && rules.iter().zip(changes).all(|(rule, change)| rule.matches(change))
+}
+
+// Formats a UTC offset in seconds as [+-]HHMM, with seconds when there are any
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    let formatted = format!("{}{:02}{:02}", sign, seconds / 3600, seconds % 3600 / 60);
+    match seconds % 60 {
+        0 => formatted,
+        rest => format!("{}{:02}", formatted, rest),
+    }
+}
@@ -10,3 +10,17 @@ pub mod calendar;
 pub mod event;
 pub mod time;
 pub mod cali_error;
+pub mod migration;
+pub mod recurrence;
+pub mod date_expr;
+pub mod view;
+pub mod ical;
+pub mod paths;
+pub mod config;
+pub mod store;
+pub mod sqlite_store;
+pub mod memory_store;
+pub mod vdir_store;
+pub mod tag;
+pub mod task;
+pub mod search;
@@ -6,8 +6,7 @@ fn main() {
     let args = InputParser::parse();
 
     if let Err(e) = args.run() {
-        println!("Application error: {e}");
-        process::exit(1);
+        eprintln!("Application error: {e}");
+        process::exit(e.exit_code());
     }
 }
-
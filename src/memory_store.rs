@@ -0,0 +1,138 @@
+use crate::{cali_error::*, event::*, store::*, task::*};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// The calendars, events and tasks of a memory store, each event and task
+// with the id of its calendar
+#[derive(Clone, Default)]
+struct MemoryData {
+    calendars: Vec<CalendarRecord>,
+    events: Vec<(i64, Event)>,
+    tasks: Vec<(i64, Task)>,
+    last_id: i64,
+}
+
+// Calendars kept in memory only, for tests and scratch use
+#[derive(Default)]
+pub struct MemoryStore {
+    data: RefCell<MemoryData>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl CalendarStore for MemoryStore {
+    fn get_path(&self) -> Option<&PathBuf> {
+        None
+    }
+
+    fn get_calendars(&self) -> Result<Vec<CalendarRecord>, CaliError> {
+        let mut calendars = self.data.borrow().calendars.clone();
+        calendars.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(calendars)
+    }
+
+    fn insert_calendar(&self, name: &str, default: bool) -> Result<i64, CaliError> {
+        let mut data = self.data.borrow_mut();
+        data.last_id += 1;
+        let id = data.last_id;
+        data.calendars.push(CalendarRecord { id, name: name.to_string(), default });
+        Ok(id)
+    }
+
+    fn rename_calendar(&self, id: i64, new_name: &str) -> Result<(), CaliError> {
+        for calendar in self.data.borrow_mut().calendars.iter_mut().filter(|calendar| calendar.id == id) {
+            calendar.name = new_name.to_string();
+        }
+        Ok(())
+    }
+
+    fn remove_calendar(&self, id: i64) -> Result<(), CaliError> {
+        let mut data = self.data.borrow_mut();
+        data.calendars.retain(|calendar| calendar.id != id);
+        data.events.retain(|(calendar_id, _)| *calendar_id != id);
+        data.tasks.retain(|(calendar_id, _)| *calendar_id != id);
+        Ok(())
+    }
+
+    fn update_default(&self, id: i64) -> Result<(), CaliError> {
+        for calendar in self.data.borrow_mut().calendars.iter_mut() {
+            calendar.default = calendar.id == id;
+        }
+        Ok(())
+    }
+
+    fn insert_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError> {
+        self.data.borrow_mut().events.push((calendar_id, event.clone()));
+        Ok(())
+    }
+
+    fn update_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError> {
+        for existing in self.data.borrow_mut().events.iter_mut().filter(|(_, existing)| existing.get_id() == event.get_id()) {
+            *existing = (calendar_id, event.clone());
+        }
+        Ok(())
+    }
+
+    fn remove_event(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError> {
+        self.data.borrow_mut().events.retain(|(existing_calendar, existing)| {
+            *existing_calendar != calendar_id || existing.get_id() != id
+        });
+        Ok(())
+    }
+
+    fn get_events(&self, calendar_id: i64) -> Result<Vec<Event>, CaliError> {
+        let mut events: Vec<Event> = self.data.borrow().events.iter()
+            .filter(|(existing_calendar, _)| *existing_calendar == calendar_id)
+            .map(|(_, event)| event.clone())
+            .collect();
+        events.sort_by(|a, b| a.get_start().cmp(b.get_start()).then_with(|| a.get_name().cmp(b.get_name())));
+        Ok(events)
+    }
+
+    fn check_event(&self, id: &Uuid) -> Result<bool, CaliError> {
+        Ok(self.data.borrow().events.iter().any(|(_, event)| event.get_id() == id))
+    }
+
+    fn insert_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError> {
+        self.data.borrow_mut().tasks.push((calendar_id, task.clone()));
+        Ok(())
+    }
+
+    fn update_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError> {
+        for existing in self.data.borrow_mut().tasks.iter_mut().filter(|(_, existing)| existing.get_id() == task.get_id()) {
+            *existing = (calendar_id, task.clone());
+        }
+        Ok(())
+    }
+
+    fn remove_task(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError> {
+        self.data.borrow_mut().tasks.retain(|(existing_calendar, existing)| {
+            *existing_calendar != calendar_id || existing.get_id() != id
+        });
+        Ok(())
+    }
+
+    fn get_tasks(&self, calendar_id: i64) -> Result<Vec<Task>, CaliError> {
+        Ok(self.data.borrow().tasks.iter()
+            .filter(|(existing_calendar, _)| *existing_calendar == calendar_id)
+            .map(|(_, task)| task.clone())
+            .collect())
+    }
+
+    fn check_task(&self, id: &Uuid) -> Result<bool, CaliError> {
+        Ok(self.data.borrow().tasks.iter().any(|(_, task)| task.get_id() == id))
+    }
+
+    // Puts back what the store held before the operation if it fails
+    fn transaction(&self, operation: &mut dyn FnMut() -> Result<(), CaliError>) -> Result<(), CaliError> {
+        let before = self.data.borrow().clone();
+        operation().inspect_err(|_| {
+            self.data.replace(before);
+        })
+    }
+}
@@ -0,0 +1,352 @@
+use crate::{cali_error::*, time::*};
+use uuid::Uuid;
+use rusqlite::{params, Connection, Result, Transaction};
+
+type Migration = fn(&Transaction) -> Result<()>;
+
+// Ordered schema upgrades. Applying the migration at index `n` brings the
+// database to schema version `n + 1`, which is recorded in `PRAGMA user_version`.
+const MIGRATIONS: &[Migration] = &[
+    migrate_to_calendar_and_event_tables,
+    migrate_to_utc_event_times,
+    migrate_to_indexed_event_times,
+    migrate_to_recurrence_exceptions,
+    migrate_to_event_uids,
+    migrate_to_all_day_events,
+    migrate_to_event_details,
+    migrate_to_event_tags,
+    migrate_to_tasks,
+    migrate_to_task_relations,
+    migrate_to_event_search,
+];
+
+/// The schema version produced by applying every known migration.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+// Reads the schema version recorded in the database
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    conn.query_row("PRAGMA user_version", params![], |row| row.get(0))
+}
+
+// Applies every pending migration in order, each inside its own transaction
+pub fn migrate(conn: &mut Connection) -> Result<u32, CaliError> {
+    let found = schema_version(conn)?;
+    if found > SCHEMA_VERSION {
+        return Err(SchemaVersionError { found, supported: SCHEMA_VERSION }.into());
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(found as usize) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", &(index as u32 + 1))?;
+        tx.commit()?;
+    }
+
+    Ok(SCHEMA_VERSION)
+}
+
+// Checks whether `table` currently has a column called `column`
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map(params![], |row| row.get::<_, String>("name"))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Version 1: separate `calendars` and `events` tables.
+//
+// Databases written before versioning kept one flat `calendars` table in which
+// every row was an event carrying its calendar's name and default flag. Those
+// rows are split into one calendar row per name and one event row per event.
+fn migrate_to_calendar_and_event_tables(tx: &Transaction) -> Result<()> {
+    let legacy = has_column(tx, "calendars", "event_id")?;
+    if legacy {
+        tx.execute_batch("ALTER TABLE calendars RENAME TO legacy_calendars;")?;
+    }
+
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS calendars (
+            calendar_id INTEGER PRIMARY KEY,
+            calendar_name TEXT NOT NULL UNIQUE,
+            is_default INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE UNIQUE INDEX IF NOT EXISTS calendars_default_idx
+            ON calendars (is_default) WHERE is_default = 1;
+        CREATE TABLE IF NOT EXISTS events (
+            event_id TEXT PRIMARY KEY,
+            calendar_id INTEGER NOT NULL
+                REFERENCES calendars (calendar_id) ON DELETE CASCADE,
+            event_name TEXT NOT NULL,
+            event_start TEXT NOT NULL,
+            event_end TEXT NOT NULL,
+            event_recurring TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS events_calendar_idx ON events (calendar_id);",
+    )?;
+
+    if !legacy {
+        return Ok(());
+    }
+
+    tx.execute_batch(
+        "INSERT INTO calendars (calendar_name)
+            SELECT calendar_name FROM legacy_calendars
+            GROUP BY calendar_name ORDER BY MIN(rowid);
+        UPDATE calendars SET is_default = 1 WHERE calendar_id = (
+            SELECT calendars.calendar_id FROM calendars
+            JOIN legacy_calendars ON legacy_calendars.calendar_name = calendars.calendar_name
+            WHERE legacy_calendars.is_default <> 0
+            ORDER BY calendars.calendar_id LIMIT 1
+        );",
+    )?;
+
+    // Legacy ids were not guaranteed to be present, valid or unique
+    {
+        let mut select = tx.prepare(
+            "SELECT calendars.calendar_id, legacy_calendars.event_id,
+                CAST(event_name AS TEXT), CAST(event_start AS TEXT),
+                CAST(event_end AS TEXT), CAST(event_recurring AS TEXT)
+            FROM legacy_calendars
+            JOIN calendars ON calendars.calendar_name = legacy_calendars.calendar_name
+            ORDER BY legacy_calendars.rowid",
+        )?;
+        let mut insert = tx.prepare(
+            "INSERT INTO events (event_id, calendar_id, event_name, event_start, event_end, event_recurring)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        let mut seen_ids = Vec::new();
+        let mut rows = select.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let calendar_id: i64 = row.get(0)?;
+            let legacy_id: Option<String> = row.get(1)?;
+            let event_id = match legacy_id.as_deref().map(Uuid::parse_str) {
+                Some(Ok(id)) if !seen_ids.contains(&id) => id,
+                _ => Uuid::new_v4(),
+            };
+            seen_ids.push(event_id);
+
+            let recurring: Option<String> = row.get(5)?;
+            let recurring = recurring
+                .filter(|value| ["Daily", "Weekly", "Monthly", "Yearly"].contains(&value.as_str()))
+                .unwrap_or_else(|| "No".to_string());
+
+            insert.execute(params![
+                event_id.to_string(),
+                calendar_id,
+                row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                recurring,
+            ])?;
+        }
+    }
+
+    tx.execute_batch("DROP TABLE legacy_calendars;")?;
+
+    Ok(())
+}
+
+// Version 2: event times stored as canonical UTC text plus an IANA time zone.
+//
+// Earlier versions stored whatever text the caller supplied. Values that can be
+// read as a date or date-time are rewritten in UTC. An event with a time that
+// cannot be read is moved, with the reason, to `unreadable_events`, so that it
+// does not stop the rest of its calendar from loading.
+fn migrate_to_utc_event_times(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE events ADD COLUMN event_timezone TEXT NOT NULL DEFAULT 'UTC';
+        CREATE TABLE IF NOT EXISTS unreadable_events (
+            event_id TEXT PRIMARY KEY,
+            calendar_id INTEGER NOT NULL
+                REFERENCES calendars (calendar_id) ON DELETE CASCADE,
+            event_name TEXT NOT NULL,
+            event_start TEXT NOT NULL,
+            event_end TEXT NOT NULL,
+            event_recurring TEXT NOT NULL,
+            reason TEXT NOT NULL
+        );",
+    )?;
+
+    let rows: Vec<(String, String, String)> = {
+        let mut select = tx.prepare("SELECT event_id, event_start, event_end FROM events")?;
+        let rows = select.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect::<Result<_>>()?
+    };
+    let mut update = tx.prepare("UPDATE events SET event_start = ?2, event_end = ?3 WHERE event_id = ?1")?;
+    let mut set_aside = tx.prepare(
+        "INSERT INTO unreadable_events (event_id, calendar_id, event_name, event_start, event_end, event_recurring, reason)
+            SELECT event_id, calendar_id, event_name, event_start, event_end, event_recurring, ?2
+            FROM events WHERE event_id = ?1",
+    )?;
+    let mut delete = tx.prepare("DELETE FROM events WHERE event_id = ?1")?;
+    let utc = chrono_tz::Tz::UTC;
+    for (id, start, end) in rows {
+        match (parse_legacy_time(&start), parse_legacy_time(&end)) {
+            (Some(start), Some(end)) => {
+                update.execute(params![id, to_storage_time(&start.with_timezone(&utc)), to_storage_time(&end.with_timezone(&utc))])?;
+            }
+            (start_time, _) => {
+                let reason = match start_time {
+                    None => format!("the start '{}' is not a date or time", start),
+                    Some(_) => format!("the end '{}' is not a date or time", end),
+                };
+                set_aside.execute(params![id, reason])?;
+                delete.execute(params![id])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Version 3: index event times for range queries within a calendar
+fn migrate_to_indexed_event_times(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS events_start_idx ON events (calendar_id, event_start);
+        CREATE INDEX IF NOT EXISTS events_end_idx ON events (calendar_id, event_end);",
+    )
+}
+
+// Version 4: per-occurrence exceptions to recurring events. Recurrence dates
+// add (RDATE) or skip (EXDATE) single occurrences and overrides replace the
+// time or name of one occurrence, identified by its original start.
+fn migrate_to_recurrence_exceptions(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS event_recurrence_dates (
+            event_id TEXT NOT NULL
+                REFERENCES events (event_id) ON DELETE CASCADE,
+            recurrence_date TEXT NOT NULL,
+            is_excluded INTEGER NOT NULL,
+            PRIMARY KEY (event_id, is_excluded, recurrence_date)
+        );
+        CREATE TABLE IF NOT EXISTS event_overrides (
+            event_id TEXT NOT NULL
+                REFERENCES events (event_id) ON DELETE CASCADE,
+            recurrence_id TEXT NOT NULL,
+            override_start TEXT NOT NULL,
+            override_end TEXT NOT NULL,
+            override_name TEXT,
+            PRIMARY KEY (event_id, recurrence_id)
+        );",
+    )
+}
+
+// Version 5: the UID an event was imported with, kept when it is not itself a
+// UUID that can serve as the event id
+fn migrate_to_event_uids(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("ALTER TABLE events ADD COLUMN event_uid TEXT;")
+}
+
+// Version 6: whether an event takes up whole days rather than running
+// between two times
+fn migrate_to_all_day_events(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("ALTER TABLE events ADD COLUMN event_all_day INTEGER NOT NULL DEFAULT 0;")
+}
+
+// Version 7: notes, a location, a URL and categories for each event, the
+// categories kept as a comma separated list
+fn migrate_to_event_details(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE events ADD COLUMN event_description TEXT;
+        ALTER TABLE events ADD COLUMN event_location TEXT;
+        ALTER TABLE events ADD COLUMN event_url TEXT;
+        ALTER TABLE events ADD COLUMN event_categories TEXT NOT NULL DEFAULT '';",
+    )
+}
+
+// Version 8: tags, shared by the events of every calendar, and which events
+// carry each one
+fn migrate_to_event_tags(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tags (
+            tag_id INTEGER PRIMARY KEY,
+            tag_name TEXT NOT NULL UNIQUE
+        );
+        CREATE TABLE IF NOT EXISTS event_tags (
+            event_id TEXT NOT NULL
+                REFERENCES events (event_id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL
+                REFERENCES tags (tag_id) ON DELETE CASCADE,
+            PRIMARY KEY (event_id, tag_id)
+        );
+        CREATE INDEX IF NOT EXISTS event_tags_tag_idx ON event_tags (tag_id);",
+    )
+}
+
+// Version 9: tasks, each in a calendar. A task due on a day keeps the date as
+// YYYY-MM-DD with no time zone, and one due at a time keeps the UTC time and
+// the zone it was given in.
+fn migrate_to_tasks(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tasks (
+            task_id TEXT PRIMARY KEY,
+            calendar_id INTEGER NOT NULL
+                REFERENCES calendars (calendar_id) ON DELETE CASCADE,
+            task_title TEXT NOT NULL,
+            task_due TEXT,
+            task_due_timezone TEXT,
+            task_priority INTEGER NOT NULL DEFAULT 0,
+            task_status TEXT NOT NULL DEFAULT 'NEEDS-ACTION',
+            task_percent_complete INTEGER NOT NULL DEFAULT 0,
+            task_completed TEXT,
+            task_uid TEXT
+        );
+        CREATE INDEX IF NOT EXISTS tasks_calendar_idx ON tasks (calendar_id);",
+    )
+}
+
+// Version 10: the task each task is a subtask of, and the tasks that block
+// it. Neither refers to tasks through a foreign key, since an imported task
+// may name one that is not imported yet, or at all.
+fn migrate_to_task_relations(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE tasks ADD COLUMN task_parent_id TEXT;
+        CREATE TABLE IF NOT EXISTS task_blockers (
+            task_id TEXT NOT NULL
+                REFERENCES tasks (task_id) ON DELETE CASCADE,
+            blocker_id TEXT NOT NULL,
+            PRIMARY KEY (task_id, blocker_id)
+        );
+        CREATE INDEX IF NOT EXISTS tasks_parent_idx ON tasks (task_parent_id);",
+    )
+}
+
+// Version 11: a full-text index over the title, description and location of
+// every event. The index reads its text from the events table and is kept up
+// to date by triggers, keyed on the events' rowids so that no change has to
+// scan it. Words are matched as written, accents included, as the other
+// backends match them.
+fn migrate_to_event_search(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS events_fts USING fts5(
+            event_name,
+            event_description,
+            event_location,
+            content = 'events',
+            content_rowid = 'rowid',
+            tokenize = 'unicode61 remove_diacritics 0'
+        );
+        INSERT INTO events_fts (events_fts) VALUES ('rebuild');
+        CREATE TRIGGER IF NOT EXISTS events_fts_insert AFTER INSERT ON events BEGIN
+            INSERT INTO events_fts (rowid, event_name, event_description, event_location)
+                VALUES (new.rowid, new.event_name, new.event_description, new.event_location);
+        END;
+        CREATE TRIGGER IF NOT EXISTS events_fts_update
+            AFTER UPDATE OF event_name, event_description, event_location ON events BEGIN
+            INSERT INTO events_fts (events_fts, rowid, event_name, event_description, event_location)
+                VALUES ('delete', old.rowid, old.event_name, old.event_description, old.event_location);
+            INSERT INTO events_fts (rowid, event_name, event_description, event_location)
+                VALUES (new.rowid, new.event_name, new.event_description, new.event_location);
+        END;
+        CREATE TRIGGER IF NOT EXISTS events_fts_delete AFTER DELETE ON events BEGIN
+            INSERT INTO events_fts (events_fts, rowid, event_name, event_description, event_location)
+                VALUES ('delete', old.rowid, old.event_name, old.event_description, old.event_location);
+        END;",
+    )
+}
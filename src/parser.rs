@@ -1,112 +1,1406 @@
-use crate::{calendar::*};
-use clap::Parser;
-use std::io;
-use std::error::Error;
+use crate::{calendar::*, cali_error::*, config::*, date_expr::*, event::*, ical::*, paths::*, search::*, store::*, tag::*, task::*, time::*, view::*};
+use clap::{Parser, Subcommand};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use std::{env, fs};
+use std::io::{self, IsTerminal, Write};
 use std::path::PathBuf;
+use terminal_size::{terminal_size, Width};
+use toml::Value;
+use uuid::Uuid;
 
 /// A parser for command line input.
-/// 
-/// Reads the `query` and `path` arguments for the search along with a 
-/// number of options from the command line.
-/// 
+///
+/// Reads a subcommand and its arguments from the command line. Event
+/// subcommands work on the default calendar unless `--calendar` is given.
+/// Without a subcommand, the view named by the `view` setting is shown.
+///
 /// # Options #
 #[doc = include_str!("../examples/help.md")]
 ///
 #[derive(Parser)]
 #[command(author, version, about = "A simple to use command line calendar.", long_about = None)]
 pub struct InputParser {
-    /// Calendar name. Selects the specified calendar to use. 
-    /// If there is not a current calendar using that name then a new one is created. 
-    /// If no name is given then the default calendar is used. 
-    /// If no default exists, then a new calendar named "default calendar" is created.
-    calendar_name: Option<String>,
-    #[arg(short, long)]
-    /// Deletes the specified calendar
-    delete: bool,
-    #[arg(short, long)]
-    /// Renames the specified calendar
-    rename: bool,
-    #[arg(short, long)]
-    /// Sets the specified calendar as default
-    set_default: bool,
+    /// The database or vdir directory to use, instead of the db setting or $XDG_DATA_HOME/cali/calendar.db
+    #[arg(long, global = true, value_name = "PATH")]
+    db: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// The subcommands accepted by `cali`.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Adds a new event
+    Add {
+        /// Title of the event
+        title: String,
+        /// When the event happens, such as "tomorrow 9:30 for 15m" or "next friday 2pm-4pm"
+        when: Vec<String>,
+        #[arg(short, long, conflicts_with = "when", required_unless_present = "when")]
+        /// Start time, such as "2023-07-24 09:30" or "tomorrow 9am"
+        start: Option<String>,
+        #[arg(short, long)]
+        /// End time. Defaults to the end given with the start, or one hour after the start
+        end: Option<String>,
+        #[arg(short, long)]
+        /// Recurrence: none, daily, weekly, monthly, yearly or an RRULE
+        recurrence: Option<String>,
+        #[arg(short = 'z', long)]
+        /// IANA time zone of the event. Defaults to the timezone setting
+        timezone: Option<String>,
+        #[arg(short, long)]
+        /// Calendar to add the event to, created if it does not exist
+        calendar: Option<String>,
+        #[arg(short, long)]
+        /// Makes the event take up whole days, such as "friday" or "jul 24 to jul 26", the same days in every time zone
+        all_day: bool,
+        #[arg(short, long)]
+        /// Notes on the event, which may run over several lines
+        description: Option<String>,
+        #[arg(short, long)]
+        /// Where the event takes place
+        location: Option<String>,
+        #[arg(short, long)]
+        /// Link to more about the event, such as a meeting URL
+        url: Option<String>,
+        #[arg(long)]
+        /// Comma separated categories, such as "work,travel"
+        categories: Option<String>,
+    },
+    /// Lists the occurrences of events within a time range
+    List {
+        #[arg(short, long)]
+        /// Start of the range, such as "monday" or "2023-07-24". Defaults to the start of today
+        from: Option<String>,
+        #[arg(short, long)]
+        /// End of the range. Defaults to seven days after the start
+        to: Option<String>,
+        #[arg(short, long)]
+        /// Calendar to list
+        calendar: Option<String>,
+        #[arg(long)]
+        /// Only list events whose title, description, location, URL or categories contain this text
+        text: Option<String>,
+        #[arg(long = "tag", value_name = "FILTER")]
+        /// Only include events whose tags match, such as "work and not urgent". Repeat to require several
+        tags: Vec<String>,
+    },
+    /// Shows the details of the events matching a title or id
+    Show {
+        /// Event id, or part of an event title
+        query: String,
+        #[arg(short, long)]
+        /// Calendar to search
+        calendar: Option<String>,
+        #[arg(long = "tag", value_name = "FILTER")]
+        /// Only include events whose tags match, such as "work and not urgent". Repeat to require several
+        tags: Vec<String>,
+    },
+    /// Searches the titles, descriptions and locations of events, best matches first
+    Search {
+        /// Words that must all appear; "quoted words" must appear together, and word* matches words starting with it
+        #[arg(required = true)]
+        query: Vec<String>,
+        #[arg(short, long)]
+        /// Calendar to search; may be given more than once. Defaults to every calendar
+        calendar: Vec<String>,
+        #[arg(short, long)]
+        /// Only include events taking place from this time on. Defaults to the start of today when --to is given
+        from: Option<String>,
+        #[arg(short, long)]
+        /// Only include events taking place before this time. Defaults to seven days after --from when it is given
+        to: Option<String>,
+        #[arg(long = "tag", value_name = "FILTER")]
+        /// Only include events whose tags match, such as "work and not urgent". Repeat to require several
+        tags: Vec<String>,
+    },
+    /// Changes an event, or some occurrences of a recurring event
+    Edit {
+        /// Event id, or part of an event title
+        query: String,
+        #[arg(long)]
+        /// New title
+        title: Option<String>,
+        #[arg(short, long)]
+        /// New start time
+        start: Option<String>,
+        #[arg(short, long)]
+        /// New end time
+        end: Option<String>,
+        #[arg(short, long, conflicts_with = "at")]
+        /// New recurrence: none, daily, weekly, monthly, yearly or an RRULE
+        recurrence: Option<String>,
+        #[arg(short, long, conflicts_with = "at")]
+        /// New notes. An empty value removes them
+        description: Option<String>,
+        #[arg(short, long, conflicts_with = "at")]
+        /// New location. An empty value removes it
+        location: Option<String>,
+        #[arg(short, long, conflicts_with = "at")]
+        /// New URL. An empty value removes it
+        url: Option<String>,
+        #[arg(long, conflicts_with = "at")]
+        /// New comma separated categories, replacing the old ones. An empty value removes them
+        categories: Option<String>,
+        #[arg(short, long)]
+        /// Only change the occurrence originally starting at this time
+        at: Option<String>,
+        #[arg(long, requires = "at")]
+        /// Also change every occurrence after the one given by `--at`
+        following: bool,
+        #[arg(short, long)]
+        /// Calendar holding the event
+        calendar: Option<String>,
+    },
+    /// Removes an event, or some occurrences of a recurring event
+    Rm {
+        /// Event id, or part of an event title
+        query: String,
+        #[arg(short, long)]
+        /// Only remove the occurrence originally starting at this time
+        at: Option<String>,
+        #[arg(long, requires = "at")]
+        /// Also remove every occurrence after the one given by `--at`
+        following: bool,
+        #[arg(short, long)]
+        /// Calendar holding the event
+        calendar: Option<String>,
+    },
+    /// Lists upcoming occurrences and tasks grouped by day, across every calendar unless one is given
+    Agenda {
+        #[arg(short, long)]
+        /// First day to list, such as "monday" or "2023-07-24". Defaults to today
+        from: Option<String>,
+        #[arg(short, long, default_value_t = 7)]
+        /// Number of days to list
+        days: u32,
+        #[arg(short, long)]
+        /// Calendar to list
+        calendar: Option<String>,
+        #[arg(long = "tag", value_name = "FILTER")]
+        /// Only include events whose tags match, such as "work and not urgent", leaving out tasks, which have no tags. Repeat to require several
+        tags: Vec<String>,
+    },
+    /// Shows a week as a timeline, across every calendar unless one is given
+    Week {
+        /// A day in the week to show, such as "next week". Defaults to today
+        date: Option<String>,
+        #[arg(long)]
+        /// Day the weeks start on. Defaults to the week_start setting
+        week_start: Option<String>,
+        #[arg(long, default_value_t = 30)]
+        /// Minutes covered by each row
+        slot: u32,
+        #[arg(short, long)]
+        /// Calendar to show
+        calendar: Option<String>,
+        #[arg(long = "tag", value_name = "FILTER")]
+        /// Only include events whose tags match, such as "work and not urgent". Repeat to require several
+        tags: Vec<String>,
+    },
+    /// Shows a day as a timeline, across every calendar unless one is given
+    Day {
+        /// Day to show, such as "tomorrow". Defaults to today
+        date: Option<String>,
+        #[arg(long, default_value_t = 30)]
+        /// Minutes covered by each row
+        slot: u32,
+        #[arg(short, long)]
+        /// Calendar to show
+        calendar: Option<String>,
+        #[arg(long = "tag", value_name = "FILTER")]
+        /// Only include events whose tags match, such as "work and not urgent". Repeat to require several
+        tags: Vec<String>,
+    },
+    /// Shows a month as a grid, marking the days that have events
+    Month {
+        /// Month to show, as YYYY-MM. Defaults to the current month
+        month: Option<String>,
+        #[arg(long)]
+        /// Day the weeks start on. Defaults to the week_start setting
+        week_start: Option<String>,
+        #[arg(short, long)]
+        /// Shows ISO week numbers
+        week_numbers: bool,
+        #[arg(short, long)]
+        /// Calendar to show
+        calendar: Option<String>,
+        #[arg(long = "tag", value_name = "FILTER")]
+        /// Only include events whose tags match, such as "work and not urgent". Repeat to require several
+        tags: Vec<String>,
+    },
+    /// Imports the events and tasks of an iCalendar (.ics) file
+    Import {
+        /// Path of the .ics file
+        file: PathBuf,
+        #[arg(short = 'z', long)]
+        /// IANA time zone for times given without one. Defaults to the timezone setting
+        timezone: Option<String>,
+        #[arg(short, long)]
+        /// Calendar to import into, created if it does not exist
+        calendar: Option<String>,
+    },
+    /// Exports the events and tasks of a calendar as an iCalendar (.ics) file
+    Export {
+        #[arg(short, long)]
+        /// Path to write the file to. Defaults to standard output
+        output: Option<PathBuf>,
+        #[arg(short, long)]
+        /// Calendar to export
+        calendar: Option<String>,
+    },
+    /// Tags events, or lists the tags in use
+    Tag {
+        #[command(subcommand)]
+        command: Option<TagCommand>,
+    },
+    /// Adds tasks and subtasks, marks them done, or lists them
+    Todo {
+        #[command(subcommand)]
+        command: Option<TodoCommand>,
+    },
+    /// Manages calendars
+    Calendars {
+        #[command(subcommand)]
+        command: Option<CalendarCommand>,
+    },
+    /// Shows and changes settings
+    Config {
+        #[command(subcommand)]
+        command: Option<ConfigCommand>,
+    },
+}
+
+/// The subcommands of `cali calendars`.
+#[derive(Subcommand)]
+pub enum CalendarCommand {
+    /// Lists every calendar
+    List,
+    /// Creates a new calendar
+    Add {
+        /// Calendar name
+        name: String,
+    },
+    /// Deletes a calendar and all of its events
+    Rm {
+        /// Calendar name
+        name: String,
+    },
+    /// Renames a calendar
+    Rename {
+        /// Current calendar name
+        name: String,
+        /// New calendar name
+        new_name: String,
+    },
+    /// Sets the calendar used when none is given
+    Default {
+        /// Calendar name
+        name: String,
+    },
+}
+
+/// The subcommands of `cali tag`.
+#[derive(Subcommand)]
+pub enum TagCommand {
+    /// Lists every tag with the number of events carrying it
+    List,
+    /// Adds tags to an event
+    Add {
+        /// Event id, or part of an event title
+        query: String,
+        /// Tags to add, such as "project-a"
+        #[arg(required = true)]
+        tags: Vec<String>,
+        #[arg(short, long)]
+        /// Calendar holding the event
+        calendar: Option<String>,
+    },
+    /// Removes tags from an event
+    Rm {
+        /// Event id, or part of an event title
+        query: String,
+        /// Tags to remove
+        #[arg(required = true)]
+        tags: Vec<String>,
+        #[arg(short, long)]
+        /// Calendar holding the event
+        calendar: Option<String>,
+    },
+}
+
+/// The subcommands of `cali todo`.
+#[derive(Subcommand)]
+pub enum TodoCommand {
+    /// Lists the open tasks, across every calendar unless one is given
+    List {
+        #[arg(short, long)]
+        /// Also lists completed and cancelled tasks
+        all: bool,
+        #[arg(short, long)]
+        /// Calendar to list
+        calendar: Option<String>,
+    },
+    /// Adds a new task
+    Add {
+        /// Title of the task
+        title: String,
+        #[arg(short, long)]
+        /// When the task is due: a day such as "friday", or a time such as "friday 5pm"
+        due: Option<String>,
+        #[arg(short, long)]
+        /// Priority from 1, the most urgent, to 9
+        priority: Option<u8>,
+        #[arg(long)]
+        /// Task to add this one as a subtask of: an id, or part of a title
+        parent: Option<String>,
+        #[arg(short, long)]
+        /// Task that has to be done first; may be given more than once
+        blocked_by: Vec<String>,
+        #[arg(short, long)]
+        /// Calendar to add the task to, created if it does not exist
+        calendar: Option<String>,
+    },
+    /// Shows the tasks with their subtasks beneath them, and what blocks them
+    Tree {
+        #[arg(short, long)]
+        /// Also shows completed and cancelled tasks
+        all: bool,
+        #[arg(short, long)]
+        /// Calendar to show
+        calendar: Option<String>,
+    },
+    /// Makes a task a subtask of another, or a top-level task again
+    Move {
+        /// Task id, or part of a task title
+        query: String,
+        /// The new parent task, or none to make the task top-level
+        parent: Option<String>,
+        #[arg(short, long)]
+        /// Calendar holding the tasks
+        calendar: Option<String>,
+    },
+    /// Notes that tasks have to be done before another
+    Block {
+        /// Task id, or part of a task title
+        query: String,
+        /// Tasks that have to be done first
+        #[arg(required = true)]
+        blockers: Vec<String>,
+        #[arg(short, long)]
+        /// Calendar holding the tasks
+        calendar: Option<String>,
+    },
+    /// Removes tasks from those blocking another
+    Unblock {
+        /// Task id, or part of a task title
+        query: String,
+        /// Tasks that no longer have to be done first
+        #[arg(required = true)]
+        blockers: Vec<String>,
+        #[arg(short, long)]
+        /// Calendar holding the tasks
+        calendar: Option<String>,
+    },
+    /// Marks a task as done, once nothing blocks it
+    Done {
+        /// Task id, or part of a task title
+        query: String,
+        #[arg(short, long)]
+        /// Calendar holding the task
+        calendar: Option<String>,
+    },
+    /// Marks a done or cancelled task as still to do
+    Undo {
+        /// Task id, or part of a task title
+        query: String,
+        #[arg(short, long)]
+        /// Calendar holding the task
+        calendar: Option<String>,
+    },
+}
+
+/// The subcommands of `cali config`.
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Lists every setting with its value and where the value comes from
+    List,
+    /// Shows the value of a setting
+    Get {
+        /// Setting name
+        name: String,
+    },
+    /// Changes a setting in the configuration file
+    Set {
+        /// Setting name
+        name: String,
+        /// New value
+        value: String,
+    },
 }
 
 /// Defines methods expected to run on `InputParser`.
 pub trait RunArgs {
     /// Executes the search process given the command line arguments.
-    fn run(&self) -> Result<(), Box<dyn Error>>;
+    fn run(&self) -> Result<(), CaliError>;
 }
 
 impl InputParser {
     /// Creates a new `InputParser`.
-    /// 
+    ///
     /// # Returns
-    /// Returns a `InputParser` containing the specified arguments.
-    /// 
+    /// Returns a `InputParser` containing the specified subcommand.
+    ///
     /// # Example
     /// ```
-    /// # use crate::cali::InputParser;
-    /// # use std::path::PathBuf;
-    /// let calendar_name = "Jon's Calendar";
-    /// let delete = false;
-    /// let rename = false;
-    /// let set_default = true;
-    /// 
-    /// let new_parser = InputParser { calendar_name,, delete, rename, set_default };
+    /// # use cali::parser::{CalendarCommand, Command, InputParser};
+    /// let command = Command::Calendars {
+    ///     command: Some(CalendarCommand::Default { name: "Jon's Calendar".to_string() }),
+    /// };
+    ///
+    /// let new_parser = InputParser::new(command);
     /// ```
-    /// 
-    pub fn new(calendar_name: Option<String>, delete: bool, rename: bool, set_default: bool) -> InputParser {
+    ///
+    pub fn new(command: Command) -> InputParser {
         InputParser {
-            calendar_name, 
-            delete,
-            rename,
-            set_default
+            db: None,
+            command: Some(command),
         }
     }
+
+    /// Executes the subcommand against the database at `path` with the
+    /// settings in `config`, writing its output to `out`.
+    ///
+    /// # Returns
+    /// Returns () if successful.
+    ///
+    pub fn execute(&self, path: &PathBuf, config: &Config, out: &mut dyn Write) -> Result<(), CaliError> {
+        let command = match &self.command {
+            Some(command) => command,
+            None => &default_command(&config.get_value("view")?),
+        };
+        if let Command::Config { command } = command {
+            return run_config_command(config, command.as_ref().unwrap_or(&ConfigCommand::List), out);
+        }
+        let store = Store::open(path)?;
+        let format = config.get_time_format()?;
+
+        match command {
+            Command::Add { title, when, start, end, recurrence, timezone, calendar, all_day, description, location, url, categories } => {
+                let calendar = Calendar::from(&calendar_name(&store, calendar)?, &store)?;
+                let timezone = match timezone {
+                    Some(name) => parse_timezone(name)?,
+                    None => config.get_timezone()?,
+                };
+                let now = now_in(timezone);
+                let recurring = match recurrence {
+                    Some(recurrence) => parse_recurrence(recurrence)?,
+                    None => Recurring::No,
+                };
+
+                let mut event = if *all_day {
+                    // An --end or second date names the last day
+                    let (first, last) = match start {
+                        Some(start) => (parse_datetime(start, &now)?.date_naive(), None),
+                        None => {
+                            let (first, last) = parse_day_range(&when.join(" "), &now)?;
+                            (first, Some(last))
+                        }
+                    };
+                    let last = match end {
+                        Some(end) => parse_datetime(end, &now)?.date_naive(),
+                        None => last.unwrap_or(first),
+                    };
+                    Event::new_all_day(title, first, last, recurring)?
+                } else {
+                    let (start, range_end) = match start {
+                        Some(start) => (parse_datetime(start, &now)?, None),
+                        None => parse_range(&when.join(" "), &now)?,
+                    };
+                    let end = match end {
+                        Some(end) => parse_datetime(end, &now)?,
+                        None => range_end.unwrap_or(start + Duration::hours(1)),
+                    };
+                    Event::new(title, start, end, recurring)?
+                };
+                update_details(&mut event, description, location, url, categories)?;
+                calendar.add_event(&event)?;
+                writeln!(out, "Added '{}' ({}) to '{}'.", event.get_name(), event.get_id(), calendar.get_name())?;
+            }
+            Command::List { from, to, calendar, text, tags } => {
+                let calendar = read_calendar(&store, calendar)?;
+                let timezone = config.get_timezone()?;
+                let now = now_in(timezone);
+                let from = parse_datetime(from.as_deref().unwrap_or("today"), &now)?;
+                let to = match to {
+                    Some(to) => parse_datetime(to, &now)?,
+                    None => from + Duration::days(7),
+                };
+
+                let occurrences = match (calendar, parse_tag_filters(tags)?) {
+                    (None, _) => Vec::new(),
+                    (Some(calendar), Some(filter)) => calendar.events_between_tagged(&from, &to, &filter)?,
+                    (Some(calendar), None) => calendar.events_between(&from, &to)?,
+                };
+                let occurrences = occurrences
+                    .into_iter()
+                    .filter(|occurrence| text.as_ref().is_none_or(|text| occurrence.get_event().contains_text(text)));
+                for occurrence in occurrences {
+                    writeln!(out, "{}", format_occurrence(&occurrence, timezone, &format))?;
+                }
+            }
+            Command::Show { query, calendar, tags } => {
+                let calendar = open_calendar(&store, calendar)?;
+                let filter = parse_tag_filters(tags)?;
+                let mut events = find_events(&calendar, query)?;
+                events.retain(|event| filter.as_ref().is_none_or(|filter| filter.matches(event.get_tags())));
+                if events.is_empty() {
+                    return Err(EventNotFoundError { query: query.to_string() }.into());
+                }
+
+                for (index, event) in events.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(out)?;
+                    }
+                    write_event_details(out, event, &format)?;
+                }
+            }
+            Command::Search { query, calendar, from, to, tags } => {
+                let query: SearchQuery = query.join(" ").parse()?;
+                let filter = parse_tag_filters(tags)?;
+                let timezone = config.get_timezone()?;
+                let now = now_in(timezone);
+                let calendars = match calendar.is_empty() {
+                    true => get_calendars(&store)?,
+                    false => calendar.iter().map(|name| open_calendar(&store, &Some(name.clone()))).collect::<Result<Vec<Calendar>, CaliError>>()?,
+                };
+                let range = match (from, to) {
+                    (None, None) => None,
+                    _ => {
+                        let from = parse_datetime(from.as_deref().unwrap_or("today"), &now)?;
+                        let to = match to {
+                            Some(to) => parse_datetime(to, &now)?,
+                            None => from + Duration::days(7),
+                        };
+                        Some((from, to))
+                    }
+                };
+
+                let color = use_color(config)?;
+                let mut found = false;
+                let hits = search_calendars(&calendars, &query)?
+                    .into_iter()
+                    .filter(|hit| filter.as_ref().is_none_or(|filter| filter.matches(hit.event.get_tags())));
+                for hit in hits {
+                    // Each event is shown by its first occurrence, within the range if one is given
+                    let occurrence = match range {
+                        Some((from, to)) => hit.event.occurrences_between(&from, &to)
+                            .into_iter()
+                            .min_by(|a, b| a.get_start().cmp(b.get_start())),
+                        None => hit.event.occurrences().next(),
+                    };
+                    if let Some(occurrence) = occurrence {
+                        write_search_hit(out, &hit, &occurrence, &query, timezone, &format, color)?;
+                        found = true;
+                    }
+                }
+                if !found {
+                    writeln!(out, "No events.")?;
+                }
+            }
+            Command::Edit { query, title, start, end, recurrence, description, location, url, categories, at, following, calendar } => {
+                let calendar = open_calendar(&store, calendar)?;
+                let mut event = find_event(&calendar, query)?;
+                let timezone = event.get_timezone();
+                let now = now_in(timezone);
+                let changes = EventChanges {
+                    name: title.clone(),
+                    start: start.as_deref().map(|start| parse_datetime(start, &now)).transpose()?,
+                    end: end.as_deref().map(|end| parse_datetime(end, &now)).transpose()?,
+                };
+                if let Some(recurrence) = recurrence {
+                    event.update_recurring(parse_recurrence(recurrence)?)?;
+                }
+                update_details(&mut event, description, location, url, categories)?;
+
+                let scope = scope(at, *following, timezone)?;
+                match calendar.update_event_scoped(&mut event, scope, &changes)? {
+                    Some(split) => writeln!(out, "Updated '{}' from {} as {}.", split.get_name(), format.format_date_time(split.get_start()), split.get_id())?,
+                    None => writeln!(out, "Updated '{}'.", event.get_name())?,
+                }
+            }
+            Command::Rm { query, at, following, calendar } => {
+                let calendar = open_calendar(&store, calendar)?;
+                let mut event = find_event(&calendar, query)?;
+                let scope = scope(at, *following, event.get_timezone())?;
+                calendar.remove_event_scoped(&mut event, scope)?;
+                writeln!(out, "Removed '{}'.", event.get_name())?;
+            }
+            Command::Agenda { from, days, calendar, tags } => {
+                let timezone = config.get_timezone()?;
+                let from = parse_datetime(from.as_deref().unwrap_or("today"), &now_in(timezone))?.date_naive();
+                let filter = parse_tag_filters(tags)?;
+                let entries = collect_entries(&store, calendar, filter.as_ref(), from, *days, timezone)?;
+                // Tasks carry no tags, so a tag filter leaves them all out
+                let tasks = match filter {
+                    Some(_) => Vec::new(),
+                    None => collect_tasks(&store, calendar)?,
+                };
+                let now = now_in(timezone);
+                let highlight = use_color(config)?;
+                write!(out, "{}", render_agenda_with_tasks(&entries, &tasks, from, *days, timezone, &format, &now, highlight))?;
+            }
+            Command::Week { date, week_start, slot, calendar, tags } => {
+                let timezone = config.get_timezone()?;
+                let date = parse_datetime(date.as_deref().unwrap_or("today"), &now_in(timezone))?.date_naive();
+                let week_start = match week_start {
+                    Some(week_start) => parse_week_start(week_start)?,
+                    None => config.get_week_start()?,
+                };
+                let offset = (date.weekday().num_days_from_monday() + 7 - week_start.num_days_from_monday()) % 7;
+                let first = date - Duration::days(offset as i64);
+                let filter = parse_tag_filters(tags)?;
+                write_timeline(out, &store, calendar, filter.as_ref(), first, 7, *slot, timezone, &format)?;
+            }
+            Command::Day { date, slot, calendar, tags } => {
+                let timezone = config.get_timezone()?;
+                let date = parse_datetime(date.as_deref().unwrap_or("today"), &now_in(timezone))?.date_naive();
+                let filter = parse_tag_filters(tags)?;
+                write_timeline(out, &store, calendar, filter.as_ref(), date, 1, *slot, timezone, &format)?;
+            }
+            Command::Month { month, week_start, week_numbers, calendar, tags } => {
+                let calendar = read_calendar(&store, calendar)?;
+                let timezone = config.get_timezone()?;
+                let today = now_in(timezone).date_naive();
+                let month = match month {
+                    Some(month) => NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+                        .map_err(|_| InvalidTimeError { reason: format!("'{}' is not a month such as 2023-07", month) })?,
+                    None => today,
+                };
+                let options = MonthOptions {
+                    week_start: match week_start {
+                        Some(week_start) => parse_week_start(week_start)?,
+                        None => config.get_week_start()?,
+                    },
+                    week_numbers: *week_numbers,
+                    highlight_today: use_color(config)?,
+                };
+
+                let (first, last) = month_bounds(month);
+                let from = start_of_day(first, timezone)?;
+                let to = start_of_day(last + Duration::days(1), timezone)?;
+                let occurrences = match (calendar, parse_tag_filters(tags)?) {
+                    (None, _) => Vec::new(),
+                    (Some(calendar), Some(filter)) => calendar.events_between_tagged(&from, &to, &filter)?,
+                    (Some(calendar), None) => calendar.events_between(&from, &to)?,
+                };
+                let (all_day, timed): (Vec<Occurrence>, Vec<Occurrence>) = occurrences
+                    .into_iter()
+                    .partition(|occurrence| occurrence.get_event().is_all_day());
+                let marked = days_with_events(&timed, timezone);
+                let all_day = days_with_events(&all_day, timezone);
+                write!(out, "{}", render_month(month, today, &marked, &all_day, &options))?;
+            }
+            Command::Import { file, timezone, calendar } => {
+                let calendar = Calendar::from(&calendar_name(&store, calendar)?, &store)?;
+                let timezone = match timezone {
+                    Some(name) => parse_timezone(name)?,
+                    None => config.get_timezone()?,
+                };
+                let text = fs::read_to_string(file)?;
+                let summary = import_ical(&calendar, &text, timezone)?;
+                writeln!(
+                    out,
+                    "Imported {} new and {} updated events into '{}'.",
+                    summary.added,
+                    summary.updated,
+                    calendar.get_name(),
+                )?;
+                if summary.tasks > 0 {
+                    writeln!(out, "Imported {} tasks.", summary.tasks)?;
+                }
+                for reason in &summary.skipped {
+                    writeln!(out, "Skipped: {}", reason)?;
+                }
+            }
+            Command::Export { output, calendar } => {
+                let calendar = open_calendar(&store, calendar)?;
+                let text = export_ical(&calendar)?;
+                match output {
+                    Some(output) => {
+                        fs::write(output, text)?;
+                        writeln!(out, "Exported '{}' to {}.", calendar.get_name(), output.display())?;
+                    }
+                    None => write!(out, "{}", text)?,
+                }
+            }
+            Command::Tag { command } => run_tag_command(&store, command.as_ref().unwrap_or(&TagCommand::List), out)?,
+            Command::Todo { command } => {
+                let list = TodoCommand::List { all: false, calendar: None };
+                run_todo_command(&store, config, command.as_ref().unwrap_or(&list), out)?;
+            }
+            Command::Calendars { command } => run_calendar_command(&store, command.as_ref().unwrap_or(&CalendarCommand::List), out)?,
+            Command::Config { .. } => (),
+        }
+
+        Ok(())
+    }
 }
 
 impl RunArgs for InputParser {
-    /// 
+    ///
     /// # Returns
     /// Returns () if successful.
-    /// 
-    fn run(&self) -> Result<(), Box<dyn Error>> {
-        let path = PathBuf::from("calendar.db");
-        init_database(&path)?;
+    ///
+    fn run(&self) -> Result<(), CaliError> {
+        let config = Config::load(Some(config_path()?))?;
+        let db = match config.get_value("db")?.as_str() {
+            "" => None,
+            db => Some(PathBuf::from(db)),
+        };
+        let path = database_path(self.db.clone().or(db))?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        self.execute(&path, &config, &mut io::stdout())
+    }
+}
+
+// Runs one of the `cali tag` subcommands
+fn run_tag_command(store: &Store, command: &TagCommand, out: &mut dyn Write) -> Result<(), CaliError> {
+    match command {
+        TagCommand::List => {
+            for (tag, count) in get_tags(store)? {
+                writeln!(out, "{} ({})", tag, count)?;
+            }
+        }
+        TagCommand::Add { query, tags, calendar } => {
+            let calendar = open_calendar(store, calendar)?;
+            let mut event = find_event(&calendar, query)?;
+            for tag in tags {
+                event.add_tag(tag)?;
+            }
+            calendar.update_event(&event)?;
+            writeln!(out, "'{}' is tagged {}.", event.get_name(), event.get_tags().join(", "))?;
+        }
+        TagCommand::Rm { query, tags, calendar } => {
+            let calendar = open_calendar(store, calendar)?;
+            let mut event = find_event(&calendar, query)?;
+            for tag in tags {
+                if !event.remove_tag(tag)? {
+                    writeln!(out, "'{}' was not tagged {}.", event.get_name(), tag)?;
+                }
+            }
+            calendar.update_event(&event)?;
+            match event.get_tags().is_empty() {
+                true => writeln!(out, "'{}' has no tags.", event.get_name())?,
+                false => writeln!(out, "'{}' is tagged {}.", event.get_name(), event.get_tags().join(", "))?,
+            }
+        }
+    }
 
-        let name: String;
-        if let None = self.calendar_name {
-            if let Some(default_name) = get_default(&path)? {
-                name = default_name;
-            } else {
-                name = "default calendar".to_string();
+    Ok(())
+}
+
+// Runs one of the `cali todo` subcommands
+fn run_todo_command(store: &Store, config: &Config, command: &TodoCommand, out: &mut dyn Write) -> Result<(), CaliError> {
+    let timezone = config.get_timezone()?;
+    let format = config.get_time_format()?;
+    let now = now_in(timezone);
+    match command {
+        TodoCommand::List { all, calendar } => {
+            let tasks = collect_tasks(store, calendar)?;
+            let tasks: Vec<&TaskEntry> = tasks.iter().filter(|entry| *all || entry.task.is_open()).collect();
+            if tasks.is_empty() {
+                writeln!(out, "No tasks.")?;
+            }
+            for entry in tasks {
+                writeln!(out, "{}", format_task(entry, timezone, &format, &now))?;
             }
-        } else {
-            name = self.calendar_name.as_ref().unwrap().to_string();
         }
+        TodoCommand::Add { title, due, priority, parent, blocked_by, calendar } => {
+            let calendar = Calendar::from(&calendar_name(store, calendar)?, store)?;
+            let due = match due {
+                Some(due) => Some(match parse_date_or_time(due, &now)? {
+                    (time, true) => Due::Time(time),
+                    (time, false) => Due::Date(time.date_naive()),
+                }),
+                None => None,
+            };
+            let mut task = Task::new(title, due);
+            if let Some(priority) = priority {
+                task.update_priority(*priority)?;
+            }
+            let parent = parent.as_ref().map(|parent| find_task(&calendar, parent)).transpose()?;
+            let blockers = blocked_by.iter().map(|blocker| find_task(&calendar, blocker)).collect::<Result<Vec<Task>, CaliError>>()?;
+            store.transaction(|_| {
+                calendar.add_subtask(&mut task, parent.as_ref())?;
+                for blocker in &blockers {
+                    calendar.block_task(&mut task, blocker)?;
+                }
+                Ok(())
+            })?;
+            match parent {
+                Some(parent) => writeln!(out, "Added task '{}' ({}) to '{}' under '{}'.", task.get_title(), task.get_id(), calendar.get_name(), parent.get_title())?,
+                None => writeln!(out, "Added task '{}' ({}) to '{}'.", task.get_title(), task.get_id(), calendar.get_name())?,
+            }
+        }
+        TodoCommand::Tree { all, calendar } => {
+            let calendars = match calendar {
+                Some(_) => vec![open_calendar(store, calendar)?],
+                None => get_calendars(store)?,
+            };
+            let mut shown = false;
+            for calendar in &calendars {
+                let tasks = get_tasks(calendar)?;
+                let tasks: Vec<Task> = match all {
+                    true => tasks,
+                    false => with_open_subtasks(tasks),
+                };
+                if tasks.is_empty() {
+                    continue;
+                }
+                writeln!(out, "{}", calendar.get_name())?;
+                write_task_tree(out, &tasks, timezone, &format, &now)?;
+                shown = true;
+            }
+            if !shown {
+                writeln!(out, "No tasks.")?;
+            }
+        }
+        TodoCommand::Move { query, parent, calendar } => {
+            let calendar = open_calendar(store, calendar)?;
+            let mut task = find_task(&calendar, query)?;
+            let parent = parent.as_ref().map(|parent| find_task(&calendar, parent)).transpose()?;
+            calendar.move_task(&mut task, parent.as_ref())?;
+            match parent {
+                Some(parent) => writeln!(out, "Moved '{}' under '{}'.", task.get_title(), parent.get_title())?,
+                None => writeln!(out, "Moved '{}' to the top level.", task.get_title())?,
+            }
+        }
+        TodoCommand::Block { query, blockers, calendar } => {
+            let calendar = open_calendar(store, calendar)?;
+            let mut task = find_task(&calendar, query)?;
+            let blockers = blockers.iter().map(|blocker| find_task(&calendar, blocker)).collect::<Result<Vec<Task>, CaliError>>()?;
+            store.transaction(|_| {
+                for blocker in &blockers {
+                    calendar.block_task(&mut task, blocker)?;
+                }
+                Ok(())
+            })?;
+            let titles: Vec<&str> = blockers.iter().map(Task::get_title).collect();
+            writeln!(out, "'{}' is now blocked by {}.", task.get_title(), titles.join(", "))?;
+        }
+        TodoCommand::Unblock { query, blockers, calendar } => {
+            let calendar = open_calendar(store, calendar)?;
+            let mut task = find_task(&calendar, query)?;
+            let blockers = blockers.iter().map(|blocker| find_task(&calendar, blocker)).collect::<Result<Vec<Task>, CaliError>>()?;
+            let unblocked = store.transaction(|_| {
+                blockers.iter().map(|blocker| calendar.unblock_task(&mut task, blocker)).collect::<Result<Vec<bool>, CaliError>>()
+            })?;
+            for (blocker, unblocked) in blockers.iter().zip(unblocked) {
+                match unblocked {
+                    true => writeln!(out, "'{}' is no longer blocked by '{}'.", task.get_title(), blocker.get_title())?,
+                    false => writeln!(out, "'{}' is not blocked by '{}'.", task.get_title(), blocker.get_title())?,
+                }
+            }
+        }
+        TodoCommand::Done { query, calendar } => {
+            let calendar = open_calendar(store, calendar)?;
+            let mut task = find_task(&calendar, query)?;
+            calendar.complete_task(&mut task, now)?;
+            writeln!(out, "Completed '{}'.", task.get_title())?;
+        }
+        TodoCommand::Undo { query, calendar } => {
+            let calendar = open_calendar(store, calendar)?;
+            let mut task = find_task(&calendar, query)?;
+            if task.is_open() {
+                writeln!(out, "'{}' is not done.", task.get_title())?;
+                return Ok(());
+            }
+            calendar.reopen_task(&mut task)?;
+            writeln!(out, "Reopened '{}'.", task.get_title())?;
+        }
+    }
 
-        let mut calendar = Calendar::from(&name, &path)?;
+    Ok(())
+}
 
-        if self.delete {
+// Runs one of the `cali calendars` subcommands
+fn run_calendar_command(store: &Store, command: &CalendarCommand, out: &mut dyn Write) -> Result<(), CaliError> {
+    match command {
+        CalendarCommand::List => {
+            for calendar in get_calendars(store)? {
+                let marker = if *calendar.get_default() { " (default)" } else { "" };
+                writeln!(out, "{}{}", calendar.get_name(), marker)?;
+            }
+            let unreadable = get_unreadable_events(store)?;
+            if !unreadable.is_empty() {
+                writeln!(out, "Set aside when the database was upgraded, as they could not be read:")?;
+            }
+            for event in unreadable {
+                writeln!(out, "  '{}' ({}) in '{}': {}", event.name, event.id, event.calendar, event.reason)?;
+            }
+        }
+        CalendarCommand::Add { name } => {
+            let calendar = Calendar::new(name, store)?;
+            writeln!(out, "'{}' was created.", calendar.get_name())?;
+        }
+        CalendarCommand::Rm { name } => {
+            let calendar = open_calendar(store, &Some(name.to_string()))?;
             remove_calendar(&calendar)?;
-            println!("'{}' was deleted.", calendar.get_name());
-            return Ok(());
+            writeln!(out, "'{}' was deleted.", calendar.get_name())?;
+        }
+        CalendarCommand::Rename { name, new_name } => {
+            let mut calendar = open_calendar(store, &Some(name.to_string()))?;
+            calendar.update_name(new_name)?;
+            writeln!(out, "'{}' was renamed to '{}'.", name, new_name)?;
         }
+        CalendarCommand::Default { name } => {
+            let calendar = open_calendar(store, &Some(name.to_string()))?;
+            update_default(store, calendar.get_name())?;
+            writeln!(out, "'{}' is now set as default.", calendar.get_name())?;
+        }
+    }
+
+    Ok(())
+}
 
-        if self.rename {
-            let mut new_name = String::new();
-            println!("Enter a new name for calendar: '{}'", calendar.get_name());
-            io::stdin().read_line(&mut new_name).expect("failed to readline");
-            new_name = new_name.trim().to_string();
-            calendar.update_name(&new_name)?;
-            println!("'{}' was renamed to '{}'.", name, new_name);
+// Runs one of the `cali config` subcommands
+fn run_config_command(config: &Config, command: &ConfigCommand, out: &mut dyn Write) -> Result<(), CaliError> {
+    match command {
+        ConfigCommand::List => {
+            for (name, _, description) in SETTINGS {
+                let (value, source) = config.get_setting(name)?;
+                writeln!(out, "{} = {}  # {} ({})", name, Value::String(value), description, source)?;
+            }
+        }
+        ConfigCommand::Get { name } => writeln!(out, "{}", config.get_value(name)?)?,
+        ConfigCommand::Set { name, value } => {
+            let mut config = config.clone();
+            config.update_setting(name, value)?;
+            config.save()?;
+            if let Some(path) = config.get_path() {
+                writeln!(out, "Set {} to '{}' in {}.", name, value, path.display())?;
+            }
+            if config.get_setting(name)?.1 == Source::Environment {
+                writeln!(out, "{} overrides this setting.", environment_variable(name))?;
+            }
         }
+    }
 
-        if self.set_default {
-            update_default(&path, calendar.get_name())?;
-            println!("'{}' is now set as default.", calendar.get_name());
+    Ok(())
+}
+
+// Builds the command showing a view named by the `view` setting
+fn default_command(view: &str) -> Command {
+    match view {
+        "week" => Command::Week { date: None, week_start: None, slot: 30, calendar: None, tags: Vec::new() },
+        "day" => Command::Day { date: None, slot: 30, calendar: None, tags: Vec::new() },
+        "month" => Command::Month { month: None, week_start: None, week_numbers: false, calendar: None, tags: Vec::new() },
+        _ => Command::Agenda { from: None, days: 7, calendar: None, tags: Vec::new() },
+    }
+}
+
+// Gets the name of the calendar to use: the one given, the default, or
+// "default calendar" when there is no default yet
+fn calendar_name(store: &Store, name: &Option<String>) -> Result<String, CaliError> {
+    match name {
+        Some(name) => Ok(name.to_string()),
+        None => Ok(get_default(store)?.unwrap_or_else(|| "default calendar".to_string())),
+    }
+}
+
+// Opens an existing calendar without creating it
+fn open_calendar(store: &Store, name: &Option<String>) -> Result<Calendar, CaliError> {
+    let name = calendar_name(store, name)?;
+    if !check_calendar(store, &name)? {
+        return Err(CalendarNotFoundError { name }.into());
+    }
+    Calendar::from(&name, store)
+}
+
+// Opens the calendar a read-only command shows. With no calendar named and
+// none created yet there is nothing to show, rather than an error.
+fn read_calendar(store: &Store, name: &Option<String>) -> Result<Option<Calendar>, CaliError> {
+    if name.is_none() && !check_calendar(store, &calendar_name(store, name)?)? {
+        return Ok(None);
+    }
+    open_calendar(store, name).map(Some)
+}
+
+// Finds the events matching an event id or part of an event title. An exact
+// title match hides events whose titles only contain the query.
+fn find_events(calendar: &Calendar, query: &str) -> Result<Vec<Event>, CaliError> {
+    if let Ok(id) = Uuid::parse_str(query) {
+        return Ok(get_event_by_id(calendar, &id)?.into_iter().collect());
+    }
+
+    let exact = get_event(calendar, query, true)?;
+    if !exact.is_empty() {
+        return Ok(exact);
+    }
+    get_event(calendar, query, false)
+}
+
+// Finds the single event matching a query
+fn find_event(calendar: &Calendar, query: &str) -> Result<Event, CaliError> {
+    let mut events = find_events(calendar, query)?;
+    match events.len() {
+        0 => Err(EventNotFoundError { query: query.to_string() }.into()),
+        1 => Ok(events.remove(0)),
+        _ => Err(AmbiguousEventError {
+            query: query.to_string(),
+            matches: events.iter().map(|event| format!("{} {}", event.get_id(), event.get_name())).collect(),
+        }.into()),
+    }
+}
+
+// Finds the tasks matching a task id or part of a task title, in any case.
+// An exact title match hides tasks whose titles only contain the query.
+fn find_tasks(calendar: &Calendar, query: &str) -> Result<Vec<Task>, CaliError> {
+    let tasks = get_tasks(calendar)?;
+    if let Ok(id) = Uuid::parse_str(query) {
+        return Ok(tasks.into_iter().filter(|task| *task.get_id() == id).collect());
+    }
+
+    let lowercase = query.to_lowercase();
+    let (exact, partial): (Vec<Task>, Vec<Task>) = tasks
+        .into_iter()
+        .filter(|task| task.get_title().to_lowercase().contains(&lowercase))
+        .partition(|task| task.get_title() == query);
+    Ok(if exact.is_empty() { partial } else { exact })
+}
+
+// Finds the single task matching a query
+fn find_task(calendar: &Calendar, query: &str) -> Result<Task, CaliError> {
+    let mut tasks = find_tasks(calendar, query)?;
+    match tasks.len() {
+        0 => Err(TaskNotFoundError { query: query.to_string() }.into()),
+        1 => Ok(tasks.remove(0)),
+        _ => Err(AmbiguousTaskError {
+            query: query.to_string(),
+            matches: tasks.iter().map(|task| format!("{} {}", task.get_id(), task.get_title())).collect(),
+        }.into()),
+    }
+}
+
+// Reads a recurrence given on the command line
+fn parse_recurrence(value: &str) -> Result<Recurring, InvalidRecurrenceError> {
+    match value.to_lowercase().as_str() {
+        "none" | "no" => Ok(Recurring::No),
+        "daily" => Ok(Recurring::Daily),
+        "weekly" => Ok(Recurring::Weekly),
+        "monthly" => Ok(Recurring::Monthly),
+        "yearly" => Ok(Recurring::Yearly),
+        _ => value.parse(),
+    }
+}
+
+// Builds the scope of an edit or removal from the `--at` and `--following` options
+fn scope(at: &Option<String>, following: bool, timezone: Tz) -> Result<Scope, CaliError> {
+    let now = now_in(timezone);
+    Ok(match at {
+        Some(at) if following => Scope::ThisAndFollowing(parse_datetime(at, &now)?),
+        Some(at) => Scope::This(parse_datetime(at, &now)?),
+        None => Scope::All,
+    })
+}
+
+// Gets the occurrences on `days` days from `from` in the named calendar, or in
+// every calendar when none is named, in order of their start. Only events
+// whose tags match `filter` are included when one is given.
+fn collect_entries(store: &Store, calendar: &Option<String>, filter: Option<&TagFilter>, from: NaiveDate, days: u32, timezone: Tz) -> Result<Vec<AgendaEntry>, CaliError> {
+    let calendars = match calendar {
+        Some(_) => vec![open_calendar(store, calendar)?],
+        None => get_calendars(store)?,
+    };
+    let start = start_of_day(from, timezone)?;
+    let end = start_of_day(from + Duration::days(days as i64), timezone)?;
+
+    let mut entries = Vec::new();
+    for calendar in &calendars {
+        let occurrences = match filter {
+            Some(filter) => calendar.events_between_tagged(&start, &end, filter)?,
+            None => calendar.events_between(&start, &end)?,
+        };
+        for occurrence in occurrences {
+            entries.push(AgendaEntry { calendar: calendar.get_name().to_string(), occurrence });
         }
+    }
+    entries.sort_by(|a, b| {
+        (a.occurrence.get_start(), a.occurrence.get_name(), &a.calendar)
+            .cmp(&(b.occurrence.get_start(), b.occurrence.get_name(), &b.calendar))
+    });
 
-        Ok(())
+    Ok(entries)
+}
+
+// Gets the tasks in the named calendar, or in every calendar when none is
+// named, in the order of compare_tasks
+fn collect_tasks(store: &Store, calendar: &Option<String>) -> Result<Vec<TaskEntry>, CaliError> {
+    let calendars = match calendar {
+        Some(_) => vec![open_calendar(store, calendar)?],
+        None => get_calendars(store)?,
+    };
+
+    let mut tasks = Vec::new();
+    for calendar in &calendars {
+        for task in get_tasks(calendar)? {
+            tasks.push(TaskEntry { calendar: calendar.get_name().to_string(), task });
+        }
+    }
+    tasks.sort_by(|a, b| compare_tasks(&a.task, &b.task).then_with(|| a.calendar.cmp(&b.calendar)));
+
+    Ok(tasks)
+}
+
+// Writes a timeline of `days` days from `from`, fitted to the terminal width
+#[allow(clippy::too_many_arguments)]
+fn write_timeline(out: &mut dyn Write, store: &Store, calendar: &Option<String>, filter: Option<&TagFilter>, from: NaiveDate, days: u32, slot: u32, timezone: Tz, format: &TimeFormat) -> Result<(), CaliError> {
+    let occurrences: Vec<Occurrence> = collect_entries(store, calendar, filter, from, days, timezone)?
+        .into_iter()
+        .map(|entry| entry.occurrence)
+        .collect();
+    let dates: Vec<NaiveDate> = (0..days).map(|offset| from + Duration::days(offset as i64)).collect();
+    let options = TimelineOptions {
+        width: terminal_width(),
+        slot_minutes: slot,
+        twelve_hour: format.twelve_hour,
+    };
+    write!(out, "{}", render_timeline(&dates, &occurrences, timezone, &options))?;
+
+    Ok(())
+}
+
+// Gets the width of the terminal standard output is written to or, when it
+// is not a terminal, from the COLUMNS environment variable
+fn terminal_width() -> usize {
+    if let Some((Width(width), _)) = terminal_size().filter(|_| io::stdout().is_terminal()) {
+        return width as usize;
+    }
+    env::var("COLUMNS").ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(TimelineOptions::default().width)
+}
+
+// Gets midnight at the start of `day` in `timezone`
+fn start_of_day(day: NaiveDate, timezone: Tz) -> Result<DateTime<Tz>, InvalidTimeError> {
+    resolve_local(timezone, &day.and_time(NaiveTime::MIN))
+        .ok_or_else(|| InvalidTimeError { reason: format!("{} has no start in {}", day, timezone) })
+}
+
+// Checks whether output should be highlighted with terminal escape codes.
+// With the color setting on auto, it is when writing to a terminal and
+// NO_COLOR is not set.
+fn use_color(config: &Config) -> Result<bool, CaliError> {
+    Ok(match config.get_value("color")?.as_str() {
+        "always" => true,
+        "never" => false,
+        _ => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    })
+}
+
+// Gets the current time in `timezone`
+fn now_in(timezone: Tz) -> DateTime<Tz> {
+    Utc::now().with_timezone(&timezone)
+}
+
+// Sets whichever details of an event were given on the command line
+fn update_details(
+    event: &mut Event,
+    description: &Option<String>,
+    location: &Option<String>,
+    url: &Option<String>,
+    categories: &Option<String>,
+) -> Result<(), CaliError> {
+    if let Some(description) = description {
+        event.update_description(Some(description))?;
+    }
+    if let Some(location) = location {
+        event.update_location(Some(location))?;
     }
-}
\ No newline at end of file
+    if let Some(url) = url {
+        event.update_url(Some(url))?;
+    }
+    if let Some(categories) = categories {
+        event.update_categories(&[categories])?;
+    }
+
+    Ok(())
+}
+
+// Formats one line of `cali list` output, read in `timezone`
+fn format_occurrence(occurrence: &Occurrence, timezone: Tz, format: &TimeFormat) -> String {
+    format!("{}  {}", format_when(occurrence, timezone, format), occurrence.get_name())
+}
+
+// Formats when an occurrence takes place, read in `timezone`. All-day
+// occurrences show only their days.
+fn format_when(occurrence: &Occurrence, timezone: Tz, format: &TimeFormat) -> String {
+    if occurrence.get_event().is_all_day() {
+        let (first, last) = occurrence_days(occurrence, timezone);
+        let days = match first == last {
+            true => format.format_date(first),
+            false => format!("{} - {}", format.format_date(first), format.format_date(last)),
+        };
+        return format!("{} (all day)", days);
+    }
+
+    let start = occurrence.get_start().with_timezone(&timezone);
+    let end = occurrence.get_end().with_timezone(&timezone);
+    let end = if end.date_naive() == start.date_naive() {
+        format.format_clock(&end)
+    } else {
+        format.format_date_time(&end)
+    };
+
+    format!("{} - {}", format.format_date_time(&start), end)
+}
+
+// Writes one event found by `cali search`: when it takes place, its title
+// and calendar, then its location and the lines of its description that
+// match, if any, with every match highlighted
+fn write_search_hit(out: &mut dyn Write, hit: &SearchHit, occurrence: &Occurrence, query: &SearchQuery, timezone: Tz, format: &TimeFormat, color: bool) -> io::Result<()> {
+    let title = highlight_matches(query, occurrence.get_name(), color);
+    writeln!(out, "{}  {}  [{}]", format_when(occurrence, timezone, format), title, hit.calendar)?;
+    if let Some(location) = hit.event.get_location().filter(|location| query.matches(location)) {
+        writeln!(out, "  at {}", highlight_matches(query, location, color))?;
+    }
+    let description = hit.event.get_description().unwrap_or_default();
+    for line in description.lines().filter(|line| query.matches(line)) {
+        writeln!(out, "  {}", highlight_matches(query, line.trim(), color))?;
+    }
+
+    Ok(())
+}
+
+// Formats one line of `cali todo list` output, such as
+// "[ ] Pay rent (priority 1)  due 2023-07-24 (overdue)  [Home]". Due times
+// are read in `timezone` and overdue is judged at `now`.
+fn format_task(entry: &TaskEntry, timezone: Tz, format: &TimeFormat, now: &DateTime<Tz>) -> String {
+    format!("{}  [{}]", format_task_line(&entry.task, timezone, format, now), entry.calendar)
+}
+
+// Formats a task without the calendar holding it
+fn format_task_line(task: &Task, timezone: Tz, format: &TimeFormat, now: &DateTime<Tz>) -> String {
+    let check = match task.get_status() {
+        TaskStatus::Completed => "[x]",
+        TaskStatus::Cancelled => "[-]",
+        TaskStatus::NeedsAction | TaskStatus::InProcess => "[ ]",
+    };
+    let mut details = Vec::new();
+    if task.get_priority() > 0 {
+        details.push(format!("priority {}", task.get_priority()));
+    }
+    if task.is_open() && task.get_percent_complete() > 0 {
+        details.push(format!("{}%", task.get_percent_complete()));
+    }
+
+    let mut line = format!("{} {}", check, task.get_title());
+    if !details.is_empty() {
+        line.push_str(&format!(" ({})", details.join(", ")));
+    }
+    match task.get_due() {
+        Some(Due::Date(day)) => line.push_str(&format!("  due {}", format.format_date(*day))),
+        Some(Due::Time(time)) => line.push_str(&format!("  due {}", format.format_date_time(&time.with_timezone(&timezone)))),
+        None => {}
+    }
+    if task.is_overdue(now) {
+        line.push_str(" (overdue)");
+    }
+    if let Some(completed) = task.get_completed() {
+        line.push_str(&format!("  done {}", format.format_date_time(&completed.with_timezone(&timezone))));
+    }
+
+    line
+}
+
+// Keeps the open tasks, and the done or cancelled ones that still have an
+// open task somewhere beneath them
+fn with_open_subtasks(tasks: Vec<Task>) -> Vec<Task> {
+    let mut kept: Vec<Uuid> = Vec::new();
+    for task in tasks.iter().filter(|task| task.is_open()) {
+        let mut current = Some(task);
+        while let Some(task) = current.filter(|task| !kept.contains(task.get_id())) {
+            kept.push(*task.get_id());
+            current = task.get_parent().and_then(|parent| tasks.iter().find(|task| task.get_id() == parent));
+        }
+    }
+
+    tasks.into_iter().filter(|task| kept.contains(task.get_id())).collect()
+}
+
+// Writes the tasks of one calendar for `cali todo tree`, each subtask
+// indented beneath its parent and followed by the open tasks blocking it
+fn write_task_tree(out: &mut dyn Write, tasks: &[Task], timezone: Tz, format: &TimeFormat, now: &DateTime<Tz>) -> io::Result<()> {
+    for (depth, task) in task_tree(tasks) {
+        let mut line = format!("{}{}", "  ".repeat(depth + 1), format_task_line(task, timezone, format, now));
+        let blockers: Vec<&str> = get_open_blockers(tasks, task).iter().map(|blocker| blocker.get_title()).collect();
+        if task.is_open() && !blockers.is_empty() {
+            line.push_str(&format!("  blocked by {}", blockers.join(", ")));
+        }
+        writeln!(out, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+// Writes the details of an event for `cali show`, read in its own time zone.
+// An all-day event shows only dates, and its first and last days. The
+// description comes last, indented, as it may run over several lines.
+fn write_event_details(out: &mut dyn Write, event: &Event, format: &TimeFormat) -> io::Result<()> {
+    let timezone = event.get_timezone();
+    let show = |time: &DateTime<Tz>| match event.is_all_day() {
+        true => format.format_date(time.date_naive()),
+        false => format.format_date_time(&time.with_timezone(&timezone)),
+    };
+    writeln!(out, "{}", event.get_name())?;
+    writeln!(out, "  id:         {}", event.get_id())?;
+    if event.is_all_day() {
+        let (first, last) = event.get_days();
+        writeln!(out, "  start:      {} (all day)", format.format_date(first))?;
+        writeln!(out, "  end:        {} (all day)", format.format_date(last))?;
+    } else {
+        writeln!(out, "  start:      {} {}", show(event.get_start()), timezone)?;
+        writeln!(out, "  end:        {} {}", show(event.get_end()), timezone)?;
+    }
+    writeln!(out, "  recurrence: {}", event.get_recurring())?;
+    if let Some(location) = event.get_location() {
+        writeln!(out, "  location:   {}", location)?;
+    }
+    if let Some(url) = event.get_url() {
+        writeln!(out, "  url:        {}", url)?;
+    }
+    if !event.get_categories().is_empty() {
+        writeln!(out, "  categories: {}", event.get_categories().join(", "))?;
+    }
+    if !event.get_tags().is_empty() {
+        writeln!(out, "  tags:       {}", event.get_tags().join(", "))?;
+    }
+    for exdate in event.get_exdates() {
+        writeln!(out, "  excluded:   {}", show(exdate))?;
+    }
+    for rdate in event.get_rdates() {
+        writeln!(out, "  added:      {}", show(rdate))?;
+    }
+    for event_override in event.get_overrides() {
+        writeln!(
+            out,
+            "  changed:    {} to {} - {}{}",
+            show(event_override.get_recurrence_id()),
+            show(event_override.get_start()),
+            show(event_override.get_end()),
+            event_override.get_name().map(|name| format!(" '{}'", name)).unwrap_or_default(),
+        )?;
+    }
+    if let Some(description) = event.get_description() {
+        writeln!(out, "  description:")?;
+        for line in description.lines() {
+            writeln!(out, "    {}", line)?;
+        }
+    }
+
+    Ok(())
+}
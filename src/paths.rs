@@ -0,0 +1,50 @@
+use crate::cali_error::*;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const DATABASE_FILE: &str = "calendar.db";
+const CONFIG_FILE: &str = "config.toml";
+
+// Gets the database to use: `db` when given, otherwise calendar.db in the
+// cali data directory
+pub fn database_path(db: Option<PathBuf>) -> Result<PathBuf, HomeDirectoryError> {
+    match db {
+        Some(db) => Ok(db),
+        None => Ok(data_directory(env::var_os("XDG_DATA_HOME"), env::var_os("HOME"))?.join(DATABASE_FILE)),
+    }
+}
+
+// Gets the configuration file to use: the CALI_CONFIG environment variable,
+// otherwise config.toml in the cali configuration directory
+pub fn config_path() -> Result<PathBuf, HomeDirectoryError> {
+    match env::var_os("CALI_CONFIG") {
+        Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => Ok(config_directory(env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"))?.join(CONFIG_FILE)),
+    }
+}
+
+// Gets the cali data directory from the values of XDG_DATA_HOME and HOME
+pub fn data_directory(xdg_data_home: Option<OsString>, home: Option<OsString>) -> Result<PathBuf, HomeDirectoryError> {
+    base_directory("XDG_DATA_HOME", xdg_data_home, home, &[".local", "share"])
+}
+
+// Gets the cali configuration directory from the values of XDG_CONFIG_HOME and HOME
+pub fn config_directory(xdg_config_home: Option<OsString>, home: Option<OsString>) -> Result<PathBuf, HomeDirectoryError> {
+    base_directory("XDG_CONFIG_HOME", xdg_config_home, home, &[".config"])
+}
+
+// Gets the cali directory under an XDG base directory, or under its default
+// in the home directory. As the XDG base directory specification requires, a
+// base directory that is empty or relative is ignored.
+fn base_directory(variable: &str, xdg: Option<OsString>, home: Option<OsString>, default: &[&str]) -> Result<PathBuf, HomeDirectoryError> {
+    let base = match xdg.map(PathBuf::from) {
+        Some(xdg) if xdg.is_absolute() => xdg,
+        _ => match home {
+            Some(home) if !home.is_empty() => default.iter().fold(PathBuf::from(home), |path, part| path.join(part)),
+            _ => return Err(HomeDirectoryError { variable: variable.to_string() }),
+        },
+    };
+
+    Ok(base.join("cali"))
+}
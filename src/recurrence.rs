@@ -0,0 +1,651 @@
+use crate::{cali_error::*, time::*};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+// Occurrences are not generated past this year
+const MAX_RECURRENCE_YEAR: i32 = 9999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Secondly,
+    Minutely,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frequency::Secondly => write!(f, "SECONDLY"),
+            Frequency::Minutely => write!(f, "MINUTELY"),
+            Frequency::Hourly => write!(f, "HOURLY"),
+            Frequency::Daily => write!(f, "DAILY"),
+            Frequency::Weekly => write!(f, "WEEKLY"),
+            Frequency::Monthly => write!(f, "MONTHLY"),
+            Frequency::Yearly => write!(f, "YEARLY"),
+        }
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = InvalidRecurrenceError;
+
+    fn from_str(value: &str) -> Result<Frequency, InvalidRecurrenceError> {
+        match value.to_ascii_uppercase().as_str() {
+            "SECONDLY" => Ok(Frequency::Secondly),
+            "MINUTELY" => Ok(Frequency::Minutely),
+            "HOURLY" => Ok(Frequency::Hourly),
+            "DAILY" => Ok(Frequency::Daily),
+            "WEEKLY" => Ok(Frequency::Weekly),
+            "MONTHLY" => Ok(Frequency::Monthly),
+            "YEARLY" => Ok(Frequency::Yearly),
+            _ => Err(invalid(format!("unknown frequency '{}'", value))),
+        }
+    }
+}
+
+// A BYDAY entry: a weekday, optionally limited to its nth (or nth from last)
+// occurrence within the month or year, e.g. "TU", "2MO" or "-1FR"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeekdayNum {
+    ordinal: Option<i32>,
+    weekday: Weekday,
+}
+
+impl WeekdayNum {
+    pub fn new(ordinal: Option<i32>, weekday: Weekday) -> WeekdayNum {
+        WeekdayNum { ordinal, weekday }
+    }
+
+    pub fn get_ordinal(&self) -> Option<i32> {
+        self.ordinal
+    }
+
+    pub fn get_weekday(&self) -> Weekday {
+        self.weekday
+    }
+}
+
+impl fmt::Display for WeekdayNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ordinal) = self.ordinal {
+            write!(f, "{}", ordinal)?;
+        }
+        write!(f, "{}", weekday_code(self.weekday))
+    }
+}
+
+impl FromStr for WeekdayNum {
+    type Err = InvalidRecurrenceError;
+
+    fn from_str(value: &str) -> Result<WeekdayNum, InvalidRecurrenceError> {
+        let split = value.len().saturating_sub(2);
+        if !value.is_char_boundary(split) {
+            return Err(invalid(format!("'{}' is not a weekday", value)));
+        }
+        let (ordinal, weekday) = value.split_at(split);
+        let weekday = parse_weekday(weekday)
+            .ok_or_else(|| invalid(format!("'{}' is not a weekday", value)))?;
+        let ordinal = match ordinal {
+            "" => None,
+            ordinal => Some(parse_number::<i32>("BYDAY", ordinal)?),
+        };
+
+        Ok(WeekdayNum { ordinal, weekday })
+    }
+}
+
+// The inclusive end of a recurrence: a date, or an instant in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    Date(NaiveDate),
+    DateTime(DateTime<Utc>),
+}
+
+impl fmt::Display for Until {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Until::Date(date) => write!(f, "{}", date.format("%Y%m%d")),
+            Until::DateTime(time) => write!(f, "{}", time.format("%Y%m%dT%H%M%SZ")),
+        }
+    }
+}
+
+impl FromStr for Until {
+    type Err = InvalidRecurrenceError;
+
+    fn from_str(value: &str) -> Result<Until, InvalidRecurrenceError> {
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+            return Ok(Until::Date(date));
+        }
+        // Local UNTIL values are only valid with floating start times, which
+        // are not supported, so they are read as UTC
+        let time = value.strip_suffix('Z').unwrap_or(value);
+        NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%S")
+            .map(|time| Until::DateTime(Utc.from_utc_datetime(&time)))
+            .map_err(|_| invalid(format!("'{}' is not a valid UNTIL value", value)))
+    }
+}
+
+/// An RFC 5545 recurrence rule (RRULE).
+///
+/// Supports FREQ, INTERVAL, COUNT, UNTIL, BYDAY (with ordinals), BYMONTHDAY,
+/// BYMONTH, BYSETPOS and WKST. Rules are usually read from their text form:
+///
+/// ```
+/// # use cali::recurrence::*;
+/// let every_other_tuesday: RRule = "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU".parse().unwrap();
+/// let last_friday: RRule = "FREQ=MONTHLY;BYDAY=-1FR".parse().unwrap();
+/// assert_eq!(last_friday.to_string(), "FREQ=MONTHLY;BYDAY=-1FR");
+/// # assert_eq!(every_other_tuesday.get_interval(), 2);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<Until>,
+    by_day: Vec<WeekdayNum>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+    by_set_pos: Vec<i32>,
+    week_start: Weekday,
+}
+
+impl RRule {
+    pub fn new(frequency: Frequency) -> RRule {
+        RRule {
+            frequency,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+            week_start: Weekday::Mon,
+        }
+    }
+
+    pub fn get_frequency(&self) -> Frequency {
+        self.frequency
+    }
+
+    pub fn get_interval(&self) -> u32 {
+        self.interval
+    }
+
+    pub fn get_count(&self) -> Option<u32> {
+        self.count
+    }
+
+    pub fn get_until(&self) -> Option<Until> {
+        self.until
+    }
+
+    pub fn get_by_day(&self) -> &[WeekdayNum] {
+        &self.by_day
+    }
+
+    pub fn get_by_month_day(&self) -> &[i32] {
+        &self.by_month_day
+    }
+
+    pub fn get_by_month(&self) -> &[u32] {
+        &self.by_month
+    }
+
+    pub fn get_by_set_pos(&self) -> &[i32] {
+        &self.by_set_pos
+    }
+
+    pub fn get_week_start(&self) -> Weekday {
+        self.week_start
+    }
+
+    // COUNT and UNTIL are mutually exclusive, so setting one clears the other
+    pub fn update_count(&mut self, count: Option<u32>) {
+        self.count = count;
+        if count.is_some() {
+            self.until = None;
+        }
+    }
+
+    pub fn update_until(&mut self, until: Option<Until>) {
+        self.until = until;
+        if until.is_some() {
+            self.count = None;
+        }
+    }
+
+    /// Iterates over the start times produced by this rule for a series that
+    /// begins at `start`. The start itself is always the first occurrence.
+    pub fn occurrences(&self, start: DateTime<Tz>) -> RuleOccurrences {
+        RuleOccurrences {
+            rule: self.with_defaults(&start),
+            start,
+            period: 0,
+            pending: VecDeque::from([start]),
+            emitted: 0,
+            finished: false,
+        }
+    }
+
+    // Checks the combinations of parts that RFC 5545 rules out
+    fn validate(&self) -> Result<(), InvalidRecurrenceError> {
+        if self.interval == 0 {
+            return Err(invalid("INTERVAL must be at least 1".to_string()));
+        }
+        if self.count.is_some() && self.until.is_some() {
+            return Err(invalid("COUNT and UNTIL cannot both be set".to_string()));
+        }
+        if self.count == Some(0) {
+            return Err(invalid("COUNT must be at least 1".to_string()));
+        }
+        let ordinal_limit = match self.frequency {
+            Frequency::Monthly => 5,
+            Frequency::Yearly if self.by_month.is_empty() => 53,
+            Frequency::Yearly => 5,
+            _ => 0,
+        };
+        for day in &self.by_day {
+            match day.ordinal {
+                Some(_) if ordinal_limit == 0 => {
+                    return Err(invalid(format!("BYDAY ordinals are not allowed with FREQ={}", self.frequency)));
+                }
+                Some(ordinal) if ordinal == 0 || ordinal.abs() > ordinal_limit => {
+                    return Err(invalid(format!("BYDAY ordinal {} is out of range", ordinal)));
+                }
+                _ => {}
+            }
+        }
+        if self.frequency == Frequency::Weekly && !self.by_month_day.is_empty() {
+            return Err(invalid("BYMONTHDAY is not allowed with FREQ=WEEKLY".to_string()));
+        }
+        if let Some(day) = self.by_month_day.iter().find(|day| **day == 0 || day.abs() > 31) {
+            return Err(invalid(format!("BYMONTHDAY {} is out of range", day)));
+        }
+        if let Some(month) = self.by_month.iter().find(|month| !(1..=12).contains(*month)) {
+            return Err(invalid(format!("BYMONTH {} is out of range", month)));
+        }
+        if let Some(position) = self.by_set_pos.iter().find(|position| **position == 0 || position.abs() > 366) {
+            return Err(invalid(format!("BYSETPOS {} is out of range", position)));
+        }
+        if !self.by_set_pos.is_empty()
+            && self.by_day.is_empty() && self.by_month_day.is_empty() && self.by_month.is_empty() {
+            return Err(invalid("BYSETPOS requires another BYxxx rule part".to_string()));
+        }
+
+        Ok(())
+    }
+
+    // Fills in the parts a rule inherits from its start when they are left
+    // out, e.g. a plain monthly rule repeats on the start's day of the month
+    fn with_defaults(&self, start: &DateTime<Tz>) -> RRule {
+        let mut rule = self.clone();
+        if rule.by_day.is_empty() && rule.by_month_day.is_empty() {
+            match rule.frequency {
+                Frequency::Yearly => {
+                    if rule.by_month.is_empty() {
+                        rule.by_month.push(start.month());
+                    }
+                    rule.by_month_day.push(start.day() as i32);
+                }
+                Frequency::Monthly => rule.by_month_day.push(start.day() as i32),
+                Frequency::Weekly => rule.by_day.push(WeekdayNum::new(None, start.weekday())),
+                _ => {}
+            }
+        }
+        rule
+    }
+
+    // Checks a date against the BYMONTH, BYMONTHDAY and BYDAY parts
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !self.by_month.is_empty() && !self.by_month.contains(&date.month()) {
+            return false;
+        }
+        if !self.by_month_day.is_empty() {
+            let day = date.day() as i32;
+            let last = days_in_month(date) as i32;
+            if !self.by_month_day.iter().any(|wanted| *wanted == day || *wanted == day - last - 1) {
+                return false;
+            }
+        }
+        if !self.by_day.is_empty() && !self.by_day.iter().any(|wanted| self.matches_weekday(wanted, date)) {
+            return false;
+        }
+
+        true
+    }
+
+    // Ordinals count within the month for monthly rules and for yearly rules
+    // limited to certain months, and within the whole year otherwise
+    fn matches_weekday(&self, wanted: &WeekdayNum, date: NaiveDate) -> bool {
+        if date.weekday() != wanted.weekday {
+            return false;
+        }
+        let ordinal = match wanted.ordinal {
+            Some(ordinal) => ordinal,
+            None => return true,
+        };
+        let (position, length) = if self.frequency == Frequency::Monthly || !self.by_month.is_empty() {
+            (date.day0() as i32, days_in_month(date) as i32)
+        } else {
+            (date.ordinal0() as i32, days_in_year(date.year()) as i32)
+        };
+        if ordinal > 0 {
+            position / 7 + 1 == ordinal
+        } else {
+            (length - 1 - position) / 7 + 1 == -ordinal
+        }
+    }
+
+    // Keeps only the BYSETPOS positions of a sorted set of candidates
+    fn select_positions(&self, candidates: Vec<DateTime<Tz>>) -> Vec<DateTime<Tz>> {
+        if self.by_set_pos.is_empty() {
+            return candidates;
+        }
+        let length = candidates.len() as i32;
+        let mut selected: Vec<DateTime<Tz>> = self.by_set_pos
+            .iter()
+            .map(|position| if *position > 0 { position - 1 } else { length + position })
+            .filter(|index| (0..length).contains(index))
+            .map(|index| candidates[index as usize])
+            .collect();
+        selected.sort();
+        selected.dedup();
+        selected
+    }
+}
+
+impl fmt::Display for RRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FREQ={}", self.frequency)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until)?;
+        }
+        write_list(f, "BYMONTH", &self.by_month)?;
+        write_list(f, "BYMONTHDAY", &self.by_month_day)?;
+        write_list(f, "BYDAY", &self.by_day)?;
+        write_list(f, "BYSETPOS", &self.by_set_pos)?;
+        if self.week_start != Weekday::Mon {
+            write!(f, ";WKST={}", weekday_code(self.week_start))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RRule {
+    type Err = InvalidRecurrenceError;
+
+    fn from_str(value: &str) -> Result<RRule, InvalidRecurrenceError> {
+        let value = value.trim();
+        let value = match value.get(..6) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("RRULE:") => &value[6..],
+            _ => value,
+        };
+
+        let mut rule = RRule::new(Frequency::Daily);
+        let mut seen: Vec<String> = Vec::new();
+        for part in value.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("'{}' is not a NAME=VALUE pair", part)))?;
+            let name = name.to_ascii_uppercase();
+            if seen.contains(&name) {
+                return Err(invalid(format!("{} appears more than once", name)));
+            }
+            match name.as_str() {
+                "FREQ" => rule.frequency = value.parse()?,
+                "INTERVAL" => rule.interval = parse_number(&name, value)?,
+                "COUNT" => rule.count = Some(parse_number(&name, value)?),
+                "UNTIL" => rule.until = Some(value.parse()?),
+                "BYDAY" => rule.by_day = parse_list(value, str::parse)?,
+                "BYMONTHDAY" => rule.by_month_day = parse_list(value, |day| parse_number(&name, day))?,
+                "BYMONTH" => rule.by_month = parse_list(value, |month| parse_number(&name, month))?,
+                "BYSETPOS" => rule.by_set_pos = parse_list(value, |position| parse_number(&name, position))?,
+                "WKST" => {
+                    rule.week_start = parse_weekday(value)
+                        .ok_or_else(|| invalid(format!("'{}' is not a weekday", value)))?;
+                }
+                _ => return Err(invalid(format!("unsupported rule part '{}'", name))),
+            }
+            seen.push(name);
+        }
+        if !seen.iter().any(|name| name == "FREQ") {
+            return Err(invalid("FREQ is required".to_string()));
+        }
+        rule.validate()?;
+
+        Ok(rule)
+    }
+}
+
+/// Iterates over the start times of a recurrence rule in chronological order.
+///
+/// Candidates are generated one period (year, month, week, day, ...) at a time,
+/// filtered by the BYxxx parts and then limited by BYSETPOS, COUNT and UNTIL.
+pub struct RuleOccurrences {
+    rule: RRule,
+    start: DateTime<Tz>,
+    period: i64,
+    pending: VecDeque<DateTime<Tz>>,
+    emitted: u32,
+    finished: bool,
+}
+
+impl Iterator for RuleOccurrences {
+    type Item = DateTime<Tz>;
+
+    fn next(&mut self) -> Option<DateTime<Tz>> {
+        loop {
+            if self.rule.count.is_some_and(|count| self.emitted >= count) {
+                return None;
+            }
+            if let Some(candidate) = self.pending.pop_front() {
+                if self.is_past_until(&candidate) {
+                    self.finished = true;
+                    self.pending.clear();
+                    return None;
+                }
+                self.emitted += 1;
+                return Some(candidate);
+            }
+            if self.finished {
+                return None;
+            }
+            self.expand_period();
+        }
+    }
+}
+
+impl RuleOccurrences {
+    fn is_past_until(&self, time: &DateTime<Tz>) -> bool {
+        match self.rule.until {
+            None => false,
+            Some(Until::Date(date)) => time.date_naive() > date,
+            Some(Until::DateTime(until)) => time.with_timezone(&Utc) > until,
+        }
+    }
+
+    // Generates the candidates of the next period and queues those after the start
+    fn expand_period(&mut self) {
+        let period = self.period;
+        self.period += 1;
+        let interval = self.rule.interval as i64;
+        let timezone = self.start.timezone();
+        let local = self.start.naive_local();
+
+        let dates: Vec<NaiveDate> = match self.rule.frequency {
+            Frequency::Yearly => {
+                let year = local.year() as i64 + period * interval;
+                match i32::try_from(year).ok().and_then(|year| NaiveDate::from_ymd_opt(year, 1, 1)) {
+                    Some(first) => days_from(first, days_in_year(first.year())),
+                    None => Vec::new(),
+                }
+            }
+            Frequency::Monthly => {
+                let month_index = local.year() as i64 * 12 + local.month0() as i64 + period * interval;
+                let first = i32::try_from(month_index.div_euclid(12)).ok().and_then(|year| {
+                    NaiveDate::from_ymd_opt(year, month_index.rem_euclid(12) as u32 + 1, 1)
+                });
+                match first {
+                    Some(first) => days_from(first, days_in_month(first)),
+                    None => Vec::new(),
+                }
+            }
+            Frequency::Weekly => {
+                let offset = (7 + local.weekday().num_days_from_monday() as i64
+                    - self.rule.week_start.num_days_from_monday() as i64) % 7;
+                let first = local.date().checked_sub_signed(Duration::days(offset))
+                    .and_then(|week| week.checked_add_signed(Duration::weeks(period * interval)));
+                match first {
+                    Some(first) => days_from(first, 7),
+                    None => Vec::new(),
+                }
+            }
+            Frequency::Daily => local.date()
+                .checked_add_signed(Duration::days(period * interval))
+                .into_iter()
+                .collect(),
+            Frequency::Hourly | Frequency::Minutely | Frequency::Secondly => {
+                return self.expand_instant(period);
+            }
+        };
+
+        let past_until = match (dates.first(), self.rule.until) {
+            (Some(first), Some(Until::Date(until))) => *first > until,
+            (Some(first), Some(Until::DateTime(until))) => *first > until.date_naive() + Duration::days(1),
+            _ => false,
+        };
+        if dates.first().is_none_or(|first| first.year() > MAX_RECURRENCE_YEAR) || past_until {
+            self.finished = true;
+            return;
+        }
+
+        let mut candidates: Vec<DateTime<Tz>> = dates
+            .into_iter()
+            .filter(|date| self.rule.matches_date(*date))
+            .filter_map(|date| resolve_local(timezone, &date.and_time(local.time())))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        let start = self.start;
+        self.pending.extend(self.rule.select_positions(candidates).into_iter().filter(|time| *time > start));
+    }
+
+    // Sub-daily frequencies step by a fixed duration from the start. Steps on
+    // dates the BYxxx parts exclude are skipped a whole day at a time.
+    fn expand_instant(&mut self, period: i64) {
+        let step = match self.rule.frequency {
+            Frequency::Hourly => 3600,
+            Frequency::Minutely => 60,
+            _ => 1,
+        } * self.rule.interval as i64;
+        let instant = match period.checked_mul(step).and_then(|seconds| {
+            self.start.checked_add_signed(Duration::seconds(seconds))
+        }) {
+            Some(instant) => instant,
+            None => {
+                self.finished = true;
+                return;
+            }
+        };
+        if instant.year() > MAX_RECURRENCE_YEAR || self.is_past_until(&instant) {
+            self.finished = true;
+            return;
+        }
+
+        let date = instant.date_naive();
+        if self.rule.matches_date(date) {
+            if instant > self.start && !self.rule.select_positions(vec![instant]).is_empty() {
+                self.pending.push_back(instant);
+            }
+            return;
+        }
+        let next_day = date.succ_opt()
+            .and_then(|day| resolve_local(instant.timezone(), &day.and_hms_opt(0, 0, 0)?));
+        if let Some(next_day) = next_day {
+            let skipped = ((next_day - instant).num_seconds() + step - 1) / step;
+            self.period = period + skipped.max(1);
+        }
+    }
+}
+
+fn invalid(reason: String) -> InvalidRecurrenceError {
+    InvalidRecurrenceError { reason }
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, InvalidRecurrenceError> {
+    value.trim_start_matches('+')
+        .parse()
+        .map_err(|_| invalid(format!("'{}' is not a valid {} value", value, name)))
+}
+
+fn parse_list<T, F>(value: &str, parse: F) -> Result<Vec<T>, InvalidRecurrenceError>
+where
+    F: Fn(&str) -> Result<T, InvalidRecurrenceError>,
+{
+    value.split(',').map(|item| parse(item.trim())).collect()
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, name: &str, values: &[T]) -> fmt::Result {
+    if values.is_empty() {
+        return Ok(());
+    }
+    let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    write!(f, ";{}={}", name, values.join(","))
+}
+
+// Two-letter weekday codes used by RFC 5545
+pub fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+pub fn parse_weekday(code: &str) -> Option<Weekday> {
+    match code.to_ascii_uppercase().as_str() {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn days_from(first: NaiveDate, days: u32) -> Vec<NaiveDate> {
+    first.iter_days().take(days as usize).collect()
+}
+
+pub fn days_in_month(date: NaiveDate) -> u32 {
+    let (year, month) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|next| next.pred_opt())
+        .map_or(31, |last| last.day())
+}
+
+fn days_in_year(year: i32) -> u32 {
+    if NaiveDate::from_ymd_opt(year, 2, 29).is_some() { 366 } else { 365 }
+}
@@ -0,0 +1,216 @@
+use crate::{calendar::*, cali_error::*, event::*};
+use std::str::FromStr;
+
+// How much a match counts in each field searched, the title most of all
+pub const TITLE_WEIGHT: f64 = 10.0;
+pub const LOCATION_WEIGHT: f64 = 5.0;
+pub const DESCRIPTION_WEIGHT: f64 = 1.0;
+
+// Words that must appear next to each other, in order, in one field. The
+// last may be only the start of a word when the term is a prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchTerm {
+    words: Vec<String>,
+    prefix: bool,
+}
+
+impl SearchTerm {
+    // Gets the words of the term, in lowercase
+    pub fn get_words(&self) -> &[String] {
+        &self.words
+    }
+
+    pub fn is_prefix(&self) -> bool {
+        self.prefix
+    }
+
+    // Finds where the term appears among the words of a text, as byte ranges
+    fn find(&self, text: &[Word]) -> Vec<(usize, usize)> {
+        let last = self.words.len() - 1;
+        text.windows(self.words.len())
+            .filter(|window| window.iter().zip(&self.words).enumerate().all(|(index, (word, wanted))| {
+                match self.prefix && index == last {
+                    true => word.text.starts_with(wanted.as_str()),
+                    false => word.text == *wanted,
+                }
+            }))
+            .map(|window| (window[0].start, window[last].end))
+            .collect()
+    }
+}
+
+// A full-text search for the events whose title, description or location
+// hold every one of its terms, such as `budget "design review" confer*`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    terms: Vec<SearchTerm>,
+}
+
+impl SearchQuery {
+    pub fn get_terms(&self) -> &[SearchTerm] {
+        &self.terms
+    }
+
+    // Writes the query in the syntax of an SQLite FTS5 MATCH, with each term
+    // as a quoted phrase so that no word is read as an operator
+    pub fn to_fts(&self) -> String {
+        self.terms.iter()
+            .map(|term| format!("\"{}\"{}", term.words.join(" "), if term.prefix { " *" } else { "" }))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    // Scores how well an event matches: every match counts the weight of the
+    // field it is in. Gets None when a term is found in no field.
+    pub fn score(&self, event: &Event) -> Option<f64> {
+        let fields = [
+            (Some(event.get_name()), TITLE_WEIGHT),
+            (event.get_description(), DESCRIPTION_WEIGHT),
+            (event.get_location(), LOCATION_WEIGHT),
+        ];
+        let fields: Vec<(Vec<Word>, f64)> = fields.iter()
+            .filter_map(|(text, weight)| text.map(|text| (split_words(text), *weight)))
+            .collect();
+
+        let mut score = 0.0;
+        for term in &self.terms {
+            let term_score: f64 = fields.iter().map(|(words, weight)| term.find(words).len() as f64 * weight).sum();
+            if term_score == 0.0 {
+                return None;
+            }
+            score += term_score;
+        }
+
+        Some(score)
+    }
+
+    // Checks whether any term of the query appears in `text`
+    pub fn matches(&self, text: &str) -> bool {
+        let words = split_words(text);
+        self.terms.iter().any(|term| !term.find(&words).is_empty())
+    }
+
+    // Puts `before` and `after` around every part of `text` that a term matches
+    pub fn highlight(&self, text: &str, before: &str, after: &str) -> String {
+        let words = split_words(text);
+        let mut spans: Vec<(usize, usize)> = self.terms.iter().flat_map(|term| term.find(&words)).collect();
+        spans.sort();
+
+        // Overlapping matches are highlighted as one
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in spans {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        let mut highlighted = String::new();
+        let mut position = 0;
+        for (start, end) in merged {
+            highlighted.push_str(&text[position..start]);
+            highlighted.push_str(before);
+            highlighted.push_str(&text[start..end]);
+            highlighted.push_str(after);
+            position = end;
+        }
+        highlighted.push_str(&text[position..]);
+
+        highlighted
+    }
+}
+
+// Reads a query of words, which must all appear. Words in double quotes
+// must appear together as a phrase, and a word or phrase followed by "*"
+// matches words that start with it.
+impl FromStr for SearchQuery {
+    type Err = InvalidSearchError;
+
+    fn from_str(value: &str) -> Result<SearchQuery, InvalidSearchError> {
+        let mut terms = Vec::new();
+        let mut rest = value.trim_start();
+        while !rest.is_empty() {
+            let (text, after) = match rest.strip_prefix('"') {
+                Some(quoted) => match quoted.find('"') {
+                    Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                    None => return Err(InvalidSearchError { reason: format!("'{}' has no closing quote", value) }),
+                },
+                None => {
+                    let end = rest.find(|c: char| c.is_whitespace() || c == '"').unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            let words: Vec<String> = split_words(text).into_iter().map(|word| word.text).collect();
+            let prefix = text.ends_with('*') || after.starts_with('*');
+            if !words.is_empty() {
+                terms.push(SearchTerm { words, prefix });
+            }
+            rest = after.trim_start_matches('*').trim_start();
+        }
+
+        match terms.is_empty() {
+            true => Err(InvalidSearchError { reason: format!("'{}' has no words to search for", value) }),
+            false => Ok(SearchQuery { terms }),
+        }
+    }
+}
+
+// One word of a text, in lowercase, and where it is in the text
+#[derive(Debug)]
+struct Word {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+// Splits a text into words of letters and digits, as the FTS5 unicode61
+// tokenizer does, so that every backend finds the same matches
+fn split_words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(first)) => {
+                words.push(Word { start: first, end: index, text: text[first..index].to_lowercase() });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+// An event found by a search, with the calendar holding it and its score,
+// which is higher the better it matches. Scores are on the scale of the
+// store's backend and are only compared within one store.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub calendar: String,
+    pub event: Event,
+    pub rank: f64,
+}
+
+// Searches the events of a calendar, in no particular order
+pub fn search_events(calendar: &Calendar, query: &SearchQuery) -> Result<Vec<(Event, f64)>, CaliError> {
+    calendar.get_store().get_backend().search_events(calendar.get_id(), query)
+}
+
+// Searches the events of every calendar given, best matches first, and
+// earlier events first among equal matches
+pub fn search_calendars(calendars: &[Calendar], query: &SearchQuery) -> Result<Vec<SearchHit>, CaliError> {
+    let mut hits = Vec::new();
+    for calendar in calendars {
+        for (event, rank) in search_events(calendar, query)? {
+            hits.push(SearchHit { calendar: calendar.get_name().to_string(), event, rank });
+        }
+    }
+    hits.sort_by(|a, b| {
+        b.rank.total_cmp(&a.rank)
+            .then_with(|| a.event.get_start().cmp(b.event.get_start()))
+            .then_with(|| a.event.get_name().cmp(b.event.get_name()))
+    });
+
+    Ok(hits)
+}
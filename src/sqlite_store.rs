@@ -0,0 +1,626 @@
+use crate::{calendar::*, cali_error::*, event::*, migration::*, search::*, store::*, task::*, time::*};
+use std::path::PathBuf;
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
+use rusqlite::{params, Connection, OptionalExtension, Params, Result, Row};
+use uuid::Uuid;
+
+// Calendars kept in an SQLite database
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl SqliteStore {
+    // Opens the database at `path`, creating or upgrading its schema
+    pub fn open(path: &PathBuf) -> Result<SqliteStore, CaliError> {
+        let mut conn = open_database(path)?;
+        migrate(&mut conn)?;
+
+        Ok(SqliteStore {
+            conn,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn get_connection(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl CalendarStore for SqliteStore {
+    fn get_path(&self) -> Option<&PathBuf> {
+        Some(&self.path)
+    }
+
+    fn get_calendars(&self) -> Result<Vec<CalendarRecord>, CaliError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT calendar_id, calendar_name, is_default FROM calendars ORDER BY calendar_name",
+        )?;
+        let calendar_iter = stmt.query_map(params![], |row| {
+            Ok(CalendarRecord {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                default: row.get(2)?,
+            })
+        })?;
+
+        let mut calendars = Vec::new();
+        for calendar in calendar_iter {
+            calendars.push(calendar?);
+        }
+
+        Ok(calendars)
+    }
+
+    fn insert_calendar(&self, name: &str, default: bool) -> Result<i64, CaliError> {
+        self.conn.execute(
+            "INSERT INTO calendars (calendar_name, is_default) VALUES (?1, ?2)",
+            params![name, default],
+        )?;
+
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn rename_calendar(&self, id: i64, new_name: &str) -> Result<(), CaliError> {
+        self.conn.execute(
+            "UPDATE calendars SET calendar_name = ?2 WHERE calendar_id = ?1",
+            params![id, new_name],
+        )?;
+
+        Ok(())
+    }
+
+    // Removes a calendar and, through the cascade, all of its events and tasks
+    fn remove_calendar(&self, id: i64) -> Result<(), CaliError> {
+        let tag_ids = select_tag_ids(
+            &self.conn,
+            "SELECT DISTINCT tag_id FROM event_tags JOIN events USING (event_id) WHERE calendar_id = ?1",
+            params![id],
+        )?;
+        self.conn.execute("DELETE FROM calendars WHERE calendar_id = ?1", params![id])?;
+        remove_unused_tags(&self.conn, &tag_ids)?;
+
+        Ok(())
+    }
+
+    fn update_default(&self, id: i64) -> Result<(), CaliError> {
+        self.conn.execute("UPDATE calendars SET is_default = (calendar_id = ?1)", params![id])?;
+
+        Ok(())
+    }
+
+    fn insert_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError> {
+        self.conn.execute(
+            "INSERT INTO events (event_id, calendar_id, event_name, event_start, event_end, event_timezone, event_recurring, event_uid, event_all_day,
+                event_description, event_location, event_url, event_categories)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                event.get_id().to_string(),
+                calendar_id,
+                event.get_name().to_string(),
+                to_storage_time(event.get_start()),
+                to_storage_time(event.get_end()),
+                event.get_timezone().name(),
+                event.get_recurring().to_string(),
+                event.get_uid(),
+                event.is_all_day(),
+                event.get_description(),
+                event.get_location(),
+                event.get_url(),
+                event.get_categories().join(","),
+                ],
+        )?;
+        save_event_exceptions(&self.conn, event)?;
+        save_event_tags(&self.conn, event)?;
+
+        Ok(())
+    }
+
+    fn update_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError> {
+        self.conn.execute(
+            "UPDATE events
+                SET calendar_id = ?1,
+                event_name = ?3,
+                event_start = ?4,
+                event_end = ?5,
+                event_timezone = ?6,
+                event_recurring = ?7,
+                event_uid = ?8,
+                event_all_day = ?9,
+                event_description = ?10,
+                event_location = ?11,
+                event_url = ?12,
+                event_categories = ?13
+            WHERE event_id = ?2",
+            params![
+                calendar_id,
+                event.get_id().to_string(),
+                event.get_name().to_string(),
+                to_storage_time(event.get_start()),
+                to_storage_time(event.get_end()),
+                event.get_timezone().name(),
+                event.get_recurring().to_string(),
+                event.get_uid(),
+                event.is_all_day(),
+                event.get_description(),
+                event.get_location(),
+                event.get_url(),
+                event.get_categories().join(","),
+                ],
+        )?;
+        save_event_exceptions(&self.conn, event)?;
+        save_event_tags(&self.conn, event)?;
+
+        Ok(())
+    }
+
+    fn remove_event(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError> {
+        let tag_ids = select_tag_ids(
+            &self.conn,
+            "SELECT tag_id FROM event_tags WHERE event_id = ?1",
+            params![id.to_string()],
+        )?;
+        self.conn.execute(
+            "DELETE FROM events WHERE calendar_id = ?1 AND event_id = ?2",
+            params![calendar_id, id.to_string()],
+        )?;
+        remove_unused_tags(&self.conn, &tag_ids)?;
+
+        Ok(())
+    }
+
+    fn get_events(&self, calendar_id: i64) -> Result<Vec<Event>, CaliError> {
+        query_events(
+            &self.conn,
+            "SELECT * FROM events WHERE calendar_id = ?1 ORDER BY event_start, event_name",
+            params![calendar_id],
+        )
+    }
+
+    fn check_event(&self, id: &Uuid) -> Result<bool, CaliError> {
+        let check_id: Option<String> = self.conn.query_row(
+            "SELECT event_id FROM events WHERE event_id = ?1",
+            params![id.to_string()],
+            |row| row.get(0),
+        ).optional()?;
+
+        Ok(check_id.is_some())
+    }
+
+    fn insert_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError> {
+        let (due, due_timezone) = due_columns(task.get_due());
+        self.conn.execute(
+            "INSERT INTO tasks (task_id, calendar_id, task_title, task_due, task_due_timezone, task_priority, task_status,
+                task_percent_complete, task_completed, task_uid, task_parent_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                task.get_id().to_string(),
+                calendar_id,
+                task.get_title(),
+                due,
+                due_timezone,
+                task.get_priority(),
+                task.get_status().to_string(),
+                task.get_percent_complete(),
+                task.get_completed().map(to_storage_time),
+                task.get_uid(),
+                task.get_parent().map(Uuid::to_string),
+                ],
+        )?;
+        save_task_blockers(&self.conn, task)?;
+
+        Ok(())
+    }
+
+    fn update_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError> {
+        let (due, due_timezone) = due_columns(task.get_due());
+        self.conn.execute(
+            "UPDATE tasks
+                SET calendar_id = ?1,
+                task_title = ?3,
+                task_due = ?4,
+                task_due_timezone = ?5,
+                task_priority = ?6,
+                task_status = ?7,
+                task_percent_complete = ?8,
+                task_completed = ?9,
+                task_uid = ?10,
+                task_parent_id = ?11
+            WHERE task_id = ?2",
+            params![
+                calendar_id,
+                task.get_id().to_string(),
+                task.get_title(),
+                due,
+                due_timezone,
+                task.get_priority(),
+                task.get_status().to_string(),
+                task.get_percent_complete(),
+                task.get_completed().map(to_storage_time),
+                task.get_uid(),
+                task.get_parent().map(Uuid::to_string),
+                ],
+        )?;
+        save_task_blockers(&self.conn, task)?;
+
+        Ok(())
+    }
+
+    fn remove_task(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError> {
+        self.conn.execute(
+            "DELETE FROM tasks WHERE calendar_id = ?1 AND task_id = ?2",
+            params![calendar_id, id.to_string()],
+        )?;
+
+        Ok(())
+    }
+
+    fn get_tasks(&self, calendar_id: i64) -> Result<Vec<Task>, CaliError> {
+        let mut stmt = self.conn.prepare("SELECT * FROM tasks WHERE calendar_id = ?1")?;
+        let mut rows = stmt.query(params![calendar_id])?;
+
+        let mut tasks = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mut task = task_from_row(row)?;
+            load_task_blockers(&self.conn, &mut task)?;
+            tasks.push(task);
+        }
+
+        Ok(tasks)
+    }
+
+    fn check_task(&self, id: &Uuid) -> Result<bool, CaliError> {
+        let check_id: Option<String> = self.conn.query_row(
+            "SELECT task_id FROM tasks WHERE task_id = ?1",
+            params![id.to_string()],
+            |row| row.get(0),
+        ).optional()?;
+
+        Ok(check_id.is_some())
+    }
+
+    // Runs the operation inside a savepoint, so transactions may be nested
+    // and a failing inner one only undoes its own changes
+    fn transaction(&self, operation: &mut dyn FnMut() -> Result<(), CaliError>) -> Result<(), CaliError> {
+        self.conn.execute_batch("SAVEPOINT cali")?;
+        match operation() {
+            Ok(()) => {
+                self.conn.execute_batch("RELEASE cali")?;
+                Ok(())
+            }
+            Err(e) => {
+                self.conn.execute_batch("ROLLBACK TO cali; RELEASE cali")?;
+                Err(e)
+            }
+        }
+    }
+
+    fn get_unreadable_events(&self) -> Result<Vec<UnreadableEvent>, CaliError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT event_id, calendar_name, event_name, reason
+            FROM unreadable_events JOIN calendars USING (calendar_id)
+            ORDER BY calendar_name, event_name",
+        )?;
+        let events = stmt.query_map(params![], |row| {
+            Ok(UnreadableEvent {
+                id: row.get(0)?,
+                calendar: row.get(1)?,
+                name: row.get(2)?,
+                reason: row.get(3)?,
+            })
+        })?;
+
+        Ok(events.collect::<Result<Vec<UnreadableEvent>>>()?)
+    }
+
+    fn get_events_named(&self, calendar_id: i64, name: &str, exact: bool) -> Result<Vec<Event>, CaliError> {
+        let get_query = if exact {
+            "SELECT * FROM events WHERE calendar_id = ?1 AND event_name = ?2"
+        } else {
+            "SELECT * FROM events WHERE calendar_id = ?1 AND event_name LIKE ?2"
+        };
+
+        let event_name = if exact {
+            name.to_string()
+        } else {
+            format!("%{}%", name)
+        };
+
+        query_events(&self.conn, get_query, params![calendar_id, event_name])
+    }
+
+    fn get_events_containing(&self, calendar_id: i64, text: &str) -> Result<Vec<Event>, CaliError> {
+        query_events(
+            &self.conn,
+            "SELECT * FROM events
+            WHERE calendar_id = ?1 AND (event_name LIKE ?2 OR event_description LIKE ?2
+                OR event_location LIKE ?2 OR event_url LIKE ?2 OR event_categories LIKE ?2)",
+            params![calendar_id, format!("%{}%", text)],
+        )
+    }
+
+    fn get_event_by_id(&self, calendar_id: i64, id: &Uuid) -> Result<Option<Event>, CaliError> {
+        let mut events = query_events(
+            &self.conn,
+            "SELECT * FROM events WHERE calendar_id = ?1 AND event_id = ?2",
+            params![calendar_id, id.to_string()],
+        )?;
+
+        Ok(events.pop())
+    }
+
+    // Finds the events through the events_fts index, scored by bm25. The
+    // fields are weighted as in the other backends, but bm25 also counts how
+    // rare each word is, so its scores only compare with each other and not
+    // with those of another backend.
+    fn search_events(&self, calendar_id: i64, query: &SearchQuery) -> Result<Vec<(Event, f64)>, CaliError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT events.*, -bm25(events_fts, ?3, ?4, ?5) AS search_rank
+            FROM events_fts JOIN events ON events.rowid = events_fts.rowid
+            WHERE events_fts MATCH ?2 AND events.calendar_id = ?1",
+        )?;
+        let mut rows = stmt.query(params![calendar_id, query.to_fts(), TITLE_WEIGHT, DESCRIPTION_WEIGHT, LOCATION_WEIGHT])?;
+
+        let mut hits = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mut event = event_from_row(row)?;
+            load_event_exceptions(&self.conn, &mut event)?;
+            load_event_tags(&self.conn, &mut event)?;
+            hits.push((event, row.get("search_rank")?));
+        }
+
+        Ok(hits)
+    }
+
+    fn get_events_overlapping(&self, calendar_id: i64, start: &DateTime<Tz>, end: &DateTime<Tz>) -> Result<Vec<Event>, CaliError> {
+        // Recurring events may repeat into the window however long ago they
+        // began, and added or moved occurrences may land in it from anywhere
+        query_events(
+            &self.conn,
+            "SELECT * FROM events
+            WHERE calendar_id = ?1 AND (
+                (event_start < ?3 AND (event_end >= ?2 OR event_recurring <> 'No'))
+                OR event_id IN (SELECT event_id FROM event_recurrence_dates WHERE is_excluded = 0)
+                OR event_id IN (SELECT event_id FROM event_overrides))",
+            params![calendar_id, to_storage_time(start), to_storage_time(end)],
+        )
+    }
+
+    fn get_events_after(&self, calendar_id: i64, after: &DateTime<Tz>) -> Result<Vec<Event>, CaliError> {
+        // Of the single events only the first to start can come next. All-day
+        // events start at local midnight rather than the time kept, so they
+        // are all looked at.
+        let mut events = query_events(
+            &self.conn,
+            "SELECT * FROM events
+            WHERE calendar_id = ?1 AND event_recurring = 'No' AND event_all_day = 0 AND event_start >= ?2
+                AND event_id NOT IN (SELECT event_id FROM event_recurrence_dates)
+                AND event_id NOT IN (SELECT event_id FROM event_overrides)
+            ORDER BY event_start, event_name LIMIT 1",
+            params![calendar_id, to_storage_time(after)],
+        )?;
+        events.extend(query_events(
+            &self.conn,
+            "SELECT * FROM events
+            WHERE calendar_id = ?1 AND (event_recurring <> 'No' OR event_all_day = 1
+                OR event_id IN (SELECT event_id FROM event_recurrence_dates)
+                OR event_id IN (SELECT event_id FROM event_overrides))",
+            params![calendar_id],
+        )?);
+
+        Ok(events)
+    }
+}
+
+// Runs a query against the events table and converts each row into an Event
+fn query_events<P: Params>(conn: &Connection, query: &str, params: P) -> Result<Vec<Event>, CaliError> {
+    let mut stmt = conn.prepare(query)?;
+    let mut rows = stmt.query(params)?;
+
+    let mut events = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut event = event_from_row(row)?;
+        load_event_exceptions(conn, &mut event)?;
+        load_event_tags(conn, &mut event)?;
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+// Writes an event's recurrence dates and overrides, replacing those stored before
+fn save_event_exceptions(conn: &Connection, event: &Event) -> Result<()> {
+    let id = event.get_id().to_string();
+    conn.execute("DELETE FROM event_recurrence_dates WHERE event_id = ?1", params![id])?;
+    conn.execute("DELETE FROM event_overrides WHERE event_id = ?1", params![id])?;
+
+    let mut insert_date = conn.prepare(
+        "INSERT INTO event_recurrence_dates (event_id, recurrence_date, is_excluded) VALUES (?1, ?2, ?3)",
+    )?;
+    for exdate in event.get_exdates() {
+        insert_date.execute(params![id, to_storage_time(exdate), true])?;
+    }
+    for rdate in event.get_rdates() {
+        insert_date.execute(params![id, to_storage_time(rdate), false])?;
+    }
+
+    let mut insert_override = conn.prepare(
+        "INSERT INTO event_overrides (event_id, recurrence_id, override_start, override_end, override_name)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for existing in event.get_overrides() {
+        insert_override.execute(params![
+            id,
+            to_storage_time(existing.get_recurrence_id()),
+            to_storage_time(existing.get_start()),
+            to_storage_time(existing.get_end()),
+            existing.get_name(),
+        ])?;
+    }
+
+    Ok(())
+}
+
+// Reads an event's recurrence dates and overrides
+fn load_event_exceptions(conn: &Connection, event: &mut Event) -> Result<(), CaliError> {
+    let id = event.get_id().to_string();
+    let timezone = event.get_timezone();
+
+    let mut select_dates = conn.prepare(
+        "SELECT recurrence_date, is_excluded FROM event_recurrence_dates WHERE event_id = ?1",
+    )?;
+    let mut rows = select_dates.query(params![id])?;
+    while let Some(row) = rows.next()? {
+        let date = from_storage_time(&row.get::<_, String>(0)?, timezone)?;
+        if row.get(1)? {
+            event.add_exdate(date);
+        } else {
+            event.add_rdate(date);
+        }
+    }
+
+    let mut select_overrides = conn.prepare(
+        "SELECT recurrence_id, override_start, override_end, override_name FROM event_overrides
+        WHERE event_id = ?1 ORDER BY recurrence_id",
+    )?;
+    let mut rows = select_overrides.query(params![id])?;
+    while let Some(row) = rows.next()? {
+        let name: Option<String> = row.get(3)?;
+        event.restore_override(Override::new(
+            from_storage_time(&row.get::<_, String>(0)?, timezone)?,
+            from_storage_time(&row.get::<_, String>(1)?, timezone)?,
+            from_storage_time(&row.get::<_, String>(2)?, timezone)?,
+            name.as_deref(),
+        )?);
+    }
+
+    Ok(())
+}
+
+// Writes an event's tags, replacing those stored before. Tags it dropped
+// that no other event carries are removed.
+fn save_event_tags(conn: &Connection, event: &Event) -> Result<()> {
+    let id = event.get_id().to_string();
+    let previous = select_tag_ids(conn, "SELECT tag_id FROM event_tags WHERE event_id = ?1", params![id])?;
+    conn.execute("DELETE FROM event_tags WHERE event_id = ?1", params![id])?;
+
+    let mut insert_tag = conn.prepare("INSERT OR IGNORE INTO tags (tag_name) VALUES (?1)")?;
+    let mut insert_event_tag = conn.prepare(
+        "INSERT INTO event_tags (event_id, tag_id) SELECT ?1, tag_id FROM tags WHERE tag_name = ?2",
+    )?;
+    for tag in event.get_tags() {
+        insert_tag.execute(params![tag])?;
+        insert_event_tag.execute(params![id, tag])?;
+    }
+    remove_unused_tags(conn, &previous)
+}
+
+// Gets the tag ids a query selects
+fn select_tag_ids<P: Params>(conn: &Connection, query: &str, params: P) -> Result<Vec<i64>> {
+    conn.prepare(query)?.query_map(params, |row| row.get(0))?.collect()
+}
+
+// Removes those of the given tags that no event carries any longer
+fn remove_unused_tags(conn: &Connection, tag_ids: &[i64]) -> Result<()> {
+    let mut remove_tag = conn.prepare(
+        "DELETE FROM tags WHERE tag_id = ?1 AND NOT EXISTS (SELECT 1 FROM event_tags WHERE tag_id = ?1)",
+    )?;
+    for tag_id in tag_ids {
+        remove_tag.execute(params![tag_id])?;
+    }
+
+    Ok(())
+}
+
+// Reads an event's tags
+fn load_event_tags(conn: &Connection, event: &mut Event) -> Result<(), CaliError> {
+    let mut select_tags = conn.prepare(
+        "SELECT tag_name FROM tags JOIN event_tags USING (tag_id) WHERE event_id = ?1",
+    )?;
+    let tags = select_tags
+        .query_map(params![event.get_id().to_string()], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+    event.update_tags(&tags)
+}
+
+// Writes the tasks that block a task, replacing those stored before
+fn save_task_blockers(conn: &Connection, task: &Task) -> Result<()> {
+    let id = task.get_id().to_string();
+    conn.execute("DELETE FROM task_blockers WHERE task_id = ?1", params![id])?;
+
+    let mut insert_blocker = conn.prepare("INSERT INTO task_blockers (task_id, blocker_id) VALUES (?1, ?2)")?;
+    for blocker in task.get_blocked_by() {
+        insert_blocker.execute(params![id, blocker.to_string()])?;
+    }
+
+    Ok(())
+}
+
+// Reads the tasks that block a task
+fn load_task_blockers(conn: &Connection, task: &mut Task) -> Result<(), CaliError> {
+    let mut select_blockers = conn.prepare("SELECT blocker_id FROM task_blockers WHERE task_id = ?1")?;
+    let blockers = select_blockers
+        .query_map(params![task.get_id().to_string()], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+    for blocker in blockers {
+        task.add_blocker(parse_id(&blocker, "a task id")?)?;
+    }
+
+    Ok(())
+}
+
+// Converts a row of the events table into an Event
+fn event_from_row(row: &Row) -> Result<Event, CaliError> {
+    let id: String = row.get("event_id")?;
+    let name: String = row.get("event_name")?;
+    let timezone = parse_timezone(&row.get::<_, String>("event_timezone")?)?;
+    let start = from_storage_time(&row.get::<_, String>("event_start")?, timezone)?;
+    let end = from_storage_time(&row.get::<_, String>("event_end")?, timezone)?;
+    let recurring_str: String = row.get("event_recurring")?;
+
+    // Parse the recurring field from the database string representation into the Recurring enum
+    let recurring: Recurring = recurring_str.parse()?;
+
+    let mut event = Event::from(&id, &name, start, end, recurring)?;
+    event.update_uid(row.get::<_, Option<String>>("event_uid")?.as_deref())?;
+    event.update_all_day(row.get("event_all_day")?)?;
+    event.update_description(row.get::<_, Option<String>>("event_description")?.as_deref())?;
+    event.update_location(row.get::<_, Option<String>>("event_location")?.as_deref())?;
+    event.update_url(row.get::<_, Option<String>>("event_url")?.as_deref())?;
+    event.update_categories(&[row.get::<_, String>("event_categories")?])?;
+    Ok(event)
+}
+
+// Gets the task_due and task_due_timezone columns for when a task is due
+fn due_columns(due: Option<&Due>) -> (Option<String>, Option<&'static str>) {
+    match due {
+        Some(Due::Date(day)) => (Some(day.format("%Y-%m-%d").to_string()), None),
+        Some(Due::Time(time)) => (Some(to_storage_time(time)), Some(time.timezone().name())),
+        None => (None, None),
+    }
+}
+
+// Converts a row of the tasks table into a Task
+fn task_from_row(row: &Row) -> Result<Task, CaliError> {
+    let id: String = row.get("task_id")?;
+    let title: String = row.get("task_title")?;
+    let due = match (row.get::<_, Option<String>>("task_due")?, row.get::<_, Option<String>>("task_due_timezone")?) {
+        (Some(due), Some(timezone)) => Some(Due::Time(from_storage_time(&due, parse_timezone(&timezone)?)?)),
+        (Some(due), None) => Some(Due::Date(NaiveDate::parse_from_str(&due, "%Y-%m-%d").map_err(|_| InvalidTaskError {
+            reason: format!("'{}' is not a stored due date", due),
+        })?)),
+        (None, _) => None,
+    };
+
+    let mut task = Task::from(&id, &title, due)?;
+    task.update_priority(row.get("task_priority")?)?;
+    task.update_status(row.get::<_, String>("task_status")?.parse()?)?;
+    task.update_percent_complete(row.get("task_percent_complete")?)?;
+    let completed = row.get::<_, Option<String>>("task_completed")?;
+    task.update_completed(completed.map(|completed| from_storage_time(&completed, Tz::UTC)).transpose()?)?;
+    task.update_uid(row.get::<_, Option<String>>("task_uid")?.as_deref())?;
+    let parent = row.get::<_, Option<String>>("task_parent_id")?;
+    task.update_parent(parent.map(|parent| parse_id(&parent, "a task id")).transpose()?)?;
+    Ok(task)
+}
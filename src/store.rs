@@ -0,0 +1,197 @@
+use crate::{cali_error::*, event::*, memory_store::*, search::*, sqlite_store::*, task::*, vdir_store::*};
+use std::path::PathBuf;
+use std::rc::Rc;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use uuid::Uuid;
+
+// The database path SQLite opens as a private database held in memory
+pub const MEMORY_DATABASE: &str = ":memory:";
+
+// Reads the id of an event or task, naming what it is, such as "a task id",
+// if it cannot be read
+pub fn parse_id(text: &str, what: &str) -> Result<Uuid, ParseError> {
+    Uuid::parse_str(text).map_err(|e| ParseError { what: what.to_string(), reason: e.to_string() })
+}
+
+// A calendar as a backend keeps it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarRecord {
+    pub id: i64,
+    pub name: String,
+    pub default: bool,
+}
+
+// An event set aside when the database was upgraded, because it could not
+// be read, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnreadableEvent {
+    pub id: String,
+    pub calendar: String,
+    pub name: String,
+    pub reason: String,
+}
+
+// Somewhere calendars and their events and tasks are kept. A backend only has to store
+// and read back whole calendars and events; the queries after those have
+// implementations built on them, which a backend may replace with faster ones.
+pub trait CalendarStore {
+    // Gets the file or directory the calendars are kept in, if there is one
+    fn get_path(&self) -> Option<&PathBuf>;
+
+    // Gets every calendar, ordered by name
+    fn get_calendars(&self) -> Result<Vec<CalendarRecord>, CaliError>;
+
+    // Adds an empty calendar and returns its id
+    fn insert_calendar(&self, name: &str, default: bool) -> Result<i64, CaliError>;
+
+    fn rename_calendar(&self, id: i64, new_name: &str) -> Result<(), CaliError>;
+
+    // Removes a calendar and all of its events and tasks
+    fn remove_calendar(&self, id: i64) -> Result<(), CaliError>;
+
+    // Makes the calendar the default one, and every other calendar not
+    fn update_default(&self, id: i64) -> Result<(), CaliError>;
+
+    fn insert_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError>;
+
+    // Replaces the event with the same id, moving it to the calendar if it
+    // was in another one
+    fn update_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError>;
+
+    fn remove_event(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError>;
+
+    // Gets every event in a calendar, ordered by start and then name
+    fn get_events(&self, calendar_id: i64) -> Result<Vec<Event>, CaliError>;
+
+    // Checks if any calendar has an event with the given id
+    fn check_event(&self, id: &Uuid) -> Result<bool, CaliError>;
+
+    fn insert_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError>;
+
+    // Replaces the task with the same id, moving it to the calendar if it
+    // was in another one
+    fn update_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError>;
+
+    fn remove_task(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError>;
+
+    // Gets every task in a calendar, in no particular order
+    fn get_tasks(&self, calendar_id: i64) -> Result<Vec<Task>, CaliError>;
+
+    // Checks if any calendar has a task with the given id
+    fn check_task(&self, id: &Uuid) -> Result<bool, CaliError>;
+
+    // Runs `operation` so that every change it makes is kept if it succeeds,
+    // and none are if it fails. Transactions may be nested.
+    fn transaction(&self, operation: &mut dyn FnMut() -> Result<(), CaliError>) -> Result<(), CaliError>;
+
+    // Gets the events set aside as unreadable when the store was upgraded
+    fn get_unreadable_events(&self) -> Result<Vec<UnreadableEvent>, CaliError> {
+        Ok(Vec::new())
+    }
+
+    // Gets the events in a calendar named `name` or, when not `exact`,
+    // with a name containing it in any case
+    fn get_events_named(&self, calendar_id: i64, name: &str, exact: bool) -> Result<Vec<Event>, CaliError> {
+        let lowercase = name.to_lowercase();
+        Ok(self.get_events(calendar_id)?
+            .into_iter()
+            .filter(|event| match exact {
+                true => event.get_name() == name,
+                false => event.get_name().to_lowercase().contains(&lowercase),
+            })
+            .collect())
+    }
+
+    // Gets the events in a calendar whose name, description, location, URL
+    // or categories contain `text`, in any case
+    fn get_events_containing(&self, calendar_id: i64, text: &str) -> Result<Vec<Event>, CaliError> {
+        Ok(self.get_events(calendar_id)?.into_iter().filter(|event| event.contains_text(text)).collect())
+    }
+
+    fn get_event_by_id(&self, calendar_id: i64, id: &Uuid) -> Result<Option<Event>, CaliError> {
+        Ok(self.get_events(calendar_id)?.into_iter().find(|event| event.get_id() == id))
+    }
+
+    // Gets the events in a calendar whose title, description or location
+    // match a full-text search, each with a score that is higher the better
+    // it matches, in no particular order
+    fn search_events(&self, calendar_id: i64, query: &SearchQuery) -> Result<Vec<(Event, f64)>, CaliError> {
+        Ok(self.get_events(calendar_id)?
+            .into_iter()
+            .filter_map(|event| query.score(&event).map(|score| (event, score)))
+            .collect())
+    }
+
+    // Gets the events in a calendar that may have an occurrence overlapping
+    // the window from `start` to `end`. Others may be included too.
+    fn get_events_overlapping(&self, calendar_id: i64, _start: &DateTime<Tz>, _end: &DateTime<Tz>) -> Result<Vec<Event>, CaliError> {
+        self.get_events(calendar_id)
+    }
+
+    // Gets the events in a calendar that may have the first occurrence
+    // starting at or after `after`. Others may be included too.
+    fn get_events_after(&self, calendar_id: i64, _after: &DateTime<Tz>) -> Result<Vec<Event>, CaliError> {
+        self.get_events(calendar_id)
+    }
+}
+
+// A handle on a storage backend. The backend is opened once and shared by
+// every clone of the handle and every calendar opened through it.
+#[derive(Clone)]
+pub struct Store {
+    backend: Rc<dyn CalendarStore>,
+}
+
+impl Store {
+    // Opens the calendars at `path`: a directory is read as a vdir of
+    // iCalendar files, and anything else as an SQLite database
+    pub fn open(path: &PathBuf) -> Result<Store, CaliError> {
+        if path.is_dir() {
+            Ok(Store::new(VdirStore::open(path)?))
+        } else {
+            Ok(Store::new(SqliteStore::open(path)?))
+        }
+    }
+
+    // Opens an empty SQLite database held in memory, which is lost once dropped
+    pub fn open_in_memory() -> Result<Store, CaliError> {
+        Store::open(&PathBuf::from(MEMORY_DATABASE))
+    }
+
+    // Creates an empty store kept in memory, which is lost once dropped
+    pub fn memory() -> Store {
+        Store::new(MemoryStore::new())
+    }
+
+    pub fn new(backend: impl CalendarStore + 'static) -> Store {
+        Store { backend: Rc::new(backend) }
+    }
+
+    pub fn get_path(&self) -> Option<&PathBuf> {
+        self.backend.get_path()
+    }
+
+    pub fn get_backend(&self) -> &dyn CalendarStore {
+        self.backend.as_ref()
+    }
+
+    // Runs `operation` as one transaction: every change it makes is kept if
+    // it succeeds, and none are if it fails. Transactions may be nested, in
+    // which case a failing inner one only undoes its own changes.
+    pub fn transaction<T, F>(&self, operation: F) -> Result<T, CaliError>
+    where
+        F: FnOnce(&Store) -> Result<T, CaliError>,
+    {
+        let mut operation = Some(operation);
+        let mut value = None;
+        self.backend.transaction(&mut || {
+            if let Some(operation) = operation.take() {
+                value = Some(operation(self)?);
+            }
+            Ok(())
+        })?;
+
+        Ok(value.expect("the backend runs the operation"))
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{calendar::*, cali_error::*, event::*, store::*};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+// Words with a meaning of their own in tag filters, which tags cannot be named
+const KEYWORDS: [&str; 3] = ["and", "or", "not"];
+
+// Which tags an event must have, such as "work and not urgent" or
+// "(home or garden) and !errand"
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagFilter {
+    Tag(String),
+    Not(Box<TagFilter>),
+    And(Vec<TagFilter>),
+    Or(Vec<TagFilter>),
+}
+
+impl TagFilter {
+    // Combines filters so that an event must match every one of them
+    pub fn all(mut filters: Vec<TagFilter>) -> Option<TagFilter> {
+        match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(TagFilter::And(filters)),
+        }
+    }
+
+    // Checks whether an event with `tags` matches the filter
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagFilter::Tag(tag) => tags.contains(tag),
+            TagFilter::Not(filter) => !filter.matches(tags),
+            TagFilter::And(filters) => filters.iter().all(|filter| filter.matches(tags)),
+            TagFilter::Or(filters) => filters.iter().any(|filter| filter.matches(tags)),
+        }
+    }
+}
+
+// Reads a filter in which "not" or "!" binds tightest, then "and" or "+",
+// then "or" or ",", and parentheses group
+impl FromStr for TagFilter {
+    type Err = InvalidTagError;
+
+    fn from_str(value: &str) -> Result<TagFilter, InvalidTagError> {
+        let tokens = tokenize(value);
+        let mut reader = FilterReader { tokens: &tokens, position: 0 };
+        let filter = reader.read_or()?;
+        match reader.peek() {
+            None => Ok(filter),
+            Some(token) => Err(InvalidTagError { reason: format!("expected 'and' or 'or' before '{}' in '{}'", token, value) }),
+        }
+    }
+}
+
+// Reads a tag filter one token at a time
+struct FilterReader<'a> {
+    tokens: &'a [String],
+    position: usize,
+}
+
+impl FilterReader<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    // Moves past the next token if it is one of `words`
+    fn accept(&mut self, words: &[&str]) -> bool {
+        let found = self.peek().is_some_and(|token| words.contains(&token));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn read_or(&mut self) -> Result<TagFilter, InvalidTagError> {
+        let mut filters = vec![self.read_and()?];
+        while self.accept(&["or", ","]) {
+            filters.push(self.read_and()?);
+        }
+        Ok(if filters.len() == 1 { filters.remove(0) } else { TagFilter::Or(filters) })
+    }
+
+    fn read_and(&mut self) -> Result<TagFilter, InvalidTagError> {
+        let mut filters = vec![self.read_not()?];
+        while self.accept(&["and", "+"]) {
+            filters.push(self.read_not()?);
+        }
+        Ok(if filters.len() == 1 { filters.remove(0) } else { TagFilter::And(filters) })
+    }
+
+    fn read_not(&mut self) -> Result<TagFilter, InvalidTagError> {
+        if self.accept(&["not", "!"]) {
+            return Ok(TagFilter::Not(Box::new(self.read_not()?)));
+        }
+        if self.accept(&["("]) {
+            let filter = self.read_or()?;
+            if !self.accept(&[")"]) {
+                return Err(InvalidTagError { reason: "a '(' is not closed".to_string() });
+            }
+            return Ok(filter);
+        }
+
+        let Some(token) = self.peek() else {
+            return Err(InvalidTagError { reason: "the filter ends where a tag was expected".to_string() });
+        };
+        let tag = normalize_tag(token)?;
+        self.position += 1;
+        Ok(TagFilter::Tag(tag))
+    }
+}
+
+// Splits a filter into words and the symbols ( ) , + !
+fn tokenize(value: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in value.chars() {
+        if c.is_whitespace() || "(),+!".contains(c) {
+            if !word.is_empty() {
+                tokens.push(word.to_lowercase());
+                word.clear();
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word.to_lowercase());
+    }
+
+    tokens
+}
+
+// Checks a tag name and puts it in lowercase. Names are made of letters,
+// digits and the characters - _ / . : so that they can be used in filters.
+pub fn normalize_tag(name: &str) -> Result<String, InvalidTagError> {
+    let tag = name.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err(InvalidTagError { reason: "a tag name cannot be empty".to_string() });
+    }
+    if KEYWORDS.contains(&tag.as_str()) {
+        return Err(InvalidTagError { reason: format!("'{}' is used in filters and cannot be a tag", tag) });
+    }
+    if let Some(c) = tag.chars().find(|c| !c.is_alphanumeric() && !"-_/.:".contains(*c)) {
+        return Err(InvalidTagError { reason: format!("'{}' cannot hold '{}'", name.trim(), c) });
+    }
+
+    Ok(tag)
+}
+
+// Reads the filter given by each `--tag` option, combined so that an event
+// must match every one
+pub fn parse_tag_filters(values: &[String]) -> Result<Option<TagFilter>, InvalidTagError> {
+    let filters = values.iter().map(|value| value.parse()).collect::<Result<Vec<TagFilter>, _>>()?;
+    Ok(TagFilter::all(filters))
+}
+
+// Counts the events carrying each tag across every calendar, by tag name
+pub fn get_tags(store: &Store) -> Result<BTreeMap<String, usize>, CaliError> {
+    let mut tags = BTreeMap::new();
+    for calendar in get_calendars(store)? {
+        for event in get_events(&calendar)? {
+            for tag in event.get_tags() {
+                *tags.entry(tag.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    Ok(tags)
+}
@@ -0,0 +1,521 @@
+use crate::{calendar::*, cali_error::*, store::*};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use chrono_tz::Tz;
+use uuid::Uuid;
+
+// The highest PRIORITY a task may have; 1 is the most urgent and 0 means none
+pub const LOWEST_PRIORITY: u8 = 9;
+
+// Where a task stands, named as in the STATUS of a VTODO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStatus {
+    NeedsAction,
+    InProcess,
+    Completed,
+    Cancelled,
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskStatus::NeedsAction => write!(f, "NEEDS-ACTION"),
+            TaskStatus::InProcess => write!(f, "IN-PROCESS"),
+            TaskStatus::Completed => write!(f, "COMPLETED"),
+            TaskStatus::Cancelled => write!(f, "CANCELLED"),
+        }
+    }
+}
+
+impl FromStr for TaskStatus {
+    type Err = InvalidTaskError;
+
+    fn from_str(value: &str) -> Result<TaskStatus, InvalidTaskError> {
+        match value.trim().to_uppercase().as_str() {
+            "NEEDS-ACTION" => Ok(TaskStatus::NeedsAction),
+            "IN-PROCESS" => Ok(TaskStatus::InProcess),
+            "COMPLETED" => Ok(TaskStatus::Completed),
+            "CANCELLED" => Ok(TaskStatus::Cancelled),
+            _ => Err(InvalidTaskError { reason: format!("'{}' is not a task status", value) }),
+        }
+    }
+}
+
+// When a task is due: by the end of a day, or by a time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Due {
+    Date(NaiveDate),
+    Time(DateTime<Tz>),
+}
+
+impl Due {
+    // Gets the day the task is due on, read in `timezone`
+    pub fn get_day(&self, timezone: Tz) -> NaiveDate {
+        match self {
+            Due::Date(day) => *day,
+            Due::Time(time) => time.with_timezone(&timezone).date_naive(),
+        }
+    }
+
+    // Checks whether the task is past due at `now`. A task due on a day is
+    // only late once that day is over.
+    pub fn has_passed(&self, now: &DateTime<Tz>) -> bool {
+        match self {
+            Due::Date(day) => now.date_naive() > *day,
+            Due::Time(time) => now > time,
+        }
+    }
+
+    // Gets the wall-clock time tasks are ordered by, with a task due on a
+    // day coming after those due at a time that day
+    fn sort_time(&self) -> NaiveDateTime {
+        match self {
+            Due::Date(day) => day.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap()),
+            Due::Time(time) => time.naive_local(),
+        }
+    }
+}
+
+// A to-do with the semantics of a VTODO. Unlike an event it does not take up
+// time, but may be due by a day or a time. A task may be a subtask of a parent
+// in the same calendar, and may be blocked by other tasks until they are done.
+#[derive(Debug, Clone)]
+pub struct Task {
+    id: Uuid,
+    title: String,
+    due: Option<Due>,
+    priority: u8,
+    status: TaskStatus,
+    percent_complete: u8,
+    completed: Option<DateTime<Tz>>,
+    uid: Option<String>,
+    parent: Option<Uuid>,
+    blocked_by: Vec<Uuid>,
+}
+
+impl Task {
+    pub fn new(title: &str, due: Option<Due>) -> Task {
+        Task {
+            id: Uuid::new_v4(),
+            title: title.to_string(),
+            due: due.map(truncate_due),
+            priority: 0,
+            status: TaskStatus::NeedsAction,
+            percent_complete: 0,
+            completed: None,
+            uid: None,
+            parent: None,
+            blocked_by: Vec::new(),
+        }
+    }
+
+    pub fn from(id: &str, title: &str, due: Option<Due>) -> Result<Task, CaliError> {
+        Ok(Task { id: parse_id(id, "a task id")?, ..Task::new(title, due) })
+    }
+
+    pub fn get_id(&self) -> &Uuid {
+        &self.id
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn get_due(&self) -> Option<&Due> {
+        self.due.as_ref()
+    }
+
+    pub fn get_priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn get_status(&self) -> TaskStatus {
+        self.status
+    }
+
+    pub fn get_percent_complete(&self) -> u8 {
+        self.percent_complete
+    }
+
+    pub fn get_completed(&self) -> Option<&DateTime<Tz>> {
+        self.completed.as_ref()
+    }
+
+    // Gets the UID the task was imported with, if it was not a UUID
+    pub fn get_uid(&self) -> Option<&str> {
+        self.uid.as_deref()
+    }
+
+    // Gets the id of the task this is a subtask of
+    pub fn get_parent(&self) -> Option<&Uuid> {
+        self.parent.as_ref()
+    }
+
+    // Gets the ids of the tasks that have to be done before this one, in order
+    pub fn get_blocked_by(&self) -> &[Uuid] {
+        &self.blocked_by
+    }
+
+    // Checks whether the task still has to be done
+    pub fn is_open(&self) -> bool {
+        matches!(self.status, TaskStatus::NeedsAction | TaskStatus::InProcess)
+    }
+
+    // Checks whether the task is still open after it was due
+    pub fn is_overdue(&self, now: &DateTime<Tz>) -> bool {
+        self.is_open() && self.due.is_some_and(|due| due.has_passed(now))
+    }
+
+    // Marks the task done at `at`
+    pub fn complete(&mut self, at: DateTime<Tz>) {
+        self.status = TaskStatus::Completed;
+        self.percent_complete = 100;
+        self.completed = Some(at.with_nanosecond(0).unwrap_or(at));
+    }
+
+    // Marks a completed or cancelled task as still to be done
+    pub fn reopen(&mut self) {
+        if self.percent_complete == 100 {
+            self.percent_complete = 0;
+        }
+        self.status = match self.percent_complete {
+            0 => TaskStatus::NeedsAction,
+            _ => TaskStatus::InProcess,
+        };
+        self.completed = None;
+    }
+
+    pub fn update_title(&mut self, new_title: &str) -> Result<(), CaliError> {
+        self.title = new_title.to_string();
+        Ok(())
+    }
+
+    pub fn update_due(&mut self, new_due: Option<Due>) -> Result<(), CaliError> {
+        self.due = new_due.map(truncate_due);
+        Ok(())
+    }
+
+    // Sets the priority, from 1 for the most urgent to 9, or 0 for none
+    pub fn update_priority(&mut self, new_priority: u8) -> Result<(), CaliError> {
+        if new_priority > LOWEST_PRIORITY {
+            return Err(InvalidTaskError { reason: format!("priority {} is not from 0 to {}", new_priority, LOWEST_PRIORITY) }.into());
+        }
+        self.priority = new_priority;
+        Ok(())
+    }
+
+    pub fn update_status(&mut self, new_status: TaskStatus) -> Result<(), CaliError> {
+        self.status = new_status;
+        Ok(())
+    }
+
+    pub fn update_percent_complete(&mut self, new_percent_complete: u8) -> Result<(), CaliError> {
+        if new_percent_complete > 100 {
+            return Err(InvalidTaskError { reason: format!("{}% is more than complete", new_percent_complete) }.into());
+        }
+        self.percent_complete = new_percent_complete;
+        Ok(())
+    }
+
+    pub fn update_completed(&mut self, new_completed: Option<DateTime<Tz>>) -> Result<(), CaliError> {
+        self.completed = new_completed.map(|completed| completed.with_nanosecond(0).unwrap_or(completed));
+        Ok(())
+    }
+
+    pub fn update_uid(&mut self, new_uid: Option<&str>) -> Result<(), CaliError> {
+        self.uid = new_uid.map(str::to_string);
+        Ok(())
+    }
+
+    // Makes the task a subtask of the task with id `new_parent`, or a
+    // top-level task when none is given
+    pub fn update_parent(&mut self, new_parent: Option<Uuid>) -> Result<(), CaliError> {
+        if new_parent == Some(self.id) {
+            return Err(InvalidTaskError { reason: format!("'{}' cannot be a subtask of itself", self.title) }.into());
+        }
+        self.parent = new_parent;
+        Ok(())
+    }
+
+    // Notes that the task with id `blocker` has to be done before this one
+    pub fn add_blocker(&mut self, blocker: Uuid) -> Result<(), CaliError> {
+        if blocker == self.id {
+            return Err(InvalidTaskError { reason: format!("'{}' cannot be blocked by itself", self.title) }.into());
+        }
+        if let Err(index) = self.blocked_by.binary_search(&blocker) {
+            self.blocked_by.insert(index, blocker);
+        }
+        Ok(())
+    }
+
+    // Removes a blocker, returning whether the task was blocked by it
+    pub fn remove_blocker(&mut self, blocker: &Uuid) -> bool {
+        let before = self.blocked_by.len();
+        self.blocked_by.retain(|existing| existing != blocker);
+        self.blocked_by.len() != before
+    }
+}
+
+// Orders tasks by when they are due, those without a due date last, then by
+// priority, with no priority last, and title
+pub fn compare_tasks(a: &Task, b: &Task) -> Ordering {
+    let due = |task: &Task| task.due.map(|due| due.sort_time());
+    let priority = |task: &Task| match task.priority {
+        0 => LOWEST_PRIORITY + 1,
+        priority => priority,
+    };
+    match (due(a), due(b)) {
+        (Some(a_due), Some(b_due)) => a_due.cmp(&b_due),
+        (a_due, b_due) => b_due.is_some().cmp(&a_due.is_some()),
+    }
+    .then_with(|| priority(a).cmp(&priority(b)))
+    .then_with(|| a.title.cmp(&b.title))
+}
+
+// Gets the subtasks of the task with id `parent` among `tasks`
+pub fn get_subtasks<'a>(tasks: &'a [Task], parent: &Uuid) -> Vec<&'a Task> {
+    tasks.iter().filter(|task| task.parent.as_ref() == Some(parent)).collect()
+}
+
+// Gets the tasks among `tasks` that block `task` and are still open. Blockers
+// that no longer exist do not block it.
+pub fn get_open_blockers<'a>(tasks: &'a [Task], task: &Task) -> Vec<&'a Task> {
+    tasks.iter().filter(|other| task.blocked_by.contains(&other.id) && other.is_open()).collect()
+}
+
+// Orders `tasks` as a tree: each task is followed by its subtasks, one level
+// deeper, in the order of compare_tasks. Tasks whose parent is not among
+// `tasks` are at the top level, as is the first of any tasks that are each
+// other's parents.
+pub fn task_tree(tasks: &[Task]) -> Vec<(usize, &Task)> {
+    let mut sorted: Vec<&Task> = tasks.iter().collect();
+    sorted.sort_by(|a, b| compare_tasks(a, b));
+    let is_top = |task: &&Task| task.parent.is_none_or(|parent| !tasks.iter().any(|other| other.id == parent));
+    let roots: Vec<&Task> = sorted.iter().copied().filter(is_top).chain(sorted.iter().copied()).collect();
+
+    let mut tree: Vec<(usize, &Task)> = Vec::new();
+    for root in roots {
+        let mut stack = vec![(0, root)];
+        while let Some((depth, task)) = stack.pop() {
+            if tree.iter().any(|(_, shown)| shown.id == task.id) {
+                continue;
+            }
+            tree.push((depth, task));
+            let children = sorted.iter().rev().filter(|child| child.parent == Some(task.id));
+            stack.extend(children.map(|child| (depth + 1, *child)));
+        }
+    }
+
+    tree
+}
+
+// Checks whether following the links given by `next` from `from` leads to
+// the task with id `to`, among `tasks`
+fn reaches(tasks: &[Task], from: &Uuid, to: &Uuid, next: &dyn Fn(&Task) -> Vec<Uuid>) -> bool {
+    let mut seen = Vec::new();
+    let mut pending = vec![*from];
+    while let Some(id) = pending.pop() {
+        if id == *to {
+            return true;
+        }
+        if seen.contains(&id) {
+            continue;
+        }
+        seen.push(id);
+        if let Some(task) = tasks.iter().find(|task| task.id == id) {
+            pending.extend(next(task));
+        }
+    }
+
+    false
+}
+
+// Sets the completion of the open task `parent` and of each of its open
+// ancestors from that of their subtasks, nearest first: the average of their
+// percentages, counting a completed subtask as 100% and leaving out cancelled
+// ones. A task is in process while partly complete, and back to nothing done
+// once it has no subtasks left to count.
+fn roll_up(calendar: &Calendar, tasks: &mut [Task], mut parent: Option<Uuid>) -> Result<(), CaliError> {
+    let mut visited = Vec::new();
+    while let Some(parent_id) = parent.filter(|parent_id| !visited.contains(parent_id)) {
+        visited.push(parent_id);
+        let percents: Vec<u32> = tasks.iter()
+            .filter(|task| task.parent == Some(parent_id) && task.status != TaskStatus::Cancelled)
+            .map(|task| match task.status {
+                TaskStatus::Completed => 100,
+                _ => task.percent_complete as u32,
+            })
+            .collect();
+        let Some(task) = tasks.iter_mut().find(|task| task.id == parent_id) else {
+            break;
+        };
+        if task.is_open() {
+            task.percent_complete = match percents.len() {
+                0 => 0,
+                count => (percents.iter().sum::<u32>() / count as u32) as u8,
+            };
+            task.status = match task.percent_complete {
+                0 => TaskStatus::NeedsAction,
+                _ => TaskStatus::InProcess,
+            };
+            update_task(calendar, task)?;
+        }
+        parent = task.parent;
+    }
+
+    Ok(())
+}
+
+// Truncates a due time to the second stored in the database
+fn truncate_due(due: Due) -> Due {
+    match due {
+        Due::Time(time) => Due::Time(time.with_nanosecond(0).unwrap_or(time)),
+        date => date,
+    }
+}
+
+// Inserts a new task into the calendar's store
+pub fn insert_task(calendar: &Calendar, task: &Task) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|store| store.get_backend().insert_task(calendar.get_id(), task))
+}
+
+// Updates an existing task in the calendar's store, moving it to the
+// calendar if it was in another one
+pub fn update_task(calendar: &Calendar, task: &Task) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|store| store.get_backend().update_task(calendar.get_id(), task))
+}
+
+// Removes an existing task from the calendar's store. Its subtasks become
+// top-level tasks, the tasks it blocked are no longer blocked by it, and its
+// parent's completion is worked out again from the subtasks left.
+pub fn remove_task(calendar: &Calendar, task: &Task) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|store| {
+        let mut tasks = get_tasks(calendar)?;
+        store.get_backend().remove_task(calendar.get_id(), task.get_id())?;
+        for other in tasks.iter_mut().filter(|other| other.id != task.id) {
+            let was_child = other.parent == Some(task.id);
+            if was_child {
+                other.parent = None;
+            }
+            if other.remove_blocker(&task.id) || was_child {
+                update_task(calendar, other)?;
+            }
+        }
+        tasks.retain(|other| other.id != task.id);
+        roll_up(calendar, &mut tasks, task.parent)
+    })
+}
+
+// Marks a task done at `at` and rolls its completion up to its ancestors. A
+// task cannot be done while any task blocking it is still open.
+pub fn complete_task(calendar: &Calendar, task: &mut Task, at: DateTime<Tz>) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|_| {
+        let mut tasks = get_tasks(calendar)?;
+        let blockers = get_open_blockers(&tasks, task);
+        if !blockers.is_empty() {
+            return Err(BlockedTaskError {
+                title: task.title.clone(),
+                blockers: blockers.iter().map(|blocker| blocker.title.clone()).collect(),
+            }.into());
+        }
+        task.complete(at);
+        update_task(calendar, task)?;
+        replace_task(&mut tasks, task);
+        roll_up(calendar, &mut tasks, task.parent)
+    })
+}
+
+// Marks a done or cancelled task as still to do and rolls its completion up
+// to its ancestors
+pub fn reopen_task(calendar: &Calendar, task: &mut Task) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|_| {
+        let mut tasks = get_tasks(calendar)?;
+        task.reopen();
+        update_task(calendar, task)?;
+        replace_task(&mut tasks, task);
+        roll_up(calendar, &mut tasks, task.parent)
+    })
+}
+
+// Adds a task to the calendar as a subtask of `parent`, or at the top level,
+// and rolls its completion up to its new ancestors
+pub fn insert_subtask(calendar: &Calendar, task: &mut Task, parent: Option<&Task>) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|_| {
+        task.update_parent(parent.map(|parent| parent.id))?;
+        insert_task(calendar, task)?;
+        let mut tasks = get_tasks(calendar)?;
+        roll_up(calendar, &mut tasks, task.parent)
+    })
+}
+
+// Moves a task under `parent`, or to the top level, rolling completion up to
+// both its old and new ancestors. A task cannot be moved under one of its own
+// subtasks.
+pub fn move_task(calendar: &Calendar, task: &mut Task, parent: Option<&Task>) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|_| {
+        let mut tasks = get_tasks(calendar)?;
+        if let Some(parent) = parent {
+            let parent_of = |task: &Task| task.parent.into_iter().collect();
+            if reaches(&tasks, &parent.id, &task.id, &parent_of) {
+                return Err(InvalidTaskError {
+                    reason: format!("'{}' cannot be a subtask of '{}', which is one of its own subtasks", task.title, parent.title),
+                }.into());
+            }
+        }
+        let old_parent = task.parent;
+        task.update_parent(parent.map(|parent| parent.id))?;
+        update_task(calendar, task)?;
+        replace_task(&mut tasks, task);
+        roll_up(calendar, &mut tasks, old_parent)?;
+        roll_up(calendar, &mut tasks, task.parent)
+    })
+}
+
+// Notes that `blocker` has to be done before `task`. A task cannot be blocked
+// by one that it blocks itself, directly or through others.
+pub fn block_task(calendar: &Calendar, task: &mut Task, blocker: &Task) -> Result<(), CaliError> {
+    calendar.get_store().transaction(|_| {
+        let tasks = get_tasks(calendar)?;
+        let blockers_of = |task: &Task| task.blocked_by.clone();
+        if reaches(&tasks, &blocker.id, &task.id, &blockers_of) {
+            return Err(InvalidTaskError {
+                reason: format!("'{}' cannot be blocked by '{}', which it blocks", task.title, blocker.title),
+            }.into());
+        }
+        task.add_blocker(blocker.id)?;
+        update_task(calendar, task)
+    })
+}
+
+// Notes that `blocker` no longer has to be done before `task`. Gets whether
+// it did.
+pub fn unblock_task(calendar: &Calendar, task: &mut Task, blocker: &Task) -> Result<bool, CaliError> {
+    calendar.get_store().transaction(|_| {
+        if !task.remove_blocker(&blocker.id) {
+            return Ok(false);
+        }
+        update_task(calendar, task)?;
+        Ok(true)
+    })
+}
+
+// Puts the new version of a task in place of the old one in `tasks`
+fn replace_task(tasks: &mut [Task], task: &Task) {
+    if let Some(existing) = tasks.iter_mut().find(|existing| existing.id == task.id) {
+        *existing = task.clone();
+    }
+}
+
+// Reads every task in a calendar, in the order of compare_tasks
+pub fn get_tasks(calendar: &Calendar) -> Result<Vec<Task>, CaliError> {
+    let mut tasks = calendar.get_store().get_backend().get_tasks(calendar.get_id())?;
+    tasks.sort_by(compare_tasks);
+    Ok(tasks)
+}
+
+// Checks if any calendar in the store has a task with the given id
+pub fn check_task(store: &Store, id: &Uuid) -> Result<bool, CaliError> {
+    store.get_backend().check_task(id)
+}
@@ -1,4 +1,10 @@
-use chrono::{Local};
+use crate::cali_error::*;
+use std::{env, fs};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+// Format of the canonical UTC timestamps stored in the database
+const STORAGE_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
 
 pub fn get_local_offset() -> (i32, i32) {
     let local_time = Local::now();
@@ -8,4 +14,88 @@ pub fn get_local_offset() -> (i32, i32) {
     let offset_minutes = (offset.local_minus_utc() % 3600) / 60;
 
     (offset_hours, offset_minutes)
-}
\ No newline at end of file
+}
+
+// Finds this machine's IANA time zone from the TZ environment variable or the
+// system configuration, falling back to a fixed zone with the current offset
+pub fn get_local_timezone() -> Tz {
+    if let Ok(name) = env::var("TZ") {
+        if let Ok(timezone) = name.trim_start_matches(':').parse() {
+            return timezone;
+        }
+    }
+    if let Ok(target) = fs::read_link("/etc/localtime") {
+        let target = target.to_string_lossy();
+        if let Some((_, name)) = target.split_once("zoneinfo/") {
+            if let Ok(timezone) = name.parse() {
+                return timezone;
+            }
+        }
+    }
+    if let Ok(name) = fs::read_to_string("/etc/timezone") {
+        if let Ok(timezone) = name.trim().parse() {
+            return timezone;
+        }
+    }
+
+    // The Etc zones use POSIX signs, so UTC-5 is "Etc/GMT+5"
+    match get_local_offset() {
+        (hours, 0) => format!("Etc/GMT{:+}", -hours).parse().unwrap_or(Tz::UTC),
+        _ => Tz::UTC,
+    }
+}
+
+// Looks up an IANA time zone such as "America/Chicago" by name
+pub fn parse_timezone(name: &str) -> Result<Tz, InvalidTimeError> {
+    name.parse::<Tz>().map_err(|_| InvalidTimeError {
+        reason: format!("unknown time zone '{}'", name),
+    })
+}
+
+// Formats a time as the canonical UTC text stored in the database.
+// The text sorts in chronological order, so it can be compared and indexed.
+pub fn to_storage_time(time: &DateTime<Tz>) -> String {
+    time.with_timezone(&Utc).format(STORAGE_FORMAT).to_string()
+}
+
+// Parses canonical UTC text from the database into a time in `timezone`
+pub fn from_storage_time(value: &str, timezone: Tz) -> Result<DateTime<Tz>, InvalidTimeError> {
+    let naive = NaiveDateTime::parse_from_str(value, STORAGE_FORMAT).map_err(|_| InvalidTimeError {
+        reason: format!("'{}' is not a stored UTC timestamp", value),
+    })?;
+
+    Ok(Utc.from_utc_datetime(&naive).with_timezone(&timezone))
+}
+
+// Interprets loosely formatted legacy text (RFC 3339, or a date and optional
+// time without an offset) as a UTC instant
+pub fn parse_legacy_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(Utc.from_utc_datetime(&naive));
+        }
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+}
+
+// Resolves a wall-clock time in `timezone`. Ambiguous times (the repeated hour
+// when clocks go back) take the earlier instant; times skipped when clocks go
+// forward are moved past the gap.
+pub fn resolve_local(timezone: Tz, naive: &NaiveDateTime) -> Option<DateTime<Tz>> {
+    if let Some(time) = timezone.from_local_datetime(naive).earliest() {
+        return Some(time);
+    }
+    timezone.from_local_datetime(&(*naive + Duration::hours(1))).earliest()
+}
+
+// Checks whether the span from `start` to `end` overlaps the window from
+// `from` (inclusive) to `to` (exclusive). Zero-length spans count when they
+// fall inside the window.
+pub fn overlaps<T: TimeZone, U: TimeZone>(start: &DateTime<T>, end: &DateTime<T>, from: &DateTime<U>, to: &DateTime<U>) -> bool {
+    *start < *to && (*end > *from || *start >= *from)
+}
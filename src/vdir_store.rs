@@ -0,0 +1,433 @@
+use crate::{cali_error::*, event::*, ical::*, store::*, task::*, time::*};
+use std::cell::RefCell;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use uuid::Uuid;
+
+// The file in a vdir naming the directory of the default calendar
+const DEFAULT_FILE: &str = ".default";
+
+// The file in a calendar's directory holding its name
+const NAME_FILE: &str = "displayname";
+
+// A change made in a transaction, which rolling it back undoes
+enum Change {
+    // A file changed, with what it held before, if it existed
+    File(PathBuf, Option<Vec<u8>>),
+    // A directory created
+    Directory(PathBuf),
+}
+
+// The changes made in a transaction, in the order they were made
+type Journal = Vec<Change>;
+
+// The events and tasks held in a file or directory
+type Items = (Vec<Event>, Vec<Task>);
+
+// Calendars kept as a vdir: a directory with a subdirectory for each calendar,
+// holding one iCalendar file per event or task, as read and written by vdirsyncer,
+// khal and similar tools. Calendar ids are given out as the directories are
+// found and only last as long as the store.
+pub struct VdirStore {
+    path: PathBuf,
+    directories: RefCell<Vec<String>>,
+    // One journal for each open transaction, innermost last
+    journals: RefCell<Vec<Journal>>,
+}
+
+impl VdirStore {
+    // Opens the vdir at `path`, creating the directory if there is none
+    pub fn open(path: &PathBuf) -> Result<VdirStore, CaliError> {
+        fs::create_dir_all(path)?;
+
+        Ok(VdirStore {
+            path: path.to_path_buf(),
+            directories: RefCell::new(Vec::new()),
+            journals: RefCell::new(Vec::new()),
+        })
+    }
+
+    // Gets the id of a calendar's directory, giving it one if it has none yet
+    fn directory_id(&self, directory: &str) -> i64 {
+        let mut directories = self.directories.borrow_mut();
+        let index = directories.iter().position(|existing| existing == directory).unwrap_or_else(|| {
+            directories.push(directory.to_string());
+            directories.len() - 1
+        });
+        index as i64 + 1
+    }
+
+    // Gets the directory of the calendar with the given id
+    fn calendar_directory(&self, id: i64) -> Result<PathBuf, CaliError> {
+        let directories = self.directories.borrow();
+        match usize::try_from(id - 1).ok().and_then(|index| directories.get(index)) {
+            Some(directory) if self.path.join(directory).is_dir() => Ok(self.path.join(directory)),
+            _ => Err(CalendarNotFoundError { name: id.to_string() }.into()),
+        }
+    }
+
+    // Gets the name of the calendar in `directory`
+    fn calendar_name(&self, directory: &Path) -> Result<String, CaliError> {
+        match fs::read_to_string(directory.join(NAME_FILE)) {
+            Ok(name) => Ok(name.trim_end().to_string()),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                Ok(directory.file_name().unwrap_or_default().to_string_lossy().to_string())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Gets the name of the default calendar's directory
+    fn default_directory(&self) -> Result<Option<String>, CaliError> {
+        match fs::read_to_string(self.path.join(DEFAULT_FILE)) {
+            Ok(directory) => Ok(Some(directory.trim_end().to_string())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Reads the events and tasks in one iCalendar file
+    fn read_file(&self, path: &Path) -> Result<Items, CaliError> {
+        let parsed = parse_ical(&fs::read_to_string(path)?, get_local_timezone())?;
+        let (events, _) = events_from_vevents(parsed.events);
+        let (tasks, _) = tasks_from_vtodos(parsed.todos);
+
+        Ok((events, tasks))
+    }
+
+    // Reads the events and tasks in a calendar's directory
+    fn read_directory(&self, directory: &Path) -> Result<Items, CaliError> {
+        let (mut events, mut tasks) = (Vec::new(), Vec::new());
+        for path in ics_files(directory)? {
+            let (file_events, file_tasks) = self.read_file(&path)?;
+            events.extend(file_events);
+            tasks.extend(file_tasks);
+        }
+
+        Ok((events, tasks))
+    }
+
+    // Finds the file holding the event or task with the given id in any
+    // calendar, with everything the file holds. Items are looked for in the
+    // file named by their id first, and then in files named some other way,
+    // as written by other tools.
+    fn find_file(&self, id: &Uuid) -> Result<Option<(PathBuf, Items)>, CaliError> {
+        let holds = |(events, tasks): &Items| {
+            events.iter().any(|event| event.get_id() == id) || tasks.iter().any(|task| task.get_id() == id)
+        };
+        let directories = subdirectories(&self.path)?;
+        for directory in &directories {
+            let path = directory.join(format!("{}.ics", id));
+            if path.is_file() {
+                let items = self.read_file(&path)?;
+                if holds(&items) {
+                    return Ok(Some((path, items)));
+                }
+            }
+        }
+        for directory in &directories {
+            for path in ics_files(directory)?.into_iter().filter(|path| !named_by_id(path)) {
+                let items = self.read_file(&path)?;
+                if holds(&items) {
+                    return Ok(Some((path, items)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Writes an event or task to its own file in a calendar's directory
+    fn write_item(&self, calendar_id: i64, id: &Uuid, events: &[Event], tasks: &[Task]) -> Result<(), CaliError> {
+        let directory = self.calendar_directory(calendar_id)?;
+        let text = write_calendar(&self.calendar_name(&directory)?, events, tasks, &Utc::now());
+        self.write_file(&directory.join(format!("{}.ics", id)), text.as_bytes())
+    }
+
+    // Takes the event or task with the given id out of the file it is in,
+    // removing the file if nothing else remains in it
+    fn unlink(&self, id: &Uuid) -> Result<(), CaliError> {
+        let Some((path, (mut events, mut tasks))) = self.find_file(id)? else {
+            return Ok(());
+        };
+        events.retain(|event| event.get_id() != id);
+        tasks.retain(|task| task.get_id() != id);
+        if events.is_empty() && tasks.is_empty() {
+            return self.remove_file(&path);
+        }
+        let directory = path.parent().unwrap_or(&self.path);
+        let text = write_calendar(&self.calendar_name(directory)?, &events, &tasks, &Utc::now());
+        self.write_file(&path, text.as_bytes())
+    }
+
+    // Unlinks the event or task with the given id if its file is in the
+    // calendar's directory
+    fn unlink_from(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError> {
+        let directory = self.calendar_directory(calendar_id)?;
+        match self.find_file(id)? {
+            Some((path, _)) if path.parent() == Some(directory.as_path()) => self.unlink(id),
+            _ => Ok(()),
+        }
+    }
+
+    // Notes what a file holds before it is changed, so a failing transaction
+    // can put it back
+    fn record(&self, path: &Path) -> Result<(), CaliError> {
+        if let Some(changes) = self.journals.borrow_mut().last_mut() {
+            let contents = match fs::read(path) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            changes.push(Change::File(path.to_path_buf(), contents));
+        }
+
+        Ok(())
+    }
+
+    // Creates a directory along with any missing parents, noting each one
+    // created so a failing transaction can remove it
+    fn create_directory(&self, directory: &Path) -> Result<(), CaliError> {
+        let mut missing: Vec<&Path> = directory.ancestors().take_while(|ancestor| !ancestor.exists()).collect();
+        fs::create_dir_all(directory)?;
+        if let Some(changes) = self.journals.borrow_mut().last_mut() {
+            missing.reverse();
+            changes.extend(missing.into_iter().map(|created| Change::Directory(created.to_path_buf())));
+        }
+
+        Ok(())
+    }
+
+    // Writes a file, noting in the open transaction's journal what it held
+    // before and the directories created for it
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<(), CaliError> {
+        if let Some(directory) = path.parent() {
+            self.create_directory(directory)?;
+        }
+        self.record(path)?;
+        Ok(replace_file(path, contents)?)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), CaliError> {
+        self.record(path)?;
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl CalendarStore for VdirStore {
+    fn get_path(&self) -> Option<&PathBuf> {
+        Some(&self.path)
+    }
+
+    fn get_calendars(&self) -> Result<Vec<CalendarRecord>, CaliError> {
+        let default = self.default_directory()?;
+        let mut calendars = Vec::new();
+        for directory in subdirectories(&self.path)? {
+            let directory_name = directory.file_name().unwrap_or_default().to_string_lossy().to_string();
+            calendars.push(CalendarRecord {
+                id: self.directory_id(&directory_name),
+                name: self.calendar_name(&directory)?,
+                default: default.as_ref() == Some(&directory_name),
+            });
+        }
+        calendars.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(calendars)
+    }
+
+    fn insert_calendar(&self, name: &str, default: bool) -> Result<i64, CaliError> {
+        let base = directory_name(name);
+        let mut directory = base.clone();
+        let mut copy = 1;
+        while self.path.join(&directory).exists() {
+            copy += 1;
+            directory = format!("{}-{}", base, copy);
+        }
+
+        self.write_file(&self.path.join(&directory).join(NAME_FILE), format!("{}\n", name).as_bytes())?;
+        let id = self.directory_id(&directory);
+        if default {
+            self.update_default(id)?;
+        }
+
+        Ok(id)
+    }
+
+    fn rename_calendar(&self, id: i64, new_name: &str) -> Result<(), CaliError> {
+        let directory = self.calendar_directory(id)?;
+        self.write_file(&directory.join(NAME_FILE), format!("{}\n", new_name).as_bytes())
+    }
+
+    fn remove_calendar(&self, id: i64) -> Result<(), CaliError> {
+        let directory = self.calendar_directory(id)?;
+        let directory_name = directory.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if self.default_directory()?.as_ref() == Some(&directory_name) {
+            self.remove_file(&self.path.join(DEFAULT_FILE))?;
+        }
+        let files = fs::read_dir(&directory)?.collect::<Result<Vec<_>, _>>()?;
+        for path in files.iter().map(|entry| entry.path()).filter(|path| path.is_file()) {
+            self.remove_file(&path)?;
+        }
+        if directory.is_dir() {
+            fs::remove_dir_all(&directory)?;
+        }
+
+        Ok(())
+    }
+
+    fn update_default(&self, id: i64) -> Result<(), CaliError> {
+        let directory = self.calendar_directory(id)?;
+        let directory_name = directory.file_name().unwrap_or_default().to_string_lossy().to_string();
+        self.write_file(&self.path.join(DEFAULT_FILE), format!("{}\n", directory_name).as_bytes())
+    }
+
+    fn insert_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError> {
+        self.write_item(calendar_id, event.get_id(), std::slice::from_ref(event), &[])
+    }
+
+    fn update_event(&self, calendar_id: i64, event: &Event) -> Result<(), CaliError> {
+        self.calendar_directory(calendar_id)?;
+        self.unlink(event.get_id())?;
+        self.write_item(calendar_id, event.get_id(), std::slice::from_ref(event), &[])
+    }
+
+    fn remove_event(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError> {
+        self.unlink_from(calendar_id, id)
+    }
+
+    fn get_events(&self, calendar_id: i64) -> Result<Vec<Event>, CaliError> {
+        let directory = self.calendar_directory(calendar_id)?;
+        let (mut events, _) = self.read_directory(&directory)?;
+        events.sort_by(|a, b| a.get_start().cmp(b.get_start()).then_with(|| a.get_name().cmp(b.get_name())));
+
+        Ok(events)
+    }
+
+    fn check_event(&self, id: &Uuid) -> Result<bool, CaliError> {
+        Ok(self.find_file(id)?.is_some_and(|(_, (events, _))| events.iter().any(|event| event.get_id() == id)))
+    }
+
+    fn insert_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError> {
+        self.write_item(calendar_id, task.get_id(), &[], std::slice::from_ref(task))
+    }
+
+    fn update_task(&self, calendar_id: i64, task: &Task) -> Result<(), CaliError> {
+        self.calendar_directory(calendar_id)?;
+        self.unlink(task.get_id())?;
+        self.write_item(calendar_id, task.get_id(), &[], std::slice::from_ref(task))
+    }
+
+    fn remove_task(&self, calendar_id: i64, id: &Uuid) -> Result<(), CaliError> {
+        self.unlink_from(calendar_id, id)
+    }
+
+    fn get_tasks(&self, calendar_id: i64) -> Result<Vec<Task>, CaliError> {
+        let directory = self.calendar_directory(calendar_id)?;
+        let (_, tasks) = self.read_directory(&directory)?;
+
+        Ok(tasks)
+    }
+
+    fn check_task(&self, id: &Uuid) -> Result<bool, CaliError> {
+        Ok(self.find_file(id)?.is_some_and(|(_, (_, tasks))| tasks.iter().any(|task| task.get_id() == id)))
+    }
+
+    // Keeps a journal of the files the operation changes and, if it fails,
+    // writes back what they held before and removes the directories it created
+    fn transaction(&self, operation: &mut dyn FnMut() -> Result<(), CaliError>) -> Result<(), CaliError> {
+        self.journals.borrow_mut().push(Vec::new());
+        let result = operation();
+        let changes = self.journals.borrow_mut().pop().unwrap_or_default();
+
+        match result {
+            Ok(()) => {
+                if let Some(outer) = self.journals.borrow_mut().last_mut() {
+                    outer.extend(changes);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                // Undoing is not journaled, as an outer transaction never
+                // saw these changes, and goes on past anything it cannot undo
+                for change in changes.into_iter().rev() {
+                    let _ = match change {
+                        Change::File(path, Some(contents)) => restore_file(&path, &contents),
+                        Change::File(path, None) => fs::remove_file(&path),
+                        Change::Directory(directory) => fs::remove_dir(&directory),
+                    };
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+// Replaces a file through a temporary one, so that other tools never see it
+// half written
+fn replace_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temporary = path.with_file_name(format!(".{}.tmp", path.file_name().unwrap_or_default().to_string_lossy()));
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+// Puts back what a file held before a failed transaction, along with the
+// directory it is in. It is written in place, as writing the temporary file
+// may be what failed.
+fn restore_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    fs::write(path, contents)
+}
+
+// Gets the subdirectories of a vdir, which are its calendars, in order
+fn subdirectories(path: &Path) -> Result<Vec<PathBuf>, CaliError> {
+    let mut directories = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry_path = entry?.path();
+        let hidden = entry_path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if entry_path.is_dir() && !hidden {
+            directories.push(entry_path);
+        }
+    }
+    directories.sort();
+
+    Ok(directories)
+}
+
+// Gets the iCalendar files in a calendar's directory, in order
+fn ics_files(directory: &Path) -> Result<Vec<PathBuf>, CaliError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "ics") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+// Checks whether a file is named by the id of an event or task, as the
+// files cali writes are
+fn named_by_id(path: &Path) -> bool {
+    path.file_stem().is_some_and(|stem| Uuid::parse_str(&stem.to_string_lossy()).is_ok())
+}
+
+// Gets a directory name for a calendar, keeping only characters that are
+// safe in file names
+fn directory_name(name: &str) -> String {
+    let directory: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    match directory.is_empty() {
+        true => "calendar".to_string(),
+        false => directory,
+    }
+}
@@ -0,0 +1,460 @@
+use crate::{event::*, search::*, task::*};
+use chrono::{Datelike, DateTime, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use std::collections::BTreeSet;
+
+const REVERSE: &str = "\x1b[7m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+// How a month grid is laid out
+pub struct MonthOptions {
+    pub week_start: Weekday,
+    pub week_numbers: bool,
+    pub highlight_today: bool,
+}
+
+impl Default for MonthOptions {
+    fn default() -> MonthOptions {
+        MonthOptions {
+            week_start: Weekday::Mon,
+            week_numbers: false,
+            highlight_today: false,
+        }
+    }
+}
+
+// How a week or day timeline is laid out
+pub struct TimelineOptions {
+    pub width: usize,
+    pub slot_minutes: u32,
+    pub twelve_hour: bool,
+}
+
+impl Default for TimelineOptions {
+    fn default() -> TimelineOptions {
+        TimelineOptions {
+            width: 80,
+            slot_minutes: 30,
+            twelve_hour: false,
+        }
+    }
+}
+
+// How dates and times of day are written
+#[derive(Clone)]
+pub struct TimeFormat {
+    pub date: String,
+    pub twelve_hour: bool,
+}
+
+impl Default for TimeFormat {
+    fn default() -> TimeFormat {
+        TimeFormat {
+            date: "%Y-%m-%d".to_string(),
+            twelve_hour: false,
+        }
+    }
+}
+
+impl TimeFormat {
+    // Formats a date, such as "2023-07-24"
+    pub fn format_date(&self, day: NaiveDate) -> String {
+        day.format(&self.date).to_string()
+    }
+
+    // Formats the time of day of `time`, such as "14:30" or "2:30pm"
+    pub fn format_clock(&self, time: &DateTime<Tz>) -> String {
+        if self.twelve_hour {
+            time.format("%-I:%M%P").to_string()
+        } else {
+            time.format("%H:%M").to_string()
+        }
+    }
+
+    // Formats the date and time of day of `time`
+    pub fn format_date_time(&self, time: &DateTime<Tz>) -> String {
+        format!("{} {}", self.format_date(time.date_naive()), self.format_clock(time))
+    }
+}
+
+// An occurrence listed in the agenda, with the name of its calendar
+pub struct AgendaEntry {
+    pub calendar: String,
+    pub occurrence: Occurrence,
+}
+
+// A task listed in the agenda, with the name of its calendar
+pub struct TaskEntry {
+    pub calendar: String,
+    pub task: Task,
+}
+
+// Gets the first and last days an occurrence takes place on, read in
+// `timezone`. An occurrence ending exactly at midnight does not take place on
+// the day it ends on.
+pub fn occurrence_days(occurrence: &Occurrence, timezone: Tz) -> (NaiveDate, NaiveDate) {
+    let start = occurrence.get_start().with_timezone(&timezone);
+    let end = occurrence.get_end().with_timezone(&timezone);
+    let mut last = end.date_naive();
+    if end > start && end.time() == NaiveTime::MIN {
+        last -= Duration::days(1);
+    }
+
+    (start.date_naive(), last)
+}
+
+// Gets the days on which the occurrences take place, read in `timezone`
+pub fn days_with_events(occurrences: &[Occurrence], timezone: Tz) -> BTreeSet<NaiveDate> {
+    let mut days = BTreeSet::new();
+    for occurrence in occurrences {
+        let (mut day, last) = occurrence_days(occurrence, timezone);
+        while day <= last {
+            days.insert(day);
+            day += Duration::days(1);
+        }
+    }
+
+    days
+}
+
+// Gets the first and last days of the month containing `day`
+pub fn month_bounds(day: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let first = day.with_day(1).unwrap();
+    let next = if first.month() == 12 {
+        NaiveDate::from_ymd_opt(first.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(first.year(), first.month() + 1, 1)
+    };
+
+    (first, next.map_or(NaiveDate::MAX, |next| next - Duration::days(1)))
+}
+
+// Renders the month containing `month` as a grid in the style of cal(1).
+// Days in `marked` are followed by "*" and other days in `all_day` by "+",
+// and `today` is shown in reverse video when highlighting is enabled.
+pub fn render_month(month: NaiveDate, today: NaiveDate, marked: &BTreeSet<NaiveDate>, all_day: &BTreeSet<NaiveDate>, options: &MonthOptions) -> String {
+    let (first, last) = month_bounds(month);
+    let week_column = if options.week_numbers { "    " } else { "" };
+
+    let mut header = String::from(if options.week_numbers { "Wk  " } else { "" });
+    let mut weekday = options.week_start;
+    let mut names = Vec::new();
+    for _ in 0..7 {
+        names.push(format!("{:<3}", &weekday.to_string()[..2]));
+        weekday = weekday.succ();
+    }
+    header.push_str(names.join(" ").trim_end());
+
+    let title = first.format("%B %Y").to_string();
+    let width = week_column.len() + 27;
+    let mut lines = vec![
+        format!("{:^width$}", title, width = width).trim_end().to_string(),
+        header,
+    ];
+
+    let offset = (first.weekday().num_days_from_monday() + 7 - options.week_start.num_days_from_monday()) % 7;
+    let mut row_start = first - Duration::days(offset as i64);
+    while row_start <= last {
+        let mut line = String::new();
+        if options.week_numbers {
+            // The ISO week is that of the row's Thursday, whatever day the row starts on
+            let thursday_offset = (Weekday::Thu.num_days_from_monday() + 7 - options.week_start.num_days_from_monday()) % 7;
+            let thursday = row_start + Duration::days(thursday_offset as i64);
+            line.push_str(&format!("{:>2}  ", thursday.iso_week().week()));
+        }
+
+        let mut cells = Vec::new();
+        for index in 0..7 {
+            let day = row_start + Duration::days(index);
+            if day < first || day > last {
+                cells.push("   ".to_string());
+                continue;
+            }
+            let number = format!("{:>2}", day.day());
+            let number = if options.highlight_today && day == today {
+                format!("{}{}{}", REVERSE, number, RESET)
+            } else {
+                number
+            };
+            let marker = if marked.contains(&day) {
+                "*"
+            } else if all_day.contains(&day) {
+                "+"
+            } else {
+                " "
+            };
+            cells.push(format!("{}{}", number, marker));
+        }
+        line.push_str(&cells.join(" "));
+        lines.push(line.trim_end().to_string());
+        row_start += Duration::days(7);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+// Marks every match of a search in `text`: in reverse video when `color`,
+// and otherwise between asterisks
+pub fn highlight_matches(query: &SearchQuery, text: &str, color: bool) -> String {
+    match color {
+        true => query.highlight(text, REVERSE, RESET),
+        false => query.highlight(text, "*", "*"),
+    }
+}
+
+// Formats a length of time compactly, such as "15m", "1h 30m" or "2d 6h"
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes();
+    let parts = [(minutes / 1440, "d"), (minutes % 1440 / 60, "h"), (minutes % 60, "m")];
+    let text: Vec<String> = parts.iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
+        .collect();
+
+    if text.is_empty() { "0m".to_string() } else { text.join(" ") }
+}
+
+// Renders the entries taking place on each of `days` days from `from`, read
+// in `timezone`, under a heading for each day that has any. An entry spanning
+// several days is listed under each of them, and all-day entries are counted
+// in whole days.
+pub fn render_agenda(entries: &[AgendaEntry], from: NaiveDate, days: u32, timezone: Tz, format: &TimeFormat) -> String {
+    let now = Utc::now().with_timezone(&timezone);
+    render_agenda_with_tasks(entries, &[], from, days, timezone, format, &now, false)
+}
+
+// Renders the agenda like render_agenda, with the open tasks due on each day
+// listed after its events. Open tasks due before `from` that are overdue at
+// `now` come first, under their own heading. Overdue tasks are marked as
+// such and, when `highlight` is set, shown in red.
+#[allow(clippy::too_many_arguments)]
+pub fn render_agenda_with_tasks(
+    entries: &[AgendaEntry],
+    tasks: &[TaskEntry],
+    from: NaiveDate,
+    days: u32,
+    timezone: Tz,
+    format: &TimeFormat,
+    now: &DateTime<Tz>,
+    highlight: bool,
+) -> String {
+    let times_width = if format.twelve_hour { 15 } else { 11 };
+    let open: Vec<&TaskEntry> = tasks.iter().filter(|entry| entry.task.is_open()).collect();
+    let task_line = |entry: &TaskEntry, times: String| {
+        let overdue = entry.task.is_overdue(now);
+        let line = format!(
+            "{:<width$}  {:<8}  {}{}  [{}]",
+            times,
+            "task",
+            entry.task.get_title(),
+            if overdue { " (overdue)" } else { "" },
+            entry.calendar,
+            width = times_width,
+        );
+        match overdue && highlight {
+            true => format!("  {}{}{}", RED, line.trim_end(), RESET),
+            false => format!("  {}", line),
+        }
+    };
+
+    let mut output = String::new();
+    let mut overdue = Vec::new();
+    for entry in &open {
+        let Some(due) = entry.task.get_due() else { continue };
+        if due.get_day(timezone) < from && entry.task.is_overdue(now) {
+            let times = match due {
+                Due::Date(day) => format!("due {}", format.format_date(*day)),
+                Due::Time(time) => format!("due {}", format.format_date_time(&time.with_timezone(&timezone))),
+            };
+            overdue.push(task_line(entry, times));
+        }
+    }
+    if !overdue.is_empty() {
+        output.push_str("Overdue\n");
+        for line in overdue {
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+    }
+
+    for offset in 0..days {
+        let day = from + Duration::days(offset as i64);
+        let mut lines = Vec::new();
+        for entry in entries {
+            let (first, last) = occurrence_days(&entry.occurrence, timezone);
+            if day < first || day > last {
+                continue;
+            }
+
+            if entry.occurrence.get_event().is_all_day() {
+                let length = (last - first).num_days() + 1;
+                let name = match length {
+                    1 => entry.occurrence.get_name().to_string(),
+                    _ => format!("{} (day {}/{})", entry.occurrence.get_name(), (day - first).num_days() + 1, length),
+                };
+                lines.push(format!(
+                    "  {:<width$}  {:<8}  {}  [{}]",
+                    "all day",
+                    format!("{}d", length),
+                    name,
+                    entry.calendar,
+                    width = times_width,
+                ));
+                continue;
+            }
+
+            let start = entry.occurrence.get_start().with_timezone(&timezone);
+            let end = entry.occurrence.get_end().with_timezone(&timezone);
+            let times = match (day == first, day == last) {
+                (true, true) => format!("{}-{}", format.format_clock(&start), format.format_clock(&end)),
+                (true, false) => format!("{} →", format.format_clock(&start)),
+                (false, true) => format!("→ {}", format.format_clock(&end)),
+                (false, false) => "all day".to_string(),
+            };
+            lines.push(format!(
+                "  {:<width$}  {:<8}  {}  [{}]",
+                times,
+                format_duration(end - start),
+                entry.occurrence.get_name(),
+                entry.calendar,
+                width = times_width,
+            ));
+        }
+        for entry in &open {
+            let times = match entry.task.get_due() {
+                Some(due) if due.get_day(timezone) == day => match due {
+                    Due::Date(_) => "due".to_string(),
+                    Due::Time(time) => format!("due {}", format.format_clock(&time.with_timezone(&timezone))),
+                },
+                _ => continue,
+            };
+            lines.push(task_line(entry, times));
+        }
+
+        if !lines.is_empty() {
+            output.push_str(&format!("{} {}\n", day.format("%a"), format.format_date(day)));
+            for line in lines {
+                output.push_str(line.trim_end());
+                output.push('\n');
+            }
+        }
+    }
+
+    if output.is_empty() {
+        output.push_str("No events.\n");
+    }
+    output
+}
+
+// A timed occurrence placed in one day column of a timeline, in minutes
+// from the start of the day
+struct Placement<'a> {
+    name: &'a str,
+    start: i64,
+    end: i64,
+    lane: usize,
+}
+
+// Renders `days` side by side as a timeline with one row per slot. Each timed
+// occurrence shows its name in the row it starts in and dots in the rows it
+// continues through. Overlapping occurrences share their day's column in
+// lanes, and occurrences lasting all day or running over several days are
+// listed in a band above the grid. The grid covers 08:00 to 18:00, stretched
+// to fit every occurrence shown in it.
+pub fn render_timeline(days: &[NaiveDate], occurrences: &[Occurrence], timezone: Tz, options: &TimelineOptions) -> String {
+    let gutter = 6;
+    let columns = days.len().max(1);
+    let column_width = (options.width.saturating_sub(gutter) / columns).saturating_sub(1).max(4);
+    let slot = options.slot_minutes.max(1) as i64;
+
+    let mut band: Vec<Vec<&str>> = vec![Vec::new(); days.len()];
+    let mut timed: Vec<Vec<Placement>> = days.iter().map(|_| Vec::new()).collect();
+    for occurrence in occurrences {
+        let (first, last) = occurrence_days(occurrence, timezone);
+        let start = occurrence.get_start().with_timezone(&timezone);
+        let end = occurrence.get_end().with_timezone(&timezone);
+        let all_day = occurrence.get_event().is_all_day()
+            || first != last
+            || (start.time() == NaiveTime::MIN && end - start >= Duration::days(1));
+
+        for (index, day) in days.iter().enumerate() {
+            if *day < first || *day > last {
+                continue;
+            }
+            if all_day {
+                band[index].push(occurrence.get_name());
+                continue;
+            }
+            let start = (start.naive_local() - day.and_time(NaiveTime::MIN)).num_minutes();
+            let end = (end.naive_local() - day.and_time(NaiveTime::MIN)).num_minutes();
+            timed[index].push(Placement { name: occurrence.get_name(), start, end: end.max(start + 1), lane: 0 });
+        }
+    }
+
+    // Each occurrence takes the first lane that is free when it starts
+    let mut lane_counts = Vec::new();
+    for placements in timed.iter_mut() {
+        placements.sort_by_key(|placement| (placement.start, -placement.end));
+        let mut lane_ends: Vec<i64> = Vec::new();
+        for placement in placements.iter_mut() {
+            placement.lane = match lane_ends.iter().position(|end| *end <= placement.start) {
+                Some(lane) => lane,
+                None => {
+                    lane_ends.push(0);
+                    lane_ends.len() - 1
+                }
+            };
+            lane_ends[placement.lane] = placement.end;
+        }
+        lane_counts.push(lane_ends.len().max(1));
+    }
+
+    let first_minute = timed.iter().flatten().map(|p| p.start.div_euclid(slot) * slot).fold(8 * 60, i64::min);
+    let last_minute = timed.iter().flatten().map(|p| (p.end + slot - 1).div_euclid(slot) * slot).fold(18 * 60, i64::max);
+
+    let mut lines = Vec::new();
+    let headings: Vec<String> = days.iter().map(|day| fit(&day.format("%a %d").to_string(), column_width)).collect();
+    lines.push(format!("{}{}", " ".repeat(gutter), headings.join("|")));
+    for row in 0..band.iter().map(Vec::len).max().unwrap_or(0) {
+        let cells: Vec<String> = band.iter().map(|names| fit(names.get(row).copied().unwrap_or(""), column_width)).collect();
+        lines.push(format!("{}{}", " ".repeat(gutter), cells.join("|")));
+    }
+    lines.push("-".repeat(gutter + columns * (column_width + 1) - 1));
+
+    let mut minute = first_minute;
+    while minute < last_minute {
+        let label = if minute % 60 == 0 && options.twelve_hour {
+            let hour = minute.div_euclid(60) % 24;
+            format!("{:>4}  ", format!("{}{}", (hour + 11) % 12 + 1, if hour < 12 { "am" } else { "pm" }))
+        } else if minute % 60 == 0 {
+            format!("{:02}:{:02} ", minute.div_euclid(60) % 24, minute % 60)
+        } else {
+            " ".repeat(gutter)
+        };
+
+        let cells: Vec<String> = timed.iter().zip(&lane_counts).map(|(placements, lanes)| {
+            let lane_width = (column_width.saturating_sub(lanes - 1) / lanes).max(1);
+            let lane_cells: Vec<String> = (0..*lanes).map(|lane| {
+                let placement = placements.iter()
+                    .find(|p| p.lane == lane && p.start < minute + slot && p.end > minute);
+                match placement {
+                    Some(p) if p.start >= minute || minute == first_minute => fit(p.name, lane_width),
+                    Some(_) => ".".repeat(lane_width),
+                    None => fit("", lane_width),
+                }
+            }).collect();
+            fit(&lane_cells.join(" "), column_width)
+        }).collect();
+        lines.push(format!("{}{}", label, cells.join("|")));
+        minute += slot;
+    }
+
+    lines.iter().map(|line| line.trim_end().to_string() + "\n").collect()
+}
+
+// Pads or cuts `text` to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let text: String = text.chars().take(width).collect();
+    format!("{:<width$}", text, width = width)
+}
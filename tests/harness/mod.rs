@@ -0,0 +1,142 @@
+// Test Harness
+//
+// Every test gets a store of its own, so tests can run in parallel without
+// seeing each other's calendars or touching a real calendar database.
+
+use cali::{calendar::*, event::*, store::*};
+use chrono::{DateTime, NaiveDate, TimeZone};
+use chrono_tz::{America::Chicago, Tz};
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// A fresh SQLite database for one test, removed when the test ends
+pub struct TestStore {
+    store: Store,
+    path: PathBuf,
+}
+
+impl TestStore {
+    // Opens an empty database at a path no other test uses
+    pub fn new() -> TestStore {
+        let path = std::env::temp_dir().join(format!("cali-test-{}.db", Uuid::new_v4()));
+        TestStore { store: Store::open(&path).unwrap(), path }
+    }
+
+    // Opens an empty database held in memory, for tests that need no file
+    pub fn in_memory() -> TestStore {
+        TestStore { store: Store::open_in_memory().unwrap(), path: PathBuf::from(MEMORY_DATABASE) }
+    }
+
+    pub fn get_store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for TestStore {
+    fn drop(&mut self) {
+        if self.path.as_os_str() != MEMORY_DATABASE {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Known dummy start time for test events
+pub fn dummy_start() -> DateTime<Tz> {
+    Chicago.with_ymd_and_hms(2023, 7, 23, 9, 0, 0).unwrap()
+}
+
+// Known dummy end time for test events
+pub fn dummy_end() -> DateTime<Tz> {
+    Chicago.with_ymd_and_hms(2023, 7, 23, 10, 30, 0).unwrap()
+}
+
+// Builds a fixture event, at the dummy times and not recurring unless told otherwise
+pub struct EventBuilder {
+    name: String,
+    start: DateTime<Tz>,
+    end: DateTime<Tz>,
+    days: Option<(NaiveDate, NaiveDate)>,
+    recurring: Recurring,
+}
+
+impl EventBuilder {
+    pub fn new(name: &str) -> EventBuilder {
+        EventBuilder {
+            name: name.to_string(),
+            start: dummy_start(),
+            end: dummy_end(),
+            days: None,
+            recurring: Recurring::No,
+        }
+    }
+
+    pub fn between(mut self, start: DateTime<Tz>, end: DateTime<Tz>) -> EventBuilder {
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    // Makes the event take up whole days, from `first` to `last`
+    pub fn all_day(mut self, first: NaiveDate, last: NaiveDate) -> EventBuilder {
+        self.days = Some((first, last));
+        self
+    }
+
+    pub fn recurring(mut self, recurring: Recurring) -> EventBuilder {
+        self.recurring = recurring;
+        self
+    }
+
+    pub fn build(self) -> Event {
+        match self.days {
+            Some((first, last)) => Event::new_all_day(&self.name, first, last, self.recurring).unwrap(),
+            None => Event::new(&self.name, self.start, self.end, self.recurring).unwrap(),
+        }
+    }
+}
+
+// Builds a fixture calendar holding the given events. Unlike Calendar::new,
+// it only becomes the default calendar when asked to.
+pub struct CalendarBuilder {
+    name: String,
+    default: bool,
+    events: Vec<Event>,
+}
+
+impl CalendarBuilder {
+    pub fn new(name: &str) -> CalendarBuilder {
+        CalendarBuilder {
+            name: name.to_string(),
+            default: false,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn set_default(mut self, default: bool) -> CalendarBuilder {
+        self.default = default;
+        self
+    }
+
+    pub fn event(mut self, event: EventBuilder) -> CalendarBuilder {
+        self.events.push(event.build());
+        self
+    }
+
+    // Adds the calendar and its events to `store`
+    pub fn build(self, store: &Store) -> Calendar {
+        let id = store.get_backend().insert_calendar(&self.name, false).unwrap();
+        if self.default {
+            store.get_backend().update_default(id).unwrap();
+        }
+        let calendar = Calendar::from(&self.name, store).unwrap();
+        for event in &self.events {
+            calendar.add_event(event).unwrap();
+        }
+        calendar
+    }
+}
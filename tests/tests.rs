@@ -1,95 +1,116 @@
-use cali::{calendar::*, event::*};
+use cali::{calendar::*, cali_error::*, config::*, date_expr::*, event::*, ical::*, migration::*, parser::*, paths::*, recurrence::*, search::*, store::*, tag::*, task::*, time::*, vdir_store::*, view::*};
+use clap::Parser;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc, Weekday};
+use chrono_tz::{America::{Chicago, New_York}, Tz};
+use std::collections::BTreeSet;
+use std::fs;
 use std::path::PathBuf;
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Result};
+use uuid::Uuid;
 
-// Test Helper Methods
+mod harness;
+use harness::*;
 
-// Inserts a prebuilt test event under calendar 'name' into the database
-fn insert_test_calendar(path: &PathBuf, name: &str, set_default: bool) -> Result<()> {
-    let conn = Connection::open(path)?;
-    // Insert a row with calendar_name "test_calendar"
-    conn.execute(
-        "INSERT INTO calendars (calendar_name, event_id, event_name, event_start, event_end, event_recurring, is_default) 
-        VALUES (?1, '1', 'Test Event', '2023-07-23', '2023-07-25', 0, ?2)",
-        params![name, set_default],
-    )?;
-
-    Ok(())
-}
-
-// Removes any events under calendar 'name' from the database
-fn remove_test_calendar(path: &PathBuf, name: &str) -> Result<()> {
-    let conn = Connection::open(path)?;
-
-    // Check if the "calendars" table exists
-    if let Ok(table_exists) = conn.query_row(
-        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'calendars'",
-        params![],
-        |_| Ok(true),
-    ) {
-        // If the table exists, execute the DELETE statement
-        if table_exists {
-            conn.execute("DELETE FROM calendars WHERE calendar_name = ?1", params![name])?;
-        }
-    }
+// Test Helper Methods
 
-    Ok(())
+// Copies a fixture database to a fresh temporary path so migrations can modify it
+fn copy_fixture_database(fixture: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("cali-{}.db", Uuid::new_v4()));
+    fs::copy(fixture, &path).unwrap();
+    path
 }
 
-// Removes all entries in the database
-fn remove_all_calendars(path: &PathBuf) -> Result<()> {
-    let conn = Connection::open(path)?;
+// Integration Tests
 
-    // Check if the "calendars" table exists
-    if let Ok(table_exists) = conn.query_row(
-        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'calendars'",
-        params![],
-        |_| Ok(true),
-    ) {
-        // If the table exists, execute the DELETE statement
-        if table_exists {
-            conn.execute("DELETE FROM calendars WHERE calendar_name IS NOT NULL", params![])?;
-        }
-    }
 
-    Ok(())
+// Database
+
+#[test]
+fn test_new_database() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let result = init_database(&path);
+    assert!(result.is_ok());
 }
 
-// Cleans database and inserts a test calendar
-fn clean_insert_test_calendar(path: &PathBuf, name: &str, set_default: bool) -> Result<()> {
+// Migration
+
+#[test]
+fn test_migrate_legacy_fixture() {
+    let path = copy_fixture_database("tests/fixtures/legacy_calendar.db");
+    let store = Store::open(&path).unwrap();
     init_database(&path).unwrap();
-    remove_test_calendar(&path, name).unwrap();
-    insert_test_calendar(&path, name, set_default).unwrap();
-    Ok(())
+    let conn = open_database(&path).unwrap();
+    assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    // Each calendar name becomes one calendar row
+    let calendars: i64 = conn.query_row("SELECT COUNT(*) FROM calendars", params![], |row| row.get(0)).unwrap();
+    assert_eq!(calendars, 2);
+    assert_eq!(get_default(&store).unwrap(), Some("work".to_string()));
+    // Events keep valid ids and get fresh ones for duplicated legacy ids
+    let work = Calendar::from("work", &store).unwrap();
+    let standup = get_event(&work, "Standup", true).unwrap();
+    assert_eq!(standup.len(), 1);
+    assert_eq!(standup[0].get_id().to_string(), "6f1c1d9e-3f4a-4c1b-9a53-0d6f3f7f2a10");
+    assert_eq!(standup[0].get_recurring(), &Recurring::Daily);
+    assert_eq!(to_storage_time(standup[0].get_start()), "2023-07-24T09:00:00Z");
+    let home = Calendar::from("home", &store).unwrap();
+    let home_events = get_event(&home, "", false).unwrap();
+    assert_eq!(home_events.len(), 2);
+    assert_ne!(home_events[0].get_id(), home_events[1].get_id());
+    assert_eq!(home_events[1].get_recurring(), &Recurring::Weekly);
+    // An event whose times cannot be read is set aside rather than left to
+    // stop its calendar from loading
+    assert_eq!(get_events(&home).unwrap().len(), 2);
+    let unreadable = get_unreadable_events(&store).unwrap();
+    assert_eq!(unreadable.len(), 1);
+    assert_eq!((unreadable[0].name.as_str(), unreadable[0].calendar.as_str()), ("Broken", "home"));
+    assert_eq!(unreadable[0].reason, "the start 'start_time' is not a date or time");
+    let listed = run_cli(&path, &["calendars", "list"]).unwrap();
+    assert!(listed.ends_with("Set aside when the database was upgraded, as they could not be read:\n\
+        \x20 'Broken' (9d2c4b1a-7e3f-4a5b-8c6d-2e1f0a9b8c7d) in 'home': the start 'start_time' is not a date or time\n"));
+    // Events from before the search index are indexed too
+    assert_eq!(search_events(&work, &"standup".parse().unwrap()).unwrap().len(), 1);
+    drop(conn);
+    fs::remove_file(&path).unwrap();
 }
 
-// Cleans database and doesn't insert the test calendar
-fn clean_noinsert_test_calendar(path: &PathBuf, name: &str) -> Result<()> {
+#[test]
+fn test_migrate_checked_in_database() {
+    let path = copy_fixture_database("calendar.db");
+    let store = Store::open(&path).unwrap();
     init_database(&path).unwrap();
-    remove_test_calendar(&path, name).unwrap();
-    Ok(())
+    let conn = open_database(&path).unwrap();
+    assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    assert_eq!(get_default(&store).unwrap(), None);
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    insert_event(&calendar, &EventBuilder::new("test event").build()).unwrap();
+    assert_eq!(get_event(&calendar, "test event", true).unwrap().len(), 1);
+    drop(conn);
+    fs::remove_file(&path).unwrap();
 }
 
-// Creates a test event with dummy info given 'name'
-fn new_test_dummy_event(name: &str) -> Event {
-    let start = "start_time";
-    let end = "end_time";
-    let recurring = Recurring::No;
-    Event::new(name, start, end, recurring)
+#[test]
+fn test_migrate_is_idempotent() {
+    let path = copy_fixture_database("tests/fixtures/legacy_calendar.db");
+    init_database(&path).unwrap();
+    init_database(&path).unwrap();
+    let mut conn = open_database(&path).unwrap();
+    assert_eq!(migrate(&mut conn).unwrap(), SCHEMA_VERSION);
+    let events: i64 = conn.query_row("SELECT COUNT(*) FROM events", params![], |row| row.get(0)).unwrap();
+    assert_eq!(events, 4);
+    drop(conn);
+    fs::remove_file(&path).unwrap();
 }
 
-
-
-// Integration Tests
-
-
-// Database
-
 #[test]
-fn test_new_database() {
-    let path = PathBuf::from("tests/test.db");
-    let result = init_database(&path);
-    assert!(result.is_ok());
+fn test_migrate_rejects_newer_schema() {
+    let path = copy_fixture_database("tests/fixtures/legacy_calendar.db");
+    init_database(&path).unwrap();
+    let mut conn = open_database(&path).unwrap();
+    conn.pragma_update(None, "user_version", &(SCHEMA_VERSION + 1)).unwrap();
+    assert!(migrate(&mut conn).is_err());
+    drop(conn);
+    fs::remove_file(&path).unwrap();
 }
 
 // Calendar
@@ -97,120 +118,120 @@ fn test_new_database() {
 #[test]
 fn test_verify_calendar_does_exist() {
     let name = "test calendar";
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let set_default = false;
-    clean_insert_test_calendar(&path, &name, set_default).unwrap();
-    let result = check_calendar(&path, name);
+    CalendarBuilder::new(name).set_default(set_default).event(EventBuilder::new("Test Event")).build(&store);
+    let result = check_calendar(&store, name);
     assert!(result.unwrap());
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_delete_calendar_success() {
     let name = "test calendar";
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let set_default = false;
-    clean_insert_test_calendar(&path, &name, set_default).unwrap();
-    let calendar = Calendar::from(name, &path).unwrap();
+    CalendarBuilder::new(name).set_default(set_default).event(EventBuilder::new("Test Event")).build(&store);
+    let calendar = Calendar::from(name, &store).unwrap();
     remove_calendar(&calendar).unwrap();
-    let result = check_calendar(&path, name);
+    let result = check_calendar(&store, name);
     assert!(!result.unwrap());
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_verify_calendar_does_not_exist() {
     let name = "test calendar";
-    let path = PathBuf::from("tests/test.db");
-    clean_noinsert_test_calendar(&path, name).unwrap();
-    let result = check_calendar(&path, name);
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let result = check_calendar(&store, name);
     assert!(!result.unwrap());
 }
 
 #[test]
 fn test_get_default_does_exist() {
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let default_calendar = "default calendar";
     let new_calendar = "test calendar";
-    clean_insert_test_calendar(&path, &default_calendar, true).unwrap();
-    clean_insert_test_calendar(&path, &new_calendar, false).unwrap();
-    let result = get_default(&path).unwrap();
+    CalendarBuilder::new(default_calendar).set_default(true).event(EventBuilder::new("Test Event")).build(&store);
+    CalendarBuilder::new(new_calendar).set_default(false).event(EventBuilder::new("Test Event")).build(&store);
+    let result = get_default(&store).unwrap();
     assert_eq!(result, Some(default_calendar.to_string()));
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_get_default_does_not_exist() {
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let new_calendar = "test calendar";
-    clean_insert_test_calendar(&path, &new_calendar, false).unwrap();
-    let result = get_default(&path).unwrap();
+    CalendarBuilder::new(new_calendar).set_default(false).event(EventBuilder::new("Test Event")).build(&store);
+    let result = get_default(&store).unwrap();
     assert_eq!(result, None);
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_default_empty_database() {
-    let path = PathBuf::from("tests/test.db");
-    remove_all_calendars(&path).unwrap();
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let new_calendar = "test calendar";
-    let result_check = check_default(&path, new_calendar).unwrap();
-    assert_eq!(result_check, false);
-    let result_get = get_default(&path).unwrap();
+    let result_check = check_default(&store, new_calendar).unwrap();
+    assert!(!result_check);
+    let result_get = get_default(&store).unwrap();
     assert_eq!(result_get, None);
 }
 
 #[test]
 fn test_change_default() {
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let default_calendar = "default calendar";
     let new_calendar = "test calendar";
-    clean_insert_test_calendar(&path, &default_calendar, true).unwrap();
-    clean_insert_test_calendar(&path, &new_calendar, false).unwrap();
-    let result = get_default(&path).unwrap();
+    CalendarBuilder::new(default_calendar).set_default(true).event(EventBuilder::new("Test Event")).build(&store);
+    CalendarBuilder::new(new_calendar).set_default(false).event(EventBuilder::new("Test Event")).build(&store);
+    let result = get_default(&store).unwrap();
     assert_eq!(result, Some(default_calendar.to_string()));
     assert_ne!(result, Some(new_calendar.to_string()));
-    update_default(&path, new_calendar).unwrap();
-    let result2 = get_default(&path).unwrap();
+    update_default(&store, new_calendar).unwrap();
+    let result2 = get_default(&store).unwrap();
     assert_eq!(result2, Some(new_calendar.to_string()));
     assert_ne!(result2, Some(default_calendar.to_string()));
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_new_calendar_success_default_not_exists() {
     let name = "test calendar";
-    let path = PathBuf::from("tests/test.db");
-    clean_noinsert_test_calendar(&path, name).unwrap();
-    let new_calendar = Calendar::new(&name, &path).unwrap();
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
+    let new_calendar = Calendar::new(name, &store).unwrap();
     assert_eq!(new_calendar.get_name(), name);
-    assert_eq!(new_calendar.get_path(), &path);
+    assert_eq!(new_calendar.get_path(), Some(&path));
     assert_eq!(new_calendar.get_default(), &true);
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_new_calendar_success_default_exists() {
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
     let default_calendar = "default calendar";
     let name = "test calendar";
-    clean_insert_test_calendar(&path, &default_calendar, true).unwrap();
-    clean_noinsert_test_calendar(&path, &name).unwrap();
-    let new_calendar = Calendar::new(name, &path).unwrap();
+    CalendarBuilder::new(default_calendar).set_default(true).event(EventBuilder::new("Test Event")).build(&store);
+    let new_calendar = Calendar::new(name, &store).unwrap();
     assert_eq!(new_calendar.get_name(), name);
-    assert_eq!(new_calendar.get_path(), &path);
+    assert_eq!(new_calendar.get_path(), Some(&path));
     assert_eq!(new_calendar.get_default(), &false);
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_new_calendar_fail_name_exists() {
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let name = "test calendar";
-    clean_insert_test_calendar(&path, &name, true).unwrap();
-    let result = Calendar::new(name, &path);
+    CalendarBuilder::new(name).set_default(true).event(EventBuilder::new("Test Event")).build(&store);
+    let result = Calendar::new(name, &store);
     assert!(result.is_err());
-    remove_all_calendars(&path).unwrap();
 }
 
 // Event
@@ -218,24 +239,76 @@ fn test_new_calendar_fail_name_exists() {
 #[test]
 fn test_new_event_success() {
     let name = "test_event";
-    let event = new_test_dummy_event(name);
+    let event = EventBuilder::new(name).build();
     assert_eq!(event.get_name(), name);
     // compare against known dummy values
-    assert_eq!(event.get_start(), "start_time");
-    assert_eq!(event.get_end(), "end_time");
+    assert_eq!(event.get_start(), &dummy_start());
+    assert_eq!(event.get_end(), &dummy_end());
     assert_eq!(event.get_recurring(), &Recurring::No);
 }
 
+#[test]
+fn test_new_event_rejects_end_before_start() {
+    let result = Event::new("test_event", dummy_end(), dummy_start(), Recurring::No);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_update_event_rejects_end_before_start() {
+    let mut event = EventBuilder::new("test_event").build();
+    let early_end = dummy_start() - Duration::hours(1);
+    assert!(event.update_end(early_end).is_err());
+    assert_eq!(event.get_end(), &dummy_end());
+}
+
+#[test]
+fn test_event_typed_getters() {
+    let event = EventBuilder::new("test_event").build();
+    assert_eq!(event.get_timezone(), Chicago);
+    assert_eq!(event.get_duration(), Duration::minutes(90));
+    assert!(event.get_start() < event.get_end());
+}
+
+#[test]
+fn test_event_times_stored_as_utc() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    let event = EventBuilder::new("test event").build();
+    insert_event(&calendar, &event).unwrap();
+    let conn = open_database(&path).unwrap();
+    let (start, timezone): (String, String) = conn.query_row(
+        "SELECT event_start, event_timezone FROM events WHERE event_id = ?1",
+        params![event.get_id().to_string()],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).unwrap();
+    assert_eq!(start, "2023-07-23T14:00:00Z");
+    assert_eq!(timezone, "America/Chicago");
+    let got_event = get_event(&calendar, "test event", true).unwrap();
+    assert_eq!(got_event[0].get_start().timezone(), Chicago);
+    assert_eq!(got_event[0].get_start(), event.get_start());
+}
+
+#[test]
+fn test_storage_time_round_trip() {
+    let stored = to_storage_time(&dummy_start());
+    assert_eq!(stored, "2023-07-23T14:00:00Z");
+    assert_eq!(from_storage_time(&stored, Chicago).unwrap(), dummy_start());
+    assert!(from_storage_time("start_time", Chicago).is_err());
+    assert!(parse_timezone("Not/AZone").is_err());
+}
+
 #[test]
 fn test_new_insert_get_exact_event_success() {
     // Create test calendar
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let calendar_name = "test calendar";
-    clean_noinsert_test_calendar(&path, &calendar_name).unwrap();
-    let calendar = Calendar::new(calendar_name, &path).unwrap();
+    let calendar = Calendar::new(calendar_name, &store).unwrap();
     // Create test event
     let event_name = "test event";
-    let event = new_test_dummy_event(event_name);
+    let event = EventBuilder::new(event_name).build();
     // Insert
     insert_event(&calendar, &event).unwrap();
     // Get
@@ -245,21 +318,21 @@ fn test_new_insert_get_exact_event_success() {
     assert_eq!(got_event.len(), 1);
     assert_eq!(got_event[0].get_name(), event_name);
     // compare against known dummy values
-    assert_eq!(got_event[0].get_start(), "start_time");
-    assert_eq!(got_event[0].get_end(), "end_time");
+    assert_eq!(got_event[0].get_start(), &dummy_start());
+    assert_eq!(got_event[0].get_end(), &dummy_end());
     assert_eq!(got_event[0].get_recurring(), &Recurring::No);
 }
 
 #[test]
 fn test_new_insert_get_nonexact_event_success() {
     // Create test calendar
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let calendar_name = "test calendar";
-    clean_noinsert_test_calendar(&path, &calendar_name).unwrap();
-    let calendar = Calendar::new(calendar_name, &path).unwrap();
+    let calendar = Calendar::new(calendar_name, &store).unwrap();
     // Create test event
     let event_name = "test event";
-    let event = new_test_dummy_event(event_name);
+    let event = EventBuilder::new(event_name).build();
     // Insert
     insert_event(&calendar, &event).unwrap();
     // Get
@@ -270,25 +343,24 @@ fn test_new_insert_get_nonexact_event_success() {
     assert_eq!(got_event.len(), 1);
     assert_eq!(got_event[0].get_name(), event_name);
     // compare against known dummy values
-    assert_eq!(got_event[0].get_start(), "start_time");
-    assert_eq!(got_event[0].get_end(), "end_time");
+    assert_eq!(got_event[0].get_start(), &dummy_start());
+    assert_eq!(got_event[0].get_end(), &dummy_end());
     assert_eq!(got_event[0].get_recurring(), &Recurring::No);
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_new_insert_get_multiple_nonexact_event_success() {
     // Create test calendar
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let calendar_name = "test calendar";
-    clean_noinsert_test_calendar(&path, &calendar_name).unwrap();
-    let calendar = Calendar::new(calendar_name, &path).unwrap();
+    let calendar = Calendar::new(calendar_name, &store).unwrap();
     // Create and insert test events
     let event_name1 = "test event 1";
-    let event1 = new_test_dummy_event(event_name1);
+    let event1 = EventBuilder::new(event_name1).build();
     insert_event(&calendar, &event1).unwrap();
     let event_name2 = "test event 2";
-    let event2 = new_test_dummy_event(event_name2);
+    let event2 = EventBuilder::new(event_name2).build();
     insert_event(&calendar, &event2).unwrap();
     // Get
     let exact = false;
@@ -300,23 +372,22 @@ fn test_new_insert_get_multiple_nonexact_event_success() {
     assert_eq!(got_event[1].get_name(), event_name2);
     assert_ne!(got_event[0].get_name(), got_event[1].get_name());
     assert_ne!(got_event[0].get_id(), got_event[1].get_id());
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_new_insert_update_get_exact_event_success() {
     // Create test calendar
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let calendar_name = "test calendar";
-    clean_noinsert_test_calendar(&path, &calendar_name).unwrap();
-    let calendar = Calendar::new(calendar_name, &path).unwrap();
+    let calendar = Calendar::new(calendar_name, &store).unwrap();
     // Create test event
     let event_name = "test event";
-    let mut event = new_test_dummy_event(event_name);
+    let mut event = EventBuilder::new(event_name).build();
     // Insert
     insert_event(&calendar, &event).unwrap();
     // Update
-    let new_start = "new_start";
+    let new_start = Chicago.with_ymd_and_hms(2023, 7, 23, 8, 0, 0).unwrap();
     event.update_start(new_start).unwrap();
     update_event(&calendar, &event).unwrap();
     // Get
@@ -326,25 +397,24 @@ fn test_new_insert_update_get_exact_event_success() {
     assert_eq!(got_event.len(), 1);
     assert_eq!(got_event[0].get_name(), event_name);
     // compare against known dummy values
-    assert_eq!(got_event[0].get_start(), new_start);  // Verify update
-    assert_eq!(got_event[0].get_end(), "end_time"); 
+    assert_eq!(got_event[0].get_start(), &new_start);  // Verify update
+    assert_eq!(got_event[0].get_end(), &dummy_end()); 
     assert_eq!(got_event[0].get_recurring(), &Recurring::No);
-    remove_all_calendars(&path).unwrap();
 }
 
 #[test]
 fn test_new_insert_get_multiple_nonexact_delete_event_success() {
     // Create test calendar
-    let path = PathBuf::from("tests/test.db");
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
     let calendar_name = "test calendar";
-    clean_noinsert_test_calendar(&path, &calendar_name).unwrap();
-    let calendar = Calendar::new(calendar_name, &path).unwrap();
+    let calendar = Calendar::new(calendar_name, &store).unwrap();
     // Create and insert test events
     let event_name1 = "test event 1";
-    let event1 = new_test_dummy_event(event_name1);
+    let event1 = EventBuilder::new(event_name1).build();
     insert_event(&calendar, &event1).unwrap();
     let event_name2 = "test event 2";
-    let event2 = new_test_dummy_event(event_name2);
+    let event2 = EventBuilder::new(event_name2).build();
     insert_event(&calendar, &event2).unwrap();
     // Delete event
     remove_event(&calendar, &event2).unwrap();
@@ -355,5 +425,2334 @@ fn test_new_insert_get_multiple_nonexact_delete_event_success() {
     // Check values
     assert_eq!(got_event.len(), 1); 
     assert_eq!(got_event[0].get_name(), event_name1);
-    remove_all_calendars(&path).unwrap();
-}
\ No newline at end of file
+}
+#[test]
+fn test_new_calendar_persists_without_events() {
+    let name = "test calendar";
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new(name, &store).unwrap();
+    assert!(check_calendar(&store, name).unwrap());
+    let reopened = Calendar::from(name, &store).unwrap();
+    assert_eq!(reopened.get_id(), calendar.get_id());
+    assert_eq!(reopened.get_default(), &true);
+}
+
+#[test]
+fn test_delete_calendar_removes_events() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    insert_event(&calendar, &EventBuilder::new("test event").build()).unwrap();
+    remove_calendar(&calendar).unwrap();
+    let conn = open_database(&path).unwrap();
+    let remaining: i64 = conn.query_row("SELECT COUNT(*) FROM events", params![], |row| row.get(0)).unwrap();
+    assert_eq!(remaining, 0);
+}
+
+#[test]
+fn test_rename_calendar_keeps_events() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let mut calendar = Calendar::new("test calendar", &store).unwrap();
+    insert_event(&calendar, &EventBuilder::new("test event").build()).unwrap();
+    calendar.update_name("renamed calendar").unwrap();
+    assert!(!check_calendar(&store, "test calendar").unwrap());
+    assert!(check_default(&store, "renamed calendar").unwrap());
+    let got_event = get_event(&calendar, "test event", true).unwrap();
+    assert_eq!(got_event.len(), 1);
+}
+
+// Range queries
+
+// Creates an event in Chicago time from (year, month, day, hour, minute) tuples
+fn new_test_timed_event(name: &str, start: (i32, u32, u32, u32, u32), end: (i32, u32, u32, u32, u32), recurring: Recurring) -> Event {
+    let start = Chicago.with_ymd_and_hms(start.0, start.1, start.2, start.3, start.4, 0).unwrap();
+    let end = Chicago.with_ymd_and_hms(end.0, end.1, end.2, end.3, end.4, 0).unwrap();
+    EventBuilder::new(name).between(start, end).recurring(recurring).build()
+}
+
+#[test]
+fn test_events_between_single_events() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    calendar.add_event(&new_test_timed_event("before", (2023, 7, 22, 9, 0), (2023, 7, 22, 10, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("late", (2023, 7, 24, 15, 0), (2023, 7, 24, 16, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("early", (2023, 7, 24, 8, 0), (2023, 7, 24, 9, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("spanning", (2023, 7, 23, 22, 0), (2023, 7, 24, 1, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("after", (2023, 7, 25, 0, 0), (2023, 7, 25, 1, 0), Recurring::No)).unwrap();
+    let from = Chicago.with_ymd_and_hms(2023, 7, 24, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2023, 7, 25, 0, 0, 0).unwrap();
+    let occurrences = calendar.events_between(&from, &to).unwrap();
+    let names: Vec<&str> = occurrences.iter().map(|o| o.get_event().get_name()).collect();
+    assert_eq!(names, vec!["spanning", "early", "late"]);
+}
+
+#[test]
+fn test_events_between_expands_daily_across_dst() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    calendar.add_event(&new_test_timed_event("standup", (2023, 1, 2, 9, 0), (2023, 1, 2, 9, 15), Recurring::Daily)).unwrap();
+    // Clocks in Chicago go forward on 2023-03-12
+    let from = Chicago.with_ymd_and_hms(2023, 3, 11, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2023, 3, 14, 0, 0, 0).unwrap();
+    let occurrences = calendar.events_between(&from, &to).unwrap();
+    assert_eq!(occurrences.len(), 3);
+    for (day, occurrence) in (11..14).zip(&occurrences) {
+        assert_eq!(occurrence.get_start(), &Chicago.with_ymd_and_hms(2023, 3, day, 9, 0, 0).unwrap());
+        assert_eq!(*occurrence.get_end() - *occurrence.get_start(), Duration::minutes(15));
+    }
+}
+
+#[test]
+fn test_monthly_occurrences_skip_missing_days() {
+    let event = new_test_timed_event("rent", (2023, 1, 31, 12, 0), (2023, 1, 31, 13, 0), Recurring::Monthly);
+    let months: Vec<u32> = event.occurrences().take(4).map(|o| o.get_start().month()).collect();
+    assert_eq!(months, vec![1, 3, 5, 7]);
+    let single = new_test_timed_event("once", (2023, 1, 31, 12, 0), (2023, 1, 31, 13, 0), Recurring::No);
+    assert_eq!(single.occurrences().count(), 1);
+}
+
+#[test]
+fn test_next_event_across_recurring_and_single() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    calendar.add_event(&new_test_timed_event("weekly", (2023, 7, 3, 9, 0), (2023, 7, 3, 10, 0), Recurring::Weekly)).unwrap();
+    calendar.add_event(&new_test_timed_event("single", (2023, 7, 25, 9, 0), (2023, 7, 25, 10, 0), Recurring::No)).unwrap();
+    let after = Chicago.with_ymd_and_hms(2023, 7, 18, 12, 0, 0).unwrap();
+    let next = get_next_event(&calendar, &after).unwrap().unwrap();
+    assert_eq!(next.get_event().get_name(), "weekly");
+    assert_eq!(next.get_start(), &Chicago.with_ymd_and_hms(2023, 7, 24, 9, 0, 0).unwrap());
+    let after = Chicago.with_ymd_and_hms(2023, 7, 24, 12, 0, 0).unwrap();
+    let next = get_next_event(&calendar, &after).unwrap().unwrap();
+    assert_eq!(next.get_event().get_name(), "single");
+}
+
+#[test]
+fn test_next_event_empty_calendar() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    assert!(calendar.next_event().unwrap().is_none());
+}
+
+// Recurrence rules
+
+// Creates a time in New York, the zone used by the RFC 5545 examples
+fn new_york(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+    New_York.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+}
+
+// Expands at most `limit` start times of `rule` for a series beginning at `start`
+fn rule_starts(rule: &str, start: DateTime<Tz>, limit: usize) -> Vec<DateTime<Tz>> {
+    let rule: RRule = rule.parse().unwrap();
+    rule.occurrences(start).take(limit).collect()
+}
+
+// Formats start times as "YYYY-MM-DD HH:MM" in their own zone
+fn local_times(times: &[DateTime<Tz>]) -> Vec<String> {
+    times.iter().map(|time| time.format("%Y-%m-%d %H:%M").to_string()).collect()
+}
+
+#[test]
+fn test_rule_daily_count() {
+    let starts = rule_starts("FREQ=DAILY;COUNT=10", new_york(1997, 9, 2, 9, 0), 20);
+    assert_eq!(starts.len(), 10);
+    assert_eq!(starts[9], new_york(1997, 9, 11, 9, 0));
+}
+
+#[test]
+fn test_rule_daily_until() {
+    let starts = rule_starts("FREQ=DAILY;UNTIL=19971224T000000Z", new_york(1997, 9, 2, 9, 0), 200);
+    assert_eq!(starts.len(), 113);
+    // The wall-clock time is kept when daylight saving time ends
+    assert_eq!(starts[112], new_york(1997, 12, 23, 9, 0));
+}
+
+#[test]
+fn test_rule_every_other_week_with_week_start() {
+    let starts = rule_starts("FREQ=WEEKLY;INTERVAL=2;WKST=SU;BYDAY=TU,TH;COUNT=8", new_york(1997, 9, 2, 9, 0), 20);
+    assert_eq!(local_times(&starts), vec![
+        "1997-09-02 09:00", "1997-09-04 09:00", "1997-09-16 09:00", "1997-09-18 09:00",
+        "1997-09-30 09:00", "1997-10-02 09:00", "1997-10-14 09:00", "1997-10-16 09:00",
+    ]);
+}
+
+#[test]
+fn test_rule_first_friday_of_month() {
+    let starts = rule_starts("FREQ=MONTHLY;COUNT=10;BYDAY=1FR", new_york(1997, 9, 5, 9, 0), 20);
+    assert_eq!(local_times(&starts), vec![
+        "1997-09-05 09:00", "1997-10-03 09:00", "1997-11-07 09:00", "1997-12-05 09:00",
+        "1998-01-02 09:00", "1998-02-06 09:00", "1998-03-06 09:00", "1998-04-03 09:00",
+        "1998-05-01 09:00", "1998-06-05 09:00",
+    ]);
+}
+
+#[test]
+fn test_rule_last_friday_of_month() {
+    let starts = rule_starts("FREQ=MONTHLY;BYDAY=-1FR", new_york(2023, 1, 27, 16, 0), 4);
+    assert_eq!(local_times(&starts), vec![
+        "2023-01-27 16:00", "2023-02-24 16:00", "2023-03-31 16:00", "2023-04-28 16:00",
+    ]);
+}
+
+#[test]
+fn test_rule_last_work_day_with_set_position() {
+    let starts = rule_starts("FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1", new_york(1997, 9, 30, 9, 0), 7);
+    assert_eq!(local_times(&starts), vec![
+        "1997-09-30 09:00", "1997-10-31 09:00", "1997-11-28 09:00", "1997-12-31 09:00",
+        "1998-01-30 09:00", "1998-02-27 09:00", "1998-03-31 09:00",
+    ]);
+}
+
+#[test]
+fn test_rule_negative_month_day() {
+    let starts = rule_starts("FREQ=MONTHLY;BYMONTHDAY=-3;COUNT=6", new_york(1997, 9, 28, 9, 0), 10);
+    assert_eq!(local_times(&starts), vec![
+        "1997-09-28 09:00", "1997-10-29 09:00", "1997-11-28 09:00", "1997-12-29 09:00",
+        "1998-01-29 09:00", "1998-02-26 09:00",
+    ]);
+}
+
+#[test]
+fn test_rule_yearly_ordinal_weekday() {
+    let starts = rule_starts("FREQ=YEARLY;BYDAY=20MO", new_york(1997, 5, 19, 9, 0), 3);
+    assert_eq!(local_times(&starts), vec!["1997-05-19 09:00", "1998-05-18 09:00", "1999-05-17 09:00"]);
+}
+
+#[test]
+fn test_rule_yearly_by_month_and_weekday() {
+    let starts = rule_starts("FREQ=YEARLY;BYMONTH=3;BYDAY=TH", new_york(1997, 3, 13, 9, 0), 7);
+    assert_eq!(local_times(&starts), vec![
+        "1997-03-13 09:00", "1997-03-20 09:00", "1997-03-27 09:00",
+        "1998-03-05 09:00", "1998-03-12 09:00", "1998-03-19 09:00", "1998-03-26 09:00",
+    ]);
+}
+
+#[test]
+fn test_rule_yearly_leap_day() {
+    let starts = rule_starts("FREQ=YEARLY", new_york(2024, 2, 29, 9, 0), 3);
+    assert_eq!(local_times(&starts), vec!["2024-02-29 09:00", "2028-02-29 09:00", "2032-02-29 09:00"]);
+}
+
+#[test]
+fn test_rule_minutely_interval() {
+    let starts = rule_starts("FREQ=MINUTELY;INTERVAL=15;COUNT=6", new_york(1997, 9, 2, 9, 0), 10);
+    assert_eq!(local_times(&starts), vec![
+        "1997-09-02 09:00", "1997-09-02 09:15", "1997-09-02 09:30",
+        "1997-09-02 09:45", "1997-09-02 10:00", "1997-09-02 10:15",
+    ]);
+}
+
+#[test]
+fn test_rule_start_counts_as_first_occurrence() {
+    // 1997-09-01 is a Monday, so the rule itself would first match on Tuesday
+    let starts = rule_starts("FREQ=WEEKLY;BYDAY=TU;COUNT=3", new_york(1997, 9, 1, 9, 0), 10);
+    assert_eq!(local_times(&starts), vec!["1997-09-01 09:00", "1997-09-02 09:00", "1997-09-09 09:00"]);
+}
+
+#[test]
+fn test_rule_display_round_trip() {
+    let text = "FREQ=YEARLY;INTERVAL=2;UNTIL=20301231T235959Z;BYMONTH=1,7;BYDAY=-1SU;BYSETPOS=1;WKST=SU";
+    let rule: RRule = text.parse().unwrap();
+    assert_eq!(rule.to_string(), text);
+    assert_eq!(rule.get_frequency(), Frequency::Yearly);
+    assert_eq!(rule.get_by_day(), &[WeekdayNum::new(Some(-1), Weekday::Sun)]);
+    let prefixed: RRule = "RRULE:freq=monthly;bymonthday=1".parse().unwrap();
+    assert_eq!(prefixed.to_string(), "FREQ=MONTHLY;BYMONTHDAY=1");
+}
+
+#[test]
+fn test_rule_rejects_invalid_rules() {
+    for rule in [
+        "INTERVAL=2",
+        "FREQ=DAILY;COUNT=3;UNTIL=20230101",
+        "FREQ=WEEKLY;BYDAY=1MO",
+        "FREQ=WEEKLY;BYMONTHDAY=3",
+        "FREQ=MONTHLY;BYDAY=6MO",
+        "FREQ=MONTHLY;BYSETPOS=1",
+        "FREQ=DAILY;INTERVAL=0",
+        "FREQ=DAILY;BYHOUR=9",
+        "FREQ=DAILY;FREQ=WEEKLY",
+        "FREQ=FORTNIGHTLY",
+    ] {
+        assert!(rule.parse::<RRule>().is_err(), "{} should be rejected", rule);
+    }
+}
+
+#[test]
+fn test_rule_stored_and_expanded_for_event() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    let rule: RRule = "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU".parse().unwrap();
+    let event = new_test_timed_event("review", (2023, 7, 4, 14, 0), (2023, 7, 4, 15, 0), Recurring::Rule(rule.clone()));
+    calendar.add_event(&event).unwrap();
+    let got_event = get_event(&calendar, "review", true).unwrap();
+    assert_eq!(got_event[0].get_recurring(), &Recurring::Rule(rule));
+    let from = Chicago.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2023, 8, 1, 0, 0, 0).unwrap();
+    let days: Vec<u32> = calendar.events_between(&from, &to).unwrap().iter().map(|o| o.get_start().day()).collect();
+    assert_eq!(days, vec![4, 18]);
+}
+
+// Recurrence exceptions
+
+// Names and local start times of the occurrences in July 2023
+fn july_occurrences(calendar: &Calendar) -> Vec<(String, String)> {
+    let from = Chicago.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2023, 8, 1, 0, 0, 0).unwrap();
+    calendar.events_between(&from, &to).unwrap()
+        .iter()
+        .map(|o| (o.get_name().to_string(), o.get_start().format("%m-%d %H:%M").to_string()))
+        .collect()
+}
+
+// Creates a weekly Monday event from 2023-07-03 in a fresh test calendar
+fn new_weekly_test_series(store: &Store) -> (Calendar, Event) {
+    let calendar = Calendar::new("test calendar", store).unwrap();
+    let event = new_test_timed_event("sync", (2023, 7, 3, 9, 0), (2023, 7, 3, 10, 0), Recurring::Weekly);
+    calendar.add_event(&event).unwrap();
+    (calendar, event)
+}
+
+fn monday(day: u32) -> DateTime<Tz> {
+    Chicago.with_ymd_and_hms(2023, 7, day, 9, 0, 0).unwrap()
+}
+
+#[test]
+fn test_remove_single_occurrence() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let (calendar, mut event) = new_weekly_test_series(&store);
+    calendar.remove_event_scoped(&mut event, Scope::This(monday(17))).unwrap();
+    let days: Vec<String> = july_occurrences(&calendar).into_iter().map(|(_, start)| start).collect();
+    assert_eq!(days, vec!["07-03 09:00", "07-10 09:00", "07-24 09:00", "07-31 09:00"]);
+    let stored = get_event(&calendar, "sync", true).unwrap();
+    assert_eq!(stored[0].get_exdates(), &[monday(17)]);
+}
+
+#[test]
+fn test_remove_this_and_following_occurrences() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let (calendar, mut event) = new_weekly_test_series(&store);
+    calendar.remove_event_scoped(&mut event, Scope::ThisAndFollowing(monday(17))).unwrap();
+    assert_eq!(july_occurrences(&calendar).len(), 2);
+    calendar.remove_event_scoped(&mut event, Scope::ThisAndFollowing(monday(3))).unwrap();
+    assert!(get_event(&calendar, "sync", true).unwrap().is_empty());
+}
+
+#[test]
+fn test_remove_only_occurrence_removes_event() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    let mut event = EventBuilder::new("test event").build();
+    calendar.add_event(&event).unwrap();
+    calendar.remove_event_scoped(&mut event, Scope::This(dummy_start())).unwrap();
+    assert!(get_event(&calendar, "test event", true).unwrap().is_empty());
+}
+
+#[test]
+fn test_added_occurrence_date() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    let mut event = new_test_timed_event("talk", (2023, 6, 1, 9, 0), (2023, 6, 1, 10, 0), Recurring::No);
+    event.add_rdate(Chicago.with_ymd_and_hms(2023, 7, 12, 15, 0, 0).unwrap());
+    calendar.add_event(&event).unwrap();
+    assert_eq!(july_occurrences(&calendar), vec![("talk".to_string(), "07-12 15:00".to_string())]);
+}
+
+#[test]
+fn test_override_single_occurrence() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let (calendar, mut event) = new_weekly_test_series(&store);
+    let changes = EventChanges {
+        name: Some("moved sync".to_string()),
+        start: Some(Chicago.with_ymd_and_hms(2023, 7, 11, 13, 0, 0).unwrap()),
+        end: None,
+    };
+    calendar.update_event_scoped(&mut event, Scope::This(monday(10)), &changes).unwrap();
+    let occurrences = july_occurrences(&calendar);
+    assert_eq!(occurrences[1], ("moved sync".to_string(), "07-11 13:00".to_string()));
+    assert_eq!(occurrences[2], ("sync".to_string(), "07-17 09:00".to_string()));
+    // The stored override keeps the duration and is read back
+    let stored = get_event(&calendar, "sync", true).unwrap();
+    let stored_override = &stored[0].get_overrides()[0];
+    assert_eq!(stored_override.get_recurrence_id(), &monday(10));
+    assert_eq!(*stored_override.get_end() - *stored_override.get_start(), Duration::hours(1));
+}
+
+#[test]
+fn test_override_moves_occurrence_into_window() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("test calendar", &store).unwrap();
+    let mut event = new_test_timed_event("sync", (2023, 8, 7, 9, 0), (2023, 8, 7, 10, 0), Recurring::Weekly);
+    calendar.add_event(&event).unwrap();
+    let changes = EventChanges { start: Some(monday(31)), ..EventChanges::default() };
+    let recurrence_id = Chicago.with_ymd_and_hms(2023, 8, 14, 9, 0, 0).unwrap();
+    calendar.update_event_scoped(&mut event, Scope::This(recurrence_id), &changes).unwrap();
+    assert_eq!(july_occurrences(&calendar), vec![("sync".to_string(), "07-31 09:00".to_string())]);
+    let next = get_next_event(&calendar, &monday(24)).unwrap().unwrap();
+    assert_eq!(next.get_start(), &monday(31));
+    assert_eq!(next.get_recurrence_id(), &recurrence_id);
+    assert!(next.is_overridden());
+}
+
+#[test]
+fn test_update_this_and_following_splits_series() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let (calendar, mut event) = new_weekly_test_series(&store);
+    let changes = EventChanges {
+        name: Some("late sync".to_string()),
+        start: Some(Chicago.with_ymd_and_hms(2023, 7, 17, 11, 0, 0).unwrap()),
+        end: None,
+    };
+    let following = calendar.update_event_scoped(&mut event, Scope::ThisAndFollowing(monday(17)), &changes).unwrap().unwrap();
+    assert_ne!(following.get_id(), event.get_id());
+    let occurrences = july_occurrences(&calendar);
+    assert_eq!(occurrences, vec![
+        ("sync".to_string(), "07-03 09:00".to_string()),
+        ("sync".to_string(), "07-10 09:00".to_string()),
+        ("late sync".to_string(), "07-17 11:00".to_string()),
+        ("late sync".to_string(), "07-24 11:00".to_string()),
+        ("late sync".to_string(), "07-31 11:00".to_string()),
+    ]);
+}
+
+#[test]
+fn test_split_series_keeps_total_count() {
+    let rule: RRule = "FREQ=DAILY;COUNT=5".parse().unwrap();
+    let mut event = new_test_timed_event("course", (2023, 7, 3, 9, 0), (2023, 7, 3, 10, 0), Recurring::Rule(rule));
+    let following = event.split_at(&Chicago.with_ymd_and_hms(2023, 7, 5, 9, 0, 0).unwrap()).unwrap();
+    assert_eq!(event.occurrences().count(), 2);
+    assert_eq!(following.occurrences().count(), 3);
+}
+
+#[test]
+fn test_update_all_occurrences_moves_exceptions() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let (calendar, mut event) = new_weekly_test_series(&store);
+    calendar.remove_event_scoped(&mut event, Scope::This(monday(10))).unwrap();
+    let changes = EventChanges { start: Some(Chicago.with_ymd_and_hms(2023, 7, 3, 8, 0, 0).unwrap()), ..EventChanges::default() };
+    calendar.update_event_scoped(&mut event, Scope::All, &changes).unwrap();
+    let days: Vec<String> = july_occurrences(&calendar).into_iter().map(|(_, start)| start).collect();
+    assert_eq!(days, vec!["07-03 08:00", "07-17 08:00", "07-24 08:00", "07-31 08:00"]);
+}
+
+#[test]
+fn test_scoped_change_requires_existing_occurrence() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let (calendar, mut event) = new_weekly_test_series(&store);
+    let tuesday = Chicago.with_ymd_and_hms(2023, 7, 11, 9, 0, 0).unwrap();
+    assert!(calendar.remove_event_scoped(&mut event, Scope::This(tuesday)).is_err());
+    assert!(calendar.update_event_scoped(&mut event, Scope::ThisAndFollowing(tuesday), &EventChanges::default()).is_err());
+}
+
+
+// Command line
+
+// Runs `cali` with the given arguments against the test database and returns its output
+fn run_cli(path: &PathBuf, args: &[&str]) -> Result<String, String> {
+    run_cli_with(path, &Config::new(), args)
+}
+
+// Runs cali with the given arguments and settings, returning what it printed
+fn run_cli_with(path: &PathBuf, config: &Config, args: &[&str]) -> Result<String, String> {
+    let parser = InputParser::try_parse_from(std::iter::once("cali").chain(args.iter().copied()))
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    parser.execute(path, config, &mut out).map_err(|e| e.to_string())?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn test_cli_add_and_list_events() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
+    run_cli(&path, &["calendars", "add", "work"]).unwrap();
+    run_cli(&path, &["add", "Standup", "-s", "2023-07-24 09:00", "-e", "2023-07-24 09:15", "-z", "America/Chicago", "-r", "weekly"]).unwrap();
+    run_cli(&path, &["add", "Review", "-s", "2023-07-25T16:00:00Z", "-z", "UTC"]).unwrap();
+
+    let listed = run_cli(&path, &["list", "--from", "2023-07-24T00:00:00-05:00", "--to", "2023-08-01T00:00:00-05:00"]).unwrap();
+    let titles: Vec<&str> = listed.lines().map(|line| line.rsplit("  ").next().unwrap()).collect();
+    assert_eq!(titles, vec!["Standup", "Review", "Standup"]);
+    let event = &get_event(&Calendar::from("work", &store).unwrap(), "Review", true).unwrap()[0];
+    assert_eq!(event.get_duration(), Duration::hours(1));
+}
+
+#[test]
+fn test_cli_edit_and_remove_occurrences() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
+    run_cli(&path, &["add", "Standup", "-s", "2023-07-03 09:00", "-z", "America/Chicago", "-r", "FREQ=WEEKLY;COUNT=5"]).unwrap();
+    run_cli(&path, &["edit", "stand", "--at", "2023-07-10 09:00", "--title", "Late standup", "-s", "2023-07-10 11:00", "-e", "2023-07-10 12:00"]).unwrap();
+    run_cli(&path, &["rm", "Standup", "--at", "2023-07-24 09:00", "--following"]).unwrap();
+
+    let calendar = Calendar::from("default calendar", &store).unwrap();
+    let occurrences = july_occurrences(&calendar);
+    assert_eq!(occurrences, vec![
+        ("Standup".to_string(), "07-03 09:00".to_string()),
+        ("Late standup".to_string(), "07-10 11:00".to_string()),
+        ("Standup".to_string(), "07-17 09:00".to_string()),
+    ]);
+    assert!(run_cli(&path, &["show", "Standup"]).unwrap().contains("changed:    2023-07-10 09:00 to 2023-07-10 11:00 - 2023-07-10 12:00 'Late standup'"));
+
+    run_cli(&path, &["rm", "Standup"]).unwrap();
+    assert!(july_occurrences(&calendar).is_empty());
+}
+
+#[test]
+fn test_cli_ambiguous_and_missing_events() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    run_cli(&path, &["add", "Team lunch", "-s", "2023-07-24 12:00", "-z", "UTC"]).unwrap();
+    run_cli(&path, &["add", "Team review", "-s", "2023-07-25 12:00", "-z", "UTC"]).unwrap();
+
+    assert!(run_cli(&path, &["rm", "Team"]).unwrap_err().contains("matches 2 events"));
+    assert!(run_cli(&path, &["show", "Dentist"]).is_err());
+    assert!(run_cli(&path, &["list", "--calendar", "missing"]).is_err());
+    assert!(run_cli(&path, &["edit", "lunch", "--following"]).is_err());
+    run_cli(&path, &["rm", "Team lunch"]).unwrap();
+    assert!(run_cli(&path, &["show", "Team"]).unwrap().starts_with("Team review\n"));
+}
+
+#[test]
+fn test_cli_manage_calendars() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    run_cli(&path, &["calendars", "add", "home"]).unwrap();
+    run_cli(&path, &["calendars", "add", "work"]).unwrap();
+    run_cli(&path, &["calendars", "default", "work"]).unwrap();
+    run_cli(&path, &["calendars", "rename", "home", "personal"]).unwrap();
+    assert!(run_cli(&path, &["calendars", "rename", "personal", "work"]).is_err());
+    assert_eq!(run_cli(&path, &["calendars"]).unwrap(), "personal\nwork (default)\n");
+
+    run_cli(&path, &["calendars", "rm", "personal"]).unwrap();
+    assert!(run_cli(&path, &["calendars", "rm", "personal"]).is_err());
+    assert_eq!(run_cli(&path, &["calendars", "list"]).unwrap(), "work (default)\n");
+}
+
+#[test]
+fn test_cli_reads_create_no_calendar() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    assert_eq!(run_cli(&path, &["list"]).unwrap(), "");
+    assert_eq!(run_cli(&path, &["search", "standup"]).unwrap(), "No events.\n");
+    assert_eq!(run_cli(&path, &["todo"]).unwrap(), "No tasks.\n");
+    run_cli(&path, &["agenda"]).unwrap();
+    run_cli(&path, &["month"]).unwrap();
+    assert!(run_cli(&path, &["export"]).is_err());
+    assert!(run_cli(&path, &["rm", "Standup"]).is_err());
+    assert_eq!(run_cli(&path, &["calendars"]).unwrap(), "");
+
+    // Adding an event is what creates the calendar
+    run_cli(&path, &["add", "Standup", "-s", "2023-07-24 09:00"]).unwrap();
+    assert_eq!(run_cli(&path, &["calendars"]).unwrap(), "default calendar (default)\n");
+}
+
+
+// Date expressions
+
+// A fixed "now" for date expressions: Wednesday 2023-07-19 10:15 in Chicago
+fn expr_now() -> DateTime<Tz> {
+    Chicago.with_ymd_and_hms(2023, 7, 19, 10, 15, 0).unwrap()
+}
+
+// Reads a date expression against the fixed "now" and formats it in Chicago time
+fn expr(input: &str) -> String {
+    parse_datetime(input, &expr_now()).unwrap().format("%Y-%m-%d %H:%M %a").to_string()
+}
+
+// Reads a date range against the fixed "now" and formats its ends in Chicago time
+fn expr_range(input: &str) -> (String, Option<String>) {
+    let (start, end) = parse_range(input, &expr_now()).unwrap();
+    let format = |time: DateTime<Tz>| time.format("%Y-%m-%d %H:%M").to_string();
+    (format(start), end.map(format))
+}
+
+#[test]
+fn test_expr_relative_days() {
+    assert_eq!(expr("now"), "2023-07-19 10:15 Wed");
+    assert_eq!(expr("today"), "2023-07-19 00:00 Wed");
+    assert_eq!(expr("Tomorrow"), "2023-07-20 00:00 Thu");
+    assert_eq!(expr("yesterday"), "2023-07-18 00:00 Tue");
+    assert_eq!(expr("next week"), "2023-07-26 00:00 Wed");
+}
+
+#[test]
+fn test_expr_weekday_names() {
+    assert_eq!(expr("friday"), "2023-07-21 00:00 Fri");
+    assert_eq!(expr("mon"), "2023-07-24 00:00 Mon");
+    assert_eq!(expr("wednesday"), "2023-07-19 00:00 Wed");
+    assert_eq!(expr("this wed"), "2023-07-19 00:00 Wed");
+    assert_eq!(expr("next wednesday"), "2023-07-26 00:00 Wed");
+    assert_eq!(expr("next friday"), "2023-07-21 00:00 Fri");
+    assert_eq!(expr("last friday"), "2023-07-14 00:00 Fri");
+    assert_eq!(expr("last wednesday"), "2023-07-12 00:00 Wed");
+}
+
+#[test]
+fn test_expr_twelve_and_twenty_four_hour_clocks() {
+    assert_eq!(expr("9:30"), "2023-07-19 09:30 Wed");
+    assert_eq!(expr("14:05"), "2023-07-19 14:05 Wed");
+    assert_eq!(expr("2pm"), "2023-07-19 14:00 Wed");
+    assert_eq!(expr("2:45 PM"), "2023-07-19 14:45 Wed");
+    assert_eq!(expr("12am"), "2023-07-19 00:00 Wed");
+    assert_eq!(expr("12pm"), "2023-07-19 12:00 Wed");
+    assert_eq!(expr("noon"), "2023-07-19 12:00 Wed");
+    assert_eq!(expr("at 9"), "2023-07-19 09:00 Wed");
+    assert_eq!(expr("tomorrow 9:30"), "2023-07-20 09:30 Thu");
+    assert_eq!(expr("next friday at 2pm"), "2023-07-21 14:00 Fri");
+    assert_eq!(expr("6pm monday"), "2023-07-24 18:00 Mon");
+}
+
+#[test]
+fn test_expr_in_and_ago() {
+    assert_eq!(expr("in 3 days"), "2023-07-22 10:15 Sat");
+    assert_eq!(expr("in 2 weeks"), "2023-08-02 10:15 Wed");
+    assert_eq!(expr("in 90 minutes"), "2023-07-19 11:45 Wed");
+    assert_eq!(expr("in 1h30m"), "2023-07-19 11:45 Wed");
+    assert_eq!(expr("in 3 days at 9am"), "2023-07-22 09:00 Sat");
+    assert_eq!(expr("2 days ago"), "2023-07-17 10:15 Mon");
+    assert_eq!(expr("3h ago"), "2023-07-19 07:15 Wed");
+}
+
+#[test]
+fn test_expr_calendar_dates() {
+    assert_eq!(expr("2023-07-24"), "2023-07-24 00:00 Mon");
+    assert_eq!(expr("2023-07-24 09:30"), "2023-07-24 09:30 Mon");
+    assert_eq!(expr("2023-07-24T09:30"), "2023-07-24 09:30 Mon");
+    assert_eq!(expr("jul 24"), "2023-07-24 00:00 Mon");
+    assert_eq!(expr("24th July 2024 3pm"), "2024-07-24 15:00 Wed");
+    assert_eq!(expr("Dec 1, 2023 noon"), "2023-12-01 12:00 Fri");
+    assert_eq!(expr("2023-07-24T14:00:00Z"), "2023-07-24 09:00 Mon");
+}
+
+#[test]
+fn test_expr_days_keep_wall_clock_across_dst() {
+    let now = Chicago.with_ymd_and_hms(2023, 11, 4, 9, 0, 0).unwrap();
+    let shifted = parse_datetime("in 1 day", &now).unwrap();
+    assert_eq!(shifted.format("%Y-%m-%d %H:%M").to_string(), "2023-11-05 09:00");
+    let exact = parse_datetime("in 24 hours", &now).unwrap();
+    assert_eq!(exact.format("%Y-%m-%d %H:%M").to_string(), "2023-11-05 08:00");
+    let gap = parse_datetime("2023-03-12 2:30", &now).unwrap();
+    assert_eq!(gap.format("%H:%M").to_string(), "03:30");
+}
+
+#[test]
+fn test_expr_durations() {
+    assert_eq!(parse_duration("15m").unwrap(), Duration::minutes(15));
+    assert_eq!(parse_duration("1h30m").unwrap(), Duration::minutes(90));
+    assert_eq!(parse_duration("1 hour and 30 minutes").unwrap(), Duration::minutes(90));
+    assert_eq!(parse_duration("2 days 4h").unwrap(), Duration::hours(52));
+    assert_eq!(parse_duration("1w").unwrap(), Duration::days(7));
+    assert_eq!(parse_duration("45 secs").unwrap(), Duration::seconds(45));
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("15").is_err());
+    assert!(parse_duration("h").is_err());
+    assert!(parse_duration("3 fortnights").is_err());
+    assert!(parse_duration("99999999999 days").is_err());
+}
+
+#[test]
+fn test_expr_ranges() {
+    let hour = |start: &str, end: &str| (start.to_string(), Some(end.to_string()));
+    assert_eq!(expr_range("tomorrow 9:30 for 15m"), hour("2023-07-20 09:30", "2023-07-20 09:45"));
+    assert_eq!(expr_range("next friday 2pm-4pm"), hour("2023-07-21 14:00", "2023-07-21 16:00"));
+    assert_eq!(expr_range("friday 2-4pm"), hour("2023-07-21 14:00", "2023-07-21 16:00"));
+    assert_eq!(expr_range("friday 11-1pm"), hour("2023-07-21 11:00", "2023-07-21 13:00"));
+    assert_eq!(expr_range("9:00 - 10:30"), hour("2023-07-19 09:00", "2023-07-19 10:30"));
+    assert_eq!(expr_range("saturday 10pm to 2am"), hour("2023-07-22 22:00", "2023-07-23 02:00"));
+    assert_eq!(expr_range("jul 24 until jul 26"), hour("2023-07-24 00:00", "2023-07-26 00:00"));
+    assert_eq!(expr_range("2023-07-24 09:30"), ("2023-07-24 09:30".to_string(), None));
+}
+
+#[test]
+fn test_expr_rejects_unknown_input() {
+    let now = expr_now();
+    for input in ["", "someday", "tomorrow yesterday", "9:30 10:30", "13pm", "25:00", "feb 30", "next", "in", "9"] {
+        assert!(parse_datetime(input, &now).is_err(), "{} should not parse", input);
+    }
+    assert!(parse_range("tomorrow for ever", &now).is_err());
+}
+
+#[test]
+fn test_cli_add_with_date_expression() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
+    run_cli(&path, &["add", "Planning", "2023-07-21", "2pm-4pm", "-z", "America/Chicago"]).unwrap();
+    run_cli(&path, &["add", "Standup", "2023-07-24", "9:30", "for", "15m", "-z", "America/Chicago"]).unwrap();
+    assert!(run_cli(&path, &["add", "Nothing"]).is_err());
+
+    let calendar = Calendar::from("default calendar", &store).unwrap();
+    let planning = &get_event(&calendar, "Planning", true).unwrap()[0];
+    assert_eq!(*planning.get_start(), Chicago.with_ymd_and_hms(2023, 7, 21, 14, 0, 0).unwrap());
+    assert_eq!(planning.get_duration(), Duration::hours(2));
+    let standup = &get_event(&calendar, "Standup", true).unwrap()[0];
+    assert_eq!(standup.get_duration(), Duration::minutes(15));
+}
+
+
+// Month view
+
+// A day in July 2023
+fn july(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2023, 7, day).unwrap()
+}
+
+#[test]
+fn test_month_grid_marks_days() {
+    let marked: BTreeSet<NaiveDate> = [july(3), july(10), july(24), july(25)].into_iter().collect();
+    let grid = render_month(july(1), july(19), &marked, &BTreeSet::new(), &MonthOptions::default());
+    assert_eq!(grid, "         July 2023
+Mo  Tu  We  Th  Fr  Sa  Su
+                     1   2
+ 3*  4   5   6   7   8   9
+10* 11  12  13  14  15  16
+17  18  19  20  21  22  23
+24* 25* 26  27  28  29  30
+31
+");
+}
+
+#[test]
+fn test_month_grid_week_start_numbers_and_today() {
+    let options = MonthOptions { week_start: Weekday::Sun, week_numbers: true, highlight_today: true };
+    let grid = render_month(july(15), july(19), &BTreeSet::new(), &BTreeSet::new(), &options);
+    let lines: Vec<&str> = grid.lines().collect();
+    assert_eq!(lines[1], "Wk  Su  Mo  Tu  We  Th  Fr  Sa");
+    assert_eq!(lines[2], "26                           1");
+    assert_eq!(lines[5], "29  16  17  18  \x1b[7m19\x1b[0m  20  21  22");
+    assert_eq!(lines[7], "31  30  31");
+
+    let january = render_month(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(), july(19), &BTreeSet::new(), &BTreeSet::new(), &MonthOptions { week_numbers: true, ..MonthOptions::default() });
+    assert!(january.lines().nth(2).unwrap().starts_with("53"));
+    assert!(!january.contains('\x1b'));
+}
+
+#[test]
+fn test_month_marks_every_day_an_occurrence_covers() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("Test Calendar", &store).unwrap();
+    calendar.add_event(&new_test_timed_event("trip", (2023, 7, 12, 18, 0), (2023, 7, 14, 0, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("call", (2023, 7, 20, 23, 30), (2023, 7, 21, 0, 30), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("ping", (2023, 7, 27, 8, 0), (2023, 7, 27, 8, 0), Recurring::No)).unwrap();
+
+    let from = Chicago.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2023, 8, 1, 0, 0, 0).unwrap();
+    let days = days_with_events(&calendar.events_between(&from, &to).unwrap(), Chicago);
+    assert_eq!(days.into_iter().collect::<Vec<_>>(), vec![july(12), july(13), july(20), july(21), july(27)]);
+}
+
+#[test]
+fn test_cli_month() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    run_cli(&path, &["add", "Standup", "2023-07-03 09:00", "-z", "UTC", "-r", "weekly"]).unwrap();
+    let grid = run_cli(&path, &["month", "2023-07", "--week-start", "Sunday"]).unwrap();
+    assert!(grid.starts_with("         July 2023\nSu  Mo  Tu"));
+    assert!(grid.contains(" 2   3*  4 "));
+    assert!(grid.contains("30  31*\n"));
+    assert!(run_cli(&path, &["month", "2023-13"]).is_err());
+    assert!(run_cli(&path, &["month", "--week-start", "someday"]).is_err());
+}
+
+
+// Agenda view
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(Duration::minutes(15)), "15m");
+    assert_eq!(format_duration(Duration::minutes(90)), "1h 30m");
+    assert_eq!(format_duration(Duration::hours(54)), "2d 6h");
+    assert_eq!(format_duration(Duration::zero()), "0m");
+}
+
+#[test]
+fn test_agenda_groups_occurrences_by_day() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let work = Calendar::new("work", &store).unwrap();
+    let home = Calendar::new("home", &store).unwrap();
+    work.add_event(&new_test_timed_event("Standup", (2023, 7, 24, 9, 0), (2023, 7, 24, 9, 15), Recurring::Daily)).unwrap();
+    home.add_event(&new_test_timed_event("Trip", (2023, 7, 25, 18, 0), (2023, 7, 27, 12, 0), Recurring::No)).unwrap();
+    home.add_event(&new_test_timed_event("Dentist", (2023, 7, 24, 9, 0), (2023, 7, 24, 10, 0), Recurring::No)).unwrap();
+
+    let from = Chicago.with_ymd_and_hms(2023, 7, 24, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2023, 7, 27, 0, 0, 0).unwrap();
+    let mut entries = Vec::new();
+    for calendar in [&work, &home] {
+        for occurrence in calendar.events_between(&from, &to).unwrap() {
+            entries.push(AgendaEntry { calendar: calendar.get_name().to_string(), occurrence });
+        }
+    }
+    entries.sort_by(|a, b| (a.occurrence.get_start(), a.occurrence.get_name()).cmp(&(b.occurrence.get_start(), b.occurrence.get_name())));
+
+    assert_eq!(render_agenda(&entries, july(24), 4, Chicago, &TimeFormat::default()), "Mon 2023-07-24
+  09:00-10:00  1h        Dentist  [home]
+  09:00-09:15  15m       Standup  [work]
+Tue 2023-07-25
+  09:00-09:15  15m       Standup  [work]
+  18:00 →      1d 18h    Trip  [home]
+Wed 2023-07-26
+  all day      1d 18h    Trip  [home]
+  09:00-09:15  15m       Standup  [work]
+Thu 2023-07-27
+  → 12:00      1d 18h    Trip  [home]
+");
+    assert_eq!(render_agenda(&[], july(24), 4, Chicago, &TimeFormat::default()), "No events.\n");
+}
+
+#[test]
+fn test_cli_agenda_merges_calendars() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    run_cli(&path, &["add", "Standup", "2023-07-24 12:00", "for", "15m", "-z", "UTC", "-r", "daily", "-c", "work"]).unwrap();
+    run_cli(&path, &["add", "Dentist", "2023-07-25 12:00", "-z", "UTC", "-c", "home"]).unwrap();
+
+    let agenda = run_cli(&path, &["agenda", "--from", "2023-07-24", "--days", "3"]).unwrap();
+    assert_eq!(agenda.matches("Standup  [work]").count(), 3);
+    assert_eq!(agenda.matches("Dentist  [home]").count(), 1);
+    assert_eq!(agenda.lines().filter(|line| !line.starts_with(' ')).count(), 3);
+
+    let home = run_cli(&path, &["agenda", "--from", "2023-07-24", "--days", "3", "-c", "home"]).unwrap();
+    assert!(!home.contains("Standup"));
+    assert!(run_cli(&path, &["agenda", "-c", "missing"]).is_err());
+}
+
+
+// Timeline views
+
+// Renders July 2023 days as a timeline from the occurrences of a calendar
+fn july_timeline(calendar: &Calendar, days: &[u32], options: &TimelineOptions) -> String {
+    let from = Chicago.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2023, 8, 1, 0, 0, 0).unwrap();
+    let occurrences = calendar.events_between(&from, &to).unwrap();
+    let days: Vec<NaiveDate> = days.iter().map(|day| july(*day)).collect();
+    render_timeline(&days, &occurrences, Chicago, options)
+}
+
+#[test]
+fn test_day_timeline_lays_out_overlaps_side_by_side() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("Test Calendar", &store).unwrap();
+    calendar.add_event(&new_test_timed_event("Review", (2023, 7, 25, 9, 0), (2023, 7, 25, 10, 30), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("Standup", (2023, 7, 25, 9, 30), (2023, 7, 25, 9, 45), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("Lunch", (2023, 7, 25, 12, 0), (2023, 7, 25, 13, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("Late", (2023, 7, 25, 19, 0), (2023, 7, 25, 19, 30), Recurring::No)).unwrap();
+
+    let options = TimelineOptions { width: 30, slot_minutes: 30, ..TimelineOptions::default() };
+    let timeline = july_timeline(&calendar, &[25], &options);
+    let lines: Vec<&str> = timeline.lines().collect();
+    assert_eq!(lines[0], "      Tue 25");
+    assert_eq!(lines[1], "-".repeat(29));
+    assert_eq!(lines[2], "08:00");
+    assert_eq!(lines[4], "09:00 Review");
+    assert_eq!(lines[5], "      ........... Standup");
+    assert_eq!(lines[6], "10:00 ...........");
+    assert_eq!(lines[10], "12:00 Lunch");
+    assert_eq!(lines[11], "      ...........");
+    assert_eq!(lines.last().unwrap(), &"19:00 Late");
+    assert_eq!(lines.len(), 2 + 23);
+}
+
+#[test]
+fn test_week_timeline_band_and_width() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("Test Calendar", &store).unwrap();
+    calendar.add_event(&new_test_timed_event("Trip", (2023, 7, 25, 18, 0), (2023, 7, 27, 12, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("Offsite", (2023, 7, 28, 0, 0), (2023, 7, 29, 0, 0), Recurring::No)).unwrap();
+    calendar.add_event(&new_test_timed_event("Standup", (2023, 7, 24, 9, 0), (2023, 7, 24, 9, 15), Recurring::Daily)).unwrap();
+
+    let options = TimelineOptions { width: 69, slot_minutes: 60, ..TimelineOptions::default() };
+    let timeline = july_timeline(&calendar, &[24, 25, 26, 27, 28, 29, 30], &options);
+    let lines: Vec<&str> = timeline.lines().collect();
+    assert_eq!(lines[0], "      Mon 24  |Tue 25  |Wed 26  |Thu 27  |Fri 28  |Sat 29  |Sun 30");
+    assert_eq!(lines[1], "              |Trip    |Trip    |Trip    |Offsite |        |");
+    assert_eq!(lines[3], "08:00         |        |        |        |        |        |");
+    assert_eq!(lines[4], "09:00 Standup |Standup |Standup |Standup |Standup |Standup |Standup");
+    assert!(lines.iter().all(|line| line.chars().count() <= 69));
+    assert_eq!(lines.len(), 3 + 10);
+}
+
+#[test]
+fn test_cli_week_and_day() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    run_cli(&path, &["add", "Standup", "2023-07-24 12:00", "for", "15m", "-z", "UTC", "-r", "daily", "-c", "work"]).unwrap();
+    run_cli(&path, &["add", "Dentist", "2023-07-26 15:00", "-z", "UTC", "-c", "home"]).unwrap();
+
+    let week = run_cli(&path, &["week", "2023-07-26", "--week-start", "sunday"]).unwrap();
+    assert!(week.lines().next().unwrap().trim_start().starts_with("Sun 23"));
+    assert_eq!(week.matches("Standup").count(), 6);
+    assert_eq!(week.matches("Dentist").count(), 1);
+
+    let day = run_cli(&path, &["day", "2023-07-26", "-c", "home"]).unwrap();
+    assert!(day.contains("Dentist") && !day.contains("Standup"));
+}
+
+
+// iCalendar import
+
+// Reads the iCalendar export fixture
+fn export_fixture() -> String {
+    fs::read_to_string("tests/fixtures/export.ics").unwrap()
+}
+
+#[test]
+fn test_ical_components_and_content_lines() {
+    let text = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY;LANGUAGE=en:Long\r\n  title\r\nATTENDEE;CN=\"Doe; Jane\":mailto:jane@example.com\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+    let components = parse_components(text).unwrap();
+    assert_eq!(components.len(), 1);
+    let vevent = &components[0].components[0];
+    assert_eq!(vevent.name, "VEVENT");
+    assert_eq!(vevent.get("SUMMARY").unwrap().value, "Long title");
+    assert_eq!(vevent.get("SUMMARY").unwrap().get_param("language"), Some("en"));
+    let attendee = vevent.get("ATTENDEE").unwrap();
+    assert_eq!(attendee.get_param("CN"), Some("Doe; Jane"));
+    assert_eq!(attendee.value, "mailto:jane@example.com");
+
+    let unclosed = parse_components("BEGIN:VCALENDAR\nBEGIN:VEVENT\nEND:VCALENDAR\n").unwrap_err();
+    assert_eq!(unclosed.line, 3);
+    assert!(parse_components("BEGIN:VCALENDAR\nBEGIN:VEVENT\n").is_err());
+    assert!(parse_components("SUMMARY:Loose\n").is_err());
+    assert!(parse_components("BEGIN:VCALENDAR\nno colon here\nEND:VCALENDAR\n").is_err());
+    assert!(parse_ical("", Tz::UTC).is_err());
+}
+
+#[test]
+fn test_ical_durations() {
+    assert_eq!(parse_ical_duration("PT15M"), Some(Duration::minutes(15)));
+    assert_eq!(parse_ical_duration("P1DT2H30M"), Some(Duration::minutes(1590)));
+    assert_eq!(parse_ical_duration("P2W"), Some(Duration::weeks(2)));
+    assert_eq!(parse_ical_duration("-PT10S"), Some(Duration::seconds(-10)));
+    assert_eq!(parse_ical_duration("P"), None);
+    assert_eq!(parse_ical_duration("PT1D"), None);
+    assert_eq!(parse_ical_duration("P1H"), None);
+    assert_eq!(parse_ical_duration("1H"), None);
+}
+
+#[test]
+fn test_ical_parse_events() {
+    let parsed = parse_ical(&export_fixture(), Chicago).unwrap();
+    assert_eq!(parsed.events.len(), 6);
+    assert_eq!(parsed.skipped.len(), 1);
+    assert!(parsed.skipped[0].contains("BYHOUR"));
+
+    let sync = &parsed.events[0];
+    assert_eq!(sync.uid.as_deref(), Some("weekly-sync@example.com"));
+    assert_eq!(sync.summary, "Weekly sync, team");
+    assert_eq!(sync.description.as_deref(), Some("Agenda:\n1. Updates\n2. Questions about the roadmap that has a rather long description folded across lines"));
+    assert_eq!(sync.location.as_deref(), Some("Room 4; second floor"));
+    assert_eq!(sync.start, New_York.with_ymd_and_hms(2023, 7, 3, 10, 0, 0).unwrap());
+    assert_eq!(sync.start.timezone(), New_York);
+    assert_eq!(sync.exdates, vec![New_York.with_ymd_and_hms(2023, 7, 17, 10, 0, 0).unwrap()]);
+    assert!(sync.rrule.is_some());
+
+    let berlin = &parsed.events[3];
+    assert_eq!(berlin.start.timezone(), chrono_tz::Europe::Berlin);
+    assert_eq!(berlin.end - berlin.start, Duration::minutes(90));
+
+    let holiday = &parsed.events[4];
+    assert!(holiday.all_day);
+    assert_eq!(holiday.start, Chicago.with_ymd_and_hms(2023, 7, 4, 0, 0, 0).unwrap());
+    assert_eq!(holiday.end - holiday.start, Duration::days(1));
+    assert_eq!(parsed.events[5].start.timezone(), Tz::UTC);
+}
+
+#[test]
+fn test_ical_import_is_idempotent() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("Test Calendar", &store).unwrap();
+
+    let summary = import_ical(&calendar, &export_fixture(), Chicago).unwrap();
+    assert_eq!((summary.added, summary.updated, summary.skipped.len()), (4, 0, 1));
+    let first = july_occurrences(&calendar);
+    let sync: Vec<&(String, String)> = first.iter().filter(|(name, _)| name.starts_with("Weekly sync")).collect();
+    assert_eq!(sync, vec![
+        &("Weekly sync, team".to_string(), "07-03 10:00".to_string()),
+        &("Weekly sync (moved)".to_string(), "07-10 15:00".to_string()),
+        &("Weekly sync, team".to_string(), "07-31 10:00".to_string()),
+    ]);
+
+    let summary = import_ical(&calendar, &export_fixture(), Chicago).unwrap();
+    assert_eq!((summary.added, summary.updated), (0, 4));
+    assert_eq!(july_occurrences(&calendar), first);
+
+    let sync = &get_event(&calendar, "Weekly sync, team", true).unwrap()[0];
+    assert_eq!(sync.get_uid(), Some("weekly-sync@example.com"));
+    assert_eq!(*sync.get_id(), id_for_uid("weekly-sync@example.com"));
+    let berlin = &get_event(&calendar, "Berlin review", true).unwrap()[0];
+    assert_eq!(berlin.get_id().to_string(), "6f1c1d9e-3f4a-4c1b-9a53-0d6f3f7f2a11");
+    assert_eq!(berlin.get_uid(), None);
+}
+
+#[test]
+fn test_cli_import() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let output = run_cli(&path, &["import", "tests/fixtures/export.ics", "-c", "work", "-z", "America/Chicago"]).unwrap();
+    assert!(output.starts_with("Imported 4 new and 0 updated events into 'work'.\nSkipped: "));
+    assert!(run_cli(&path, &["show", "Deploy", "-c", "work"]).unwrap().contains("2023-07-20 23:00 UTC"));
+    assert!(run_cli(&path, &["import", "tests/fixtures/missing.ics"]).is_err());
+}
+
+// iCalendar export
+
+#[test]
+fn test_ical_write_folds_and_escapes() {
+    let mut event = new_test_timed_event("Review, plan; budget \\ costs and a name long enough to need folding", (2023, 7, 5, 9, 0), (2023, 7, 5, 10, 0), Recurring::No);
+    event.update_uid(Some("review@example.com")).unwrap();
+    let stamp = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+    let text = write_ical("Work", &[event], &stamp);
+
+    assert!(text.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+    assert!(text.ends_with("END:VCALENDAR\r\n"));
+    for line in text.split_terminator("\r\n") {
+        assert!(line.len() <= 75, "{}", line);
+        assert!(!line.contains('\n'));
+    }
+    assert!(text.contains("UID:review@example.com\r\nDTSTAMP:20230701T120000Z\r\n"));
+    assert!(text.contains("DTSTART;TZID=America/Chicago:20230705T090000\r\n"));
+    assert!(text.contains("BEGIN:VTIMEZONE\r\nTZID:America/Chicago\r\n"));
+    assert!(text.contains("TZOFFSETFROM:-0600\r\nTZOFFSETTO:-0500\r\nTZNAME:CDT\r\n"));
+
+    assert!(text.contains("SUMMARY:Review\\, plan\\; budget \\\\ costs"));
+
+    let parsed = parse_ical(&text, Tz::UTC).unwrap();
+    assert_eq!(parsed.events[0].summary, "Review, plan; budget \\ costs and a name long enough to need folding");
+}
+
+#[test]
+fn test_ical_write_utc_and_uid_fallback() {
+    let start = Tz::UTC.with_ymd_and_hms(2023, 7, 20, 23, 0, 0).unwrap();
+    let event = Event::new("Deploy", start, start + Duration::minutes(45), Recurring::No).unwrap();
+    let stamp = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+    let text = write_ical("Ops", std::slice::from_ref(&event), &stamp);
+
+    assert!(!text.contains("VTIMEZONE"));
+    assert!(text.contains(&format!("UID:{}\r\n", event.get_id())));
+    assert!(text.contains("DTSTART:20230720T230000Z\r\nDTEND:20230720T234500Z\r\n"));
+}
+
+#[test]
+fn test_ical_write_ongoing_timezone_rules() {
+    let weekly = new_test_timed_event("Standup", (2023, 7, 3, 9, 0), (2023, 7, 3, 9, 15), Recurring::Weekly);
+    let berlin = chrono_tz::Europe::Berlin.with_ymd_and_hms(2023, 7, 5, 18, 0, 0).unwrap();
+    let yearly = Event::new("Anniversary", berlin, berlin + Duration::hours(2), "FREQ=YEARLY;UNTIL=20600705T160000Z".parse().unwrap()).unwrap();
+    let tokyo = chrono_tz::Asia::Tokyo.with_ymd_and_hms(2023, 7, 5, 10, 0, 0).unwrap();
+    let call = Event::new("Call", tokyo, tokyo + Duration::hours(1), Recurring::Monthly).unwrap();
+    let stamp = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+    let text = write_ical("Work", &[weekly, yearly, call], &stamp);
+
+    let chicago = &text[text.find("TZID:America/Chicago").unwrap()..];
+    let chicago = &chicago[..chicago.find("END:VTIMEZONE").unwrap()];
+    assert!(chicago.contains("BEGIN:DAYLIGHT\r\nDTSTART:20230312T020000\r\nRRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=2SU\r\nTZOFFSETFROM:-0600\r\nTZOFFSETTO:-0500\r\n"));
+    assert!(chicago.contains("BEGIN:STANDARD\r\nDTSTART:20231105T020000\r\nRRULE:FREQ=YEARLY;BYMONTH=11;BYDAY=1SU\r\nTZOFFSETFROM:-0500\r\nTZOFFSETTO:-0600\r\n"));
+    assert_eq!(chicago.matches("BEGIN:DAYLIGHT").count(), 1);
+    let europe = &text[text.find("TZID:Europe/Berlin").unwrap()..];
+    let europe = &europe[..europe.find("END:VTIMEZONE").unwrap()];
+    assert!(europe.contains("RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU\r\n"));
+    assert!(europe.contains("RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU\r\n"));
+    let asia = &text[text.find("TZID:Asia/Tokyo").unwrap()..];
+    let asia = &asia[..asia.find("END:VTIMEZONE").unwrap()];
+    assert!(!asia.contains("RRULE"));
+
+    // Occurrences long after the export keep their local times when read back
+    let test = TestStore::in_memory();
+    let calendar = Calendar::new("Work", test.get_store()).unwrap();
+    let summary = import_ical(&calendar, &text, Tz::UTC).unwrap();
+    assert!(summary.skipped.is_empty());
+    let from = Chicago.with_ymd_and_hms(2045, 7, 1, 0, 0, 0).unwrap();
+    let to = Chicago.with_ymd_and_hms(2045, 7, 8, 0, 0, 0).unwrap();
+    let starts: Vec<String> = calendar.events_between(&from, &to).unwrap()
+        .iter()
+        .map(|o| format!("{} {}", o.get_name(), o.get_start().format("%m-%d %H:%M %Z")))
+        .collect();
+    assert_eq!(starts, ["Standup 07-03 09:00 CDT", "Call 07-05 10:00 JST", "Anniversary 07-05 18:00 CEST"]);
+}
+
+#[test]
+fn test_ical_write_series_ending_far_ahead() {
+    let start = Chicago.with_ymd_and_hms(2023, 7, 3, 9, 0, 0).unwrap();
+    let until = Event::new("Ping", start, start + Duration::seconds(30), "FREQ=MINUTELY;UNTIL=20991231T000000Z".parse().unwrap()).unwrap();
+    let count = Event::new("Poll", start, start + Duration::seconds(30), "FREQ=MINUTELY;COUNT=100000".parse().unwrap()).unwrap();
+    let stamp = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+    let text = write_ical("Ops", &[until, count], &stamp);
+
+    assert_eq!(text.matches("BEGIN:VTIMEZONE").count(), 1);
+    assert!(text.contains("RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=2SU\r\n"));
+    assert!(text.contains("RRULE:FREQ=MINUTELY;UNTIL=20991231T000000Z\r\n"));
+    let parsed = parse_ical(&text, Tz::UTC).unwrap();
+    assert_eq!(parsed.events.len(), 2);
+}
+
+#[test]
+fn test_ical_export_round_trip() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("Test Calendar", &store).unwrap();
+    import_ical(&calendar, &export_fixture(), Chicago).unwrap();
+    let berlin = get_event(&calendar, "Berlin review", true).unwrap().remove(0);
+    let monthly = Event::new("Monthly", *berlin.get_start(), *berlin.get_end(), "FREQ=MONTHLY;BYDAY=1WE;COUNT=3".parse().unwrap()).unwrap();
+    calendar.add_event(&monthly).unwrap();
+    let occurrences = july_occurrences(&calendar);
+    let ids: BTreeSet<String> = get_events(&calendar).unwrap().iter().map(|e| e.get_id().to_string()).collect();
+
+    let text = export_ical(&calendar).unwrap();
+    remove_calendar(&calendar).unwrap();
+    let calendar = Calendar::new("Test Calendar", &store).unwrap();
+    let summary = import_ical(&calendar, &text, Tz::UTC).unwrap();
+    assert!(summary.skipped.is_empty());
+    assert_eq!(july_occurrences(&calendar), occurrences);
+    let events = get_events(&calendar).unwrap();
+    assert_eq!(events.iter().map(|e| e.get_id().to_string()).collect::<BTreeSet<String>>(), ids);
+    let sync = events.iter().find(|e| e.get_name() == "Weekly sync, team").unwrap();
+    assert_eq!(sync.get_uid(), Some("weekly-sync@example.com"));
+    assert_eq!(sync.get_start().timezone(), New_York);
+}
+
+#[test]
+fn test_cli_export() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    run_cli(&path, &["import", "tests/fixtures/export.ics", "-c", "work", "-z", "America/Chicago"]).unwrap();
+    let output = run_cli(&path, &["export", "-c", "work"]).unwrap();
+    assert!(output.contains("X-WR-CALNAME:work\r\n"));
+    assert!(output.contains("SUMMARY:Deploy\r\n"));
+
+    let file = std::env::temp_dir().join(format!("cali-{}.ics", Uuid::new_v4()));
+    let message = run_cli(&path, &["export", "-c", "work", "-o", file.to_str().unwrap()]).unwrap();
+    assert_eq!(message, format!("Exported 'work' to {}.\n", file.display()));
+    assert!(fs::read_to_string(&file).unwrap().contains("UID:deploy@example.com\r\n"));
+    fs::remove_file(&file).unwrap();
+    assert!(run_cli(&path, &["export", "-c", "missing"]).is_err());
+}
+
+// Database location
+
+#[test]
+fn test_data_directory_follows_xdg() {
+    let home = Some("/home/jon".into());
+    assert_eq!(data_directory(Some("/data".into()), home.clone()).unwrap(), PathBuf::from("/data/cali"));
+    assert_eq!(data_directory(None, home.clone()).unwrap(), PathBuf::from("/home/jon/.local/share/cali"));
+    assert_eq!(data_directory(Some("".into()), home.clone()).unwrap(), PathBuf::from("/home/jon/.local/share/cali"));
+    assert_eq!(data_directory(Some("relative/data".into()), home).unwrap(), PathBuf::from("/home/jon/.local/share/cali"));
+    assert!(data_directory(None, None).is_err());
+    assert!(data_directory(Some("relative".into()), Some("".into())).is_err());
+}
+
+#[test]
+fn test_config_and_database_paths() {
+    assert_eq!(config_directory(Some("/config".into()), None).unwrap(), PathBuf::from("/config/cali"));
+    assert_eq!(config_directory(None, Some("/home/jon".into())).unwrap(), PathBuf::from("/home/jon/.config/cali"));
+    assert!(config_directory(None, None).is_err());
+
+    let flag = PathBuf::from("/tmp/flag.db");
+    assert_eq!(database_path(Some(flag.clone())).unwrap(), flag);
+    assert!(database_path(None).unwrap().ends_with("cali/calendar.db"));
+}
+
+#[test]
+fn test_cli_db_flag() {
+    let directory = std::env::temp_dir().join(format!("cali-{}", Uuid::new_v4()));
+    let path = directory.join("nested").join("calendar.db");
+    let parser = InputParser::try_parse_from(["cali", "calendars", "add", "work", "--db", path.to_str().unwrap()]).unwrap();
+    parser.run().unwrap();
+    assert!(check_calendar(&Store::open(&path).unwrap(), "work").unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+// Configuration
+
+#[test]
+fn test_config_sources_and_defaults() {
+    let config = Config::from("week_start = \"sunday\"\nclock = \"12h\"\n").unwrap();
+    assert_eq!(config.get_setting("week_start").unwrap(), ("sunday".to_string(), Source::File));
+    assert_eq!(config.get_setting("view").unwrap(), ("agenda".to_string(), Source::Default));
+    assert_eq!(config.get_week_start().unwrap(), Weekday::Sun);
+    assert!(config.get_time_format().unwrap().twelve_hour);
+    assert!(config.get_setting("colour").is_err());
+    assert_eq!(environment_variable("date_format"), "CALI_DATE_FORMAT");
+
+    assert!(Config::from("clock = \"13h\"").is_err());
+    assert!(Config::from("week_start = 1").is_err());
+    assert!(Config::from("timezone = \"Mars/Olympus\"").is_err());
+    assert!(Config::from("date_format = \"%Q\"").is_err());
+    assert!(Config::from("colour = \"never\"").is_err());
+    assert!(Config::from("view = ").is_err());
+    assert!(Config::from("timezone = \"local\"\ndate_format = \"%d.%m.%Y\"").is_ok());
+}
+
+#[test]
+fn test_config_save_load_and_environment() {
+    let path = std::env::temp_dir().join(format!("cali-{}", Uuid::new_v4())).join("config.toml");
+    let mut config = Config::load(Some(path.clone())).unwrap();
+    assert!(config.update_setting("clock", "weekly").is_err());
+    config.update_setting("timezone", "Europe/Berlin").unwrap();
+    config.save().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "timezone = \"Europe/Berlin\"\n");
+
+    std::env::set_var("CALI_TIMEZONE", "Asia/Tokyo");
+    let config = Config::load(Some(path.clone())).unwrap();
+    std::env::remove_var("CALI_TIMEZONE");
+    assert_eq!(config.get_setting("timezone").unwrap(), ("Asia/Tokyo".to_string(), Source::Environment));
+    assert_eq!(Config::load(Some(path.clone())).unwrap().get_timezone().unwrap(), chrono_tz::Europe::Berlin);
+    assert!(Config::new().save().is_err());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_time_format() {
+    let time = Chicago.with_ymd_and_hms(2023, 7, 24, 14, 5, 0).unwrap();
+    assert_eq!(TimeFormat::default().format_date_time(&time), "2023-07-24 14:05");
+    let format = TimeFormat { date: "%d.%m.%Y".to_string(), twelve_hour: true };
+    assert_eq!(format.format_date_time(&time), "24.07.2023 2:05pm");
+    assert_eq!(format.format_clock(&Chicago.with_ymd_and_hms(2023, 7, 24, 0, 30, 0).unwrap()), "12:30am");
+}
+
+#[test]
+fn test_cli_uses_settings() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let config = Config::from("view = \"month\"\nweek_start = \"sunday\"\nclock = \"12h\"\ntimezone = \"America/Chicago\"\ndate_format = \"%d/%m\"").unwrap();
+    run_cli_with(&path, &config, &["add", "Review", "2023-07-24 14:00 for 90m"]).unwrap();
+
+    let list = run_cli_with(&path, &config, &["list", "--from", "2023-07-24", "--to", "2023-07-25"]).unwrap();
+    assert_eq!(list, "24/07 2:00pm - 3:30pm  Review\n");
+    let agenda = run_cli_with(&path, &config, &["agenda", "--from", "2023-07-24", "--days", "1"]).unwrap();
+    assert_eq!(agenda, "Mon 24/07\n  2:00pm-3:30pm    1h 30m    Review  [default calendar]\n");
+    let month = run_cli_with(&path, &config, &["month", "2023-07"]).unwrap();
+    assert_eq!(month.lines().nth(1).unwrap(), "Su  Mo  Tu  We  Th  Fr  Sa");
+    assert_eq!(run_cli_with(&path, &config, &["month", "2023-07", "--week-start", "monday"]).unwrap().lines().nth(1).unwrap(), "Mo  Tu  We  Th  Fr  Sa  Su");
+    assert!(run_cli_with(&path, &config, &[]).unwrap().contains("Su  Mo"));
+    assert_eq!(run_cli(&path, &[]).unwrap(), "No events.\n");
+    let day = run_cli_with(&path, &config, &["day", "2023-07-24"]).unwrap();
+    assert!(day.contains("\n 8am\n") && day.contains("\n 2pm  Review\n"), "{}", day);
+}
+
+#[test]
+fn test_cli_config() {
+    let path = std::env::temp_dir().join(format!("cali-{}", Uuid::new_v4())).join("config.toml");
+    let database = PathBuf::from("unused.db");
+    let config = Config::load(Some(path.clone())).unwrap();
+    let list = run_cli_with(&database, &config, &["config"]).unwrap();
+    assert_eq!(list.lines().count(), SETTINGS.len());
+    assert!(list.contains("week_start = \"monday\"  # Day the weeks start on (default)\n"));
+
+    let output = run_cli_with(&database, &config, &["config", "set", "week_start", "sunday"]).unwrap();
+    assert_eq!(output, format!("Set week_start to 'sunday' in {}.\n", path.display()));
+    let config = Config::load(Some(path.clone())).unwrap();
+    assert_eq!(run_cli_with(&database, &config, &["config", "get", "week_start"]).unwrap(), "sunday\n");
+    assert!(run_cli_with(&database, &config, &["config", "list"]).unwrap().contains("week_start = \"sunday\"  # Day the weeks start on (file)\n"));
+    assert!(run_cli_with(&database, &config, &["config", "get", "weekstart"]).is_err());
+    assert!(run_cli_with(&database, &config, &["config", "set", "view", "year"]).is_err());
+    assert!(!database.exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+// Errors
+
+#[test]
+fn test_errors_can_be_matched() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    Calendar::new("Test Calendar", &store).unwrap();
+    let exists = Calendar::new("Test Calendar", &store).err().unwrap();
+    assert!(matches!(exists, CaliError::AlreadyExists(_)));
+    assert_eq!(exists.exit_code(), 73);
+
+    let start = dummy_start();
+    let bad_id = Event::from("not-a-uuid", "Broken", start, start + Duration::hours(1), Recurring::No).err().unwrap();
+    assert!(matches!(bad_id, CaliError::Parse(_)));
+    assert!(bad_id.to_string().starts_with("Could not read an event id: "));
+    let bad_task_id = Task::from("not-a-uuid", "Broken", None).err().unwrap();
+    assert!(bad_task_id.to_string().starts_with("Could not read a task id: "));
+    let backwards = Event::new("Backwards", start, start - Duration::hours(1), Recurring::No).err().unwrap();
+    assert!(matches!(backwards, CaliError::InvalidTime(_)));
+    assert_eq!(backwards.exit_code(), 65);
+
+    let storage = init_database(&std::env::temp_dir()).err().unwrap();
+    assert!(matches!(storage, CaliError::Storage(_)));
+    assert_eq!(storage.exit_code(), 74);
+}
+
+#[test]
+fn test_cli_error_kinds() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let execute = |args: &[&str]| {
+        let parser = InputParser::try_parse_from(std::iter::once("cali").chain(args.iter().copied())).unwrap();
+        parser.execute(&path, &Config::new(), &mut Vec::new()).err().unwrap()
+    };
+
+    let missing = execute(&["list", "-c", "missing"]);
+    assert!(matches!(missing, CaliError::CalendarNotFound(_)));
+    assert_eq!(missing.exit_code(), 66);
+    assert!(matches!(execute(&["show", "nothing"]), CaliError::CalendarNotFound(_)));
+    run_cli(&path, &["calendars", "add", "work"]).unwrap();
+    assert!(matches!(execute(&["show", "nothing"]), CaliError::EventNotFound(_)));
+    assert!(matches!(execute(&["add", "Soon", "-s", "the day after never"]), CaliError::InvalidTime(_)));
+    assert!(matches!(execute(&["add", "Soon", "-s", "tomorrow", "-r", "FREQ=FORTNIGHTLY"]), CaliError::InvalidRecurrence(_)));
+    assert!(matches!(execute(&["import", "tests/fixtures/missing.ics"]), CaliError::Io(_)));
+    assert!(matches!(execute(&["config", "get", "nothing"]), CaliError::UnknownSetting(_)));
+}
+
+// Store
+
+#[test]
+fn test_store_shares_one_connection() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let store = test.get_store().clone();
+    let work = Calendar::new("work", &store).unwrap();
+    let home = Calendar::from("home", &store).unwrap();
+    assert!(std::ptr::addr_eq(work.get_store().get_backend(), home.get_store().get_backend()));
+    assert_eq!(get_calendars(&store).unwrap()[0].get_path(), Some(&path));
+}
+
+#[test]
+fn test_store_transactions_commit_or_roll_back() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+
+    let failed: Result<(), CaliError> = store.transaction(|store| {
+        Calendar::new("work", store)?;
+        Err(CalendarExistsError.into())
+    });
+    assert!(failed.is_err());
+    assert!(!check_calendar(&store, "work").unwrap());
+
+    store.transaction(|store| {
+        Calendar::new("work", store)?;
+        let inner: Result<(), CaliError> = store.transaction(|store| {
+            Calendar::new("home", store)?;
+            Calendar::new("work", store).map(|_| ())
+        });
+        assert!(matches!(inner, Err(CaliError::AlreadyExists(_))));
+        Ok(())
+    }).unwrap();
+    assert!(check_calendar(&store, "work").unwrap());
+    assert!(!check_calendar(&store, "home").unwrap());
+}
+
+#[test]
+fn test_update_default_keeps_default_on_failure() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    Calendar::new("work", &store).unwrap();
+    Calendar::new("home", &store).unwrap();
+
+    assert!(matches!(update_default(&store, "missing"), Err(CaliError::CalendarNotFound(_))));
+    assert_eq!(get_default(&store).unwrap().as_deref(), Some("work"));
+    update_default(&store, "home").unwrap();
+    assert_eq!(get_default(&store).unwrap().as_deref(), Some("home"));
+}
+
+#[test]
+fn test_in_memory_databases_are_separate() {
+    let first = Store::open_in_memory().unwrap();
+    let second = Store::open(&PathBuf::from(MEMORY_DATABASE)).unwrap();
+    CalendarBuilder::new("work").set_default(true).event(EventBuilder::new("Standup")).build(&first);
+    assert!(check_calendar(&first, "work").unwrap());
+    assert!(!check_calendar(&second, "work").unwrap());
+
+    let mut output = Vec::new();
+    InputParser::parse_from(["cali", "calendars", "list"])
+        .execute(&PathBuf::from(MEMORY_DATABASE), &Config::new(), &mut output)
+        .unwrap();
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_test_stores_use_their_own_file() {
+    let first = TestStore::new();
+    let second = TestStore::new();
+    assert_ne!(first.get_path(), second.get_path());
+    let path = first.get_path().clone();
+    assert!(path.exists());
+    drop(first);
+    assert!(!path.exists());
+}
+
+// Storage backends
+
+// Creates an empty directory to use as a vdir
+fn new_vdir_path() -> PathBuf {
+    std::env::temp_dir().join(format!("cali-vdir-{}", Uuid::new_v4()))
+}
+
+// Runs calendar and event operations that every backend must support alike
+fn check_store_behaviour(store: &Store) {
+    let (calendar, mut event) = new_weekly_test_series(store);
+    let mut home = Calendar::new("home", store).unwrap();
+    let changes = EventChanges { name: Some("moved sync".to_string()), start: Some(monday(10) + Duration::hours(2)), end: None };
+    calendar.update_event_scoped(&mut event, Scope::This(monday(10)), &changes).unwrap();
+    calendar.remove_event_scoped(&mut event, Scope::This(monday(17))).unwrap();
+    home.add_event(&EventBuilder::new("Dentist").build()).unwrap();
+
+    let names: Vec<String> = get_calendars(store).unwrap().iter().map(|calendar| calendar.get_name().to_string()).collect();
+    assert_eq!(names, vec!["home", "test calendar"]);
+    assert_eq!(get_default(store).unwrap().as_deref(), Some("test calendar"));
+    let found = get_event(&calendar, "SYNC", false).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].get_exdates(), &[monday(17)]);
+    assert_eq!(found[0].get_overrides(), event.get_overrides());
+    assert!(check_event(store, event.get_id()).unwrap());
+    let starts: Vec<String> = july_occurrences(&calendar).into_iter().map(|(_, start)| start).collect();
+    assert_eq!(starts, vec!["07-03 09:00", "07-10 11:00", "07-24 09:00", "07-31 09:00"]);
+
+    home.update_name("family").unwrap();
+    assert!(matches!(home.update_name("test calendar"), Err(CaliError::AlreadyExists(_))));
+    assert_eq!(home.get_name(), "family");
+    assert_eq!(get_calendars(store).unwrap().len(), 2);
+    update_default(store, "family").unwrap();
+    assert!(check_default(store, "family").unwrap());
+    home.update_event(&event).unwrap();
+    assert!(get_events(&calendar).unwrap().is_empty());
+    assert_eq!(get_events(&home).unwrap().len(), 2);
+
+    let failed: Result<(), CaliError> = store.transaction(|store| {
+        remove_calendar(&home)?;
+        Calendar::new("work", store)?;
+        Err(CalendarExistsError.into())
+    });
+    assert!(failed.is_err());
+    assert!(!check_calendar(store, "work").unwrap());
+    assert_eq!(get_events(&home).unwrap().len(), 2);
+    remove_calendar(&home).unwrap();
+    assert!(!check_event(store, event.get_id()).unwrap());
+    assert_eq!(get_default(store).unwrap(), None);
+}
+
+#[test]
+fn test_sqlite_store_behaviour() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    check_store_behaviour(&Store::open(&path).unwrap());
+}
+
+#[test]
+fn test_memory_store_behaviour() {
+    let store = Store::memory();
+    check_store_behaviour(&store);
+    assert_eq!(store.get_path(), None);
+}
+
+#[test]
+fn test_vdir_store_behaviour() {
+    let path = new_vdir_path();
+    fs::create_dir_all(&path).unwrap();
+    check_store_behaviour(&Store::open(&path).unwrap());
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_vdir_store_keeps_events_in_ics_files() {
+    let path = new_vdir_path();
+    let store = Store::new(VdirStore::open(&path).unwrap());
+    let (calendar, mut event) = new_weekly_test_series(&store);
+    calendar.remove_event_scoped(&mut event, Scope::This(monday(17))).unwrap();
+
+    let file = path.join("test_calendar").join(format!("{}.ics", event.get_id()));
+    assert_eq!(fs::read_to_string(path.join("test_calendar/displayname")).unwrap(), "test calendar\n");
+    assert!(fs::read_to_string(&file).unwrap().contains("SUMMARY:sync\r\n"));
+
+    // Files written by other tools are read as they are
+    let other = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Other//EN\r\nBEGIN:VEVENT\r\nUID:lunch@example.com\r\n\
+        DTSTART;TZID=America/Chicago:20230705T120000\r\nDTEND;TZID=America/Chicago:20230705T130000\r\n\
+        SUMMARY:Lunch\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+    fs::write(path.join("test_calendar/other.ics"), other).unwrap();
+    let reopened = Store::open(&path).unwrap();
+    let calendar = Calendar::from("test calendar", &reopened).unwrap();
+    assert!(calendar.get_default());
+    let events = get_events(&calendar).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].get_id(), event.get_id());
+    assert_eq!(events[0].get_timezone(), Tz::America__Chicago);
+    assert_eq!(events[0].get_exdates(), &[monday(17)]);
+    assert_eq!(events[1].get_uid(), Some("lunch@example.com"));
+    assert!(check_event(&reopened, events[1].get_id()).unwrap());
+    calendar.remove_event(&events[1]).unwrap();
+    assert!(!path.join("test_calendar/other.ics").exists());
+    assert!(check_event(&reopened, events[0].get_id()).unwrap());
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_vdir_store_roll_back_removes_only_created_directories() {
+    let path = new_vdir_path();
+    let store = Store::new(VdirStore::open(&path).unwrap());
+    Calendar::new("work", &store).unwrap();
+    fs::create_dir_all(path.join("archive")).unwrap();
+
+    let failed: Result<(), CaliError> = store.transaction(|store| {
+        Calendar::new("home", store)?;
+        Err(CalendarExistsError.into())
+    });
+    assert!(failed.is_err());
+    assert!(!path.join("home").exists());
+    assert!(path.join("archive").is_dir());
+    assert!(path.join("work").is_dir());
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_vdir_store_nested_roll_back_keeps_events() {
+    let path = new_vdir_path();
+    let store = Store::new(VdirStore::open(&path).unwrap());
+    let (calendar, event) = new_weekly_test_series(&store);
+
+    // The event's file cannot be written again, failing the inner update
+    fs::create_dir_all(path.join("test_calendar").join(format!(".{}.ics.tmp", event.get_id()))).unwrap();
+    let changes = EventChanges { name: Some("moved sync".to_string()), start: None, end: None };
+    let mut changed = event.clone();
+    assert!(calendar.update_event_scoped(&mut changed, Scope::This(monday(10)), &changes).is_err());
+    assert!(changed.get_overrides().is_empty());
+    assert!(calendar.remove_event_scoped(&mut changed, Scope::ThisAndFollowing(monday(17))).is_err());
+    assert!(changed.has_occurrence(&monday(24)));
+    let events = get_events(&calendar).unwrap();
+    assert_eq!(events.len(), 1);
+    assert!(events[0].get_overrides().is_empty());
+    fs::remove_dir_all(&path).unwrap();
+}
+
+// All-day events
+
+#[test]
+fn test_all_day_events_fall_on_the_same_days_in_every_zone() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("holidays", &store).unwrap();
+    calendar.add_event(&EventBuilder::new("Holiday").all_day(july(24), july(24)).build()).unwrap();
+
+    for timezone in [Chicago, chrono_tz::Asia::Tokyo, chrono_tz::Pacific::Kiritimati] {
+        let day = |day: u32| timezone.with_ymd_and_hms(2023, 7, day, 0, 0, 0).unwrap();
+        let found = calendar.events_between(&day(24), &day(25)).unwrap();
+        assert_eq!(found.len(), 1, "{}", timezone);
+        assert_eq!(found[0].get_start(), &day(24));
+        assert_eq!(found[0].get_end(), &day(25));
+        assert!(calendar.events_between(&day(23), &day(24)).unwrap().is_empty(), "{}", timezone);
+        assert!(calendar.events_between(&day(25), &day(26)).unwrap().is_empty(), "{}", timezone);
+        let next = get_next_event(&calendar, &(day(23) + Duration::hours(23))).unwrap().unwrap();
+        assert_eq!(next.get_start(), &day(24));
+    }
+}
+
+#[test]
+fn test_all_day_event_days_and_changes() {
+    let mut event = EventBuilder::new("Conference").all_day(july(24), july(26)).build();
+    assert!(event.is_all_day());
+    assert_eq!(event.get_days(), (july(24), july(26)));
+    assert_eq!(event.get_duration(), Duration::days(3));
+    assert!(Event::new_all_day("Backwards", july(26), july(24), Recurring::No).is_err());
+
+    // A new end names the last day, whatever time of day it is given at
+    let changes = EventChanges { name: None, start: Some(Chicago.with_ymd_and_hms(2023, 7, 25, 15, 0, 0).unwrap()), end: Some(Chicago.with_ymd_and_hms(2023, 7, 28, 9, 0, 0).unwrap()) };
+    event.apply_changes(&changes).unwrap();
+    assert_eq!(event.get_days(), (july(25), july(28)));
+
+    // A timed event made all-day covers every day it touched
+    let mut trip = new_test_timed_event("Trip", (2023, 7, 12, 18, 0), (2023, 7, 14, 9, 0), Recurring::No);
+    trip.update_all_day(true).unwrap();
+    trip.update_all_day(true).unwrap();
+    assert_eq!(trip.get_days(), (july(12), july(14)));
+    assert_eq!(trip.get_timezone(), Tz::UTC);
+}
+
+#[test]
+fn test_recurring_all_day_event_exceptions() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("home", &store).unwrap();
+    let mut event = EventBuilder::new("Bins").all_day(july(3), july(3)).recurring(Recurring::Weekly).build();
+    calendar.add_event(&event).unwrap();
+
+    // Occurrences are found by their day, whatever time is given with it
+    calendar.remove_event_scoped(&mut event, Scope::This(Chicago.with_ymd_and_hms(2023, 7, 10, 21, 0, 0).unwrap())).unwrap();
+    let changes = EventChanges { name: None, start: Some(Chicago.with_ymd_and_hms(2023, 7, 18, 0, 0, 0).unwrap()), end: None };
+    calendar.update_event_scoped(&mut event, Scope::This(Chicago.with_ymd_and_hms(2023, 7, 17, 0, 0, 0).unwrap()), &changes).unwrap();
+    let starts: Vec<String> = july_occurrences(&calendar).into_iter().map(|(_, start)| start).collect();
+    assert_eq!(starts, vec!["07-03 00:00", "07-18 00:00", "07-24 00:00", "07-31 00:00"]);
+}
+
+#[test]
+fn test_all_day_events_are_kept_by_every_backend() {
+    let test = TestStore::new();
+    let vdir = new_vdir_path();
+    fs::create_dir_all(&vdir).unwrap();
+    for path in [test.get_path(), &vdir] {
+        let calendar = Calendar::new("holidays", &Store::open(path).unwrap()).unwrap();
+        let mut event = EventBuilder::new("Bins").all_day(july(3), july(4)).recurring(Recurring::Weekly).build();
+        event.add_exdate(Chicago.with_ymd_and_hms(2023, 7, 10, 12, 0, 0).unwrap());
+        calendar.add_event(&event).unwrap();
+
+        let reopened = Calendar::from("holidays", &Store::open(path).unwrap()).unwrap();
+        let events = get_events(&reopened).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].is_all_day());
+        assert_eq!(events[0].get_days(), (july(3), july(4)));
+        assert_eq!(events[0].get_exdates(), event.get_exdates());
+    }
+    fs::remove_dir_all(&vdir).unwrap();
+}
+
+#[test]
+fn test_ical_all_day_events_use_dates() {
+    let mut event = EventBuilder::new("Conference").all_day(july(24), july(26)).recurring(Recurring::Yearly).build();
+    event.update_uid(Some("conference@example.com")).unwrap();
+    event.add_exdate(Tz::UTC.with_ymd_and_hms(2024, 7, 24, 0, 0, 0).unwrap());
+    let text = write_ical("Work", std::slice::from_ref(&event), &Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap());
+    assert!(text.contains("DTSTART;VALUE=DATE:20230724\r\nDTEND;VALUE=DATE:20230727\r\n"));
+    assert!(text.contains("EXDATE;VALUE=DATE:20240724\r\n"));
+    assert!(!text.contains("VTIMEZONE"));
+
+    // Read back in any zone, the event keeps its days
+    let (events, skipped) = events_from_vevents(parse_ical(&text, chrono_tz::Asia::Tokyo).unwrap().events);
+    assert!(skipped.is_empty());
+    assert!(events[0].is_all_day());
+    assert_eq!(events[0].get_days(), (july(24), july(26)));
+    assert_eq!(events[0].get_exdates(), event.get_exdates());
+}
+
+#[test]
+fn test_views_show_all_day_events() {
+    let test = TestStore::in_memory();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("work", &store).unwrap();
+    calendar.add_event(&EventBuilder::new("Conference").all_day(july(24), july(26)).build()).unwrap();
+    calendar.add_event(&EventBuilder::new("Holiday").all_day(july(28), july(28)).build()).unwrap();
+    calendar.add_event(&new_test_timed_event("Standup", (2023, 7, 25, 9, 0), (2023, 7, 25, 9, 15), Recurring::No)).unwrap();
+
+    let from = Chicago.with_ymd_and_hms(2023, 7, 24, 0, 0, 0).unwrap();
+    let entries: Vec<AgendaEntry> = calendar.events_between(&from, &(from + Duration::days(5))).unwrap()
+        .into_iter()
+        .map(|occurrence| AgendaEntry { calendar: "work".to_string(), occurrence })
+        .collect();
+    assert_eq!(render_agenda(&entries, july(24), 5, Chicago, &TimeFormat::default()), "Mon 2023-07-24
+  all day      3d        Conference (day 1/3)  [work]
+Tue 2023-07-25
+  all day      3d        Conference (day 2/3)  [work]
+  09:00-09:15  15m       Standup  [work]
+Wed 2023-07-26
+  all day      3d        Conference (day 3/3)  [work]
+Fri 2023-07-28
+  all day      1d        Holiday  [work]
+");
+
+    let (all_day, timed): (Vec<Occurrence>, Vec<Occurrence>) = entries.into_iter()
+        .map(|entry| entry.occurrence)
+        .partition(|occurrence| occurrence.get_event().is_all_day());
+    let grid = render_month(july(1), july(19), &days_with_events(&timed, Chicago), &days_with_events(&all_day, Chicago), &MonthOptions::default());
+    assert_eq!(grid.lines().nth(6).unwrap(), "24+ 25* 26+ 27  28+ 29  30");
+}
+
+#[test]
+fn test_cli_all_day_events() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let config = Config::from("timezone = \"America/Chicago\"").unwrap();
+    run_cli_with(&path, &config, &["add", "Conference", "2023-07-24 to 2023-07-26", "--all-day"]).unwrap();
+    run_cli_with(&path, &config, &["add", "Holiday", "2023-07-28", "--all-day"]).unwrap();
+    run_cli_with(&path, &config, &["add", "Retreat", "2023-07-30 for 2 days", "--all-day"]).unwrap();
+    assert!(run_cli_with(&path, &config, &["add", "Nap", "2023-07-30 for 3h", "--all-day"]).is_err());
+
+    let list = run_cli_with(&path, &config, &["list", "--from", "2023-07-24", "--to", "2023-08-01"]).unwrap();
+    assert_eq!(list, "2023-07-24 - 2023-07-26 (all day)  Conference\n2023-07-28 (all day)  Holiday\n2023-07-30 - 2023-07-31 (all day)  Retreat\n");
+    let shown = run_cli_with(&path, &config, &["show", "Conference"]).unwrap();
+    assert!(shown.contains("  start:      2023-07-24 (all day)\n  end:        2023-07-26 (all day)\n"));
+}
+
+// Event details
+
+#[test]
+fn test_event_details() {
+    let mut event = EventBuilder::new("Offsite").build();
+    event.update_description(Some("Bring:\n- laptop\n- badge\n")).unwrap();
+    event.update_location(Some("  Lakeview Lodge ")).unwrap();
+    event.update_url(Some("https://example.com/offsite")).unwrap();
+    event.update_categories(&["work, travel", " work", "", "Planning"]).unwrap();
+    assert_eq!(event.get_description(), Some("Bring:\n- laptop\n- badge"));
+    assert_eq!(event.get_location(), Some("Lakeview Lodge"));
+    assert_eq!(event.get_url(), Some("https://example.com/offsite"));
+    assert_eq!(event.get_categories(), &["work", "travel", "Planning"]);
+
+    assert!(event.contains_text("LAPTOP"));
+    assert!(event.contains_text("lodge"));
+    assert!(event.contains_text("example.com"));
+    assert!(event.contains_text("plan"));
+    assert!(!event.contains_text("beach"));
+
+    event.update_location(Some(" ")).unwrap();
+    event.update_categories::<&str>(&[]).unwrap();
+    assert_eq!(event.get_location(), None);
+    assert!(event.get_categories().is_empty());
+}
+
+#[test]
+fn test_event_details_are_kept_by_every_backend() {
+    let test = TestStore::new();
+    let vdir = new_vdir_path();
+    fs::create_dir_all(&vdir).unwrap();
+    let stores = [Store::open(test.get_path()).unwrap(), Store::memory(), Store::open(&vdir).unwrap()];
+    for store in &stores {
+        let calendar = Calendar::new("work", store).unwrap();
+        let mut event = EventBuilder::new("Offsite").build();
+        event.update_description(Some("Agenda:\n1. Roadmap; budget, hiring")).unwrap();
+        event.update_location(Some("Lakeview Lodge")).unwrap();
+        event.update_url(Some("https://example.com/offsite")).unwrap();
+        event.update_categories(&["work", "travel"]).unwrap();
+        calendar.add_event(&event).unwrap();
+        calendar.add_event(&EventBuilder::new("Standup").build()).unwrap();
+
+        let reopened = match store.get_path() {
+            Some(path) => Calendar::from("work", &Store::open(path).unwrap()).unwrap(),
+            None => calendar,
+        };
+        let found = get_events_containing(&reopened, "TRAVEL").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].get_description(), Some("Agenda:\n1. Roadmap; budget, hiring"));
+        assert_eq!(found[0].get_location(), Some("Lakeview Lodge"));
+        assert_eq!(found[0].get_url(), Some("https://example.com/offsite"));
+        assert_eq!(found[0].get_categories(), &["work", "travel"]);
+        assert_eq!(get_events_containing(&reopened, "lodge").unwrap().len(), 1);
+        assert_eq!(get_events_containing(&reopened, "").unwrap().len(), 2);
+    }
+    fs::remove_dir_all(&vdir).unwrap();
+}
+
+#[test]
+fn test_ical_event_details() {
+    let mut event = EventBuilder::new("Offsite").build();
+    event.update_description(Some("Agenda:\n1. Roadmap; budget")).unwrap();
+    event.update_location(Some("Lakeview Lodge, Room 4")).unwrap();
+    event.update_url(Some("https://example.com/offsite?day=1")).unwrap();
+    event.update_categories(&["work", "travel"]).unwrap();
+    let text = write_ical("Work", std::slice::from_ref(&event), &Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap());
+    assert!(text.contains("DESCRIPTION:Agenda:\\n1. Roadmap\\; budget\r\n"));
+    assert!(text.contains("LOCATION:Lakeview Lodge\\, Room 4\r\n"));
+    assert!(text.contains("URL:https://example.com/offsite?day=1\r\n"));
+    assert!(text.contains("CATEGORIES:work,travel\r\n"));
+
+    let (events, _) = events_from_vevents(parse_ical(&text, Tz::UTC).unwrap().events);
+    assert_eq!(events[0].get_description(), event.get_description());
+    assert_eq!(events[0].get_location(), event.get_location());
+    assert_eq!(events[0].get_url(), event.get_url());
+    assert_eq!(events[0].get_categories(), event.get_categories());
+
+    // Categories may be spread over several properties
+    let other = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:a@example.com\r\nDTSTART:20230705T120000Z\r\n\
+        SUMMARY:Lunch\r\nCATEGORIES:food,social\r\nCATEGORIES:team\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+    assert_eq!(parse_ical(other, Tz::UTC).unwrap().events[0].categories, vec!["food", "social", "team"]);
+}
+
+#[test]
+fn test_cli_event_details() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    run_cli(&path, &["add", "Offsite", "-s", "2023-07-24 09:00", "-z", "America/Chicago", "-d", "Bring:\n- laptop", "-l", "Lakeview Lodge",
+        "-u", "https://example.com/offsite", "--categories", "work,travel"]).unwrap();
+    run_cli(&path, &["add", "Standup", "-s", "2023-07-24 10:00", "-z", "America/Chicago"]).unwrap();
+
+    let shown = run_cli(&path, &["show", "Offsite"]).unwrap();
+    assert!(shown.contains("  location:   Lakeview Lodge\n  url:        https://example.com/offsite\n  categories: work, travel\n"));
+    assert!(shown.ends_with("  description:\n    Bring:\n    - laptop\n"));
+
+    let listed = run_cli(&path, &["list", "--from", "2023-07-24T00:00:00-05:00", "--text", "lodge"]).unwrap();
+    assert_eq!(listed.lines().count(), 1);
+    assert!(listed.ends_with("  Offsite\n"));
+
+    run_cli(&path, &["edit", "Offsite", "--location", "", "--categories", "planning"]).unwrap();
+    let shown = run_cli(&path, &["show", "Offsite"]).unwrap();
+    assert!(!shown.contains("location:"));
+    assert!(shown.contains("  categories: planning\n"));
+    assert!(run_cli(&path, &["edit", "Offsite", "--at", "2023-07-24 09:00", "--url", "https://example.com"]).is_err());
+}
+
+// Tags
+
+// Reads a tag filter and checks it against events tagged with each of `tag_sets`
+fn filter_matches(filter: &str, tag_sets: &[&[&str]]) -> Vec<bool> {
+    let filter: TagFilter = filter.parse().unwrap();
+    tag_sets.iter()
+        .map(|tags| filter.matches(&tags.iter().map(|tag| tag.to_string()).collect::<Vec<String>>()))
+        .collect()
+}
+
+#[test]
+fn test_tag_filters() {
+    let sets: &[&[&str]] = &[&["work"], &["work", "urgent"], &["home"], &[]];
+    assert_eq!(filter_matches("work", sets), vec![true, true, false, false]);
+    assert_eq!(filter_matches("Work and not urgent", sets), vec![true, false, false, false]);
+    assert_eq!(filter_matches("work+!urgent", sets), vec![true, false, false, false]);
+    assert_eq!(filter_matches("work or home", sets), vec![true, true, true, false]);
+    assert_eq!(filter_matches("home,urgent", sets), vec![false, true, true, false]);
+    assert_eq!(filter_matches("not (work or home)", sets), vec![false, false, false, true]);
+    // "and" binds tighter than "or"
+    assert_eq!(filter_matches("home or work and urgent", sets), vec![false, true, true, false]);
+
+    for invalid in ["", "work urgent", "work and", "(work", "work)", "not", "wo#rk"] {
+        assert!(invalid.parse::<TagFilter>().is_err(), "{}", invalid);
+    }
+    assert_eq!(TagFilter::all(Vec::new()), None);
+    assert_eq!(
+        parse_tag_filters(&["work".to_string(), "!urgent".to_string()]).unwrap(),
+        Some(TagFilter::And(vec![TagFilter::Tag("work".to_string()), TagFilter::Not(Box::new(TagFilter::Tag("urgent".to_string())))])),
+    );
+}
+
+#[test]
+fn test_event_tags() {
+    let mut event = EventBuilder::new("Review").build();
+    event.add_tag("Project-B").unwrap();
+    event.add_tag("project-a").unwrap();
+    event.add_tag("project-a").unwrap();
+    assert_eq!(event.get_tags(), &["project-a", "project-b"]);
+    assert!(event.remove_tag("PROJECT-B").unwrap());
+    assert!(!event.remove_tag("project-b").unwrap());
+    assert_eq!(event.get_tags(), &["project-a"]);
+
+    for invalid in ["", " ", "two words", "a,b", "and", "not"] {
+        assert!(matches!(event.add_tag(invalid), Err(CaliError::InvalidTag(_))), "{}", invalid);
+    }
+    assert_eq!(normalize_tag(" Area/Ops.2 ").unwrap(), "area/ops.2");
+}
+
+#[test]
+fn test_tags_are_kept_by_every_backend() {
+    let test = TestStore::new();
+    let vdir = new_vdir_path();
+    fs::create_dir_all(&vdir).unwrap();
+    let stores = [Store::open(test.get_path()).unwrap(), Store::memory(), Store::open(&vdir).unwrap()];
+    for store in &stores {
+        let work = Calendar::new("work", store).unwrap();
+        let home = Calendar::new("home", store).unwrap();
+        let mut review = EventBuilder::new("Review").build();
+        review.update_tags(&["project-a", "urgent"]).unwrap();
+        work.add_event(&review).unwrap();
+        let mut planning = EventBuilder::new("Planning").build();
+        planning.add_tag("project-b").unwrap();
+        work.add_event(&planning).unwrap();
+        let mut garden = EventBuilder::new("Garden").build();
+        garden.add_tag("project-a").unwrap();
+        home.add_event(&garden).unwrap();
+
+        let reopened = match store.get_path() {
+            Some(path) => Store::open(path).unwrap(),
+            None => store.clone(),
+        };
+        let work = Calendar::from("work", &reopened).unwrap();
+        let names = |events: Vec<Event>| events.iter().map(|event| event.get_name().to_string()).collect::<Vec<String>>();
+        assert_eq!(names(get_event_tagged(&work, "", false, &"project-a".parse().unwrap()).unwrap()), vec!["Review"]);
+        assert_eq!(names(get_event_tagged(&work, "", false, &"project-a or project-b".parse().unwrap()).unwrap()).len(), 2);
+        assert_eq!(names(get_event_tagged(&work, "", false, &"not urgent".parse().unwrap()).unwrap()), vec!["Planning"]);
+        let from = Chicago.with_ymd_and_hms(2023, 7, 23, 0, 0, 0).unwrap();
+        let occurrences = work.events_between_tagged(&from, &(from + Duration::days(1)), &"urgent".parse().unwrap()).unwrap();
+        assert_eq!(occurrences.len(), 1);
+        assert_eq!(occurrences[0].get_event().get_tags(), &["project-a", "urgent"]);
+
+        let tags: Vec<(String, usize)> = get_tags(&reopened).unwrap().into_iter().collect();
+        assert_eq!(tags, vec![("project-a".to_string(), 2), ("project-b".to_string(), 1), ("urgent".to_string(), 1)]);
+    }
+    fs::remove_dir_all(&vdir).unwrap();
+}
+
+#[test]
+fn test_sqlite_tags_table() {
+    let test = TestStore::new();
+    let store = test.get_store().clone();
+    let calendar = Calendar::new("work", &store).unwrap();
+    let mut review = EventBuilder::new("Review").build();
+    review.update_tags(&["project-a", "urgent"]).unwrap();
+    calendar.add_event(&review).unwrap();
+    let mut planning = EventBuilder::new("Planning").build();
+    planning.add_tag("project-a").unwrap();
+    calendar.add_event(&planning).unwrap();
+
+    let conn = open_database(test.get_path()).unwrap();
+    let count = |table: &str| conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), params![], |row| row.get::<_, i64>(0)).unwrap();
+    assert_eq!((count("tags"), count("event_tags")), (2, 3));
+
+    // Tags no event carries any longer are dropped
+    review.remove_tag("urgent").unwrap();
+    calendar.update_event(&review).unwrap();
+    assert_eq!((count("tags"), count("event_tags")), (1, 2));
+    calendar.remove_event(&planning).unwrap();
+    assert_eq!((count("tags"), count("event_tags")), (1, 1));
+    calendar.remove_event(&review).unwrap();
+    assert_eq!((count("tags"), count("event_tags")), (0, 0));
+
+    // So are those of a removed calendar's events
+    calendar.add_event(&review).unwrap();
+    remove_calendar(&calendar).unwrap();
+    assert_eq!((count("tags"), count("event_tags")), (0, 0));
+}
+
+#[test]
+fn test_ical_tags() {
+    let mut event = EventBuilder::new("Review").build();
+    event.update_tags(&["project-a", "urgent"]).unwrap();
+    let text = write_ical("Work", std::slice::from_ref(&event), &Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap());
+    assert!(text.contains("X-CALI-TAGS:project-a,urgent\r\n"));
+    let (events, _) = events_from_vevents(parse_ical(&text, Tz::UTC).unwrap().events);
+    assert_eq!(events[0].get_tags(), event.get_tags());
+}
+
+#[test]
+fn test_cli_tags() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let config = Config::from("timezone = \"America/Chicago\"").unwrap();
+    run_cli_with(&path, &config, &["add", "Review", "2023-07-24 09:00 for 1h", "-c", "work"]).unwrap();
+    run_cli_with(&path, &config, &["add", "Planning", "2023-07-24 11:00 for 1h", "-c", "work"]).unwrap();
+    run_cli_with(&path, &config, &["add", "Garden", "2023-07-25 11:00 for 1h", "-c", "home"]).unwrap();
+    assert_eq!(run_cli_with(&path, &config, &["tag", "add", "Review", "project-a", "Urgent", "-c", "work"]).unwrap(), "'Review' is tagged project-a, urgent.\n");
+    run_cli_with(&path, &config, &["tag", "add", "Planning", "project-b", "-c", "work"]).unwrap();
+    run_cli_with(&path, &config, &["tag", "add", "Garden", "project-a", "-c", "home"]).unwrap();
+    assert!(run_cli_with(&path, &config, &["tag", "add", "Garden", "not", "-c", "home"]).is_err());
+
+    let list = run_cli_with(&path, &config, &["list", "-c", "work", "--from", "2023-07-24", "--tag", "project-a or project-b", "--tag", "not urgent"]).unwrap();
+    assert_eq!(list, "2023-07-24 11:00 - 12:00  Planning\n");
+    let agenda = run_cli_with(&path, &config, &["agenda", "--from", "2023-07-24", "--days", "2", "--tag", "project-a"]).unwrap();
+    assert_eq!(agenda, "Mon 2023-07-24\n  09:00-10:00  1h        Review  [work]\nTue 2023-07-25\n  11:00-12:00  1h        Garden  [home]\n");
+    assert!(run_cli_with(&path, &config, &["show", "Review", "-c", "work"]).unwrap().contains("  tags:       project-a, urgent\n"));
+    assert!(run_cli_with(&path, &config, &["show", "Review", "-c", "work", "--tag", "project-b"]).is_err());
+    assert_eq!(run_cli_with(&path, &config, &["tag", "list"]).unwrap(), "project-a (2)\nproject-b (1)\nurgent (1)\n");
+
+    assert_eq!(run_cli_with(&path, &config, &["tag", "rm", "Review", "urgent", "project-c", "-c", "work"]).unwrap(), "'Review' was not tagged project-c.\n'Review' is tagged project-a.\n");
+    assert_eq!(run_cli_with(&path, &config, &["tag", "rm", "Planning", "project-b", "-c", "work"]).unwrap(), "'Planning' has no tags.\n");
+    assert!(run_cli_with(&path, &config, &["list", "--tag", "work urgent"]).is_err());
+}
+
+
+// Tasks
+
+// A time in Chicago on a day of July 2023
+fn july_at(day: u32, hour: u32) -> DateTime<Tz> {
+    Chicago.with_ymd_and_hms(2023, 7, day, hour, 0, 0).unwrap()
+}
+
+#[test]
+fn test_task_status_and_overdue() {
+    let mut rent = Task::new("Pay rent", Some(Due::Date(july(24))));
+    assert!(rent.is_open());
+    assert!(!rent.is_overdue(&july_at(24, 23)));
+    assert!(rent.is_overdue(&july_at(25, 0)));
+
+    rent.complete(july_at(25, 9));
+    assert_eq!(rent.get_status(), TaskStatus::Completed);
+    assert_eq!(rent.get_percent_complete(), 100);
+    assert_eq!(rent.get_completed(), Some(&july_at(25, 9)));
+    assert!(!rent.is_overdue(&july_at(26, 0)));
+    rent.reopen();
+    assert_eq!((rent.get_status(), rent.get_percent_complete(), rent.get_completed()), (TaskStatus::NeedsAction, 0, None));
+
+    let report = Task::new("Send report", Some(Due::Time(july_at(24, 17))));
+    assert!(!report.is_overdue(&july_at(24, 17)));
+    assert!(report.is_overdue(&(july_at(24, 17) + Duration::minutes(1))));
+
+    assert!(matches!(rent.update_priority(10), Err(CaliError::InvalidTask(_))));
+    assert!(rent.update_percent_complete(101).is_err());
+    assert_eq!("in-process".parse::<TaskStatus>().unwrap(), TaskStatus::InProcess);
+    assert_eq!(TaskStatus::NeedsAction.to_string(), "NEEDS-ACTION");
+    assert!("DONE".parse::<TaskStatus>().is_err());
+}
+
+#[test]
+fn test_tasks_are_kept_by_every_backend() {
+    let test = TestStore::new();
+    let vdir = new_vdir_path();
+    fs::create_dir_all(&vdir).unwrap();
+    let stores = [Store::open(test.get_path()).unwrap(), Store::memory(), Store::open(&vdir).unwrap()];
+    for store in &stores {
+        let home = Calendar::new("home", store).unwrap();
+        let work = Calendar::new("work", store).unwrap();
+        let mut rent = Task::new("Pay rent", Some(Due::Date(july(24))));
+        rent.update_priority(1).unwrap();
+        home.add_task(&rent).unwrap();
+        let mut report = Task::new("Send report", Some(Due::Time(july_at(24, 17))));
+        report.update_percent_complete(40).unwrap();
+        report.update_status(TaskStatus::InProcess).unwrap();
+        work.add_task(&report).unwrap();
+        work.add_task(&Task::new("Tidy desk", None)).unwrap();
+        work.add_event(&EventBuilder::new("Review").build()).unwrap();
+
+        let reopened = match store.get_path() {
+            Some(path) => Store::open(path).unwrap(),
+            None => store.clone(),
+        };
+        let home = Calendar::from("home", &reopened).unwrap();
+        let work = Calendar::from("work", &reopened).unwrap();
+        let tasks = get_tasks(&work).unwrap();
+        let titles: Vec<&str> = tasks.iter().map(Task::get_title).collect();
+        assert_eq!(titles, vec!["Send report", "Tidy desk"]);
+        assert_eq!(tasks[0].get_due(), Some(&Due::Time(july_at(24, 17))));
+        assert_eq!((tasks[0].get_status(), tasks[0].get_percent_complete()), (TaskStatus::InProcess, 40));
+        assert_eq!(get_events(&work).unwrap().len(), 1);
+        assert!(check_task(&reopened, report.get_id()).unwrap());
+        assert!(!check_event(&reopened, report.get_id()).unwrap());
+
+        let mut rent = get_tasks(&home).unwrap().remove(0);
+        assert_eq!((rent.get_priority(), rent.get_due()), (1, Some(&Due::Date(july(24)))));
+        rent.complete(july_at(23, 12));
+        home.update_task(&rent).unwrap();
+        assert_eq!(get_tasks(&home).unwrap()[0].get_completed(), Some(&july_at(23, 12)));
+        home.remove_task(&rent).unwrap();
+        assert!(get_tasks(&home).unwrap().is_empty());
+        remove_calendar(&work).unwrap();
+        assert!(!check_task(&reopened, report.get_id()).unwrap());
+    }
+    fs::remove_dir_all(&vdir).unwrap();
+}
+
+#[test]
+fn test_ical_tasks() {
+    let mut rent = Task::new("Pay rent", Some(Due::Date(july(24))));
+    rent.update_priority(1).unwrap();
+    let mut report = Task::new("Send report", Some(Due::Time(july_at(24, 17))));
+    report.complete(july_at(24, 16));
+    let text = write_calendar("Home", &[], &[rent.clone(), report.clone()], &Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap());
+    assert!(text.contains("BEGIN:VTODO\r\n"));
+    assert!(text.contains("DUE;VALUE=DATE:20230724\r\n"));
+    assert!(text.contains("DUE;TZID=America/Chicago:20230724T170000\r\n"));
+    assert!(text.contains("COMPLETED:20230724T210000Z\r\n"));
+    assert!(text.contains("BEGIN:VTIMEZONE\r\n"));
+
+    let (tasks, skipped) = tasks_from_vtodos(parse_ical(&text, Tz::UTC).unwrap().todos);
+    assert!(skipped.is_empty());
+    assert_eq!(tasks[0].get_id(), rent.get_id());
+    assert_eq!((tasks[0].get_priority(), tasks[0].get_due()), (1, rent.get_due()));
+    assert_eq!(tasks[1].get_status(), TaskStatus::Completed);
+    assert_eq!(tasks[1].get_completed(), Some(&july_at(24, 16)));
+
+    let other = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTODO\r\nUID:milk@example.com\r\nSUMMARY:Buy milk\r\n\
+        STATUS:IN-PROCESS\r\nPERCENT-COMPLETE:50\r\nEND:VTODO\r\nBEGIN:VTODO\r\nSUMMARY:Bad\r\nPRIORITY:12\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+    let test = TestStore::in_memory();
+    let calendar = Calendar::new("home", test.get_store()).unwrap();
+    let summary = import_ical(&calendar, other, Tz::UTC).unwrap();
+    assert_eq!((summary.added, summary.tasks, summary.skipped.len()), (0, 1, 1));
+    let tasks = get_tasks(&calendar).unwrap();
+    assert_eq!(tasks[0].get_uid(), Some("milk@example.com"));
+    assert_eq!(tasks[0].get_percent_complete(), 50);
+    assert_eq!(import_ical(&calendar, other, Tz::UTC).unwrap().tasks, 1);
+    assert_eq!(get_tasks(&calendar).unwrap().len(), 1);
+}
+
+#[test]
+fn test_agenda_lists_tasks() {
+    let review = EventBuilder::new("Review").between(july_at(24, 9), july_at(24, 10)).build();
+    let entries = vec![AgendaEntry { calendar: "work".to_string(), occurrence: review.occurrences().next().unwrap() }];
+    let task = |title: &str, due: Due| TaskEntry { calendar: "home".to_string(), task: Task::new(title, Some(due)) };
+    let mut done = task("Old chore", Due::Date(july(1)));
+    done.task.complete(july_at(2, 9));
+    let tasks = vec![
+        task("Pay rent", Due::Date(july(20))),
+        task("Send report", Due::Time(july_at(24, 17))),
+        task("Water plants", Due::Date(july(25))),
+        done,
+    ];
+    let from = july(24);
+    let agenda = render_agenda_with_tasks(&entries, &tasks, from, 2, Tz::America__Chicago, &TimeFormat::default(), &july_at(24, 18), false);
+    assert_eq!(agenda, "Overdue\n  due 2023-07-20  task      Pay rent (overdue)  [home]\n\
+        Mon 2023-07-24\n  09:00-10:00  1h        Review  [work]\n  due 17:00    task      Send report (overdue)  [home]\n\
+        Tue 2023-07-25\n  due          task      Water plants  [home]\n");
+
+    let highlighted = render_agenda_with_tasks(&[], &tasks, from, 1, Tz::America__Chicago, &TimeFormat::default(), &july_at(24, 12), true);
+    assert!(highlighted.contains("\x1b[31mdue 2023-07-20  task      Pay rent (overdue)  [home]\x1b[0m\n"));
+    assert!(highlighted.contains("  due 17:00    task      Send report  [home]\n"));
+}
+
+#[test]
+fn test_cli_todo() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let config = Config::from("timezone = \"America/Chicago\"").unwrap();
+    run_cli_with(&path, &config, &["calendars", "add", "home"]).unwrap();
+    let added = run_cli_with(&path, &config, &["todo", "add", "Pay rent", "--due", "2023-07-20", "-p", "1"]).unwrap();
+    assert!(added.starts_with("Added task 'Pay rent' ("));
+    run_cli_with(&path, &config, &["todo", "add", "Send report", "--due", "2023-07-24 17:00", "-c", "work"]).unwrap();
+    run_cli_with(&path, &config, &["todo", "add", "Tidy desk"]).unwrap();
+    assert!(run_cli_with(&path, &config, &["todo", "add", "Bad", "-p", "10"]).is_err());
+
+    assert_eq!(run_cli_with(&path, &config, &["todo"]).unwrap(), "[ ] Pay rent (priority 1)  due 2023-07-20 (overdue)  [home]\n\
+        [ ] Send report  due 2023-07-24 17:00 (overdue)  [work]\n[ ] Tidy desk  [home]\n");
+    assert_eq!(run_cli_with(&path, &config, &["todo", "done", "rent"]).unwrap(), "Completed 'Pay rent'.\n");
+    assert_eq!(run_cli_with(&path, &config, &["todo", "list", "-c", "home"]).unwrap(), "[ ] Tidy desk  [home]\n");
+    let all = run_cli_with(&path, &config, &["todo", "list", "--all", "-c", "home"]).unwrap();
+    assert!(all.starts_with("[x] Pay rent (priority 1)  due 2023-07-20  done "));
+    assert!(all.ends_with("  [home]\n[ ] Tidy desk  [home]\n"));
+
+    assert_eq!(run_cli_with(&path, &config, &["todo", "undo", "Tidy desk"]).unwrap(), "'Tidy desk' is not done.\n");
+    assert_eq!(run_cli_with(&path, &config, &["todo", "undo", "pay"]).unwrap(), "Reopened 'Pay rent'.\n");
+    assert!(matches!(
+        InputParser::try_parse_from(["cali", "todo", "done", "nothing"]).unwrap().execute(&path, &config, &mut Vec::new()),
+        Err(CaliError::TaskNotFound(_)),
+    ));
+    run_cli_with(&path, &config, &["todo", "add", "Pay phone bill"]).unwrap();
+    assert!(run_cli_with(&path, &config, &["todo", "done", "pay"]).unwrap_err().starts_with("'pay' matches 2 tasks"));
+
+    let agenda = run_cli_with(&path, &config, &["agenda", "--from", "2023-07-24", "--days", "1"]).unwrap();
+    assert_eq!(agenda, "Overdue\n  due 2023-07-20  task      Pay rent (overdue)  [home]\nMon 2023-07-24\n  due 17:00    task      Send report (overdue)  [work]\n");
+    assert_eq!(run_cli_with(&path, &config, &["agenda", "--from", "2023-07-24", "--days", "1", "--tag", "work"]).unwrap(), "No events.\n");
+    assert_eq!(run_cli_with(&path, &config, &["agenda", "--from", "2023-07-24", "--days", "1", "--tag", "not work"]).unwrap(), "No events.\n");
+}
+
+#[test]
+fn test_subtasks_roll_up_and_blockers() {
+    let test = TestStore::in_memory();
+    let calendar = Calendar::new("home", test.get_store()).unwrap();
+    let mut house = Task::new("Move house", None);
+    calendar.add_task(&house).unwrap();
+    let mut pack = Task::new("Pack boxes", None);
+    calendar.add_subtask(&mut pack, Some(&house)).unwrap();
+    let mut van = Task::new("Book van", None);
+    calendar.add_subtask(&mut van, Some(&house)).unwrap();
+    calendar.block_task(&mut van, &pack).unwrap();
+    assert_eq!(van.get_parent(), Some(house.get_id()));
+    assert_eq!(van.get_blocked_by(), &[*pack.get_id()]);
+
+    let find = |task: &Task| get_tasks(&calendar).unwrap().into_iter().find(|other| other.get_id() == task.get_id()).unwrap();
+    assert!(matches!(calendar.complete_task(&mut van, july_at(24, 9)), Err(CaliError::BlockedTask(_))));
+    assert_eq!(CaliError::from(BlockedTaskError { title: "Book van".to_string(), blockers: vec![] }).exit_code(), 75);
+    assert!(find(&van).is_open());
+    calendar.complete_task(&mut pack, july_at(24, 9)).unwrap();
+    house = find(&house);
+    assert_eq!((house.get_status(), house.get_percent_complete()), (TaskStatus::InProcess, 50));
+    calendar.complete_task(&mut van, july_at(24, 10)).unwrap();
+    assert_eq!(find(&house).get_percent_complete(), 100);
+    calendar.reopen_task(&mut pack).unwrap();
+    assert_eq!(find(&house).get_percent_complete(), 50);
+
+    // Nothing may end up beneath or blocked by itself
+    assert!(matches!(calendar.move_task(&mut house, Some(&pack)), Err(CaliError::InvalidTask(_))));
+    assert!(calendar.block_task(&mut pack, &van).is_err());
+    assert!(house.update_parent(Some(*house.get_id())).is_err());
+    assert!(calendar.unblock_task(&mut van, &pack).unwrap());
+    assert!(!calendar.unblock_task(&mut van, &pack).unwrap());
+    assert!(find(&van).get_blocked_by().is_empty());
+    calendar.block_task(&mut van, &pack).unwrap();
+    assert_eq!(find(&van).get_blocked_by(), &[*pack.get_id()]);
+    let tasks = get_tasks(&calendar).unwrap();
+    let tree: Vec<(usize, &str)> = task_tree(&tasks).into_iter().map(|(depth, task)| (depth, task.get_title())).collect();
+    assert_eq!(tree, vec![(0, "Move house"), (1, "Book van"), (1, "Pack boxes")]);
+
+    calendar.move_task(&mut van, None).unwrap();
+    assert_eq!(find(&house).get_percent_complete(), 0);
+    assert_eq!(get_subtasks(&get_tasks(&calendar).unwrap(), house.get_id()).len(), 1);
+    calendar.complete_task(&mut pack, july_at(24, 11)).unwrap();
+    assert_eq!(find(&house).get_percent_complete(), 100);
+
+    // Without its only subtask, the parent has nothing done
+    calendar.remove_task(&pack).unwrap();
+    assert!(find(&van).get_blocked_by().is_empty());
+    house = find(&house);
+    assert_eq!((house.get_status(), house.get_percent_complete()), (TaskStatus::NeedsAction, 0));
+}
+
+#[test]
+fn test_task_relations_are_kept() {
+    let test = TestStore::new();
+    let vdir = new_vdir_path();
+    fs::create_dir_all(&vdir).unwrap();
+    let stores = [Store::open(test.get_path()).unwrap(), Store::memory(), Store::open(&vdir).unwrap()];
+    for store in &stores {
+        let calendar = Calendar::new("home", store).unwrap();
+        let house = Task::new("Move house", None);
+        calendar.add_task(&house).unwrap();
+        let pack = Task::new("Pack boxes", None);
+        calendar.add_task(&pack).unwrap();
+        let mut van = Task::new("Book van", None);
+        calendar.add_subtask(&mut van, Some(&house)).unwrap();
+        calendar.block_task(&mut van, &pack).unwrap();
+
+        let reopened = match store.get_path() {
+            Some(path) => Store::open(path).unwrap(),
+            None => store.clone(),
+        };
+        let tasks = get_tasks(&Calendar::from("home", &reopened).unwrap()).unwrap();
+        let van = tasks.iter().find(|task| task.get_title() == "Book van").unwrap();
+        assert_eq!(van.get_parent(), Some(house.get_id()));
+        assert_eq!(van.get_blocked_by(), &[*pack.get_id()]);
+        assert_eq!(get_open_blockers(&tasks, van).len(), 1);
+    }
+    fs::remove_dir_all(&vdir).unwrap();
+
+    let text = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTODO\r\nUID:house@example.com\r\nSUMMARY:Move house\r\nEND:VTODO\r\n\
+        BEGIN:VTODO\r\nUID:van@example.com\r\nSUMMARY:Book van\r\nRELATED-TO:house@example.com\r\n\
+        RELATED-TO;RELTYPE=DEPENDS-ON:pack@example.com\r\nRELATED-TO;RELTYPE=SIBLING:other@example.com\r\nEND:VTODO\r\n\
+        BEGIN:VTODO\r\nUID:pack@example.com\r\nSUMMARY:Pack boxes\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+    let (tasks, _) = tasks_from_vtodos(parse_ical(text, Tz::UTC).unwrap().todos);
+    assert_eq!(tasks[1].get_parent(), Some(tasks[0].get_id()));
+    assert_eq!(tasks[1].get_blocked_by(), &[*tasks[2].get_id()]);
+    let written = write_calendar("Home", &[], &tasks, &Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap());
+    assert!(written.contains("RELATED-TO;RELTYPE=PARENT:house@example.com\r\n"));
+    assert!(written.contains("RELATED-TO;RELTYPE=DEPENDS-ON:pack@example.com\r\n"));
+}
+
+#[test]
+fn test_cli_todo_tree() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let config = Config::from("timezone = \"America/Chicago\"").unwrap();
+    run_cli_with(&path, &config, &["calendars", "add", "home"]).unwrap();
+    run_cli_with(&path, &config, &["todo", "add", "Move house"]).unwrap();
+    let added = run_cli_with(&path, &config, &["todo", "add", "Pack boxes", "--parent", "Move house"]).unwrap();
+    assert!(added.ends_with(") to 'home' under 'Move house'.\n"));
+    run_cli_with(&path, &config, &["todo", "add", "Book van", "--parent", "Move house", "-b", "Pack boxes"]).unwrap();
+    run_cli_with(&path, &config, &["todo", "add", "Label boxes"]).unwrap();
+    assert_eq!(run_cli_with(&path, &config, &["todo", "move", "Label", "Pack"]).unwrap(), "Moved 'Label boxes' under 'Pack boxes'.\n");
+    assert_eq!(run_cli_with(&path, &config, &["todo", "block", "Book van", "Label"]).unwrap(), "'Book van' is now blocked by Label boxes.\n");
+
+    assert_eq!(run_cli_with(&path, &config, &["todo", "tree"]).unwrap(), "home\n  [ ] Move house\n\
+        \x20   [ ] Book van  blocked by Label boxes, Pack boxes\n    [ ] Pack boxes\n      [ ] Label boxes\n");
+    assert!(run_cli_with(&path, &config, &["todo", "done", "van"]).unwrap_err().starts_with("'Book van' is blocked by open tasks: "));
+    assert!(run_cli_with(&path, &config, &["todo", "move", "Move house", "Label"]).is_err());
+    run_cli_with(&path, &config, &["todo", "done", "Label"]).unwrap();
+    assert_eq!(run_cli_with(&path, &config, &["todo", "tree"]).unwrap(), "home\n  [ ] Move house (50%)\n\
+        \x20   [ ] Book van  blocked by Pack boxes\n    [ ] Pack boxes (100%)\n");
+    assert!(run_cli_with(&path, &config, &["todo", "tree", "--all"]).unwrap().contains("\n      [x] Label boxes  done "));
+    assert_eq!(run_cli_with(&path, &config, &["todo", "unblock", "van", "Pack", "Label"]).unwrap(),
+        "'Book van' is no longer blocked by 'Pack boxes'.\n'Book van' is no longer blocked by 'Label boxes'.\n");
+    assert_eq!(run_cli_with(&path, &config, &["todo", "done", "van"]).unwrap(), "Completed 'Book van'.\n");
+    assert_eq!(run_cli_with(&path, &config, &["todo", "move", "Pack"]).unwrap(), "Moved 'Pack boxes' to the top level.\n");
+    assert_eq!(run_cli_with(&path, &config, &["todo", "tree", "-c", "home"]).unwrap(), "home\n  [ ] Move house (100%)\n  [ ] Pack boxes (100%)\n");
+}
+
+
+// Search
+
+// Reads a search query that is known to be valid
+fn search(query: &str) -> SearchQuery {
+    query.parse().unwrap()
+}
+
+#[test]
+fn test_search_query() {
+    let query = search("Budget \"design  review\" confer*");
+    let terms: Vec<(Vec<String>, bool)> = query.get_terms().iter().map(|term| (term.get_words().to_vec(), term.is_prefix())).collect();
+    assert_eq!(terms, vec![
+        (vec!["budget".to_string()], false),
+        (vec!["design".to_string(), "review".to_string()], false),
+        (vec!["confer".to_string()], true),
+    ]);
+    assert_eq!(query.to_fts(), "\"budget\" \"design review\" \"confer\" *");
+    assert_eq!(search("stand-up OR").to_fts(), "\"stand up\" \"or\"");
+    assert!(matches!("\"design review".parse::<SearchQuery>(), Err(InvalidSearchError { .. })));
+    assert!(" * - ".parse::<SearchQuery>().is_err());
+
+    let query = search("review conf*");
+    assert_eq!(query.highlight("Design review at the Conference Center", "[", "]"), "Design [review] at the [Conference] Center");
+    assert_eq!(search("\"design review\" review").highlight("Design Review", "<", ">"), "<Design Review>");
+    assert!(query.matches("REVIEWS, reviewed and review."));
+    assert!(!search("view").matches("Design review"));
+}
+
+#[test]
+fn test_search_is_alike_in_every_backend() {
+    let test = TestStore::new();
+    let vdir = new_vdir_path();
+    fs::create_dir_all(&vdir).unwrap();
+    let stores = [Store::open(test.get_path()).unwrap(), Store::memory(), Store::open(&vdir).unwrap()];
+    for store in &stores {
+        let work = Calendar::new("work", store).unwrap();
+        let mut review = EventBuilder::new("Design review").between(july_at(24, 9), july_at(24, 10)).build();
+        review.update_location(Some("Room 4")).unwrap();
+        work.add_event(&review).unwrap();
+        let mut planning = EventBuilder::new("Planning").between(july_at(25, 9), july_at(25, 10)).build();
+        planning.update_description(Some("Bring the design review notes\nand the budget")).unwrap();
+        work.add_event(&planning).unwrap();
+        work.add_event(&EventBuilder::new("Reviewing designs").between(july_at(26, 9), july_at(26, 10)).build()).unwrap();
+
+        let names = |query: &str| -> Vec<String> {
+            search_calendars(std::slice::from_ref(&work), &search(query)).unwrap()
+                .into_iter()
+                .map(|hit| hit.event.get_name().to_string())
+                .collect()
+        };
+        // A match in the title ranks above one in the description
+        assert_eq!(names("review"), vec!["Design review", "Planning"]);
+        assert_eq!(names("\"design review\""), vec!["Design review", "Planning"]);
+        let prefixed = names("review*");
+        assert_eq!((prefixed.len(), prefixed[2].as_str()), (3, "Planning"));
+        assert_eq!(names("budget design"), vec!["Planning"]);
+        assert_eq!(names("\"review design\""), Vec::<String>::new());
+        assert_eq!(names("room"), vec!["Design review"]);
+
+        review.update_name("Retro").unwrap();
+        work.update_event(&review).unwrap();
+        assert_eq!(names("retro"), vec!["Retro"]);
+        assert_eq!(names("\"design review\""), vec!["Planning"]);
+        work.remove_event(&planning).unwrap();
+        assert!(names("budget").is_empty());
+        remove_calendar(&work).unwrap();
+        let work = Calendar::new("work", store).unwrap();
+        assert!(search_events(&work, &search("retro")).unwrap().is_empty());
+    }
+    fs::remove_dir_all(&vdir).unwrap();
+}
+
+#[test]
+fn test_cli_search() {
+    let test = TestStore::new();
+    let path = test.get_path().clone();
+    let config = Config::from("timezone = \"America/Chicago\"").unwrap();
+    run_cli_with(&path, &config, &["calendars", "add", "work"]).unwrap();
+    run_cli_with(&path, &config, &["add", "Design review", "--start", "2023-07-24 09:00", "--end", "2023-07-24 10:00",
+        "--location", "Review room", "-c", "work"]).unwrap();
+    run_cli_with(&path, &config, &["add", "Planning", "--start", "2023-08-01 09:00", "--end", "2023-08-01 10:00",
+        "--description", "Agenda:\nreview the budget\nbook rooms", "-c", "home"]).unwrap();
+    run_cli_with(&path, &config, &["add", "Standup", "--start", "2023-07-03 09:00", "--end", "2023-07-03 09:15",
+        "--recurrence", "weekly", "--description", "Quick review", "-c", "home"]).unwrap();
+
+    assert_eq!(run_cli_with(&path, &config, &["search", "review"]).unwrap(), "2023-07-24 09:00 - 10:00  Design *review*  [work]\n\
+        \x20 at *Review* room\n2023-07-03 09:00 - 09:15  Standup  [home]\n  Quick *review*\n\
+        2023-08-01 09:00 - 10:00  Planning  [home]\n  *review* the budget\n");
+    assert_eq!(run_cli_with(&path, &config, &["search", "\"the budget\"", "-c", "home", "-c", "work"]).unwrap(),
+        "2023-08-01 09:00 - 10:00  Planning  [home]\n  review *the budget*\n");
+    assert_eq!(run_cli_with(&path, &config, &["search", "room*", "-c", "home"]).unwrap(),
+        "2023-08-01 09:00 - 10:00  Planning  [home]\n  book *rooms*\n");
+    // Within a range, a recurring event shows its first occurrence there
+    assert_eq!(run_cli_with(&path, &config, &["search", "quick", "review", "--from", "2023-07-19", "--to", "2023-07-31"]).unwrap(),
+        "2023-07-24 09:00 - 09:15  Standup  [home]\n  *Quick* *review*\n");
+    assert_eq!(run_cli_with(&path, &config, &["search", "planning", "--from", "2023-07-19", "--to", "2023-07-31"]).unwrap(), "No events.\n");
+
+    // Tag filters apply as they do for list and agenda
+    run_cli_with(&path, &config, &["tag", "add", "Standup", "team", "-c", "home"]).unwrap();
+    assert_eq!(run_cli_with(&path, &config, &["search", "review", "--tag", "team"]).unwrap(),
+        "2023-07-03 09:00 - 09:15  Standup  [home]\n  Quick *review*\n");
+    assert_eq!(run_cli_with(&path, &config, &["search", "review", "--tag", "not team", "-c", "home"]).unwrap(),
+        "2023-08-01 09:00 - 10:00  Planning  [home]\n  *review* the budget\n");
+    assert!(matches!(
+        InputParser::try_parse_from(["cali", "search", "review", "--tag", "team and"]).unwrap().execute(&path, &config, &mut Vec::new()),
+        Err(CaliError::InvalidTag(_)),
+    ));
+
+    let colored = Config::from("timezone = \"America/Chicago\"\ncolor = \"always\"").unwrap();
+    assert!(run_cli_with(&path, &colored, &["search", "design"]).unwrap().contains("  \x1b[7mDesign\x1b[0m review  [work]\n"));
+    let error = InputParser::try_parse_from(["cali", "search", "\"review"]).unwrap().execute(&path, &config, &mut Vec::new()).unwrap_err();
+    assert!(matches!(error, CaliError::InvalidSearch(_)));
+    assert_eq!(error.exit_code(), 65);
+    assert!(matches!(
+        InputParser::try_parse_from(["cali", "search", "review", "-c", "nowhere"]).unwrap().execute(&path, &config, &mut Vec::new()),
+        Err(CaliError::CalendarNotFound(_)),
+    ));
+}